@@ -0,0 +1,673 @@
+//! Local archive of invoices sent to and received from KSeF.
+//!
+//! Every invoice is stored with its XML, SHA-256 hash, submission references,
+//! KSeF number, UPO and the history of its status changes, so the archive can
+//! serve as a system of record without access to KSeF.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use rusqlite::types::{Type, Value};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Result alias used by the archive
+pub type Result<T> = std::result::Result<T, ArchiveError>;
+
+/// Errors produced by the archive
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("Invoice not found: {0}")]
+    NotFound(String),
+}
+
+/// Current schema version, stored in `PRAGMA user_version`
+const SCHEMA_VERSION: i32 = 1;
+
+const SCHEMA_V1: &str = "
+    CREATE TABLE invoices (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        direction TEXT NOT NULL,
+        status TEXT NOT NULL,
+        invoice_number TEXT,
+        seller_nip TEXT,
+        buyer_nip TEXT,
+        issue_date TEXT,
+        gross_amount REAL,
+        currency TEXT,
+        invoice_hash TEXT NOT NULL,
+        file_size INTEGER NOT NULL,
+        ksef_number TEXT UNIQUE,
+        session_reference TEXT,
+        invoice_reference TEXT,
+        xml TEXT NOT NULL,
+        upo TEXT,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE INDEX idx_invoices_seller_nip ON invoices(seller_nip);
+    CREATE INDEX idx_invoices_buyer_nip ON invoices(buyer_nip);
+    CREATE INDEX idx_invoices_issue_date ON invoices(issue_date);
+    CREATE INDEX idx_invoices_invoice_reference ON invoices(invoice_reference);
+    CREATE TABLE status_history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        invoice_id INTEGER NOT NULL REFERENCES invoices(id),
+        status TEXT NOT NULL,
+        description TEXT,
+        changed_at TEXT NOT NULL
+    );
+    CREATE INDEX idx_status_history_invoice ON status_history(invoice_id);
+";
+
+const INVOICE_COLUMNS: &str = "id, direction, status, invoice_number, seller_nip, buyer_nip, \
+    issue_date, gross_amount, currency, invoice_hash, file_size, ksef_number, session_reference, \
+    invoice_reference, xml, upo, created_at, updated_at";
+
+/// Whether the invoice was issued by us or received from a supplier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sent" => Some(Direction::Sent),
+            "received" => Some(Direction::Received),
+            _ => None,
+        }
+    }
+}
+
+/// Lifecycle status of an archived invoice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvoiceStatus {
+    /// XML generated, not sent yet
+    Generated,
+    /// Sent to a KSeF session, waiting for processing
+    Submitted,
+    /// Accepted by KSeF (KSeF number assigned)
+    Accepted,
+    /// Rejected by KSeF
+    Rejected,
+    /// Downloaded from KSeF
+    Received,
+}
+
+impl InvoiceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvoiceStatus::Generated => "generated",
+            InvoiceStatus::Submitted => "submitted",
+            InvoiceStatus::Accepted => "accepted",
+            InvoiceStatus::Rejected => "rejected",
+            InvoiceStatus::Received => "received",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "generated" => Some(InvoiceStatus::Generated),
+            "submitted" => Some(InvoiceStatus::Submitted),
+            "accepted" => Some(InvoiceStatus::Accepted),
+            "rejected" => Some(InvoiceStatus::Rejected),
+            "received" => Some(InvoiceStatus::Received),
+            _ => None,
+        }
+    }
+}
+
+/// Invoice to be added to the archive
+#[derive(Debug, Clone)]
+pub struct NewInvoice {
+    pub direction: Direction,
+    pub invoice_number: Option<String>,
+    pub seller_nip: Option<String>,
+    pub buyer_nip: Option<String>,
+    /// Issue date (`YYYY-MM-DD`)
+    pub issue_date: Option<String>,
+    pub gross_amount: Option<f64>,
+    pub currency: Option<String>,
+    pub ksef_number: Option<String>,
+    pub xml: String,
+}
+
+impl NewInvoice {
+    /// Sent invoice with only the XML known
+    pub fn sent(xml: impl Into<String>) -> Self {
+        Self::with_direction(Direction::Sent, xml.into())
+    }
+
+    /// Received invoice with only the XML known
+    pub fn received(xml: impl Into<String>) -> Self {
+        Self::with_direction(Direction::Received, xml.into())
+    }
+
+    fn with_direction(direction: Direction, xml: String) -> Self {
+        Self {
+            direction,
+            invoice_number: None,
+            seller_nip: None,
+            buyer_nip: None,
+            issue_date: None,
+            gross_amount: None,
+            currency: None,
+            ksef_number: None,
+            xml,
+        }
+    }
+}
+
+/// Invoice stored in the archive
+#[derive(Debug, Clone)]
+pub struct StoredInvoice {
+    pub id: i64,
+    pub direction: Direction,
+    pub status: InvoiceStatus,
+    pub invoice_number: Option<String>,
+    pub seller_nip: Option<String>,
+    pub buyer_nip: Option<String>,
+    pub issue_date: Option<String>,
+    pub gross_amount: Option<f64>,
+    pub currency: Option<String>,
+    /// Base64 SHA-256 of the XML, as reported by KSeF
+    pub invoice_hash: String,
+    pub file_size: i64,
+    pub ksef_number: Option<String>,
+    pub session_reference: Option<String>,
+    pub invoice_reference: Option<String>,
+    pub xml: String,
+    pub upo: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Entry of the status history
+#[derive(Debug, Clone)]
+pub struct StatusChange {
+    pub status: InvoiceStatus,
+    pub description: Option<String>,
+    pub changed_at: String,
+}
+
+/// Processing result reported by KSeF for a submitted invoice
+#[derive(Debug, Clone)]
+pub struct StatusUpdate<'a> {
+    pub session_reference: &'a str,
+    pub invoice_reference: &'a str,
+    /// Used to match invoices submitted without a known reference (batches)
+    pub invoice_hash: &'a str,
+    pub ksef_number: Option<&'a str>,
+    pub status: InvoiceStatus,
+    pub description: Option<&'a str>,
+}
+
+/// Search criteria; all set fields must match
+#[derive(Debug, Clone, Default)]
+pub struct InvoiceQuery {
+    /// Seller or buyer NIP
+    pub nip: Option<String>,
+    pub direction: Option<Direction>,
+    pub status: Option<InvoiceStatus>,
+    /// Issue date range (`YYYY-MM-DD`, inclusive)
+    pub issue_date_from: Option<String>,
+    pub issue_date_to: Option<String>,
+    /// Gross amount range (inclusive)
+    pub min_amount: Option<f64>,
+    pub max_amount: Option<f64>,
+    /// Substring of the invoice number
+    pub invoice_number: Option<String>,
+    pub ksef_number: Option<String>,
+    /// Maximum number of results (default: 100)
+    pub limit: Option<usize>,
+}
+
+/// SQLite-backed invoice archive
+pub struct InvoiceArchive {
+    conn: Mutex<Connection>,
+}
+
+impl InvoiceArchive {
+    /// Opens (or creates) the archive database at `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Archive kept in memory only, e.g. when the database file cannot be opened
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < 1 {
+            conn.execute_batch(SCHEMA_V1)?;
+        }
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Adds an invoice and returns its id.
+    ///
+    /// An invoice with a KSeF number that is already archived is not added
+    /// again; the id of the existing entry is returned instead.
+    pub fn insert(&self, invoice: &NewInvoice) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        if let Some(ksef_number) = &invoice.ksef_number {
+            let existing = conn
+                .query_row(
+                    "SELECT id FROM invoices WHERE ksef_number = ?1",
+                    [ksef_number],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(id) = existing {
+                return Ok(id);
+            }
+        }
+
+        let status = match invoice.direction {
+            Direction::Sent => InvoiceStatus::Generated,
+            Direction::Received => InvoiceStatus::Received,
+        };
+        let now = now();
+        conn.execute(
+            "INSERT INTO invoices (direction, status, invoice_number, seller_nip, buyer_nip, \
+             issue_date, gross_amount, currency, invoice_hash, file_size, ksef_number, xml, \
+             created_at, updated_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13)",
+            params![
+                invoice.direction.as_str(),
+                status.as_str(),
+                invoice.invoice_number,
+                invoice.seller_nip,
+                invoice.buyer_nip,
+                invoice.issue_date,
+                invoice.gross_amount,
+                invoice.currency,
+                invoice_hash(&invoice.xml),
+                invoice.xml.len() as i64,
+                invoice.ksef_number,
+                invoice.xml,
+                now,
+            ],
+        )?;
+        let id = conn.last_insert_rowid();
+        record_change(&conn, id, status, None)?;
+        Ok(id)
+    }
+
+    /// Records that the invoice was sent in a session. `invoice_reference` is
+    /// unknown for invoices sent in a batch.
+    pub fn record_submission(
+        &self,
+        id: i64,
+        session_reference: &str,
+        invoice_reference: Option<&str>,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE invoices SET session_reference = ?2, invoice_reference = ?3, status = ?4, \
+             updated_at = ?5 WHERE id = ?1",
+            params![
+                id,
+                session_reference,
+                invoice_reference,
+                InvoiceStatus::Submitted.as_str(),
+                now()
+            ],
+        )?;
+        if updated == 0 {
+            return Err(ArchiveError::NotFound(id.to_string()));
+        }
+        record_change(&conn, id, InvoiceStatus::Submitted, None)
+    }
+
+    /// Applies a processing result to the matching submitted invoice.
+    ///
+    /// Returns `false` if no archived invoice matches. The history only gets
+    /// an entry when the status actually changes.
+    pub fn update_status(&self, update: &StatusUpdate) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let found: Option<(i64, String)> = conn
+            .query_row(
+                "SELECT id, status FROM invoices WHERE invoice_reference = ?1 \
+                 OR (session_reference = ?2 AND invoice_hash = ?3 AND invoice_reference IS NULL)",
+                params![
+                    update.invoice_reference,
+                    update.session_reference,
+                    update.invoice_hash
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((id, previous)) = found else {
+            return Ok(false);
+        };
+
+        conn.execute(
+            "UPDATE invoices SET status = ?2, invoice_reference = ?3, \
+             ksef_number = COALESCE(?4, ksef_number), updated_at = ?5 WHERE id = ?1",
+            params![
+                id,
+                update.status.as_str(),
+                update.invoice_reference,
+                update.ksef_number,
+                now()
+            ],
+        )?;
+        if previous != update.status.as_str() {
+            record_change(&conn, id, update.status, update.description)?;
+        }
+        Ok(true)
+    }
+
+    /// Stores the UPO of the invoice with the given KSeF number or invoice
+    /// reference number; returns `false` if it is not archived
+    pub fn attach_upo(&self, ksef_or_reference_number: &str, upo: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE invoices SET upo = ?2, updated_at = ?3 \
+             WHERE ksef_number = ?1 OR invoice_reference = ?1",
+            params![ksef_or_reference_number, upo, now()],
+        )?;
+        Ok(updated > 0)
+    }
+
+    pub fn get(&self, id: i64) -> Result<StoredInvoice> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM invoices WHERE id = ?1", INVOICE_COLUMNS),
+            [id],
+            row_to_invoice,
+        )
+        .optional()?
+        .ok_or_else(|| ArchiveError::NotFound(id.to_string()))
+    }
+
+    pub fn get_by_ksef_number(&self, ksef_number: &str) -> Result<StoredInvoice> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM invoices WHERE ksef_number = ?1",
+                INVOICE_COLUMNS
+            ),
+            [ksef_number],
+            row_to_invoice,
+        )
+        .optional()?
+        .ok_or_else(|| ArchiveError::NotFound(ksef_number.to_string()))
+    }
+
+    /// Invoices matching `query`, newest issue date first
+    pub fn find(&self, query: &InvoiceQuery) -> Result<Vec<StoredInvoice>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+        let mut push = |condition: &str, value: Value| {
+            values.push(value);
+            conditions.push(condition.replace('?', &format!("?{}", values.len())));
+        };
+
+        if let Some(nip) = &query.nip {
+            push(
+                "(seller_nip = ? OR buyer_nip = ?)",
+                Value::Text(nip.clone()),
+            );
+        }
+        if let Some(direction) = query.direction {
+            push("direction = ?", Value::Text(direction.as_str().to_string()));
+        }
+        if let Some(status) = query.status {
+            push("status = ?", Value::Text(status.as_str().to_string()));
+        }
+        if let Some(from) = &query.issue_date_from {
+            push("issue_date >= ?", Value::Text(from.clone()));
+        }
+        if let Some(to) = &query.issue_date_to {
+            push("issue_date <= ?", Value::Text(to.clone()));
+        }
+        if let Some(min) = query.min_amount {
+            push("gross_amount >= ?", Value::Real(min));
+        }
+        if let Some(max) = query.max_amount {
+            push("gross_amount <= ?", Value::Real(max));
+        }
+        if let Some(number) = &query.invoice_number {
+            push(
+                "invoice_number LIKE ?",
+                Value::Text(format!("%{}%", number)),
+            );
+        }
+        if let Some(ksef_number) = &query.ksef_number {
+            push("ksef_number = ?", Value::Text(ksef_number.clone()));
+        }
+
+        let mut sql = format!("SELECT {} FROM invoices", INVOICE_COLUMNS);
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(&format!(
+            " ORDER BY issue_date DESC, id DESC LIMIT {}",
+            query.limit.unwrap_or(100)
+        ));
+
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&sql)?;
+        let invoices = statement
+            .query_map(params_from_iter(values), row_to_invoice)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(invoices)
+    }
+
+    /// Status changes of an invoice, oldest first
+    pub fn history(&self, id: i64) -> Result<Vec<StatusChange>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT status, description, changed_at FROM status_history \
+             WHERE invoice_id = ?1 ORDER BY id",
+        )?;
+        let changes = statement
+            .query_map([id], |row| {
+                Ok(StatusChange {
+                    status: parse_column(row, 0, InvoiceStatus::parse)?,
+                    description: row.get(1)?,
+                    changed_at: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(changes)
+    }
+}
+
+/// Base64 SHA-256 of the document, the form KSeF uses for `invoiceHash`
+pub fn invoice_hash(xml: &str) -> String {
+    BASE64.encode(Sha256::digest(xml.as_bytes()))
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339()
+}
+
+fn record_change(
+    conn: &Connection,
+    id: i64,
+    status: InvoiceStatus,
+    description: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO status_history (invoice_id, status, description, changed_at) \
+         VALUES (?1, ?2, ?3, ?4)",
+        params![id, status.as_str(), description, now()],
+    )?;
+    Ok(())
+}
+
+fn parse_column<T>(row: &Row, index: usize, parse: fn(&str) -> Option<T>) -> rusqlite::Result<T> {
+    let value: String = row.get(index)?;
+    parse(&value).ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(
+            index,
+            Type::Text,
+            format!("Unknown value: {}", value).into(),
+        )
+    })
+}
+
+fn row_to_invoice(row: &Row) -> rusqlite::Result<StoredInvoice> {
+    Ok(StoredInvoice {
+        id: row.get(0)?,
+        direction: parse_column(row, 1, Direction::parse)?,
+        status: parse_column(row, 2, InvoiceStatus::parse)?,
+        invoice_number: row.get(3)?,
+        seller_nip: row.get(4)?,
+        buyer_nip: row.get(5)?,
+        issue_date: row.get(6)?,
+        gross_amount: row.get(7)?,
+        currency: row.get(8)?,
+        invoice_hash: row.get(9)?,
+        file_size: row.get(10)?,
+        ksef_number: row.get(11)?,
+        session_reference: row.get(12)?,
+        invoice_reference: row.get(13)?,
+        xml: row.get(14)?,
+        upo: row.get(15)?,
+        created_at: row.get(16)?,
+        updated_at: row.get(17)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent_invoice(number: &str, buyer: &str, date: &str, amount: f64) -> NewInvoice {
+        NewInvoice {
+            invoice_number: Some(number.to_string()),
+            seller_nip: Some("5265877635".to_string()),
+            buyer_nip: Some(buyer.to_string()),
+            issue_date: Some(date.to_string()),
+            gross_amount: Some(amount),
+            currency: Some("PLN".to_string()),
+            ..NewInvoice::sent(format!("<Faktura><P_2>{}</P_2></Faktura>", number))
+        }
+    }
+
+    #[test]
+    fn test_submission_lifecycle() {
+        let archive = InvoiceArchive::open_in_memory().unwrap();
+        let id = archive
+            .insert(&sent_invoice("FV/1", "7352765225", "2025-10-01", 123.0))
+            .unwrap();
+        archive
+            .record_submission(id, "SO-1", Some("INV-1"))
+            .unwrap();
+
+        let stored = archive.get(id).unwrap();
+        let update = StatusUpdate {
+            session_reference: "SO-1",
+            invoice_reference: "INV-1",
+            invoice_hash: &stored.invoice_hash,
+            ksef_number: Some("5265877635-20251001-0100001D5F4A-1B"),
+            status: InvoiceStatus::Accepted,
+            description: Some("Sukces"),
+        };
+        assert!(archive.update_status(&update).unwrap());
+        assert!(archive.update_status(&update).unwrap());
+        assert!(archive
+            .attach_upo("5265877635-20251001-0100001D5F4A-1B", "<UPO/>")
+            .unwrap());
+
+        let stored = archive
+            .get_by_ksef_number("5265877635-20251001-0100001D5F4A-1B")
+            .unwrap();
+        assert_eq!(stored.status, InvoiceStatus::Accepted);
+        assert_eq!(stored.upo.as_deref(), Some("<UPO/>"));
+        let statuses: Vec<_> = archive
+            .history(id)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                InvoiceStatus::Generated,
+                InvoiceStatus::Submitted,
+                InvoiceStatus::Accepted
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_filters() {
+        let archive = InvoiceArchive::open_in_memory().unwrap();
+        archive
+            .insert(&sent_invoice(
+                "FV/1/2025",
+                "7352765225",
+                "2025-09-15",
+                100.0,
+            ))
+            .unwrap();
+        archive
+            .insert(&sent_invoice(
+                "FV/2/2025",
+                "7352765225",
+                "2025-10-01",
+                2000.0,
+            ))
+            .unwrap();
+        archive
+            .insert(&sent_invoice("FV/3/2025", "1111111111", "2025-10-02", 50.0))
+            .unwrap();
+        let received = NewInvoice {
+            ksef_number: Some("7352765225-20251003-0100001D5F4A-1B".to_string()),
+            ..NewInvoice::received("<Faktura/>")
+        };
+        let first = archive.insert(&received).unwrap();
+        assert_eq!(archive.insert(&received).unwrap(), first);
+
+        let by_buyer = archive
+            .find(&InvoiceQuery {
+                nip: Some("7352765225".to_string()),
+                issue_date_from: Some("2025-10-01".to_string()),
+                ..InvoiceQuery::default()
+            })
+            .unwrap();
+        assert_eq!(by_buyer.len(), 1);
+        assert_eq!(by_buyer[0].invoice_number.as_deref(), Some("FV/2/2025"));
+
+        let cheap = archive
+            .find(&InvoiceQuery {
+                max_amount: Some(150.0),
+                invoice_number: Some("2025".to_string()),
+                ..InvoiceQuery::default()
+            })
+            .unwrap();
+        assert_eq!(cheap.len(), 2);
+
+        let received = archive
+            .find(&InvoiceQuery {
+                direction: Some(Direction::Received),
+                ..InvoiceQuery::default()
+            })
+            .unwrap();
+        assert_eq!(received.len(), 1);
+    }
+}
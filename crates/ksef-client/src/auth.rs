@@ -0,0 +1,291 @@
+//! Authentication contexts and status interpretation.
+//!
+//! `AuthContext` names whose data the session acts on: a NIP, an internal identifier
+//! (`NNNNNNNNNN-NNNNN`) or a NIP paired with an EU VAT number (`NNNNNNNNNN-CCXXXXXXXX`).
+//! It is validated before a challenge is requested, for KSeF-token and XAdES authentication
+//! alike.
+//!
+//! Once the credentials are submitted, `KsefClient::poll_auth_status` checks
+//! `/auth/{referenceNumber}` with backoff until the `AuthOutcome` is no longer pending. It
+//! gives up with `KsefError::Timeout` or `KsefError::Cancelled` as set in `AuthPollingOptions`,
+//! and a rejection carries its `AuthRejectionReason`.
+
+use crate::{AuthStatusResponse, ContextIdentifier, KsefError, Result};
+
+/// Result of an authentication status check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthOutcome {
+    /// Authentication is still being processed (code 100)
+    Pending,
+    /// Authentication completed successfully (code 200)
+    Succeeded,
+    /// Authentication was rejected by KSeF
+    Rejected {
+        reason: AuthRejectionReason,
+        code: i32,
+        description: String,
+        details: Vec<String>,
+    },
+}
+
+/// Reason of an authentication rejection, derived from the KSeF status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthRejectionReason {
+    /// 400 - generic authentication failure
+    Failed,
+    /// 415 - no permissions assigned in the requested context
+    MissingPermissions,
+    /// 425 - authentication revoked
+    Revoked,
+    /// 450 - invalid or revoked KSeF token
+    InvalidToken,
+    /// 460 - certificate error
+    InvalidCertificate,
+    /// 470 - signature error
+    InvalidSignature,
+    /// 480 - blocked because of a suspected security incident
+    SecurityBlocked,
+    /// 500 - unknown KSeF error
+    SystemError,
+    /// 550 - operation cancelled by the system
+    Cancelled,
+    /// Any other status code
+    Other,
+}
+
+impl AuthRejectionReason {
+    pub fn from_code(code: i32) -> Self {
+        match code {
+            400 => AuthRejectionReason::Failed,
+            415 => AuthRejectionReason::MissingPermissions,
+            425 => AuthRejectionReason::Revoked,
+            450 => AuthRejectionReason::InvalidToken,
+            460 => AuthRejectionReason::InvalidCertificate,
+            470 => AuthRejectionReason::InvalidSignature,
+            480 => AuthRejectionReason::SecurityBlocked,
+            500 => AuthRejectionReason::SystemError,
+            550 => AuthRejectionReason::Cancelled,
+            _ => AuthRejectionReason::Other,
+        }
+    }
+
+    /// Stable identifier used in logs and MCP error payloads
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthRejectionReason::Failed => "failed",
+            AuthRejectionReason::MissingPermissions => "missingPermissions",
+            AuthRejectionReason::Revoked => "revoked",
+            AuthRejectionReason::InvalidToken => "invalidToken",
+            AuthRejectionReason::InvalidCertificate => "invalidCertificate",
+            AuthRejectionReason::InvalidSignature => "invalidSignature",
+            AuthRejectionReason::SecurityBlocked => "securityBlocked",
+            AuthRejectionReason::SystemError => "systemError",
+            AuthRejectionReason::Cancelled => "cancelled",
+            AuthRejectionReason::Other => "other",
+        }
+    }
+}
+
+impl AuthStatusResponse {
+    /// Interprets the status code of this response
+    pub fn outcome(&self) -> AuthOutcome {
+        match self.status.code {
+            100 => AuthOutcome::Pending,
+            200 => AuthOutcome::Succeeded,
+            code => AuthOutcome::Rejected {
+                reason: AuthRejectionReason::from_code(code),
+                code,
+                description: self.status.description.clone(),
+                details: self.status.details.clone(),
+            },
+        }
+    }
+}
+
+/// Context in which the client authenticates (whose data it acts on)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthContext {
+    /// Polish tax identification number, 10 digits with checksum
+    Nip(String),
+    /// Internal identifier: NIP followed by a 5-digit suffix (`NNNNNNNNNN-NNNNN`)
+    InternalId(String),
+    /// NIP paired with an EU VAT number (`NNNNNNNNNN-CCXXXXXXXX`)
+    NipVatUe(String),
+}
+
+impl AuthContext {
+    /// Builds a context from its KSeF type name (`nip`, `internalId`, `nipVatUe`) and validates it
+    pub fn parse(identifier_type: &str, value: &str) -> Result<Self> {
+        let context = match identifier_type {
+            "nip" | "Nip" => AuthContext::Nip(value.to_string()),
+            "internalId" | "InternalId" => AuthContext::InternalId(value.to_string()),
+            "nipVatUe" | "NipVatUe" => AuthContext::NipVatUe(value.to_string()),
+            other => {
+                return Err(KsefError::Validation(format!(
+                    "Unknown context identifier type '{}' (expected nip, internalId or nipVatUe)",
+                    other
+                )))
+            }
+        };
+        context.validate()?;
+        Ok(context)
+    }
+
+    /// Checks the identifier format
+    pub fn validate(&self) -> Result<()> {
+        let valid = match self {
+            AuthContext::Nip(value) => is_valid_nip(value),
+            AuthContext::InternalId(value) => match value.split_once('-') {
+                Some((nip, suffix)) => {
+                    is_valid_nip(nip)
+                        && suffix.len() == 5
+                        && suffix.bytes().all(|b| b.is_ascii_digit())
+                }
+                None => false,
+            },
+            AuthContext::NipVatUe(value) => match value.split_once('-') {
+                Some((nip, vat_ue)) => is_valid_nip(nip) && is_valid_vat_ue(vat_ue),
+                None => false,
+            },
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(KsefError::Validation(format!(
+                "Invalid {} context identifier: '{}'",
+                self.identifier_type(),
+                self.value()
+            )))
+        }
+    }
+
+    /// Type name used in the KSeF JSON API
+    pub fn identifier_type(&self) -> &'static str {
+        match self {
+            AuthContext::Nip(_) => "nip",
+            AuthContext::InternalId(_) => "internalId",
+            AuthContext::NipVatUe(_) => "nipVatUe",
+        }
+    }
+
+    /// Element name used in the `AuthTokenRequest` XML document
+    pub(crate) fn xml_element(&self) -> &'static str {
+        match self {
+            AuthContext::Nip(_) => "Nip",
+            AuthContext::InternalId(_) => "InternalId",
+            AuthContext::NipVatUe(_) => "NipVatUe",
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            AuthContext::Nip(v) | AuthContext::InternalId(v) | AuthContext::NipVatUe(v) => v,
+        }
+    }
+
+    /// NIP of the context; for `internalId` and `nipVatUe` the part before the dash
+    pub fn nip(&self) -> &str {
+        let value = self.value();
+        value.split_once('-').map_or(value, |(nip, _)| nip)
+    }
+}
+
+impl std::fmt::Display for AuthContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.identifier_type(), self.value())
+    }
+}
+
+impl From<&AuthContext> for ContextIdentifier {
+    fn from(context: &AuthContext) -> Self {
+        ContextIdentifier {
+            identifier_type: context.identifier_type().to_string(),
+            value: context.value().to_string(),
+        }
+    }
+}
+
+fn is_valid_nip(nip: &str) -> bool {
+    const WEIGHTS: [u32; 9] = [6, 5, 7, 2, 3, 4, 5, 6, 7];
+
+    let digits: Vec<u32> = nip.chars().filter_map(|c| c.to_digit(10)).collect();
+    if nip.len() != 10 || digits.len() != 10 {
+        return false;
+    }
+    let checksum = WEIGHTS.iter().zip(&digits).map(|(w, d)| w * d).sum::<u32>() % 11;
+    checksum == digits[9]
+}
+
+/// EU VAT number: two-letter country code followed by 2-12 alphanumeric characters
+fn is_valid_vat_ue(vat_ue: &str) -> bool {
+    let (country, number) = vat_ue.split_at(vat_ue.len().min(2));
+    country.len() == 2
+        && country.bytes().all(|b| b.is_ascii_uppercase())
+        && (2..=12).contains(&number.len())
+        && number.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StatusInfo;
+
+    fn status(code: i32) -> AuthStatusResponse {
+        AuthStatusResponse {
+            start_date: "2025-10-01T10:00:00Z".to_string(),
+            authentication_method: "Token".to_string(),
+            status: StatusInfo {
+                code,
+                description: "desc".to_string(),
+                details: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_outcome_from_status_code() {
+        assert_eq!(status(100).outcome(), AuthOutcome::Pending);
+        assert_eq!(status(200).outcome(), AuthOutcome::Succeeded);
+        match status(450).outcome() {
+            AuthOutcome::Rejected { reason, code, .. } => {
+                assert_eq!(reason, AuthRejectionReason::InvalidToken);
+                assert_eq!(code, 450);
+            }
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+        assert!(matches!(
+            status(415).outcome(),
+            AuthOutcome::Rejected {
+                reason: AuthRejectionReason::MissingPermissions,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_context_identifier_validation() {
+        assert!(AuthContext::parse("nip", "5265877635").is_ok());
+        assert!(AuthContext::parse("nip", "5265877636").is_err());
+        assert!(AuthContext::parse("nip", "52658776").is_err());
+        assert!(AuthContext::parse("internalId", "5265877635-12345").is_ok());
+        assert!(AuthContext::parse("internalId", "5265877635-123").is_err());
+        assert!(AuthContext::parse("nipVatUe", "5265877635-DE123456789").is_ok());
+        assert!(AuthContext::parse("nipVatUe", "5265877635-de1").is_err());
+        assert!(AuthContext::parse("pesel", "5265877635").is_err());
+
+        let identifier =
+            ContextIdentifier::from(&AuthContext::InternalId("5265877635-12345".into()));
+        assert_eq!(identifier.identifier_type, "internalId");
+
+        assert_eq!(AuthContext::Nip("5265877635".into()).nip(), "5265877635");
+        assert_eq!(
+            AuthContext::InternalId("5265877635-12345".into()).nip(),
+            "5265877635"
+        );
+        assert_eq!(
+            AuthContext::NipVatUe("5265877635-DE123456789".into()).nip(),
+            "5265877635"
+        );
+    }
+}
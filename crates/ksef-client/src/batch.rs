@@ -0,0 +1,151 @@
+//! Batch sessions: ZIP packaging, splitting and encryption of invoice packages.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+
+use crate::{BatchFileInfo, BatchFilePart, KsefError, Result, SymmetricKey};
+
+/// Maximum size of a single encrypted batch part (100 MB)
+pub const MAX_BATCH_PART_SIZE: usize = 100 * 1000 * 1000;
+
+/// AES block size; PKCS#7 padding grows every part by up to one block
+const AES_BLOCK_SIZE: usize = 16;
+
+/// Invoice document to be packed into a batch ZIP
+#[derive(Debug, Clone)]
+pub struct BatchInvoice {
+    /// Entry name inside the ZIP (e.g. `FV-2025-001.xml`)
+    pub file_name: String,
+    pub xml: String,
+}
+
+/// Result of a submitted batch
+#[derive(Debug, Clone)]
+pub struct BatchSubmission {
+    pub reference_number: String,
+    pub invoice_count: usize,
+    pub part_count: usize,
+    /// Size of the ZIP package before splitting and encryption
+    pub package_size: u64,
+}
+
+/// Encrypted batch part ready for upload
+#[derive(Debug, Clone)]
+pub(crate) struct EncryptedPart {
+    pub ordinal_number: i32,
+    pub data: Vec<u8>,
+}
+
+/// ZIP package split into encrypted parts, with the metadata KSeF expects
+#[derive(Debug, Clone)]
+pub(crate) struct PreparedBatch {
+    pub file_info: BatchFileInfo,
+    pub parts: Vec<EncryptedPart>,
+}
+
+/// Packs invoice documents into a ZIP archive
+pub(crate) fn build_zip(invoices: &[BatchInvoice]) -> Result<Vec<u8>> {
+    if invoices.is_empty() {
+        return Err(KsefError::Validation(
+            "Batch must contain at least one invoice".to_string(),
+        ));
+    }
+
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    for invoice in invoices {
+        writer
+            .start_file(invoice.file_name.as_str(), options)
+            .map_err(|e| {
+                KsefError::Validation(format!("Cannot add {} to batch: {}", invoice.file_name, e))
+            })?;
+        writer
+            .write_all(invoice.xml.as_bytes())
+            .map_err(|e| KsefError::Crypto(format!("Failed to write batch ZIP: {}", e)))?;
+    }
+
+    let cursor = writer
+        .finish()
+        .map_err(|e| KsefError::Crypto(format!("Failed to write batch ZIP: {}", e)))?;
+    Ok(cursor.into_inner())
+}
+
+/// Splits the ZIP so that every encrypted part is at most `part_size` bytes and encrypts
+/// each of them
+pub(crate) fn prepare_batch(zip: &[u8], key: &SymmetricKey, part_size: usize) -> PreparedBatch {
+    // Padding rounds a part up to the next whole block, so a plain part must end one byte
+    // short of the last whole block that fits
+    let plain_part_size = (part_size / AES_BLOCK_SIZE * AES_BLOCK_SIZE).saturating_sub(1);
+    let parts: Vec<EncryptedPart> = zip
+        .chunks(plain_part_size.max(1))
+        .enumerate()
+        .map(|(i, chunk)| EncryptedPart {
+            ordinal_number: i as i32 + 1,
+            data: key.encrypt(chunk),
+        })
+        .collect();
+
+    let file_info = BatchFileInfo {
+        file_size: zip.len() as u64,
+        file_hash: sha256_base64(zip),
+        file_parts: parts
+            .iter()
+            .map(|part| BatchFilePart {
+                ordinal_number: part.ordinal_number,
+                file_size: part.data.len() as u64,
+                file_hash: sha256_base64(&part.data),
+            })
+            .collect(),
+    };
+
+    PreparedBatch { file_info, parts }
+}
+
+fn sha256_base64(data: &[u8]) -> String {
+    BASE64.encode(Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_batch_splits_and_describes_parts() {
+        use aes::Aes256;
+        use cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+
+        let invoices: Vec<BatchInvoice> = (1..=3)
+            .map(|i| BatchInvoice {
+                file_name: format!("FV-{}.xml", i),
+                xml: format!("<Faktura><P_2>FV/{}</P_2></Faktura>", i),
+            })
+            .collect();
+        let zip = build_zip(&invoices).unwrap();
+        let key = SymmetricKey::generate();
+
+        let batch = prepare_batch(&zip, &key, 100);
+
+        assert_eq!(batch.parts.len(), zip.len().div_ceil(95));
+        assert!(batch.parts.iter().all(|part| part.data.len() <= 100));
+        assert_eq!(batch.parts[0].data.len(), 96);
+        assert_eq!(batch.file_info.file_size, zip.len() as u64);
+        assert_eq!(batch.file_info.file_hash, sha256_base64(&zip));
+
+        let mut reassembled = Vec::new();
+        for (part, info) in batch.parts.iter().zip(&batch.file_info.file_parts) {
+            assert_eq!(info.file_size, part.data.len() as u64);
+            assert_eq!(info.file_hash, sha256_base64(&part.data));
+            reassembled.extend(
+                cbc::Decryptor::<Aes256>::new(key.key().into(), key.iv().into())
+                    .decrypt_padded_vec_mut::<Pkcs7>(&part.data)
+                    .unwrap(),
+            );
+        }
+        assert_eq!(reassembled, zip);
+
+        assert!(matches!(build_zip(&[]), Err(KsefError::Validation(_))));
+    }
+}
@@ -0,0 +1,152 @@
+//! Symmetric keys used to encrypt invoices sent in online sessions.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rand::RngCore;
+use rsa::{Oaep, RsaPublicKey};
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+use crate::{EncryptionInfo, KsefClient, KsefError, Result, SendInvoiceRequest};
+
+/// AES-256-CBC key and IV of a single session
+#[derive(Clone)]
+pub struct SymmetricKey {
+    key: [u8; 32],
+    iv: [u8; 16],
+}
+
+impl SymmetricKey {
+    /// Generates a random key and IV
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut key = [0u8; 32];
+        let mut iv = [0u8; 16];
+        rng.fill_bytes(&mut key);
+        rng.fill_bytes(&mut iv);
+        Self { key, iv }
+    }
+
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    pub fn iv(&self) -> &[u8; 16] {
+        &self.iv
+    }
+
+    /// Encrypts arbitrary data with this key (AES-256-CBC, PKCS#7 padding)
+    pub fn encrypt(&self, data: &[u8]) -> Vec<u8> {
+        aes_encrypt(&self.key, &self.iv, data)
+    }
+
+    /// Decrypts data encrypted with this key
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        use aes::Aes256;
+        use cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+
+        cbc::Decryptor::<Aes256>::new((&self.key).into(), (&self.iv).into())
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+            .map_err(|e| KsefError::Crypto(format!("AES decryption failed: {}", e)))
+    }
+
+    /// Encrypts the key with the MF `SymmetricKeyEncryption` certificate (RSA-OAEP, SHA-256)
+    pub fn encryption_info(&self, cert_base64: &str) -> Result<EncryptionInfo> {
+        Ok(EncryptionInfo {
+            encrypted_symmetric_key: rsa_oaep_encrypt(cert_base64, &self.key)?,
+            initialization_vector: BASE64.encode(self.iv),
+        })
+    }
+
+    /// Encrypts an invoice document and describes it for `submit_invoice`
+    pub fn encrypt_invoice(
+        &self,
+        invoice_xml: &str,
+        offline_mode: bool,
+        hash_of_corrected_invoice: Option<String>,
+    ) -> Result<SendInvoiceRequest> {
+        let (encrypted_content, original_hash, encrypted_hash, original_size, encrypted_size) =
+            KsefClient::encrypt_invoice_content(invoice_xml, &self.key, &self.iv)?;
+
+        Ok(SendInvoiceRequest {
+            invoice_hash: original_hash,
+            invoice_size: original_size as u64,
+            encrypted_invoice_hash: encrypted_hash,
+            encrypted_invoice_size: encrypted_size as u64,
+            encrypted_invoice_content: encrypted_content,
+            offline_mode,
+            hash_of_corrected_invoice,
+        })
+    }
+}
+
+impl std::fmt::Debug for SymmetricKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SymmetricKey(..)")
+    }
+}
+
+impl Drop for SymmetricKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+        self.iv.zeroize();
+    }
+}
+
+/// AES-256-CBC with PKCS#7 padding
+pub(crate) fn aes_encrypt(key: &[u8; 32], iv: &[u8; 16], data: &[u8]) -> Vec<u8> {
+    use aes::Aes256;
+    use cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+
+    cbc::Encryptor::<Aes256>::new(key.into(), iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data)
+}
+
+/// Encrypts `data` with the RSA public key of a base64 DER certificate (RSA-OAEP, SHA-256)
+pub(crate) fn rsa_oaep_encrypt(cert_base64: &str, data: &[u8]) -> Result<String> {
+    use rsa::pkcs8::DecodePublicKey;
+
+    // Decode base64 certificate
+    let cert_der = BASE64
+        .decode(cert_base64.as_bytes())
+        .map_err(|e| KsefError::Crypto(format!("Failed to decode certificate base64: {}", e)))?;
+
+    // Parse X.509 certificate
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert_der)
+        .map_err(|e| KsefError::Crypto(format!("Failed to parse X.509 certificate: {}", e)))?;
+
+    // Parse RSA public key from SubjectPublicKeyInfo
+    let public_key = RsaPublicKey::from_public_key_der(cert.public_key().raw).map_err(|e| {
+        KsefError::Crypto(format!(
+            "Failed to parse RSA public key from certificate: {}",
+            e
+        ))
+    })?;
+
+    let encrypted = public_key
+        .encrypt(&mut rand::thread_rng(), Oaep::new::<Sha256>(), data)
+        .map_err(|e| KsefError::Crypto(format!("RSA-OAEP encryption failed: {}", e)))?;
+
+    Ok(BASE64.encode(encrypted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_invoice_round_trip() {
+        use aes::Aes256;
+        use cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+
+        let key = SymmetricKey::generate();
+        let request = key.encrypt_invoice("<Faktura/>", false, None).unwrap();
+
+        assert_eq!(request.invoice_size, 10);
+        assert_eq!(request.encrypted_invoice_size, 16);
+
+        let encrypted = BASE64.decode(&request.encrypted_invoice_content).unwrap();
+        let decrypted = cbc::Decryptor::<Aes256>::new(key.key().into(), key.iv().into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&encrypted)
+            .unwrap();
+        assert_eq!(decrypted, b"<Faktura/>");
+    }
+}
@@ -0,0 +1,229 @@
+//! Error type returned by every fallible `KsefClient` operation.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::AuthRejectionReason;
+
+/// Result alias used throughout the client
+pub type Result<T> = std::result::Result<T, KsefError>;
+
+/// Errors produced by the KSeF client
+#[derive(Debug, thiserror::Error)]
+pub enum KsefError {
+    /// Network or protocol failure before a response was received
+    #[error("Transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// Non-success HTTP status without a recognizable KSeF exception payload
+    #[error("HTTP error ({status}): {body}")]
+    Http { status: u16, body: String },
+
+    /// KSeF business exception (`exception.exceptionDetailList`)
+    #[error("KSeF exception ({status}): {exception}")]
+    Api {
+        status: u16,
+        exception: Box<ExceptionInfo>,
+    },
+
+    /// 429 Too Many Requests
+    #[error("Rate limit exceeded{}", retry_after.map(|d| format!(", retry after {}s", d.as_secs())).unwrap_or_default())]
+    RateLimited {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+
+    /// Missing, expired or rejected access credentials (401)
+    #[error("Authentication expired or missing: {0}")]
+    AuthExpired(String),
+
+    /// Authentication process rejected by KSeF
+    #[error("Authentication failed with status {code}: {description}")]
+    AuthenticationFailed {
+        reason: AuthRejectionReason,
+        code: i32,
+        description: String,
+    },
+
+    /// Encryption, decryption or certificate handling failure
+    #[error("Cryptography error: {0}")]
+    Crypto(String),
+
+    /// Invalid input supplied by the caller
+    #[error("Validation error: {0}")]
+    Validation(String),
+
+    /// Operation did not complete within the configured time
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    /// Operation aborted through a cancellation token
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
+    /// Response body could not be interpreted
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+
+    /// Asynchronous KSeF operation (e.g. an export) finished with an error status
+    #[error("{operation} failed with status {code}: {description}")]
+    OperationFailed {
+        operation: String,
+        code: i32,
+        description: String,
+    },
+
+    /// Query result cut off at the KSeF record limit where it cannot be resumed
+    #[error("Result truncated: {0}")]
+    Truncated(String),
+
+    /// Local file system failure
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl KsefError {
+    /// Builds an error from a non-success HTTP response
+    pub(crate) async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after = crate::rate_limit::parse_retry_after(response.headers());
+
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(e) => return KsefError::Transport(e),
+        };
+
+        Self::from_status(status.as_u16(), retry_after, body)
+    }
+
+    /// Classifies a failed response by status code and body
+    pub(crate) fn from_status(status: u16, retry_after: Option<Duration>, body: String) -> Self {
+        match status {
+            401 => KsefError::AuthExpired(body),
+            429 => KsefError::RateLimited { retry_after, body },
+            _ => match serde_json::from_str::<ExceptionResponse>(&body) {
+                Ok(parsed) => KsefError::Api {
+                    status,
+                    exception: Box::new(parsed.exception),
+                },
+                Err(_) => KsefError::Http { status, body },
+            },
+        }
+    }
+
+    /// HTTP status code, if the error originates from an API response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            KsefError::Http { status, .. } | KsefError::Api { status, .. } => Some(*status),
+            KsefError::RateLimited { .. } => Some(429),
+            KsefError::AuthExpired(_) => Some(401),
+            KsefError::Transport(e) => e.status().map(|s| s.as_u16()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::header::InvalidHeaderValue> for KsefError {
+    fn from(e: reqwest::header::InvalidHeaderValue) -> Self {
+        KsefError::Validation(format!("Invalid header value: {}", e))
+    }
+}
+
+/// Error payload returned by KSeF for business exceptions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExceptionResponse {
+    pub exception: ExceptionInfo,
+}
+
+/// Exception envelope with one or more detail entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExceptionInfo {
+    #[serde(rename = "exceptionDetailList", default)]
+    pub exception_detail_list: Vec<ExceptionDetail>,
+    #[serde(rename = "referenceNumber", skip_serializing_if = "Option::is_none")]
+    pub reference_number: Option<String>,
+    #[serde(rename = "serviceCode", skip_serializing_if = "Option::is_none")]
+    pub service_code: Option<String>,
+    #[serde(rename = "serviceCtx", skip_serializing_if = "Option::is_none")]
+    pub service_ctx: Option<String>,
+    #[serde(rename = "serviceName", skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+/// Single KSeF exception code with its description
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExceptionDetail {
+    #[serde(rename = "exceptionCode")]
+    pub exception_code: i32,
+    #[serde(rename = "exceptionDescription")]
+    pub exception_description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+impl std::fmt::Display for ExceptionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<String> = self
+            .exception_detail_list
+            .iter()
+            .map(|d| {
+                if d.details.is_empty() {
+                    format!("{} {}", d.exception_code, d.exception_description)
+                } else {
+                    format!(
+                        "{} {} ({})",
+                        d.exception_code,
+                        d.exception_description,
+                        d.details.join("; ")
+                    )
+                }
+            })
+            .collect();
+        write!(f, "{}", messages.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_exception_payload() {
+        let body = r#"{
+            "exception": {
+                "exceptionDetailList": [
+                    {"exceptionCode": 21405, "exceptionDescription": "Błąd walidacji danych wejściowych.", "details": ["pageSize"]}
+                ],
+                "referenceNumber": "20251001-EX-1",
+                "timestamp": "2025-10-01T10:00:00Z"
+            }
+        }"#;
+
+        match KsefError::from_status(400, None, body.to_string()) {
+            KsefError::Api { status, exception } => {
+                assert_eq!(status, 400);
+                assert_eq!(exception.exception_detail_list[0].exception_code, 21405);
+                assert_eq!(exception.exception_detail_list[0].details, vec!["pageSize"]);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_classify_status_codes() {
+        assert!(matches!(
+            KsefError::from_status(401, None, String::new()),
+            KsefError::AuthExpired(_)
+        ));
+        assert!(matches!(
+            KsefError::from_status(429, Some(Duration::from_secs(5)), String::new()),
+            KsefError::RateLimited { retry_after: Some(d), .. } if d.as_secs() == 5
+        ));
+        assert!(matches!(
+            KsefError::from_status(502, None, "Bad gateway".to_string()),
+            KsefError::Http { status: 502, .. }
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+//! Invoice export packages: decryption, unpacking and saving.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::{ExportPackage, KsefError, Result};
+
+/// Name of the metadata file included in export packages
+pub const EXPORT_METADATA_FILE: &str = "_metadata.json";
+
+/// Invoice document extracted from an export package
+#[derive(Debug, Clone)]
+pub struct ExportedInvoice {
+    /// File name inside the package (`{ksefNumber}.xml`)
+    pub file_name: String,
+    pub xml: String,
+}
+
+impl ExportedInvoice {
+    /// KSeF number, taken from the file name
+    pub fn ksef_number(&self) -> &str {
+        self.file_name
+            .strip_suffix(".xml")
+            .unwrap_or(&self.file_name)
+    }
+}
+
+/// Downloaded and decrypted export
+#[derive(Debug, Clone)]
+pub struct InvoiceExport {
+    pub reference_number: String,
+    pub package: ExportPackage,
+    pub invoices: Vec<ExportedInvoice>,
+    /// Content of `_metadata.json`, if present in the package
+    pub metadata_json: Option<String>,
+}
+
+impl InvoiceExport {
+    /// Writes the invoices and `_metadata.json` into `dir/{reference_number}/`
+    pub fn save_to(&self, dir: &Path) -> Result<PathBuf> {
+        let target = dir.join(sanitize_file_name(&self.reference_number));
+        std::fs::create_dir_all(&target)?;
+
+        for invoice in &self.invoices {
+            std::fs::write(
+                target.join(sanitize_file_name(&invoice.file_name)),
+                &invoice.xml,
+            )?;
+        }
+        if let Some(metadata) = &self.metadata_json {
+            std::fs::write(target.join(EXPORT_METADATA_FILE), metadata)?;
+        }
+
+        Ok(target)
+    }
+}
+
+/// Checks the SHA-256 of a downloaded or decrypted part against the package description
+pub(crate) fn verify_hash(data: &[u8], expected_base64: &str, what: &str) -> Result<()> {
+    let actual = BASE64.encode(Sha256::digest(data));
+    if actual == expected_base64 {
+        Ok(())
+    } else {
+        Err(KsefError::Crypto(format!(
+            "Hash mismatch for {} (expected {}, got {})",
+            what, expected_base64, actual
+        )))
+    }
+}
+
+/// Extracts invoice XMLs and `_metadata.json` from a decrypted package ZIP
+pub(crate) fn unpack(zip: Vec<u8>) -> Result<(Vec<ExportedInvoice>, Option<String>)> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(zip))
+        .map_err(|e| KsefError::InvalidResponse(format!("Invalid export package: {}", e)))?;
+
+    let mut invoices = Vec::new();
+    let mut metadata_json = None;
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| KsefError::InvalidResponse(format!("Invalid export package: {}", e)))?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        let file_name = entry.name().to_string();
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+
+        if file_name == EXPORT_METADATA_FILE {
+            metadata_json = Some(content);
+        } else {
+            invoices.push(ExportedInvoice {
+                file_name,
+                xml: content,
+            });
+        }
+    }
+
+    Ok((invoices, metadata_json))
+}
+
+/// Keeps only the last path component so package entries cannot escape the target directory
+fn sanitize_file_name(name: &str) -> String {
+    Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| "unnamed".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{build_zip, BatchInvoice};
+
+    #[test]
+    fn test_unpack_and_save() {
+        let zip = build_zip(&[
+            BatchInvoice {
+                file_name: "5265877635-20251001-0100001D5F4A-1B.xml".to_string(),
+                xml: "<Faktura/>".to_string(),
+            },
+            BatchInvoice {
+                file_name: EXPORT_METADATA_FILE.to_string(),
+                xml: r#"{"invoices": []}"#.to_string(),
+            },
+        ])
+        .unwrap();
+
+        let (invoices, metadata) = unpack(zip).unwrap();
+        assert_eq!(invoices.len(), 1);
+        assert_eq!(
+            invoices[0].ksef_number(),
+            "5265877635-20251001-0100001D5F4A-1B"
+        );
+        assert_eq!(metadata.as_deref(), Some(r#"{"invoices": []}"#));
+
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+    }
+}
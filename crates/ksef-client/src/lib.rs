@@ -1,12 +1,49 @@
-use anyhow::{anyhow, Result};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use rsa::{Oaep, RsaPublicKey};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::Stream;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
+use rate_limit::{RateLimiter, MAX_RATE_LIMIT_RETRIES};
+use sync::{MetadataCursor, SyncProgress};
+
+mod auth;
+mod batch;
+mod encryption;
+mod error;
+mod export;
+mod models;
+mod online_session;
+mod pagination;
+mod polling;
+mod rate_limit;
+mod sync;
+mod upo;
+mod xades;
+mod xml;
+
+pub use auth::{AuthContext, AuthOutcome, AuthRejectionReason};
+pub use batch::{BatchInvoice, BatchSubmission, MAX_BATCH_PART_SIZE};
+pub use encryption::SymmetricKey;
+pub use error::{ExceptionDetail, ExceptionInfo, ExceptionResponse, KsefError, Result};
+pub use export::{ExportedInvoice, InvoiceExport, EXPORT_METADATA_FILE};
+pub use models::*;
+pub use online_session::{InvoiceReference, OnlineSession};
+pub use polling::{AuthPollingOptions, PollingOptions};
+pub use rate_limit::{EndpointGroup, RateLimitBudget};
+pub use sync::{SyncCursor, SyncOptions, SyncReport, SyncState, SyncStore, SYNC_STATE_FILE};
+pub use upo::{
+    invoice_hash, parse_trusted_certificates, verify_upo, Upo, UpoContextIdentifier, UpoDocument,
+    UpoExpectation, UpoVerification,
+};
+pub use xades::{CertificateCredentials, SignatureInfo, SigningKey};
+
 const DEFAULT_API_BASE_URL: &str = "https://api-test.ksef.mf.gov.pl/v2";
 
+/// Tokens are refreshed this many seconds before they expire
+const TOKEN_REFRESH_MARGIN_SECS: i64 = 60;
+
 // Authentication types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthChallengeResponse {
@@ -60,6 +97,8 @@ pub struct AuthStatusResponse {
 pub struct StatusInfo {
     pub code: i32,
     pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,16 +113,55 @@ pub struct TokensResponse {
 #[derive(Debug, Clone)]
 struct SessionState {
     access_token: String,
+    access_token_valid_until: Option<DateTime<Utc>>,
     refresh_token: String,
-    #[allow(dead_code)]
-    ksef_token: String, // Original KSeF token for re-authentication
-    nip: String, // NIP for re-authentication
+    refresh_token_valid_until: Option<DateTime<Utc>>,
+    credentials: Credentials, // Original credentials for re-authentication
+    context: AuthContext,     // Authentication context for re-authentication
+}
+
+/// Credentials used to (re-)authenticate a session
+#[derive(Debug, Clone)]
+enum Credentials {
+    KsefToken(String),
+    Certificate(Arc<CertificateCredentials>),
+}
+
+impl SessionState {
+    fn access_token_expiring(&self, now: DateTime<Utc>) -> bool {
+        expires_within_margin(self.access_token_valid_until, now)
+    }
+
+    fn refresh_token_expiring(&self, now: DateTime<Utc>) -> bool {
+        expires_within_margin(self.refresh_token_valid_until, now)
+    }
+}
+
+/// Unknown expiry is treated as still valid; KSeF will answer 401 if it is not
+fn expires_within_margin(valid_until: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    valid_until
+        .map(|t| t - ChronoDuration::seconds(TOKEN_REFRESH_MARGIN_SECS) <= now)
+        .unwrap_or(false)
+}
+
+fn parse_valid_until(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
 }
 
 pub struct KsefClient {
     client: reqwest::Client,
     base_url: String,
     session_state: Arc<Mutex<Option<SessionState>>>,
+    // Serializes token refresh / re-authentication between concurrent requests
+    renewal_lock: tokio::sync::Mutex<()>,
+    // Symmetric keys and form codes of open online sessions, by session reference number
+    session_keys: Mutex<HashMap<String, (SymmetricKey, FormCode)>>,
+    // Symmetric keys of exports started by this client, by export reference number
+    export_keys: Mutex<HashMap<String, SymmetricKey>>,
+    rate_limiter: RateLimiter,
+    auth_polling: AuthPollingOptions,
     disable_encryption: bool,
 }
 
@@ -106,10 +184,21 @@ impl KsefClient {
             client: reqwest::Client::new(),
             base_url,
             session_state: Arc::new(Mutex::new(None)),
+            renewal_lock: tokio::sync::Mutex::new(()),
+            session_keys: Mutex::new(HashMap::new()),
+            export_keys: Mutex::new(HashMap::new()),
+            rate_limiter: RateLimiter::default(),
+            auth_polling: AuthPollingOptions::default(),
             disable_encryption,
         }
     }
 
+    /// Overrides how authentication status is polled
+    pub fn with_auth_polling(mut self, options: AuthPollingOptions) -> Self {
+        self.auth_polling = options;
+        self
+    }
+
     fn get_access_token(&self) -> Option<String> {
         self.session_state
             .lock()
@@ -118,17 +207,133 @@ impl KsefClient {
             .map(|s| s.access_token.clone())
     }
 
-    fn build_headers(
+    /// Sends a request with the current access token.
+    ///
+    /// The request waits for the budget of its endpoint group, the access token
+    /// is refreshed proactively when it is about to expire, a request rejected
+    /// with 401 is retried once after renewing the session, and a request
+    /// answered with 429 is retried after `Retry-After` plus jitter.
+    async fn send_authorized(
         &self,
-        extra_headers: Option<reqwest::header::HeaderMap>,
-    ) -> reqwest::header::HeaderMap {
-        let mut headers = extra_headers.unwrap_or_default();
-        if let Some(token) = self.get_access_token() {
-            if let Ok(value) = format!("Bearer {}", token).parse() {
-                headers.insert("Authorization", value);
+        group: EndpointGroup,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        self.ensure_fresh_token().await?;
+
+        let mut request = request;
+        let mut renewed = false;
+        let mut rate_limit_retries = 0;
+        loop {
+            self.rate_limiter.acquire(group).await;
+
+            let retry = request.try_clone();
+            let token = self.get_access_token();
+            let response = Self::with_bearer(request, token.clone()).send().await?;
+
+            match response.status() {
+                reqwest::StatusCode::UNAUTHORIZED if !renewed => {
+                    let (Some(retry), Some(rejected_token)) = (retry, token) else {
+                        return Ok(response);
+                    };
+                    eprintln!("Access token rejected (401), renewing session...");
+                    self.renew_session(&rejected_token).await?;
+                    renewed = true;
+                    request = retry;
+                }
+                reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    let delay = self
+                        .rate_limiter
+                        .on_rate_limited(group, rate_limit::parse_retry_after(response.headers()));
+                    let Some(retry) = retry.filter(|_| rate_limit_retries < MAX_RATE_LIMIT_RETRIES)
+                    else {
+                        return Ok(response);
+                    };
+                    rate_limit_retries += 1;
+                    eprintln!(
+                        "Rate limit exceeded for {} (429), retrying in {:.1}s...",
+                        group.as_str(),
+                        delay.as_secs_f64()
+                    );
+                    tokio::time::sleep(delay).await;
+                    request = retry;
+                }
+                _ => return Ok(response),
             }
         }
-        headers
+    }
+
+    fn with_bearer(
+        request: reqwest::RequestBuilder,
+        token: Option<String>,
+    ) -> reqwest::RequestBuilder {
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn ensure_fresh_token(&self) -> Result<()> {
+        let expiring_token = {
+            let state = self.session_state.lock().unwrap();
+            state
+                .as_ref()
+                .filter(|s| s.access_token_expiring(Utc::now()))
+                .map(|s| s.access_token.clone())
+        };
+
+        match expiring_token {
+            Some(token) => self.renew_session(&token).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Obtains a new access token, falling back to full re-authentication
+    /// with the stored credentials when the refresh token is no longer usable.
+    ///
+    /// `stale_token` is the access token that triggered the renewal; if another
+    /// request has already replaced it, nothing is done.
+    async fn renew_session(&self, stale_token: &str) -> Result<()> {
+        let _guard = self.renewal_lock.lock().await;
+
+        let (refresh_usable, credentials) = {
+            let state = self.session_state.lock().unwrap();
+            let s = state
+                .as_ref()
+                .ok_or_else(|| KsefError::AuthExpired("Not authenticated".to_string()))?;
+            let now = Utc::now();
+            if stale_token != s.access_token && !s.access_token_expiring(now) {
+                return Ok(());
+            }
+            (
+                !s.refresh_token_expiring(now),
+                (s.context.clone(), s.credentials.clone()),
+            )
+        };
+
+        if refresh_usable {
+            match self.refresh_access_token().await {
+                Ok(message) => {
+                    eprintln!("{}", message);
+                    return Ok(());
+                }
+                Err(KsefError::AuthExpired(_)) => {
+                    eprintln!("Refresh token rejected, re-authenticating...");
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            eprintln!("Refresh token expired, re-authenticating...");
+        }
+
+        let (context, credentials) = credentials;
+        match credentials {
+            Credentials::KsefToken(ksef_token) => self.authenticate(&context, &ksef_token).await,
+            Credentials::Certificate(certificate) => {
+                self.authenticate_with_certificate(&context, &certificate)
+                    .await
+            }
+        }
+        .map(|_| ())
     }
 
     // Authentication Methods
@@ -144,48 +349,14 @@ impl KsefClient {
             .send()
             .await?;
 
-        let status = response.status();
-        if status.is_success() {
-            let challenge: AuthChallengeResponse = response.json().await?;
-            Ok(challenge)
-        } else {
-            let body = response.text().await?;
-            Err(anyhow!("Failed to get challenge ({}): {}", status, body))
-        }
+        Self::parse_json(response).await
     }
 
     /// Helper: Encrypt KSeF token with RSA-OAEP
     fn encrypt_token(ksef_token: &str, timestamp_ms: i64, cert_base64: &str) -> Result<String> {
-        use rsa::pkcs8::DecodePublicKey;
-        use sha2::Sha256;
-
         // Format: token|timestampMs
         let payload = format!("{}|{}", ksef_token, timestamp_ms);
-
-        // Decode base64 certificate
-        let cert_der = BASE64
-            .decode(cert_base64.as_bytes())
-            .map_err(|e| anyhow!("Failed to decode certificate base64: {}", e))?;
-
-        // Parse X.509 certificate
-        let (_, cert) = x509_parser::parse_x509_certificate(&cert_der)
-            .map_err(|e| anyhow!("Failed to parse X.509 certificate: {}", e))?;
-
-        // Extract public key from certificate (SubjectPublicKeyInfo format)
-        let public_key_der = cert.public_key().raw;
-
-        // Parse RSA public key from DER (SubjectPublicKeyInfo / PKCS#8)
-        let public_key = RsaPublicKey::from_public_key_der(public_key_der)
-            .map_err(|e| anyhow!("Failed to parse RSA public key from certificate: {}", e))?;
-
-        // Encrypt using RSA-OAEP with SHA-256
-        let padding = Oaep::new::<Sha256>();
-        let encrypted = public_key
-            .encrypt(&mut rand::thread_rng(), padding, payload.as_bytes())
-            .map_err(|e| anyhow!("Failed to encrypt token: {}", e))?;
-
-        // Encode to Base64
-        Ok(BASE64.encode(&encrypted))
+        encryption::rsa_oaep_encrypt(cert_base64, payload.as_bytes())
     }
 
     /// Helper: Encrypt invoice content with AES-256-CBC
@@ -195,13 +366,8 @@ impl KsefClient {
         symmetric_key: &[u8; 32],
         iv: &[u8; 16],
     ) -> Result<(String, String, String, usize, usize)> {
-        use aes::Aes256;
-        use cbc::Encryptor;
-        use cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
         use sha2::{Digest, Sha256};
 
-        type Aes256CbcEnc = Encryptor<Aes256>;
-
         // Get original content as bytes
         let original_bytes = invoice_xml.as_bytes();
         let original_size = original_bytes.len();
@@ -210,19 +376,17 @@ impl KsefClient {
         let mut hasher = Sha256::new();
         hasher.update(original_bytes);
         let original_hash = hasher.finalize();
-        let original_hash_base64 = BASE64.encode(&original_hash);
+        let original_hash_base64 = BASE64.encode(original_hash);
 
         // Encrypt with AES-256-CBC using PKCS#7 padding
-        let cipher = Aes256CbcEnc::new(symmetric_key.into(), iv.into());
-        let encrypted_bytes = cipher
-            .encrypt_padded_vec_mut::<Pkcs7>(original_bytes);
+        let encrypted_bytes = encryption::aes_encrypt(symmetric_key, iv, original_bytes);
         let encrypted_size = encrypted_bytes.len();
 
         // Calculate SHA256 hash of encrypted content
         let mut hasher = Sha256::new();
         hasher.update(&encrypted_bytes);
         let encrypted_hash = hasher.finalize();
-        let encrypted_hash_base64 = BASE64.encode(&encrypted_hash);
+        let encrypted_hash_base64 = BASE64.encode(encrypted_hash);
 
         // Encode encrypted content to Base64
         let encrypted_content_base64 = BASE64.encode(&encrypted_bytes);
@@ -239,10 +403,12 @@ impl KsefClient {
     /// Step 2: Authenticate with KSeF token
     pub async fn authenticate_with_ksef_token(
         &self,
-        nip: &str,
+        context: &AuthContext,
         ksef_token: &str,
         cert_base64: &str,
     ) -> Result<AuthInitResponse> {
+        context.validate()?;
+
         // Get challenge
         let challenge = self.get_auth_challenge().await?;
 
@@ -260,10 +426,7 @@ impl KsefClient {
         // Prepare request
         let request = InitTokenRequest {
             challenge: challenge.challenge.clone(),
-            context_identifier: ContextIdentifier {
-                identifier_type: "nip".to_string(), // lowercase 'nip' as shown in your curl
-                value: nip.to_string(),
-            },
+            context_identifier: ContextIdentifier::from(context),
             encrypted_token,
         };
 
@@ -278,14 +441,7 @@ impl KsefClient {
             .send()
             .await?;
 
-        let status = response.status();
-        if status.is_success() {
-            let auth_response: AuthInitResponse = response.json().await?;
-            Ok(auth_response)
-        } else {
-            let body = response.text().await?;
-            Err(anyhow!("Authentication failed ({}): {}", status, body))
-        }
+        Self::parse_json(response).await
     }
 
     /// Step 3: Check authentication status
@@ -304,17 +460,39 @@ impl KsefClient {
             .send()
             .await?;
 
-        let status = response.status();
-        if status.is_success() {
-            let status_response: AuthStatusResponse = response.json().await?;
-            Ok(status_response)
-        } else {
-            let body = response.text().await?;
-            Err(anyhow!(
-                "Failed to check auth status ({}): {}",
-                status,
-                body
-            ))
+        Self::parse_json(response).await
+    }
+
+    /// Step 3 (repeated): Poll authentication status until it is no longer pending.
+    ///
+    /// Checks are spaced with exponential backoff. Returns `KsefError::Timeout` when
+    /// `options.timeout` elapses and `KsefError::Cancelled` when the cancellation token fires.
+    pub async fn poll_auth_status(
+        &self,
+        reference_number: &str,
+        auth_token: &str,
+        options: &AuthPollingOptions,
+    ) -> Result<AuthOutcome> {
+        let deadline = tokio::time::Instant::now() + options.timeout;
+        let mut attempt = 0;
+
+        loop {
+            let outcome = self
+                .check_auth_status(reference_number, auth_token)
+                .await?
+                .outcome();
+            if outcome != AuthOutcome::Pending {
+                return Ok(outcome);
+            }
+
+            options
+                .wait(
+                    attempt,
+                    deadline,
+                    &format!("Authentication {}", reference_number),
+                )
+                .await?;
+            attempt += 1;
         }
     }
 
@@ -330,75 +508,115 @@ impl KsefClient {
             .send()
             .await?;
 
-        let status = response.status();
-        if status.is_success() {
-            let tokens: TokensResponse = response.json().await?;
-            Ok(tokens)
-        } else {
-            let body = response.text().await?;
-            Err(anyhow!("Failed to redeem tokens ({}): {}", status, body))
-        }
+        Self::parse_json(response).await
     }
 
-    /// Get public key certificate for token encryption
-    async fn get_encryption_certificate(&self) -> Result<String> {
-        #[derive(Deserialize)]
-        struct PublicKeyCertificate {
-            certificate: String,
-            usage: Vec<String>,
-        }
-
-        let url = format!("{}/security/public-key-certificates", self.base_url);
-        let response = self.client.get(&url).send().await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let body = response.text().await?;
-            return Err(anyhow!("Failed to get certificates ({}): {}", status, body));
-        }
+    /// Get the MF public key certificate for the given usage
+    /// (`KsefTokenEncryption` or `SymmetricKeyEncryption`)
+    async fn get_encryption_certificate(&self, usage: &str) -> Result<String> {
+        let certificates = self.get_public_key_certificates().await?;
 
-        let certificates: Vec<PublicKeyCertificate> = response.json().await?;
-
-        // Find certificate for KsefTokenEncryption
         let cert = certificates
             .iter()
-            .find(|c| c.usage.contains(&"KsefTokenEncryption".to_string()))
-            .ok_or_else(|| anyhow!("No certificate found for KsefTokenEncryption"))?;
+            .find(|c| c.usage.iter().any(|u| u == usage))
+            .ok_or_else(|| KsefError::Crypto(format!("No certificate found for {}", usage)))?;
 
-        // Return the base64-encoded certificate (will be parsed by encrypt_token)
+        // Return the base64-encoded certificate (will be parsed by rsa_oaep_encrypt)
         Ok(cert.certificate.clone())
     }
 
     /// Complete authentication flow (automatically fetches public key if needed)
-    pub async fn authenticate(&self, nip: &str, ksef_token: &str) -> Result<String> {
+    pub async fn authenticate(&self, context: &AuthContext, ksef_token: &str) -> Result<String> {
+        context.validate()?;
+
         // Step 0: Get public key certificate (only if encryption is enabled)
         let cert_base64 = if self.disable_encryption {
             String::new() // Not needed in test mode
         } else {
             eprintln!("Fetching public key certificate...");
-            self.get_encryption_certificate().await?
+            self.get_encryption_certificate("KsefTokenEncryption")
+                .await?
         };
 
         // Step 1 & 2: Initiate authentication
         eprintln!("Initiating authentication...");
         let auth_init = self
-            .authenticate_with_ksef_token(nip, ksef_token, &cert_base64)
+            .authenticate_with_ksef_token(context, ksef_token, &cert_base64)
             .await?;
-        let auth_token = auth_init.authentication_token.token.clone();
-        let reference_number = auth_init.reference_number.clone();
+        self.complete_authentication(
+            context,
+            auth_init,
+            Credentials::KsefToken(ksef_token.to_string()),
+        )
+        .await
+    }
 
-        // Step 3: Poll for status (simplified - you may want to add retries)
-        eprintln!("Checking authentication status...");
-        let status = self
-            .check_auth_status(&reference_number, &auth_token)
+    /// Step 2 (certificate): Submit a signed `AuthTokenRequest` document
+    pub async fn submit_xades_signature(&self, signed_xml: String) -> Result<AuthInitResponse> {
+        let url = format!("{}/auth/xades-signature", self.base_url);
+        let response = self
+            .client
+            .post(&url)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/xml")
+            .body(signed_xml)
+            .send()
             .await?;
 
-        if status.status.code != 100 && status.status.code != 200 {
-            return Err(anyhow!(
-                "Authentication failed with status {}: {}",
-                status.status.code,
-                status.status.description
-            ));
+        Self::parse_json(response).await
+    }
+
+    /// Complete authentication flow with a qualified certificate or seal (XAdES signature)
+    pub async fn authenticate_with_certificate(
+        &self,
+        context: &AuthContext,
+        credentials: &CertificateCredentials,
+    ) -> Result<String> {
+        context.validate()?;
+
+        eprintln!("Initiating certificate authentication...");
+        let challenge = self.get_auth_challenge().await?;
+        let document = xades::build_auth_token_request(&challenge.challenge, context);
+        let signed_xml = xades::sign_enveloped(&document, credentials)?;
+        let auth_init = self.submit_xades_signature(signed_xml).await?;
+
+        self.complete_authentication(
+            context,
+            auth_init,
+            Credentials::Certificate(Arc::new(credentials.clone())),
+        )
+        .await
+    }
+
+    /// Steps 3-4: Wait for KSeF to accept the authentication and redeem the tokens
+    async fn complete_authentication(
+        &self,
+        context: &AuthContext,
+        auth_init: AuthInitResponse,
+        credentials: Credentials,
+    ) -> Result<String> {
+        let auth_token = auth_init.authentication_token.token;
+        let reference_number = auth_init.reference_number;
+
+        // Step 3: Poll for status until KSeF finishes processing
+        eprintln!("Waiting for authentication to complete...");
+        match self
+            .poll_auth_status(&reference_number, &auth_token, &self.auth_polling)
+            .await?
+        {
+            AuthOutcome::Rejected {
+                reason,
+                code,
+                description,
+                ..
+            } => {
+                return Err(KsefError::AuthenticationFailed {
+                    reason,
+                    code,
+                    description,
+                })
+            }
+            AuthOutcome::Succeeded | AuthOutcome::Pending => {}
         }
 
         // Step 4: Redeem tokens
@@ -406,14 +624,22 @@ impl KsefClient {
         let tokens = self.redeem_tokens(&auth_token).await?;
 
         // Store session state
-        let mut state = self.session_state.lock().unwrap();
-        *state = Some(SessionState {
+        *self.session_state.lock().unwrap() = Some(SessionState {
             access_token: tokens.access_token.token.clone(),
+            access_token_valid_until: parse_valid_until(&tokens.access_token.valid_until),
             refresh_token: tokens.refresh_token.token.clone(),
-            ksef_token: ksef_token.to_string(),
-            nip: nip.to_string(),
+            refresh_token_valid_until: parse_valid_until(&tokens.refresh_token.valid_until),
+            credentials,
+            context: context.clone(),
         });
 
+        // Load rate limits so requests are throttled client-side (best effort)
+        if !self.rate_limiter.is_loaded() {
+            if let Err(e) = self.load_rate_limits(&tokens.access_token.token).await {
+                eprintln!("Could not load rate limits: {}", e);
+            }
+        }
+
         Ok(format!(
             "Authentication successful. Access token valid until: {}",
             tokens.access_token.valid_until
@@ -426,7 +652,11 @@ impl KsefClient {
             let state = self.session_state.lock().unwrap();
             match &*state {
                 Some(s) => s.refresh_token.clone(),
-                None => return Err(anyhow!("No refresh token available")),
+                None => {
+                    return Err(KsefError::AuthExpired(
+                        "No refresh token available".to_string(),
+                    ))
+                }
             }
         };
 
@@ -439,29 +669,25 @@ impl KsefClient {
             .send()
             .await?;
 
-        let status = response.status();
-        if status.is_success() {
-            #[derive(Deserialize)]
-            struct RefreshResponse {
-                #[serde(rename = "accessToken")]
-                access_token: TokenInfo,
-            }
-            let refresh_response: RefreshResponse = response.json().await?;
-
-            // Update access token in state
-            let mut state = self.session_state.lock().unwrap();
-            if let Some(s) = state.as_mut() {
-                s.access_token = refresh_response.access_token.token.clone();
-            }
+        #[derive(Deserialize)]
+        struct RefreshResponse {
+            #[serde(rename = "accessToken")]
+            access_token: TokenInfo,
+        }
+        let refresh_response: RefreshResponse = Self::parse_json(response).await?;
 
-            Ok(format!(
-                "Token refreshed. Valid until: {}",
-                refresh_response.access_token.valid_until
-            ))
-        } else {
-            let body = response.text().await?;
-            Err(anyhow!("Failed to refresh token ({}): {}", status, body))
+        // Update access token in state
+        let mut state = self.session_state.lock().unwrap();
+        if let Some(s) = state.as_mut() {
+            s.access_token = refresh_response.access_token.token.clone();
+            s.access_token_valid_until =
+                parse_valid_until(&refresh_response.access_token.valid_until);
         }
+
+        Ok(format!(
+            "Token refreshed. Valid until: {}",
+            refresh_response.access_token.valid_until
+        ))
     }
 
     /// Get authentication status
@@ -469,13 +695,48 @@ impl KsefClient {
         let state = self.session_state.lock().unwrap();
         match &*state {
             None => Ok("Not authenticated".to_string()),
-            Some(s) => Ok(format!(
-                "Authenticated as NIP: {}\nAccess token available",
-                s.nip
-            )),
+            Some(s) => {
+                let format_expiry = |t: Option<DateTime<Utc>>| {
+                    t.map(|t| t.to_rfc3339())
+                        .unwrap_or_else(|| "unknown".to_string())
+                };
+                let method = match &s.credentials {
+                    Credentials::KsefToken(_) => "KSeF token".to_string(),
+                    Credentials::Certificate(c) => format!("certificate ({})", c.subject()),
+                };
+                Ok(format!(
+                    "Authenticated in context: {}\nMethod: {}\nAccess token valid until: {}\nRefresh token valid until: {}",
+                    s.context,
+                    method,
+                    format_expiry(s.access_token_valid_until),
+                    format_expiry(s.refresh_token_valid_until)
+                ))
+            }
         }
     }
 
+    /// Context of the authenticated session
+    pub fn auth_context(&self) -> Option<AuthContext> {
+        self.session_state
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|s| s.context.clone())
+    }
+
+    /// Installs a session with a dummy token, for tests of authorized endpoints
+    #[cfg(test)]
+    pub(crate) fn set_test_session(&self, context: AuthContext) {
+        *self.session_state.lock().unwrap() = Some(SessionState {
+            access_token: "test-token".to_string(),
+            access_token_valid_until: None,
+            refresh_token: "test-refresh".to_string(),
+            refresh_token_valid_until: None,
+            credentials: Credentials::KsefToken("test".to_string()),
+            context,
+        });
+    }
+
     /// Logout
     pub fn logout(&self) -> Result<String> {
         let mut state = self.session_state.lock().unwrap();
@@ -483,288 +744,692 @@ impl KsefClient {
         Ok("Session cleared successfully".to_string())
     }
 
-    pub async fn get_active_sessions(
-        &self,
-        page_size: i64,
-        continuation_token: Option<&str>,
-    ) -> Result<String> {
-        let url = format!("{}/auth/sessions?pageSize={}", self.base_url, page_size);
-
-        let mut headers = reqwest::header::HeaderMap::new();
-        if let Some(token) = continuation_token {
-            headers.insert("x-continuation-token", token.parse()?);
+    /// Helper: Deserialize a successful JSON response body
+    async fn parse_json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+        if !response.status().is_success() {
+            return Err(KsefError::from_response(response).await);
         }
-        let headers = self.build_headers(Some(headers));
 
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
         let body = response.text().await?;
+        serde_json::from_str(&body).map_err(|e| {
+            KsefError::InvalidResponse(format!("Failed to parse response: {} ({})", e, body))
+        })
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
+    /// Helper: Return the raw body of a successful response (XML documents)
+    async fn read_text(response: reqwest::Response) -> Result<String> {
+        if !response.status().is_success() {
+            return Err(KsefError::from_response(response).await);
         }
+
+        Ok(response.text().await?)
     }
 
-    pub async fn get_current_session(&self) -> Result<String> {
-        let url = format!("{}/auth/sessions/current", self.base_url);
-        let headers = self.build_headers(None);
+    /// Helper: Check a response that carries no body (204 No Content)
+    async fn expect_no_content(response: reqwest::Response) -> Result<()> {
+        if !response.status().is_success() {
+            return Err(KsefError::from_response(response).await);
+        }
 
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+        Ok(())
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
+    fn continuation_headers(
+        continuation_token: Option<&str>,
+    ) -> Result<reqwest::header::HeaderMap> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(token) = continuation_token {
+            headers.insert("x-continuation-token", token.parse()?);
         }
+        Ok(headers)
     }
 
-    pub async fn terminate_session(&self, reference_number: &str) -> Result<String> {
-        let url = format!("{}/auth/sessions/{}", self.base_url, reference_number);
-        let headers = self.build_headers(None);
-
-        let response = self.client.delete(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+    pub async fn get_active_sessions(
+        &self,
+        page_size: i64,
+        continuation_token: Option<&str>,
+    ) -> Result<AuthenticationSessionsPage> {
+        let url = format!("{}/auth/sessions?pageSize={}", self.base_url, page_size);
+        let headers = Self::continuation_headers(continuation_token)?;
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+        let response = self
+            .send_authorized(EndpointGroup::Other, self.client.get(&url).headers(headers))
+            .await?;
+        Self::parse_json(response).await
     }
 
-    pub async fn get_invoice(&self, ksef_number: &str) -> Result<String> {
-        let url = format!("{}/invoices/ksef/{}", self.base_url, ksef_number);
-        let headers = self.build_headers(None);
+    /// All active authentication sessions, following continuation tokens;
+    /// `limit` caps the number of items
+    pub fn active_sessions_stream(
+        &self,
+        page_size: i64,
+        limit: Option<usize>,
+    ) -> impl Stream<Item = Result<AuthenticationSession>> + '_ {
+        pagination::paginate(None, limit, move |token: Option<String>| async move {
+            let page = self
+                .get_active_sessions(page_size, token.as_deref())
+                .await?;
+            Ok((page.items, page.continuation_token.map(Some)))
+        })
+    }
 
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+    pub async fn get_current_session(&self) -> Result<AuthenticationSession> {
+        let url = format!("{}/auth/sessions/current", self.base_url);
+        let response = self
+            .send_authorized(EndpointGroup::Other, self.client.get(&url))
+            .await?;
+        Self::parse_json(response).await
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+    pub async fn terminate_session(&self, reference_number: &str) -> Result<()> {
+        let url = format!("{}/auth/sessions/{}", self.base_url, reference_number);
+        let response = self
+            .send_authorized(EndpointGroup::Other, self.client.delete(&url))
+            .await?;
+        Self::expect_no_content(response).await
     }
 
-    pub async fn query_invoice_metadata(&self, query: &Value) -> Result<String> {
-        let url = format!("{}/invoices/query/metadata", self.base_url);
-        let headers = self.build_headers(None);
+    /// Download invoice XML by KSeF number
+    pub async fn get_invoice(&self, ksef_number: &str) -> Result<String> {
+        let url = format!("{}/invoices/ksef/{}", self.base_url, ksef_number);
+        let response = self
+            .send_authorized(EndpointGroup::InvoiceDownload, self.client.get(&url))
+            .await?;
+        Self::read_text(response).await
+    }
 
+    pub async fn query_invoice_metadata(
+        &self,
+        filters: &InvoiceQueryFilters,
+        page_offset: i64,
+        page_size: i64,
+    ) -> Result<InvoiceMetadataPage> {
+        let url = format!(
+            "{}/invoices/query/metadata?pageOffset={}&pageSize={}",
+            self.base_url, page_offset, page_size
+        );
         let response = self
-            .client
-            .post(&url)
-            .headers(headers)
-            .json(query)
-            .send()
+            .send_authorized(
+                EndpointGroup::InvoiceMetadata,
+                self.client.post(&url).json(filters),
+            )
             .await?;
-        let status = response.status();
-        let body = response.text().await?;
+        Self::parse_json(response).await
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+    /// Invoice metadata matching `filters` across all pages; `limit` caps the
+    /// number of items. A truncated result over `PermanentStorage` dates is
+    /// resumed from the last storage date, as in `sync_invoices`; for other date
+    /// types the stream ends with `KsefError::Truncated`.
+    pub fn invoice_metadata_stream<'a>(
+        &'a self,
+        filters: &'a InvoiceQueryFilters,
+        page_size: i64,
+        limit: Option<usize>,
+    ) -> impl Stream<Item = Result<InvoiceMetadata>> + 'a {
+        let first = MetadataCursor::Page {
+            from: None,
+            offset: 0,
+            seen: HashSet::new(),
+        };
+        pagination::paginate(first, limit, move |cursor| async move {
+            let MetadataCursor::Page {
+                from,
+                offset,
+                mut seen,
+            } = cursor
+            else {
+                return Err(KsefError::Truncated(format!(
+                    "more than the KSeF record limit match the {:?} date range, narrow it",
+                    filters.date_range.date_type
+                )));
+            };
+
+            let page = match from {
+                Some(from) => {
+                    let mut filters = filters.clone();
+                    filters.date_range.from = from.to_rfc3339();
+                    self.query_invoice_metadata(&filters, offset, page_size)
+                        .await?
+                }
+                None => {
+                    self.query_invoice_metadata(filters, offset, page_size)
+                        .await?
+                }
+            };
+
+            let restart = if !page.is_truncated {
+                None
+            } else if filters.date_range.date_type == DateType::PermanentStorage {
+                let start = from
+                    .or_else(|| parse_valid_until(&filters.date_range.from))
+                    .unwrap_or(DateTime::<Utc>::MIN_UTC);
+                match sync::next_page(&page, start) {
+                    sync::NextPage::From(date) => Some(Some(date)),
+                    _ => Some(None),
+                }
+            } else {
+                Some(None)
+            };
+            let has_more = page.has_more;
+            let invoices: Vec<InvoiceMetadata> = page
+                .invoices
+                .into_iter()
+                .filter(|i| seen.insert(i.ksef_number.clone()))
+                .collect();
+
+            let next = match restart {
+                Some(Some(date)) => Some(MetadataCursor::Page {
+                    from: Some(date),
+                    offset: 0,
+                    seen,
+                }),
+                Some(None) => Some(MetadataCursor::Truncated),
+                None => has_more.then_some(MetadataCursor::Page {
+                    from,
+                    offset: offset + 1,
+                    seen,
+                }),
+            };
+            Ok((invoices, next))
+        })
     }
 
-    pub async fn create_invoice_export(&self, export_params: &Value) -> Result<String> {
+    pub async fn create_invoice_export(
+        &self,
+        request: &InvoiceExportRequest,
+    ) -> Result<ReferenceNumberResponse> {
         let url = format!("{}/invoices/exports", self.base_url);
-        let headers = self.build_headers(None);
+        let response = self
+            .send_authorized(
+                EndpointGroup::InvoiceExport,
+                self.client.post(&url).json(request),
+            )
+            .await?;
+        Self::parse_json(response).await
+    }
 
+    pub async fn get_export_status(&self, reference_number: &str) -> Result<ExportStatusResponse> {
+        let url = format!("{}/invoices/exports/{}", self.base_url, reference_number);
         let response = self
-            .client
-            .post(&url)
-            .headers(headers)
-            .json(export_params)
-            .send()
+            .send_authorized(EndpointGroup::InvoiceExportStatus, self.client.get(&url))
             .await?;
-        let status = response.status();
-        let body = response.text().await?;
+        Self::parse_json(response).await
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
+    /// Starts an export encrypted with a freshly generated key kept by the client
+    pub async fn start_invoice_export(&self, filters: InvoiceQueryFilters) -> Result<String> {
+        let cert_base64 = self
+            .get_encryption_certificate("SymmetricKeyEncryption")
+            .await?;
+        let key = SymmetricKey::generate();
+        let request = InvoiceExportRequest {
+            encryption: key.encryption_info(&cert_base64)?,
+            filters,
+        };
+
+        let response = self.create_invoice_export(&request).await?;
+        self.export_keys
+            .lock()
+            .unwrap()
+            .insert(response.reference_number.clone(), key);
+        Ok(response.reference_number)
+    }
+
+    /// Polls the export status until the package is ready
+    pub async fn wait_for_export(
+        &self,
+        reference_number: &str,
+        options: &PollingOptions,
+    ) -> Result<ExportPackage> {
+        let deadline = tokio::time::Instant::now() + options.timeout;
+        let mut attempt = 0;
+
+        loop {
+            let status = self.get_export_status(reference_number).await?;
+            match status.status.code {
+                100 => {}
+                200 => {
+                    return status.package.ok_or_else(|| {
+                        KsefError::InvalidResponse(format!(
+                            "Export {} completed without a package",
+                            reference_number
+                        ))
+                    })
+                }
+                code => {
+                    return Err(KsefError::OperationFailed {
+                        operation: format!("Export {}", reference_number),
+                        code,
+                        description: status.status.description,
+                    })
+                }
+            }
+
+            options
+                .wait(attempt, deadline, &format!("Export {}", reference_number))
+                .await?;
+            attempt += 1;
         }
     }
 
-    pub async fn get_export_status(&self, reference_number: &str) -> Result<String> {
-        let url = format!("{}/invoices/exports/{}", self.base_url, reference_number);
-        let headers = self.build_headers(None);
+    /// Waits for an export started with `start_invoice_export`, then downloads,
+    /// verifies, decrypts and unpacks all package parts
+    pub async fn download_invoice_export(
+        &self,
+        reference_number: &str,
+        options: &PollingOptions,
+    ) -> Result<InvoiceExport> {
+        let key = self
+            .export_keys
+            .lock()
+            .unwrap()
+            .get(reference_number)
+            .cloned()
+            .ok_or_else(|| {
+                KsefError::Validation(format!(
+                    "No encryption key for export {} (start it with start_invoice_export)",
+                    reference_number
+                ))
+            })?;
+
+        let package = self.wait_for_export(reference_number, options).await?;
+
+        let mut parts = package.parts.clone();
+        parts.sort_by_key(|p| p.ordinal_number);
+        let mut zip = Vec::new();
+        for part in &parts {
+            eprintln!("Downloading export part {}...", part.ordinal_number);
+            let encrypted = self.download_export_part(part).await?;
+            export::verify_hash(&encrypted, &part.encrypted_part_hash, &part.part_name)?;
+            let decrypted = key.decrypt(&encrypted)?;
+            export::verify_hash(&decrypted, &part.part_hash, &part.part_name)?;
+            zip.extend(decrypted);
+        }
 
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+        let (invoices, metadata_json) = export::unpack(zip)?;
+        self.export_keys.lock().unwrap().remove(reference_number);
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
+        Ok(InvoiceExport {
+            reference_number: reference_number.to_string(),
+            package,
+            invoices,
+            metadata_json,
+        })
+    }
+
+    /// Starts an export and downloads it once ready
+    pub async fn export_invoices(
+        &self,
+        filters: InvoiceQueryFilters,
+        options: &PollingOptions,
+    ) -> Result<InvoiceExport> {
+        let reference_number = self.start_invoice_export(filters).await?;
+        eprintln!(
+            "Export {} started, waiting for the package...",
+            reference_number
+        );
+        self.download_invoice_export(&reference_number, options)
+            .await
+    }
+
+    /// Downloads invoices stored since the last run for the authenticated
+    /// context and `subject_type`, saving them and the updated cursor in `store`.
+    ///
+    /// The cursor is saved after every page, so an interrupted run resumes
+    /// where it stopped.
+    pub async fn sync_invoices(
+        &self,
+        store: &SyncStore,
+        subject_type: SubjectType,
+        options: &SyncOptions,
+    ) -> Result<SyncReport> {
+        let context = self
+            .auth_context()
+            .ok_or_else(|| KsefError::AuthExpired("Not authenticated".to_string()))?
+            .value()
+            .to_string();
+
+        let now = Utc::now();
+        let mut state = store.load_state()?;
+        let key = SyncState::key(&context, subject_type);
+        let cursor = state
+            .cursors
+            .get(&key)
+            .cloned()
+            .unwrap_or_else(|| SyncCursor::new(&context, subject_type));
+
+        let mut from = cursor
+            .high_water_mark
+            .as_deref()
+            .and_then(parse_valid_until)
+            .or(options.initial_from)
+            .unwrap_or_else(|| now - ChronoDuration::days(sync::MAX_QUERY_WINDOW_DAYS));
+        let mut progress = SyncProgress::new(cursor);
+        let mut report = SyncReport {
+            context: context.clone(),
+            subject_type,
+            new_invoices: Vec::new(),
+            duplicates: 0,
+            high_water_mark: None,
+        };
+
+        loop {
+            let window_end = from + ChronoDuration::days(sync::MAX_QUERY_WINDOW_DAYS);
+            let to = (window_end < now).then_some(window_end);
+            let mut page_offset = 0;
+
+            loop {
+                let filters = sync::sync_filters(subject_type, from, to);
+                let page = self
+                    .query_invoice_metadata(&filters, page_offset, options.page_size)
+                    .await?;
+
+                for invoice in &page.invoices {
+                    let storage_date = invoice.permanent_storage_date.as_deref();
+                    if progress.is_known(&invoice.ksef_number)
+                        || store
+                            .invoice_path(&context, subject_type, &invoice.ksef_number)
+                            .exists()
+                    {
+                        report.duplicates += 1;
+                    } else {
+                        let xml = self.get_invoice(&invoice.ksef_number).await?;
+                        store.save_invoice(&context, subject_type, &invoice.ksef_number, &xml)?;
+                        progress.cursor.synced_invoices += 1;
+                        report.new_invoices.push(invoice.clone());
+                    }
+                    progress.record(&invoice.ksef_number, storage_date);
+                }
+
+                progress.advance(page.permanent_storage_hwm_date.as_deref());
+                state.cursors.insert(key.clone(), progress.cursor.clone());
+                store.save_state(&state)?;
+
+                match sync::next_page(&page, from) {
+                    sync::NextPage::Offset => page_offset += 1,
+                    sync::NextPage::From(date) => {
+                        from = date;
+                        page_offset = 0;
+                    }
+                    sync::NextPage::Done => break,
+                }
+            }
+
+            match to {
+                Some(to) => from = to,
+                None => break,
+            }
         }
+
+        progress.cursor.last_sync = Some(Utc::now().to_rfc3339());
+        state.cursors.insert(key, progress.cursor.clone());
+        store.save_state(&state)?;
+
+        report.high_water_mark = progress.cursor.high_water_mark;
+        Ok(report)
+    }
+
+    /// Downloads one encrypted part from its pre-signed URL (no KSeF access token)
+    async fn download_export_part(&self, part: &ExportPackagePart) -> Result<Vec<u8>> {
+        let method = reqwest::Method::from_bytes(part.method.as_bytes()).map_err(|_| {
+            KsefError::InvalidResponse(format!("Invalid download method: {}", part.method))
+        })?;
+        let response = self.client.request(method, &part.url).send().await?;
+        if !response.status().is_success() {
+            return Err(KsefError::from_response(response).await);
+        }
+        Ok(response.bytes().await?.to_vec())
     }
 
-    pub async fn get_public_key_certificates(&self) -> Result<String> {
+    pub async fn get_public_key_certificates(&self) -> Result<Vec<PublicKeyCertificate>> {
         let url = format!("{}/security/public-key-certificates", self.base_url);
 
         let response = self.client.get(&url).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
-
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+        Self::parse_json(response).await
     }
 
-    pub async fn get_rate_limits(&self) -> Result<String> {
+    /// Fetches the current limits and applies them to client-side throttling
+    pub async fn get_rate_limits(&self) -> Result<RateLimits> {
         let url = format!("{}/rate-limits", self.base_url);
-        let headers = self.build_headers(None);
+        let response = self
+            .send_authorized(EndpointGroup::Other, self.client.get(&url))
+            .await?;
+        let limits: RateLimits = Self::parse_json(response).await?;
+        self.rate_limiter.apply(&limits);
+        Ok(limits)
+    }
 
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+    /// Fetches the limits with the given access token, without renewing the session:
+    /// this runs while authenticating, possibly inside `renew_session`
+    async fn load_rate_limits(&self, access_token: &str) -> Result<()> {
+        let url = format!("{}/rate-limits", self.base_url);
+        self.rate_limiter.acquire(EndpointGroup::Other).await;
+        let response = Self::with_bearer(self.client.get(&url), Some(access_token.to_string()))
+            .send()
+            .await?;
+        let limits: RateLimits = Self::parse_json(response).await?;
+        self.rate_limiter.apply(&limits);
+        Ok(())
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+    /// Remaining request budget per endpoint group (empty until limits are loaded)
+    pub fn rate_limit_budget(&self) -> Vec<RateLimitBudget> {
+        self.rate_limiter.budget()
     }
 
-    pub async fn create_online_session(&self, session_params: &Value) -> Result<String> {
+    pub async fn create_online_session(
+        &self,
+        request: &OpenOnlineSessionRequest,
+    ) -> Result<OpenOnlineSessionResponse> {
         let url = format!("{}/sessions/online", self.base_url);
-        let headers = self.build_headers(None);
-
-        eprintln!("Creating online session with params: {}", session_params);
+        eprintln!(
+            "Creating online session with form code: {}",
+            request.form_code.system_code
+        );
 
         let response = self
-            .client
-            .post(&url)
-            .headers(headers)
-            .json(session_params)
-            .send()
+            .send_authorized(
+                EndpointGroup::OnlineSession,
+                self.client.post(&url).json(request),
+            )
             .await?;
-        let status = response.status();
-        let body = response.text().await?;
+        Self::parse_json(response).await
+    }
+
+    /// Opens an online session with a freshly generated symmetric key.
+    ///
+    /// The key is kept in memory until the session is closed, so invoices can be
+    /// sent with `send_online_invoice` using only the session reference number.
+    pub async fn open_online_session(
+        &self,
+        form_code: FormCode,
+    ) -> Result<OpenOnlineSessionResponse> {
+        let (response, key) = self.open_encrypted_session(form_code.clone()).await?;
+        self.session_keys
+            .lock()
+            .unwrap()
+            .insert(response.reference_number.clone(), (key, form_code));
+        Ok(response)
+    }
 
-        eprintln!("Response status: {}, body: {}", status, body);
+    /// Form code of a session opened with `open_online_session`
+    pub fn online_session_form_code(&self, session_ref: &str) -> Option<FormCode> {
+        self.session_keys
+            .lock()
+            .unwrap()
+            .get(session_ref)
+            .map(|(_, form_code)| form_code.clone())
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+    /// Opens an online session and returns a handle owning its encryption key
+    pub async fn online_session(&self, form_code: FormCode) -> Result<OnlineSession<'_>> {
+        let (response, key) = self.open_encrypted_session(form_code.clone()).await?;
+        Ok(OnlineSession::new(
+            self,
+            response.reference_number,
+            response.valid_until,
+            form_code,
+            key,
+        ))
+    }
+
+    async fn open_encrypted_session(
+        &self,
+        form_code: FormCode,
+    ) -> Result<(OpenOnlineSessionResponse, SymmetricKey)> {
+        let cert_base64 = self
+            .get_encryption_certificate("SymmetricKeyEncryption")
+            .await?;
+        let key = SymmetricKey::generate();
+        let request = OpenOnlineSessionRequest {
+            form_code,
+            encryption: key.encryption_info(&cert_base64)?,
+        };
+
+        let response = self.create_online_session(&request).await?;
+        Ok((response, key))
     }
 
-    pub async fn close_online_session(&self, reference_number: &str) -> Result<String> {
+    /// Encrypts and sends an invoice in a session opened with `open_online_session`
+    pub async fn send_online_invoice(
+        &self,
+        session_ref: &str,
+        invoice_xml: &str,
+        offline_mode: bool,
+        hash_of_corrected_invoice: Option<String>,
+    ) -> Result<ReferenceNumberResponse> {
+        let key = self
+            .session_keys
+            .lock()
+            .unwrap()
+            .get(session_ref)
+            .map(|(key, _)| key.clone())
+            .ok_or_else(|| {
+                KsefError::Validation(format!(
+                    "No encryption key for session {} (open it with open_online_session)",
+                    session_ref
+                ))
+            })?;
+
+        let request = key.encrypt_invoice(invoice_xml, offline_mode, hash_of_corrected_invoice)?;
+        self.submit_invoice(session_ref, &request).await
+    }
+
+    pub async fn close_online_session(&self, reference_number: &str) -> Result<()> {
         let url = format!(
             "{}/sessions/online/{}/close",
             self.base_url, reference_number
         );
-        let headers = self.build_headers(None);
+        let response = self
+            .send_authorized(EndpointGroup::OnlineSession, self.client.post(&url))
+            .await?;
+        Self::expect_no_content(response).await?;
 
-        let response = self.client.post(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+        self.session_keys.lock().unwrap().remove(reference_number);
+        Ok(())
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+    /// Close request with the current access token, for sending outside of an async context
+    pub(crate) fn close_online_session_request(
+        &self,
+        reference_number: &str,
+    ) -> reqwest::RequestBuilder {
+        let url = format!(
+            "{}/sessions/online/{}/close",
+            self.base_url, reference_number
+        );
+        Self::with_bearer(self.client.post(&url), self.get_access_token())
     }
 
-    pub async fn submit_invoice(&self, session_ref: &str, invoice_data: &Value) -> Result<String> {
+    pub async fn submit_invoice(
+        &self,
+        session_ref: &str,
+        request: &SendInvoiceRequest,
+    ) -> Result<ReferenceNumberResponse> {
         let url = format!("{}/sessions/online/{}/invoices", self.base_url, session_ref);
-        let headers = self.build_headers(None);
-
         let response = self
-            .client
-            .post(&url)
-            .headers(headers)
-            .json(invoice_data)
-            .send()
+            .send_authorized(
+                EndpointGroup::InvoiceSend,
+                self.client.post(&url).json(request),
+            )
             .await?;
-        let status = response.status();
-        let body = response.text().await?;
-
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+        Self::parse_json(response).await
     }
 
     pub async fn get_sessions(
         &self,
         page_size: i64,
         continuation_token: Option<&str>,
-    ) -> Result<String> {
+    ) -> Result<SessionsPage> {
         let url = format!("{}/sessions?pageSize={}", self.base_url, page_size);
+        let headers = Self::continuation_headers(continuation_token)?;
 
-        let mut headers = reqwest::header::HeaderMap::new();
-        if let Some(token) = continuation_token {
-            headers.insert("x-continuation-token", token.parse()?);
-        }
-        let headers = self.build_headers(Some(headers));
-
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+        let response = self
+            .send_authorized(
+                EndpointGroup::SessionList,
+                self.client.get(&url).headers(headers),
+            )
+            .await?;
+        Self::parse_json(response).await
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+    /// All sessions, following continuation tokens; `limit` caps the number of items
+    pub fn sessions_stream(
+        &self,
+        page_size: i64,
+        limit: Option<usize>,
+    ) -> impl Stream<Item = Result<SessionSummary>> + '_ {
+        pagination::paginate(None, limit, move |token: Option<String>| async move {
+            let page = self.get_sessions(page_size, token.as_deref()).await?;
+            Ok((page.sessions, page.continuation_token.map(Some)))
+        })
     }
 
-    pub async fn get_session_status(&self, reference_number: &str) -> Result<String> {
+    pub async fn get_session_status(
+        &self,
+        reference_number: &str,
+    ) -> Result<SessionStatusResponse> {
         let url = format!("{}/sessions/{}", self.base_url, reference_number);
-        let headers = self.build_headers(None);
-
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
-
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+        let response = self
+            .send_authorized(EndpointGroup::SessionMisc, self.client.get(&url))
+            .await?;
+        Self::parse_json(response).await
     }
 
     pub async fn get_session_invoices(
         &self,
         reference_number: &str,
         continuation_token: Option<&str>,
-    ) -> Result<String> {
+    ) -> Result<SessionInvoicesPage> {
         let url = format!("{}/sessions/{}/invoices", self.base_url, reference_number);
+        let headers = Self::continuation_headers(continuation_token)?;
 
-        let mut headers = reqwest::header::HeaderMap::new();
-        if let Some(token) = continuation_token {
-            headers.insert("x-continuation-token", token.parse()?);
-        }
-        let headers = self.build_headers(Some(headers));
-
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+        let response = self
+            .send_authorized(
+                EndpointGroup::SessionInvoiceList,
+                self.client.get(&url).headers(headers),
+            )
+            .await?;
+        Self::parse_json(response).await
+    }
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+    /// All invoices of a session, following continuation tokens; `limit` caps
+    /// the number of items
+    pub fn session_invoices_stream<'a>(
+        &'a self,
+        reference_number: &'a str,
+        limit: Option<usize>,
+    ) -> impl Stream<Item = Result<SessionInvoiceStatus>> + 'a {
+        pagination::paginate(None, limit, move |token: Option<String>| async move {
+            let page = self
+                .get_session_invoices(reference_number, token.as_deref())
+                .await?;
+            Ok((page.invoices, page.continuation_token.map(Some)))
+        })
     }
 
+    /// Download UPO XML for an invoice by its KSeF number
     pub async fn get_invoice_upo_by_ksef(
         &self,
         session_ref: &str,
@@ -774,19 +1439,13 @@ impl KsefClient {
             "{}/sessions/{}/invoices/ksef/{}/upo",
             self.base_url, session_ref, ksef_number
         );
-        let headers = self.build_headers(None);
-
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
-
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+        let response = self
+            .send_authorized(EndpointGroup::SessionMisc, self.client.get(&url))
+            .await?;
+        Self::read_text(response).await
     }
 
+    /// Download UPO XML for an invoice by its reference number
     pub async fn get_invoice_upo_by_reference(
         &self,
         session_ref: &str,
@@ -796,19 +1455,13 @@ impl KsefClient {
             "{}/sessions/{}/invoices/{}/upo",
             self.base_url, session_ref, invoice_ref
         );
-        let headers = self.build_headers(None);
-
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
-
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+        let response = self
+            .send_authorized(EndpointGroup::SessionMisc, self.client.get(&url))
+            .await?;
+        Self::read_text(response).await
     }
 
+    /// Download collective UPO XML for a session
     pub async fn get_session_upo(
         &self,
         session_ref: &str,
@@ -818,56 +1471,113 @@ impl KsefClient {
             "{}/sessions/{}/upo/{}",
             self.base_url, session_ref, upo_ref
         );
-        let headers = self.build_headers(None);
-
-        let response = self.client.get(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
-
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+        let response = self
+            .send_authorized(EndpointGroup::SessionMisc, self.client.get(&url))
+            .await?;
+        Self::read_text(response).await
     }
 
-    pub async fn create_batch_session(&self, session_params: &Value) -> Result<String> {
+    pub async fn create_batch_session(
+        &self,
+        request: &OpenBatchSessionRequest,
+    ) -> Result<OpenBatchSessionResponse> {
         let url = format!("{}/sessions/batch", self.base_url);
-        let headers = self.build_headers(None);
-
         let response = self
-            .client
-            .post(&url)
-            .headers(headers)
-            .json(session_params)
-            .send()
+            .send_authorized(
+                EndpointGroup::BatchSession,
+                self.client.post(&url).json(request),
+            )
             .await?;
-        let status = response.status();
-        let body = response.text().await?;
-
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
-        }
+        Self::parse_json(response).await
     }
 
-    pub async fn close_batch_session(&self, reference_number: &str) -> Result<String> {
+    pub async fn close_batch_session(&self, reference_number: &str) -> Result<()> {
         let url = format!(
             "{}/sessions/batch/{}/close",
             self.base_url, reference_number
         );
-        let headers = self.build_headers(None);
+        let response = self
+            .send_authorized(EndpointGroup::BatchSession, self.client.post(&url))
+            .await?;
+        Self::expect_no_content(response).await
+    }
 
-        let response = self.client.post(&url).headers(headers).send().await?;
-        let status = response.status();
-        let body = response.text().await?;
+    /// Sends invoices in a batch session: builds the ZIP, splits and encrypts it,
+    /// opens the session, uploads every part and closes the session
+    pub async fn submit_batch(
+        &self,
+        form_code: FormCode,
+        invoices: &[BatchInvoice],
+        offline_mode: bool,
+    ) -> Result<BatchSubmission> {
+        let zip = batch::build_zip(invoices)?;
+        let key = SymmetricKey::generate();
+        let prepared = batch::prepare_batch(&zip, &key, MAX_BATCH_PART_SIZE);
+        eprintln!(
+            "Prepared batch: {} invoices, {} bytes, {} parts",
+            invoices.len(),
+            zip.len(),
+            prepared.parts.len()
+        );
 
-        if status.is_success() {
-            Ok(body)
-        } else {
-            Err(anyhow!("API error ({}): {}", status, body))
+        let cert_base64 = self
+            .get_encryption_certificate("SymmetricKeyEncryption")
+            .await?;
+        let request = OpenBatchSessionRequest {
+            form_code,
+            batch_file: prepared.file_info,
+            encryption: key.encryption_info(&cert_base64)?,
+            offline_mode,
+        };
+        let session = self.create_batch_session(&request).await?;
+
+        for part in prepared.parts {
+            let upload = session
+                .part_upload_requests
+                .iter()
+                .find(|u| u.ordinal_number == part.ordinal_number)
+                .ok_or_else(|| {
+                    KsefError::InvalidResponse(format!(
+                        "No upload URL for batch part {}",
+                        part.ordinal_number
+                    ))
+                })?;
+            eprintln!("Uploading batch part {}...", part.ordinal_number);
+            self.upload_batch_part(upload, part.data).await?;
+        }
+
+        self.close_batch_session(&session.reference_number).await?;
+
+        Ok(BatchSubmission {
+            reference_number: session.reference_number,
+            invoice_count: invoices.len(),
+            part_count: request.batch_file.file_parts.len(),
+            package_size: zip.len() as u64,
+        })
+    }
+
+    /// Uploads an encrypted part to its pre-signed URL (no KSeF access token)
+    pub async fn upload_batch_part(&self, upload: &PartUploadRequest, data: Vec<u8>) -> Result<()> {
+        let method = reqwest::Method::from_bytes(upload.method.as_bytes()).map_err(|_| {
+            KsefError::InvalidResponse(format!("Invalid upload method: {}", upload.method))
+        })?;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &upload.headers {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                KsefError::InvalidResponse(format!("Invalid upload header: {}", name))
+            })?;
+            headers.insert(name, value.parse()?);
         }
+
+        let response = self
+            .client
+            .request(method, &upload.url)
+            .headers(headers)
+            .body(data)
+            .send()
+            .await?;
+        Self::expect_no_content(response).await
     }
 }
 
@@ -876,3 +1586,197 @@ impl Default for KsefClient {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_expiry_margin() {
+        let now = Utc::now();
+
+        assert!(!expires_within_margin(None, now));
+        assert!(!expires_within_margin(
+            Some(now + ChronoDuration::minutes(10)),
+            now
+        ));
+        assert!(expires_within_margin(
+            Some(now + ChronoDuration::seconds(30)),
+            now
+        ));
+        assert!(expires_within_margin(
+            Some(now - ChronoDuration::seconds(1)),
+            now
+        ));
+    }
+
+    #[test]
+    fn test_parse_valid_until() {
+        let parsed = parse_valid_until("2025-10-01T12:30:00.1234567+02:00").unwrap();
+        assert_eq!(parsed.to_rfc3339(), "2025-10-01T10:30:00.123456700+00:00");
+        assert!(parse_valid_until("not a date").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_download_invoice_export() {
+        use sha2::{Digest, Sha256};
+
+        let zip = batch::build_zip(&[BatchInvoice {
+            file_name: "5265877635-20251001-0100001D5F4A-1B.xml".to_string(),
+            xml: "<Faktura/>".to_string(),
+        }])
+        .unwrap();
+        let key = SymmetricKey::generate();
+        let encrypted = key.encrypt(&zip);
+        let hash = |data: &[u8]| BASE64.encode(Sha256::digest(data));
+
+        let mut server = mockito::Server::new_async().await;
+        let status = serde_json::json!({
+            "status": {"code": 200, "description": "Eksport zakończony"},
+            "package": {
+                "invoiceCount": 1,
+                "size": zip.len(),
+                "parts": [{
+                    "ordinalNumber": 1,
+                    "partName": "part-1.zip.aes",
+                    "method": "GET",
+                    "url": format!("{}/download/part-1", server.url()),
+                    "partSize": zip.len(),
+                    "partHash": hash(&zip),
+                    "encryptedPartSize": encrypted.len(),
+                    "encryptedPartHash": hash(&encrypted),
+                    "expirationDate": "2025-10-02T10:00:00Z"
+                }],
+                "isTruncated": false
+            }
+        });
+        server
+            .mock("GET", "/invoices/exports/EX-1")
+            .with_body(status.to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/download/part-1")
+            .with_body(encrypted)
+            .create_async()
+            .await;
+
+        let client = KsefClient::with_base_url(server.url());
+        client
+            .export_keys
+            .lock()
+            .unwrap()
+            .insert("EX-1".to_string(), key);
+
+        let export = client
+            .download_invoice_export("EX-1", &PollingOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(export.invoices.len(), 1);
+        assert_eq!(export.invoices[0].xml, "<Faktura/>");
+        assert!(client.export_keys.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_rate_limit() {
+        let mut server = mockito::Server::new_async().await;
+        let limited = server
+            .mock("GET", "/sessions/SO-1")
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .expect(1)
+            .create_async()
+            .await;
+        let ok = server
+            .mock("GET", "/sessions/SO-1")
+            .with_status(200)
+            .with_body(r#"{"status": {"code": 200, "description": "OK"}}"#)
+            .create_async()
+            .await;
+
+        let client = KsefClient::with_base_url(server.url());
+        let status = client.get_session_status("SO-1").await.unwrap();
+
+        assert_eq!(status.status.code, 200);
+        limited.assert_async().await;
+        ok.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_reauthentication_with_rejected_rate_limits() {
+        let mut server = mockito::Server::new_async().await;
+        let rejected = server
+            .mock("GET", "/sessions/SO-1")
+            .match_header("Authorization", "Bearer old")
+            .with_status(401)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/sessions/SO-1")
+            .match_header("Authorization", "Bearer new")
+            .with_body(r#"{"status": {"code": 200, "description": "OK"}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/auth/challenge")
+            .with_body(r#"{"challenge": "C-1", "timestamp": "", "timestampMs": 0}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/auth/ksef-token")
+            .with_body(
+                r#"{"referenceNumber": "AU-1",
+                    "authenticationToken": {"token": "auth", "validUntil": ""}}"#,
+            )
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/auth/AU-1")
+            .with_body(
+                r#"{"startDate": "", "authenticationMethod": "Token",
+                    "status": {"code": 200, "description": "OK"}}"#,
+            )
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/auth/token/redeem")
+            .with_body(
+                r#"{"accessToken": {"token": "new", "validUntil": ""},
+                    "refreshToken": {"token": "refresh", "validUntil": ""}}"#,
+            )
+            .create_async()
+            .await;
+        let rate_limits = server
+            .mock("GET", "/rate-limits")
+            .with_status(401)
+            .expect(1)
+            .create_async()
+            .await;
+
+        // Expired refresh token: the 401 leads to full re-authentication
+        let mut client = KsefClient::with_base_url(server.url());
+        client.disable_encryption = true;
+        *client.session_state.lock().unwrap() = Some(SessionState {
+            access_token: "old".to_string(),
+            access_token_valid_until: None,
+            refresh_token: "refresh".to_string(),
+            refresh_token_valid_until: Some(Utc::now() - ChronoDuration::hours(1)),
+            credentials: Credentials::KsefToken("token".to_string()),
+            context: AuthContext::parse("nip", "5265877635").unwrap(),
+        });
+
+        let status = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            client.get_session_status("SO-1"),
+        )
+        .await
+        .expect("re-authentication must not wait on itself")
+        .unwrap();
+
+        assert_eq!(status.status.code, 200);
+        assert_eq!(client.get_access_token().as_deref(), Some("new"));
+        rejected.assert_async().await;
+        rate_limits.assert_async().await;
+    }
+}
@@ -0,0 +1,688 @@
+//! Request and response models for the KSeF v2 API.
+//!
+//! Field names follow the KSeF OpenAPI specification; timestamps are kept as
+//! the ISO 8601 strings returned by the API.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::StatusInfo;
+
+// Shared types
+
+/// Invoice schema (form code) used by a session or an invoice
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormCode {
+    #[serde(rename = "systemCode")]
+    pub system_code: String,
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: String,
+    pub value: String,
+}
+
+/// Symmetric key encrypted with the MF public key, plus the AES initialization vector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionInfo {
+    #[serde(rename = "encryptedSymmetricKey")]
+    pub encrypted_symmetric_key: String,
+    #[serde(rename = "initializationVector")]
+    pub initialization_vector: String,
+}
+
+/// Ministry of Finance public key certificate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicKeyCertificate {
+    pub certificate: String,
+    #[serde(rename = "validFrom", skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<String>,
+    #[serde(rename = "validTo", skip_serializing_if = "Option::is_none")]
+    pub valid_to: Option<String>,
+    #[serde(default)]
+    pub usage: Vec<String>,
+}
+
+// Authentication sessions
+
+/// Authentication session as listed by `/auth/sessions`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationSession {
+    #[serde(rename = "referenceNumber")]
+    pub reference_number: String,
+    #[serde(rename = "startDate")]
+    pub start_date: String,
+    #[serde(rename = "authenticationMethod")]
+    pub authentication_method: String,
+    pub status: StatusInfo,
+    #[serde(rename = "isCurrent", default)]
+    pub is_current: bool,
+    #[serde(rename = "isTokenRedeemed", skip_serializing_if = "Option::is_none")]
+    pub is_token_redeemed: Option<bool>,
+    #[serde(
+        rename = "lastTokenRefreshDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_token_refresh_date: Option<String>,
+    #[serde(
+        rename = "refreshTokenValidUntil",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub refresh_token_valid_until: Option<String>,
+}
+
+/// Page of authentication sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationSessionsPage {
+    #[serde(rename = "continuationToken", skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
+    #[serde(default)]
+    pub items: Vec<AuthenticationSession>,
+}
+
+// Invoice metadata
+
+/// Filters for invoice metadata queries and exports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceQueryFilters {
+    #[serde(rename = "subjectType")]
+    pub subject_type: SubjectType,
+    #[serde(rename = "dateRange")]
+    pub date_range: DateRange,
+    #[serde(rename = "ksefNumber", skip_serializing_if = "Option::is_none")]
+    pub ksef_number: Option<String>,
+    #[serde(rename = "invoiceNumber", skip_serializing_if = "Option::is_none")]
+    pub invoice_number: Option<String>,
+    #[serde(rename = "sellerNip", skip_serializing_if = "Option::is_none")]
+    pub seller_nip: Option<String>,
+    #[serde(rename = "buyerIdentifier", skip_serializing_if = "Option::is_none")]
+    pub buyer_identifier: Option<BuyerIdentifier>,
+    #[serde(rename = "currencyCodes", skip_serializing_if = "Option::is_none")]
+    pub currency_codes: Option<Vec<String>>,
+    #[serde(rename = "invoiceTypes", skip_serializing_if = "Option::is_none")]
+    pub invoice_types: Option<Vec<String>>,
+}
+
+/// Subject type of the authenticated context in relation to the invoice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SubjectType {
+    /// Seller
+    Subject1,
+    /// Buyer
+    Subject2,
+    /// Third party
+    Subject3,
+    SubjectAuthorized,
+}
+
+impl SubjectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubjectType::Subject1 => "Subject1",
+            SubjectType::Subject2 => "Subject2",
+            SubjectType::Subject3 => "Subject3",
+            SubjectType::SubjectAuthorized => "SubjectAuthorized",
+        }
+    }
+}
+
+/// Date range used for filtering invoices
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DateRange {
+    #[serde(rename = "dateType")]
+    pub date_type: DateType,
+    pub from: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+/// Which invoice date the range applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateType {
+    Issue,
+    Invoicing,
+    PermanentStorage,
+}
+
+/// Buyer identifier as reported in invoice metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuyerIdentifier {
+    #[serde(rename = "type")]
+    pub identifier_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// Seller as reported in invoice metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceSeller {
+    pub nip: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Buyer as reported in invoice metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceBuyer {
+    pub identifier: BuyerIdentifier,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Metadata of a single invoice stored in KSeF
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceMetadata {
+    #[serde(rename = "ksefNumber")]
+    pub ksef_number: String,
+    #[serde(rename = "invoiceNumber")]
+    pub invoice_number: String,
+    #[serde(rename = "issueDate")]
+    pub issue_date: String,
+    #[serde(rename = "invoicingDate")]
+    pub invoicing_date: String,
+    #[serde(rename = "acquisitionDate", skip_serializing_if = "Option::is_none")]
+    pub acquisition_date: Option<String>,
+    #[serde(
+        rename = "permanentStorageDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub permanent_storage_date: Option<String>,
+    pub seller: InvoiceSeller,
+    pub buyer: InvoiceBuyer,
+    #[serde(rename = "netAmount")]
+    pub net_amount: f64,
+    #[serde(rename = "grossAmount")]
+    pub gross_amount: f64,
+    #[serde(rename = "vatAmount")]
+    pub vat_amount: f64,
+    pub currency: String,
+    #[serde(rename = "invoicingMode", skip_serializing_if = "Option::is_none")]
+    pub invoicing_mode: Option<String>,
+    #[serde(rename = "invoiceType", skip_serializing_if = "Option::is_none")]
+    pub invoice_type: Option<String>,
+    #[serde(rename = "formCode", skip_serializing_if = "Option::is_none")]
+    pub form_code: Option<FormCode>,
+    #[serde(rename = "isSelfInvoicing", default)]
+    pub is_self_invoicing: bool,
+    #[serde(rename = "hasAttachment", default)]
+    pub has_attachment: bool,
+    #[serde(rename = "invoiceHash", skip_serializing_if = "Option::is_none")]
+    pub invoice_hash: Option<String>,
+    #[serde(
+        rename = "hashOfCorrectedInvoice",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub hash_of_corrected_invoice: Option<String>,
+}
+
+/// Page of invoice metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceMetadataPage {
+    #[serde(rename = "hasMore", default)]
+    pub has_more: bool,
+    #[serde(rename = "isTruncated", default)]
+    pub is_truncated: bool,
+    #[serde(
+        rename = "permanentStorageHwmDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub permanent_storage_hwm_date: Option<String>,
+    #[serde(default)]
+    pub invoices: Vec<InvoiceMetadata>,
+}
+
+// Invoice exports
+
+/// Request body for `/invoices/exports`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceExportRequest {
+    pub encryption: EncryptionInfo,
+    pub filters: InvoiceQueryFilters,
+}
+
+/// Reference number returned when an asynchronous operation is accepted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferenceNumberResponse {
+    #[serde(rename = "referenceNumber")]
+    pub reference_number: String,
+}
+
+/// Single encrypted part of an export package
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportPackagePart {
+    #[serde(rename = "ordinalNumber")]
+    pub ordinal_number: i32,
+    #[serde(rename = "partName")]
+    pub part_name: String,
+    pub method: String,
+    pub url: String,
+    #[serde(rename = "partSize")]
+    pub part_size: i64,
+    #[serde(rename = "partHash")]
+    pub part_hash: String,
+    #[serde(rename = "encryptedPartSize")]
+    pub encrypted_part_size: i64,
+    #[serde(rename = "encryptedPartHash")]
+    pub encrypted_part_hash: String,
+    #[serde(rename = "expirationDate")]
+    pub expiration_date: String,
+}
+
+/// Export package description
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportPackage {
+    #[serde(rename = "invoiceCount")]
+    pub invoice_count: i64,
+    pub size: i64,
+    #[serde(default)]
+    pub parts: Vec<ExportPackagePart>,
+    #[serde(rename = "isTruncated", default)]
+    pub is_truncated: bool,
+    #[serde(rename = "lastIssueDate", skip_serializing_if = "Option::is_none")]
+    pub last_issue_date: Option<String>,
+    #[serde(rename = "lastInvoicingDate", skip_serializing_if = "Option::is_none")]
+    pub last_invoicing_date: Option<String>,
+    #[serde(
+        rename = "lastPermanentStorageDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_permanent_storage_date: Option<String>,
+    #[serde(
+        rename = "permanentStorageHwmDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub permanent_storage_hwm_date: Option<String>,
+}
+
+/// Status of an invoice export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportStatusResponse {
+    pub status: StatusInfo,
+    #[serde(rename = "completedDate", skip_serializing_if = "Option::is_none")]
+    pub completed_date: Option<String>,
+    #[serde(
+        rename = "packageExpirationDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub package_expiration_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<ExportPackage>,
+}
+
+// Rate limits
+
+/// Request budget for a single endpoint group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitValues {
+    #[serde(rename = "perSecond")]
+    pub per_second: u32,
+    #[serde(rename = "perMinute")]
+    pub per_minute: u32,
+    #[serde(rename = "perHour")]
+    pub per_hour: u32,
+}
+
+/// Effective API rate limits per endpoint group; a group missing from the response is unlimited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimits {
+    #[serde(rename = "onlineSession", skip_serializing_if = "Option::is_none")]
+    pub online_session: Option<RateLimitValues>,
+    #[serde(rename = "batchSession", skip_serializing_if = "Option::is_none")]
+    pub batch_session: Option<RateLimitValues>,
+    #[serde(rename = "invoiceSend", skip_serializing_if = "Option::is_none")]
+    pub invoice_send: Option<RateLimitValues>,
+    #[serde(rename = "invoiceStatus", skip_serializing_if = "Option::is_none")]
+    pub invoice_status: Option<RateLimitValues>,
+    #[serde(rename = "sessionList", skip_serializing_if = "Option::is_none")]
+    pub session_list: Option<RateLimitValues>,
+    #[serde(rename = "sessionInvoiceList", skip_serializing_if = "Option::is_none")]
+    pub session_invoice_list: Option<RateLimitValues>,
+    #[serde(rename = "sessionMisc", skip_serializing_if = "Option::is_none")]
+    pub session_misc: Option<RateLimitValues>,
+    #[serde(rename = "invoiceMetadata", skip_serializing_if = "Option::is_none")]
+    pub invoice_metadata: Option<RateLimitValues>,
+    #[serde(rename = "invoiceExport", skip_serializing_if = "Option::is_none")]
+    pub invoice_export: Option<RateLimitValues>,
+    #[serde(
+        rename = "invoiceExportStatus",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub invoice_export_status: Option<RateLimitValues>,
+    #[serde(rename = "invoiceDownload", skip_serializing_if = "Option::is_none")]
+    pub invoice_download: Option<RateLimitValues>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other: Option<RateLimitValues>,
+}
+
+impl RateLimits {
+    /// Returns all endpoint groups with their API names
+    pub fn groups(&self) -> [(&'static str, Option<&RateLimitValues>); 12] {
+        [
+            ("onlineSession", self.online_session.as_ref()),
+            ("batchSession", self.batch_session.as_ref()),
+            ("invoiceSend", self.invoice_send.as_ref()),
+            ("invoiceStatus", self.invoice_status.as_ref()),
+            ("sessionList", self.session_list.as_ref()),
+            ("sessionInvoiceList", self.session_invoice_list.as_ref()),
+            ("sessionMisc", self.session_misc.as_ref()),
+            ("invoiceMetadata", self.invoice_metadata.as_ref()),
+            ("invoiceExport", self.invoice_export.as_ref()),
+            ("invoiceExportStatus", self.invoice_export_status.as_ref()),
+            ("invoiceDownload", self.invoice_download.as_ref()),
+            ("other", self.other.as_ref()),
+        ]
+    }
+}
+
+// Online sessions
+
+/// Request body for `/sessions/online`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenOnlineSessionRequest {
+    #[serde(rename = "formCode")]
+    pub form_code: FormCode,
+    pub encryption: EncryptionInfo,
+}
+
+/// Response of `/sessions/online`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenOnlineSessionResponse {
+    #[serde(rename = "referenceNumber")]
+    pub reference_number: String,
+    #[serde(rename = "validUntil")]
+    pub valid_until: String,
+}
+
+/// Request body for `/sessions/online/{referenceNumber}/invoices`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendInvoiceRequest {
+    #[serde(rename = "invoiceHash")]
+    pub invoice_hash: String,
+    #[serde(rename = "invoiceSize")]
+    pub invoice_size: u64,
+    #[serde(rename = "encryptedInvoiceHash")]
+    pub encrypted_invoice_hash: String,
+    #[serde(rename = "encryptedInvoiceSize")]
+    pub encrypted_invoice_size: u64,
+    #[serde(rename = "encryptedInvoiceContent")]
+    pub encrypted_invoice_content: String,
+    #[serde(rename = "offlineMode", default)]
+    pub offline_mode: bool,
+    #[serde(
+        rename = "hashOfCorrectedInvoice",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub hash_of_corrected_invoice: Option<String>,
+}
+
+// Sessions
+
+/// Session summary as listed by `/sessions`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    #[serde(rename = "referenceNumber")]
+    pub reference_number: String,
+    pub status: StatusInfo,
+    #[serde(rename = "dateCreated")]
+    pub date_created: String,
+    #[serde(rename = "dateUpdated")]
+    pub date_updated: String,
+    #[serde(rename = "validUntil", skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<String>,
+    #[serde(rename = "totalInvoiceCount", default)]
+    pub total_invoice_count: i64,
+    #[serde(rename = "successfulInvoiceCount", default)]
+    pub successful_invoice_count: i64,
+    #[serde(rename = "failedInvoiceCount", default)]
+    pub failed_invoice_count: i64,
+}
+
+/// Page of sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionsPage {
+    #[serde(rename = "continuationToken", skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
+    #[serde(default)]
+    pub sessions: Vec<SessionSummary>,
+}
+
+/// Reference to a collective UPO page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpoPageReference {
+    #[serde(rename = "referenceNumber")]
+    pub reference_number: String,
+    #[serde(rename = "downloadUrl")]
+    pub download_url: String,
+    #[serde(
+        rename = "downloadUrlExpirationDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub download_url_expiration_date: Option<String>,
+}
+
+/// Collective UPO of a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionUpo {
+    #[serde(default)]
+    pub pages: Vec<UpoPageReference>,
+}
+
+/// Status of an online or batch session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStatusResponse {
+    pub status: StatusInfo,
+    #[serde(rename = "dateCreated", skip_serializing_if = "Option::is_none")]
+    pub date_created: Option<String>,
+    #[serde(rename = "dateUpdated", skip_serializing_if = "Option::is_none")]
+    pub date_updated: Option<String>,
+    #[serde(rename = "validUntil", skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upo: Option<SessionUpo>,
+    #[serde(rename = "invoiceCount", skip_serializing_if = "Option::is_none")]
+    pub invoice_count: Option<i64>,
+    #[serde(
+        rename = "successfulInvoiceCount",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub successful_invoice_count: Option<i64>,
+    #[serde(rename = "failedInvoiceCount", skip_serializing_if = "Option::is_none")]
+    pub failed_invoice_count: Option<i64>,
+}
+
+/// Processing status of a single invoice within a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInvoiceStatus {
+    #[serde(rename = "ordinalNumber")]
+    pub ordinal_number: i64,
+    #[serde(rename = "referenceNumber")]
+    pub reference_number: String,
+    #[serde(rename = "invoiceNumber", skip_serializing_if = "Option::is_none")]
+    pub invoice_number: Option<String>,
+    #[serde(rename = "ksefNumber", skip_serializing_if = "Option::is_none")]
+    pub ksef_number: Option<String>,
+    #[serde(rename = "invoiceHash")]
+    pub invoice_hash: String,
+    #[serde(rename = "invoiceFileName", skip_serializing_if = "Option::is_none")]
+    pub invoice_file_name: Option<String>,
+    #[serde(rename = "invoicingDate", skip_serializing_if = "Option::is_none")]
+    pub invoicing_date: Option<String>,
+    #[serde(rename = "acquisitionDate", skip_serializing_if = "Option::is_none")]
+    pub acquisition_date: Option<String>,
+    #[serde(
+        rename = "permanentStorageDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub permanent_storage_date: Option<String>,
+    #[serde(rename = "upoDownloadUrl", skip_serializing_if = "Option::is_none")]
+    pub upo_download_url: Option<String>,
+    #[serde(rename = "invoicingMode", skip_serializing_if = "Option::is_none")]
+    pub invoicing_mode: Option<String>,
+    pub status: StatusInfo,
+}
+
+/// Page of invoices within a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInvoicesPage {
+    #[serde(rename = "continuationToken", skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
+    #[serde(default)]
+    pub invoices: Vec<SessionInvoiceStatus>,
+}
+
+// Batch sessions
+
+/// Single part of a batch file, described before upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchFilePart {
+    #[serde(rename = "ordinalNumber")]
+    pub ordinal_number: i32,
+    #[serde(rename = "fileSize")]
+    pub file_size: u64,
+    #[serde(rename = "fileHash")]
+    pub file_hash: String,
+}
+
+/// Batch ZIP file description
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchFileInfo {
+    #[serde(rename = "fileSize")]
+    pub file_size: u64,
+    #[serde(rename = "fileHash")]
+    pub file_hash: String,
+    #[serde(rename = "fileParts")]
+    pub file_parts: Vec<BatchFilePart>,
+}
+
+/// Request body for `/sessions/batch`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenBatchSessionRequest {
+    #[serde(rename = "formCode")]
+    pub form_code: FormCode,
+    #[serde(rename = "batchFile")]
+    pub batch_file: BatchFileInfo,
+    pub encryption: EncryptionInfo,
+    #[serde(rename = "offlineMode", default)]
+    pub offline_mode: bool,
+}
+
+/// Pre-signed upload target for a batch part
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartUploadRequest {
+    #[serde(rename = "ordinalNumber")]
+    pub ordinal_number: i32,
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Response of `/sessions/batch`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenBatchSessionResponse {
+    #[serde(rename = "referenceNumber")]
+    pub reference_number: String,
+    #[serde(rename = "partUploadRequests", default)]
+    pub part_upload_requests: Vec<PartUploadRequest>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_session_status() {
+        let json = r#"{
+            "status": {"code": 200, "description": "Sesja przetworzona pomyślnie"},
+            "dateCreated": "2025-10-01T10:00:00+00:00",
+            "dateUpdated": "2025-10-01T10:05:00+00:00",
+            "upo": {"pages": [{"referenceNumber": "20251001-EU-UPO", "downloadUrl": "https://example.com/upo"}]},
+            "invoiceCount": 2,
+            "successfulInvoiceCount": 1,
+            "failedInvoiceCount": 1
+        }"#;
+
+        let status: SessionStatusResponse = serde_json::from_str(json).unwrap();
+
+        assert_eq!(status.status.code, 200);
+        assert!(status.status.details.is_empty());
+        assert_eq!(status.invoice_count, Some(2));
+        assert_eq!(
+            status.upo.unwrap().pages[0].reference_number,
+            "20251001-EU-UPO"
+        );
+    }
+
+    #[test]
+    fn test_parse_rate_limits_with_missing_groups() {
+        let json = r#"{
+            "onlineSession": {"perSecond": 10, "perMinute": 30, "perHour": 120},
+            "other": {"perSecond": 10, "perMinute": 100, "perHour": 1000}
+        }"#;
+
+        let limits: RateLimits = serde_json::from_str(json).unwrap();
+
+        assert_eq!(limits.online_session.unwrap().per_minute, 30);
+        assert!(limits.invoice_send.is_none());
+        assert_eq!(
+            limits.groups().iter().filter(|(_, v)| v.is_some()).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_parse_invoice_metadata_page() {
+        let json = r#"{
+            "hasMore": true,
+            "isTruncated": false,
+            "invoices": [{
+                "ksefNumber": "5265877635-20250826-0100001AF629-AF",
+                "invoiceNumber": "FA/1/2025",
+                "issueDate": "2025-08-26",
+                "invoicingDate": "2025-08-26T12:00:00+00:00",
+                "seller": {"nip": "5265877635", "name": "Seller"},
+                "buyer": {"identifier": {"type": "Nip", "value": "7352765225"}},
+                "netAmount": 100.0,
+                "grossAmount": 123.0,
+                "vatAmount": 23.0,
+                "currency": "PLN"
+            }]
+        }"#;
+
+        let page: InvoiceMetadataPage = serde_json::from_str(json).unwrap();
+
+        assert!(page.has_more);
+        assert_eq!(page.invoices.len(), 1);
+        assert_eq!(
+            page.invoices[0].buyer.identifier.value.as_deref(),
+            Some("7352765225")
+        );
+        assert_eq!(page.invoices[0].gross_amount, 123.0);
+    }
+
+    #[test]
+    fn test_serialize_query_filters_skips_empty() {
+        let filters = InvoiceQueryFilters {
+            subject_type: SubjectType::Subject2,
+            date_range: DateRange {
+                date_type: DateType::PermanentStorage,
+                from: "2025-08-01T00:00:00Z".to_string(),
+                to: None,
+            },
+            ksef_number: None,
+            invoice_number: None,
+            seller_nip: None,
+            buyer_identifier: None,
+            currency_codes: None,
+            invoice_types: None,
+        };
+
+        let value = serde_json::to_value(&filters).unwrap();
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "subjectType": "Subject2",
+                "dateRange": {"dateType": "PermanentStorage", "from": "2025-08-01T00:00:00Z"}
+            })
+        );
+    }
+}
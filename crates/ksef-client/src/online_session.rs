@@ -0,0 +1,172 @@
+//! Handle for sending invoices in a single online session.
+
+use crate::{FormCode, KsefClient, Result, SessionStatusResponse, SymmetricKey};
+
+/// Reference of an invoice accepted for processing in an online session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvoiceReference {
+    /// Reference number assigned by KSeF
+    pub reference_number: String,
+    /// Base64 SHA-256 hash of the invoice document
+    pub invoice_hash: String,
+}
+
+/// Open online session with its encryption key.
+///
+/// Created by [`KsefClient::online_session`]. The session is closed by [`close`](Self::close);
+/// a handle dropped without closing sends the close request in the background (best effort).
+pub struct OnlineSession<'a> {
+    client: &'a KsefClient,
+    reference_number: String,
+    valid_until: String,
+    form_code: FormCode,
+    key: SymmetricKey,
+    closed: bool,
+}
+
+impl<'a> OnlineSession<'a> {
+    pub(crate) fn new(
+        client: &'a KsefClient,
+        reference_number: String,
+        valid_until: String,
+        form_code: FormCode,
+        key: SymmetricKey,
+    ) -> Self {
+        Self {
+            client,
+            reference_number,
+            valid_until,
+            form_code,
+            key,
+            closed: false,
+        }
+    }
+
+    pub fn reference_number(&self) -> &str {
+        &self.reference_number
+    }
+
+    pub fn valid_until(&self) -> &str {
+        &self.valid_until
+    }
+
+    pub fn form_code(&self) -> &FormCode {
+        &self.form_code
+    }
+
+    /// Encrypts and sends an invoice document
+    pub async fn send_invoice(&self, invoice_xml: &str) -> Result<InvoiceReference> {
+        let request = self.key.encrypt_invoice(invoice_xml, false, None)?;
+        let invoice_hash = request.invoice_hash.clone();
+        let response = self
+            .client
+            .submit_invoice(&self.reference_number, &request)
+            .await?;
+
+        Ok(InvoiceReference {
+            reference_number: response.reference_number,
+            invoice_hash,
+        })
+    }
+
+    /// Current processing status of the session
+    pub async fn status(&self) -> Result<SessionStatusResponse> {
+        self.client.get_session_status(&self.reference_number).await
+    }
+
+    /// Closes the session, starting UPO generation
+    pub async fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.client
+            .close_online_session(&self.reference_number)
+            .await
+    }
+}
+
+impl Drop for OnlineSession<'_> {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            eprintln!(
+                "Online session {} dropped outside of a Tokio runtime, not closed",
+                self.reference_number
+            );
+            return;
+        };
+
+        let request = self
+            .client
+            .close_online_session_request(&self.reference_number);
+        let reference_number = std::mem::take(&mut self.reference_number);
+        runtime.spawn(async move {
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    eprintln!("Online session {} closed on drop", reference_number)
+                }
+                Ok(response) => eprintln!(
+                    "Failed to close online session {} on drop: HTTP {}",
+                    reference_number,
+                    response.status()
+                ),
+                Err(e) => eprintln!(
+                    "Failed to close online session {} on drop: {}",
+                    reference_number, e
+                ),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form_code() -> FormCode {
+        FormCode {
+            system_code: "FA (2)".to_string(),
+            schema_version: "1-0E".to_string(),
+            value: "FA".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_invoice_and_close_on_drop() {
+        let mut server = mockito::Server::new_async().await;
+        let send = server
+            .mock("POST", "/sessions/online/SO-1/invoices")
+            .with_status(202)
+            .with_body(r#"{"referenceNumber": "INV-1"}"#)
+            .create_async()
+            .await;
+        let close = server
+            .mock("POST", "/sessions/online/SO-1/close")
+            .with_status(204)
+            .create_async()
+            .await;
+
+        let client = KsefClient::with_base_url(server.url());
+        let session = OnlineSession::new(
+            &client,
+            "SO-1".to_string(),
+            "2025-10-01T12:00:00Z".to_string(),
+            form_code(),
+            SymmetricKey::generate(),
+        );
+
+        let reference = session.send_invoice("<Faktura/>").await.unwrap();
+        assert_eq!(reference.reference_number, "INV-1");
+        send.assert_async().await;
+
+        drop(session);
+        for _ in 0..50 {
+            if close.matched_async().await {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        close.assert_async().await;
+    }
+}
@@ -0,0 +1,61 @@
+//! Streams over paginated KSeF endpoints.
+
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use std::future::Future;
+
+use crate::Result;
+
+/// Turns a page fetcher into a stream of items.
+///
+/// `fetch` receives the cursor of the page to load (continuation token or
+/// page offset) and returns its items with the cursor of the next page, or
+/// `None` on the last page. Pages are requested lazily, so a `limit` stops
+/// fetching once enough items have been consumed.
+pub(crate) fn paginate<'a, C, T, F, Fut>(
+    first: C,
+    limit: Option<usize>,
+    fetch: F,
+) -> impl Stream<Item = Result<T>> + 'a
+where
+    C: 'a,
+    T: 'a,
+    F: Fn(C) -> Fut + 'a,
+    Fut: Future<Output = Result<(Vec<T>, Option<C>)>> + 'a,
+{
+    stream::try_unfold((Some(first), fetch), |(cursor, fetch)| async move {
+        let Some(cursor) = cursor else {
+            return Ok(None);
+        };
+        fetch(cursor)
+            .await
+            .map(|(items, next)| Some((stream::iter(items.into_iter().map(Ok)), (next, fetch))))
+    })
+    .try_flatten()
+    .take(limit.unwrap_or(usize::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_paginate_follows_cursor_and_stops_at_limit() {
+        let fetched = AtomicUsize::new(0);
+        let fetch = |offset: i64| {
+            fetched.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let items = vec![offset * 2, offset * 2 + 1];
+                Ok((items, (offset < 4).then_some(offset + 1)))
+            }
+        };
+
+        let all: Vec<i64> = paginate(0, None, fetch).try_collect().await.unwrap();
+        assert_eq!(all, (0..10).collect::<Vec<_>>());
+        assert_eq!(fetched.swap(0, Ordering::SeqCst), 5);
+
+        let first: Vec<i64> = paginate(0, Some(3), fetch).try_collect().await.unwrap();
+        assert_eq!(first, vec![0, 1, 2]);
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,101 @@
+//! Polling configuration for asynchronous KSeF operations (authentication, exports).
+
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::{KsefError, Result};
+
+/// Controls how `KsefClient` polls a status endpoint until an operation completes
+#[derive(Debug, Clone)]
+pub struct PollingOptions {
+    /// Delay before the second status check
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between checks
+    pub max_delay: Duration,
+    /// Multiplier applied to the delay after each pending check
+    pub backoff_factor: f64,
+    /// Total time after which polling gives up
+    pub timeout: Duration,
+    /// Optional token that aborts polling when cancelled
+    pub cancellation: Option<CancellationToken>,
+}
+
+/// Polling of `/auth/{referenceNumber}`
+pub type AuthPollingOptions = PollingOptions;
+
+impl Default for PollingOptions {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(5),
+            backoff_factor: 2.0,
+            timeout: Duration::from_secs(60),
+            cancellation: None,
+        }
+    }
+}
+
+impl PollingOptions {
+    /// Defaults suited to invoice exports, which may take several minutes
+    pub fn for_export() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(15),
+            timeout: Duration::from_secs(15 * 60),
+            ..Self::default()
+        }
+    }
+
+    /// Delay before check number `attempt + 1` (zero-based)
+    pub(crate) fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = self.backoff_factor.max(1.0).powi(attempt as i32);
+        self.initial_delay.mul_f64(factor).min(self.max_delay)
+    }
+
+    /// Sleeps before the next check, failing when the deadline would be
+    /// exceeded or the cancellation token fires. `what` names the operation
+    /// in error messages.
+    pub(crate) async fn wait(
+        &self,
+        attempt: u32,
+        deadline: tokio::time::Instant,
+        what: &str,
+    ) -> Result<()> {
+        let delay = self.delay_for(attempt);
+        if tokio::time::Instant::now() + delay > deadline {
+            return Err(KsefError::Timeout(format!(
+                "{} still in progress after {}s",
+                what,
+                self.timeout.as_secs()
+            )));
+        }
+
+        match self.cancellation {
+            Some(ref token) => tokio::select! {
+                _ = token.cancelled() => {
+                    Err(KsefError::Cancelled(format!("{} polling cancelled", what)))
+                }
+                _ = tokio::time::sleep(delay) => Ok(()),
+            },
+            None => {
+                tokio::time::sleep(delay).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let options = PollingOptions::default();
+
+        assert_eq!(options.delay_for(0), Duration::from_millis(500));
+        assert_eq!(options.delay_for(1), Duration::from_secs(1));
+        assert_eq!(options.delay_for(2), Duration::from_secs(2));
+        assert_eq!(options.delay_for(10), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,320 @@
+//! Client-side throttling based on the limits published by `/rate-limits`.
+
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{RateLimitValues, RateLimits};
+
+/// Retries of a request answered with 429 before the error is returned
+pub(crate) const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+
+/// Delay used when a 429 response carries no Retry-After header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Upper bound of the random delay added to every 429 retry
+const MAX_RETRY_JITTER: Duration = Duration::from_millis(500);
+
+/// Endpoint group sharing one KSeF rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EndpointGroup {
+    OnlineSession,
+    BatchSession,
+    InvoiceSend,
+    InvoiceStatus,
+    SessionList,
+    SessionInvoiceList,
+    SessionMisc,
+    InvoiceMetadata,
+    InvoiceExport,
+    InvoiceExportStatus,
+    InvoiceDownload,
+    Other,
+}
+
+impl EndpointGroup {
+    pub const ALL: [EndpointGroup; 12] = [
+        EndpointGroup::OnlineSession,
+        EndpointGroup::BatchSession,
+        EndpointGroup::InvoiceSend,
+        EndpointGroup::InvoiceStatus,
+        EndpointGroup::SessionList,
+        EndpointGroup::SessionInvoiceList,
+        EndpointGroup::SessionMisc,
+        EndpointGroup::InvoiceMetadata,
+        EndpointGroup::InvoiceExport,
+        EndpointGroup::InvoiceExportStatus,
+        EndpointGroup::InvoiceDownload,
+        EndpointGroup::Other,
+    ];
+
+    /// Group name as used by `/rate-limits`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EndpointGroup::OnlineSession => "onlineSession",
+            EndpointGroup::BatchSession => "batchSession",
+            EndpointGroup::InvoiceSend => "invoiceSend",
+            EndpointGroup::InvoiceStatus => "invoiceStatus",
+            EndpointGroup::SessionList => "sessionList",
+            EndpointGroup::SessionInvoiceList => "sessionInvoiceList",
+            EndpointGroup::SessionMisc => "sessionMisc",
+            EndpointGroup::InvoiceMetadata => "invoiceMetadata",
+            EndpointGroup::InvoiceExport => "invoiceExport",
+            EndpointGroup::InvoiceExportStatus => "invoiceExportStatus",
+            EndpointGroup::InvoiceDownload => "invoiceDownload",
+            EndpointGroup::Other => "other",
+        }
+    }
+
+    fn limits<'a>(&self, limits: &'a RateLimits) -> Option<&'a RateLimitValues> {
+        match self {
+            EndpointGroup::OnlineSession => limits.online_session.as_ref(),
+            EndpointGroup::BatchSession => limits.batch_session.as_ref(),
+            EndpointGroup::InvoiceSend => limits.invoice_send.as_ref(),
+            EndpointGroup::InvoiceStatus => limits.invoice_status.as_ref(),
+            EndpointGroup::SessionList => limits.session_list.as_ref(),
+            EndpointGroup::SessionInvoiceList => limits.session_invoice_list.as_ref(),
+            EndpointGroup::SessionMisc => limits.session_misc.as_ref(),
+            EndpointGroup::InvoiceMetadata => limits.invoice_metadata.as_ref(),
+            EndpointGroup::InvoiceExport => limits.invoice_export.as_ref(),
+            EndpointGroup::InvoiceExportStatus => limits.invoice_export_status.as_ref(),
+            EndpointGroup::InvoiceDownload => limits.invoice_download.as_ref(),
+            EndpointGroup::Other => limits.other.as_ref(),
+        }
+    }
+}
+
+/// Token bucket refilled continuously at `capacity / window`
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, window: Duration, now: Instant) -> Self {
+        let capacity = capacity as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec: capacity / window.as_secs_f64(),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until one token is available
+    fn wait_time(&self) -> Duration {
+        if self.tokens >= 1.0 || self.refill_per_sec <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_sec)
+        }
+    }
+}
+
+/// Per-second, per-minute and per-hour buckets of one endpoint group
+#[derive(Debug, Clone)]
+struct GroupState {
+    limits: RateLimitValues,
+    buckets: [TokenBucket; 3],
+    throttled: u64,
+    rate_limited: u64,
+}
+
+impl GroupState {
+    fn new(limits: RateLimitValues, now: Instant) -> Self {
+        let buckets = [
+            TokenBucket::new(limits.per_second, Duration::from_secs(1), now),
+            TokenBucket::new(limits.per_minute, Duration::from_secs(60), now),
+            TokenBucket::new(limits.per_hour, Duration::from_secs(3600), now),
+        ];
+        Self {
+            limits,
+            buckets,
+            throttled: 0,
+            rate_limited: 0,
+        }
+    }
+
+    /// Takes a token from every bucket, or returns how long to wait for one
+    fn try_acquire(&mut self, now: Instant) -> Option<Duration> {
+        self.buckets.iter_mut().for_each(|b| b.refill(now));
+        let wait = self
+            .buckets
+            .iter()
+            .map(|b| b.wait_time())
+            .max()
+            .unwrap_or_default();
+
+        if wait.is_zero() {
+            self.buckets.iter_mut().for_each(|b| b.tokens -= 1.0);
+            None
+        } else {
+            Some(wait)
+        }
+    }
+}
+
+/// Remaining request budget of an endpoint group
+#[derive(Debug, Clone)]
+pub struct RateLimitBudget {
+    pub group: EndpointGroup,
+    pub limits: RateLimitValues,
+    pub available_per_second: u32,
+    pub available_per_minute: u32,
+    pub available_per_hour: u32,
+    /// Requests delayed locally to stay within the limits
+    pub throttled_requests: u64,
+    /// Responses with status 429
+    pub rate_limited_responses: u64,
+}
+
+/// Token buckets for every endpoint group; unlimited until limits are loaded
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    groups: Mutex<HashMap<EndpointGroup, GroupState>>,
+}
+
+impl RateLimiter {
+    /// Replaces the limits, starting every group with a full budget; missing groups are unlimited
+    pub(crate) fn apply(&self, limits: &RateLimits) {
+        let now = Instant::now();
+        let mut groups = self.groups.lock().unwrap();
+        for group in EndpointGroup::ALL {
+            let Some(values) = group.limits(limits) else {
+                groups.remove(&group);
+                continue;
+            };
+            let previous = groups.get(&group).map(|s| (s.throttled, s.rate_limited));
+            let mut state = GroupState::new(*values, now);
+            if let Some((throttled, rate_limited)) = previous {
+                state.throttled = throttled;
+                state.rate_limited = rate_limited;
+            }
+            groups.insert(group, state);
+        }
+    }
+
+    pub(crate) fn is_loaded(&self) -> bool {
+        !self.groups.lock().unwrap().is_empty()
+    }
+
+    /// Waits until a request of the group fits within its limits
+    pub(crate) async fn acquire(&self, group: EndpointGroup) {
+        let mut counted = false;
+        loop {
+            let wait = {
+                let mut groups = self.groups.lock().unwrap();
+                let Some(state) = groups.get_mut(&group) else {
+                    return;
+                };
+                match state.try_acquire(Instant::now()) {
+                    None => return,
+                    Some(wait) => {
+                        if !counted {
+                            state.throttled += 1;
+                            counted = true;
+                        }
+                        wait
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Records a 429 response and returns the delay before retrying
+    pub(crate) fn on_rate_limited(
+        &self,
+        group: EndpointGroup,
+        retry_after: Option<Duration>,
+    ) -> Duration {
+        if let Some(state) = self.groups.lock().unwrap().get_mut(&group) {
+            state.rate_limited += 1;
+        }
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=MAX_RETRY_JITTER);
+        retry_after.unwrap_or(DEFAULT_RETRY_AFTER) + jitter
+    }
+
+    pub(crate) fn budget(&self) -> Vec<RateLimitBudget> {
+        let now = Instant::now();
+        let mut groups = self.groups.lock().unwrap();
+        EndpointGroup::ALL
+            .iter()
+            .filter_map(|group| {
+                let state = groups.get_mut(group)?;
+                state.buckets.iter_mut().for_each(|b| b.refill(now));
+                let available = |i: usize| state.buckets[i].tokens.floor().max(0.0) as u32;
+                Some(RateLimitBudget {
+                    group: *group,
+                    limits: state.limits,
+                    available_per_second: available(0),
+                    available_per_minute: available(1),
+                    available_per_hour: available(2),
+                    throttled_requests: state.throttled,
+                    rate_limited_responses: state.rate_limited,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Parses a Retry-After header given in seconds
+pub(crate) fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_waits_for_refill() {
+        let start = Instant::now();
+        let mut state = GroupState::new(
+            RateLimitValues {
+                per_second: 2,
+                per_minute: 60,
+                per_hour: 3600,
+            },
+            start,
+        );
+
+        assert_eq!(state.try_acquire(start), None);
+        assert_eq!(state.try_acquire(start), None);
+        let wait = state.try_acquire(start).unwrap();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+
+        assert_eq!(state.try_acquire(start + Duration::from_millis(500)), None);
+    }
+
+    #[test]
+    fn test_hourly_limit_dominates() {
+        let start = Instant::now();
+        let mut state = GroupState::new(
+            RateLimitValues {
+                per_second: 10,
+                per_minute: 100,
+                per_hour: 1,
+            },
+            start,
+        );
+
+        assert_eq!(state.try_acquire(start), None);
+        let wait = state.try_acquire(start + Duration::from_secs(1)).unwrap();
+        assert!(wait > Duration::from_secs(3500));
+    }
+}
@@ -0,0 +1,451 @@
+//! Incremental invoice synchronisation based on the `PermanentStorage` date.
+//!
+//! For every authentication context and subject type the store keeps a
+//! high-water mark: the storage date up to which all invoices have been
+//! downloaded. Invoices stored at or after the mark are remembered by KSeF
+//! number, so the overlap of consecutive runs is not downloaded twice.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    parse_valid_until, DateRange, DateType, InvoiceMetadata, InvoiceMetadataPage,
+    InvoiceQueryFilters, KsefError, Result, SubjectType,
+};
+
+/// Name of the state file inside the sync directory
+pub const SYNC_STATE_FILE: &str = "sync-state.json";
+
+/// Longest date range accepted by `/invoices/query/metadata`
+pub(crate) const MAX_QUERY_WINDOW_DAYS: i64 = 90;
+
+/// Progress of one context and subject type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncCursor {
+    /// Context identifier value (usually the NIP)
+    pub context: String,
+    #[serde(rename = "subjectType")]
+    pub subject_type: SubjectType,
+    /// All invoices stored up to this date have been downloaded
+    #[serde(rename = "highWaterMark", skip_serializing_if = "Option::is_none")]
+    pub high_water_mark: Option<String>,
+    /// KSeF numbers stored at or after the high-water mark, with their storage dates
+    #[serde(default)]
+    pub recent: BTreeMap<String, String>,
+    #[serde(rename = "lastSync", skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<String>,
+    #[serde(rename = "syncedInvoices", default)]
+    pub synced_invoices: u64,
+}
+
+impl SyncCursor {
+    pub(crate) fn new(context: &str, subject_type: SubjectType) -> Self {
+        Self {
+            context: context.to_string(),
+            subject_type,
+            high_water_mark: None,
+            recent: BTreeMap::new(),
+            last_sync: None,
+            synced_invoices: 0,
+        }
+    }
+}
+
+/// Persisted state of all cursors
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    #[serde(default)]
+    pub cursors: BTreeMap<String, SyncCursor>,
+}
+
+impl SyncState {
+    pub(crate) fn key(context: &str, subject_type: SubjectType) -> String {
+        format!("{}/{}", context, subject_type.as_str())
+    }
+
+    pub fn cursor(&self, context: &str, subject_type: SubjectType) -> Option<&SyncCursor> {
+        self.cursors.get(&Self::key(context, subject_type))
+    }
+}
+
+/// Directory holding the sync state and the downloaded invoices
+/// (`{dir}/{context}/{subjectType}/{ksefNumber}.xml`)
+#[derive(Debug, Clone)]
+pub struct SyncStore {
+    dir: PathBuf,
+}
+
+impl SyncStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Loads the state, or an empty one if nothing has been synced yet
+    pub fn load_state(&self) -> Result<SyncState> {
+        let path = self.dir.join(SYNC_STATE_FILE);
+        if !path.exists() {
+            return Ok(SyncState::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&content).map_err(|e| {
+            KsefError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid sync state {}: {}", path.display(), e),
+            ))
+        })
+    }
+
+    /// Writes the state through a temporary file so a crash cannot corrupt it
+    pub fn save_state(&self, state: &SyncState) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let content = serde_json::to_string_pretty(state)
+            .map_err(|e| KsefError::Io(std::io::Error::other(e)))?;
+        let tmp = self.dir.join(format!("{}.tmp", SYNC_STATE_FILE));
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, self.dir.join(SYNC_STATE_FILE))?;
+        Ok(())
+    }
+
+    pub fn invoice_path(
+        &self,
+        context: &str,
+        subject_type: SubjectType,
+        ksef_number: &str,
+    ) -> PathBuf {
+        self.dir
+            .join(context)
+            .join(subject_type.as_str())
+            .join(format!("{}.xml", ksef_number))
+    }
+
+    pub(crate) fn save_invoice(
+        &self,
+        context: &str,
+        subject_type: SubjectType,
+        ksef_number: &str,
+        xml: &str,
+    ) -> Result<()> {
+        let path = self.invoice_path(context, subject_type, ksef_number);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, xml)?;
+        Ok(())
+    }
+}
+
+/// Parameters of a sync run
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Start date used when the cursor has no high-water mark yet (default: 90 days ago)
+    pub initial_from: Option<DateTime<Utc>>,
+    pub page_size: i64,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            initial_from: None,
+            page_size: 100,
+        }
+    }
+}
+
+/// Outcome of a sync run
+#[derive(Debug, Clone)]
+pub struct SyncReport {
+    pub context: String,
+    pub subject_type: SubjectType,
+    /// Metadata of the invoices downloaded in this run
+    pub new_invoices: Vec<InvoiceMetadata>,
+    /// Invoices skipped because they had already been downloaded
+    pub duplicates: usize,
+    pub high_water_mark: Option<String>,
+}
+
+/// Tracks the invoices seen in a run and advances the cursor
+pub(crate) struct SyncProgress {
+    pub cursor: SyncCursor,
+    max_seen: Option<DateTime<Utc>>,
+}
+
+impl SyncProgress {
+    pub(crate) fn new(cursor: SyncCursor) -> Self {
+        Self {
+            cursor,
+            max_seen: None,
+        }
+    }
+
+    pub(crate) fn is_known(&self, ksef_number: &str) -> bool {
+        self.cursor.recent.contains_key(ksef_number)
+    }
+
+    pub(crate) fn record(&mut self, ksef_number: &str, storage_date: Option<&str>) {
+        let Some(date) = storage_date else {
+            return;
+        };
+        self.cursor
+            .recent
+            .insert(ksef_number.to_string(), date.to_string());
+        if let Some(parsed) = parse_valid_until(date) {
+            self.max_seen = self.max_seen.max(Some(parsed));
+        }
+    }
+
+    /// Moves the high-water mark to the newest storage date seen, but not past
+    /// the date up to which KSeF guarantees completeness, and forgets invoices
+    /// stored before the new mark
+    pub(crate) fn advance(&mut self, server_hwm: Option<&str>) {
+        let Some(mut mark) = self.max_seen else {
+            return;
+        };
+        if let Some(server_hwm) = server_hwm.and_then(parse_valid_until) {
+            mark = mark.min(server_hwm);
+        }
+        match self
+            .cursor
+            .high_water_mark
+            .as_deref()
+            .and_then(parse_valid_until)
+        {
+            Some(current) if current >= mark => mark = current,
+            _ => self.cursor.high_water_mark = Some(mark.to_rfc3339()),
+        }
+
+        self.cursor
+            .recent
+            .retain(|_, date| parse_valid_until(date).is_none_or(|date| date >= mark));
+    }
+}
+
+pub(crate) fn sync_filters(
+    subject_type: SubjectType,
+    from: DateTime<Utc>,
+    to: Option<DateTime<Utc>>,
+) -> InvoiceQueryFilters {
+    InvoiceQueryFilters {
+        subject_type,
+        date_range: DateRange {
+            date_type: DateType::PermanentStorage,
+            from: from.to_rfc3339(),
+            to: to.map(|t| t.to_rfc3339()),
+        },
+        ksef_number: None,
+        invoice_number: None,
+        seller_nip: None,
+        buyer_identifier: None,
+        currency_codes: None,
+        invoice_types: None,
+    }
+}
+
+/// Position in `invoice_metadata_stream`
+pub(crate) enum MetadataCursor {
+    /// Page `offset` of the query, restarted from `from` after a truncated result;
+    /// `seen` holds the KSeF numbers returned so far, as restarts overlap
+    Page {
+        from: Option<DateTime<Utc>>,
+        offset: i64,
+        seen: HashSet<String>,
+    },
+    /// The previous page was truncated and the query cannot be resumed
+    Truncated,
+}
+
+pub(crate) enum NextPage {
+    Offset,
+    /// Result was truncated: query again starting from this storage date
+    From(DateTime<Utc>),
+    Done,
+}
+
+pub(crate) fn next_page(page: &InvoiceMetadataPage, from: DateTime<Utc>) -> NextPage {
+    if page.is_truncated {
+        let last = page
+            .invoices
+            .iter()
+            .filter_map(|i| {
+                i.permanent_storage_date
+                    .as_deref()
+                    .and_then(parse_valid_until)
+            })
+            .max();
+        // Restarting from the same date would return the same pages again
+        if let Some(last) = last.filter(|last| *last > from) {
+            return NextPage::From(last);
+        }
+    }
+    if page.has_more {
+        NextPage::Offset
+    } else {
+        NextPage::Done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthContext, KsefClient};
+
+    fn metadata(ksef_number: &str, storage_date: &str) -> serde_json::Value {
+        serde_json::json!({
+            "ksefNumber": ksef_number,
+            "invoiceNumber": "FV/1",
+            "issueDate": "2025-10-01",
+            "invoicingDate": "2025-10-01T10:00:00Z",
+            "permanentStorageDate": storage_date,
+            "seller": {"nip": "5265877635"},
+            "buyer": {"identifier": {"type": "Nip", "value": "7352765225"}},
+            "netAmount": 100.0,
+            "grossAmount": 123.0,
+            "vatAmount": 23.0,
+            "currency": "PLN"
+        })
+    }
+
+    #[tokio::test]
+    async fn test_sync_skips_known_invoices() {
+        let hours_ago = |h: i64| (Utc::now() - chrono::Duration::hours(h)).to_rfc3339();
+        let newest = hours_ago(2);
+        let mut server = mockito::Server::new_async().await;
+        let page = serde_json::json!({
+            "hasMore": false,
+            "isTruncated": false,
+            "permanentStorageHwmDate": hours_ago(1),
+            "invoices": [
+                metadata("KSEF-1", &hours_ago(3)),
+                metadata("KSEF-2", &newest)
+            ]
+        });
+        server
+            .mock(
+                "POST",
+                mockito::Matcher::Regex("^/invoices/query/metadata".to_string()),
+            )
+            .with_body(page.to_string())
+            .create_async()
+            .await;
+        let downloads = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex("^/invoices/ksef/KSEF-".to_string()),
+            )
+            .with_body("<Faktura/>")
+            .expect(2)
+            .create_async()
+            .await;
+
+        let client = KsefClient::with_base_url(server.url());
+        client.set_test_session(AuthContext::Nip("5265877635".to_string()));
+        let dir = std::env::temp_dir().join(format!("ksef-sync-test-{}", std::process::id()));
+        let store = SyncStore::new(&dir);
+        let options = SyncOptions::default();
+
+        let first = client
+            .sync_invoices(&store, SubjectType::Subject2, &options)
+            .await
+            .unwrap();
+        let second = client
+            .sync_invoices(&store, SubjectType::Subject2, &options)
+            .await
+            .unwrap();
+
+        let downloaded: Vec<_> = first.new_invoices.iter().map(|i| &i.ksef_number).collect();
+        assert_eq!(downloaded, vec!["KSEF-1", "KSEF-2"]);
+        assert!(second.new_invoices.is_empty());
+        assert_eq!(second.duplicates, 2);
+        downloads.assert_async().await;
+
+        let state = store.load_state().unwrap();
+        let cursor = state.cursor("5265877635", SubjectType::Subject2).unwrap();
+        assert_eq!(
+            cursor
+                .high_water_mark
+                .as_deref()
+                .and_then(parse_valid_until),
+            parse_valid_until(&newest)
+        );
+        assert_eq!(cursor.recent.len(), 1);
+        assert_eq!(cursor.synced_invoices, 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metadata_stream_resumes_truncated_result() {
+        use futures::StreamExt;
+
+        let (start, restart) = ("2025-10-01T00:00:00+00:00", "2025-10-01T12:00:00+00:00");
+        let mut server = mockito::Server::new_async().await;
+        let pages = [
+            (
+                start,
+                true,
+                ["KSEF-1", "KSEF-2"],
+                ["2025-10-01T06:00:00Z", restart],
+            ),
+            (
+                restart,
+                false,
+                ["KSEF-2", "KSEF-3"],
+                [restart, "2025-10-02T06:00:00Z"],
+            ),
+        ];
+        for (from, truncated, numbers, dates) in pages {
+            let body = serde_json::json!({
+                "hasMore": false,
+                "isTruncated": truncated,
+                "invoices": [metadata(numbers[0], dates[0]), metadata(numbers[1], dates[1])]
+            });
+            server
+                .mock(
+                    "POST",
+                    mockito::Matcher::Regex("^/invoices/query/metadata".to_string()),
+                )
+                .match_body(mockito::Matcher::PartialJson(
+                    serde_json::json!({"dateRange": {"from": from}}),
+                ))
+                .with_body(body.to_string())
+                .create_async()
+                .await;
+        }
+
+        let client = KsefClient::with_base_url(server.url());
+        client.set_test_session(AuthContext::Nip("5265877635".to_string()));
+        let mut filters = sync_filters(
+            SubjectType::Subject2,
+            parse_valid_until(start).unwrap(),
+            None,
+        );
+        let numbers = |items: Vec<Result<InvoiceMetadata>>| -> Vec<String> {
+            items
+                .into_iter()
+                .filter_map(|i| i.ok().map(|i| i.ksef_number))
+                .collect()
+        };
+
+        // Storage dates: the query restarts from the last one, skipping the overlap
+        let items: Vec<_> = client
+            .invoice_metadata_stream(&filters, 10, None)
+            .collect()
+            .await;
+        assert!(items.iter().all(|i| i.is_ok()));
+        assert_eq!(numbers(items), vec!["KSEF-1", "KSEF-2", "KSEF-3"]);
+
+        // Other date types cannot be resumed: the stream ends with an error
+        filters.date_range.date_type = DateType::Issue;
+        let mut items: Vec<_> = client
+            .invoice_metadata_stream(&filters, 10, None)
+            .collect()
+            .await;
+        assert!(matches!(items.pop(), Some(Err(KsefError::Truncated(_)))));
+        assert_eq!(numbers(items), vec!["KSEF-1", "KSEF-2"]);
+    }
+}
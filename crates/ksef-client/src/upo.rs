@@ -0,0 +1,399 @@
+//! UPO (Urzędowe Poświadczenie Odbioru): the official receipt KSeF issues for
+//! accepted invoices, parsed into a typed model and checked against its
+//! XAdES signature, the certificates trusted to sign it and the invoice it confirms.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::xades::{self, SignatureInfo};
+use crate::{xml, KsefError, Result};
+
+/// Context the session was authenticated in (`Uwierzytelnienie/IdKontekstu`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpoContextIdentifier {
+    /// Identifier kind as named in the UPO (`Nip`, `IdWewn`, `IdZlozonyVatUE`, ...)
+    pub identifier_type: String,
+    pub value: String,
+}
+
+/// Confirmation of one invoice (`Dokument`)
+#[derive(Debug, Clone)]
+pub struct UpoDocument {
+    pub seller_nip: Option<String>,
+    pub ksef_number: Option<String>,
+    pub invoice_number: Option<String>,
+    pub issue_date: Option<String>,
+    /// When the invoice was received by KSeF
+    pub submitted_at: Option<String>,
+    /// When the KSeF number was assigned, i.e. the invoice was accepted
+    pub accepted_at: Option<String>,
+    /// SHA-256 (base64) of the invoice file
+    pub invoice_hash: Option<String>,
+    /// `Online`, `Wsadowy` or `Offline`
+    pub submission_mode: Option<String>,
+}
+
+/// Parsed UPO document
+#[derive(Debug, Clone)]
+pub struct Upo {
+    pub session_reference_number: Option<String>,
+    pub receiving_entity: Option<String>,
+    pub context_identifier: Option<UpoContextIdentifier>,
+    /// SHA-256 (base64) of the document used to authenticate the session
+    pub auth_document_hash: Option<String>,
+    pub schema_name: Option<String>,
+    pub form_code: Option<String>,
+    pub documents: Vec<UpoDocument>,
+}
+
+impl Upo {
+    /// Parses UPO XML (namespaces are ignored, so all UPO schema versions are accepted)
+    pub fn parse(xml: &str) -> Result<Self> {
+        Self::from_element(&xml::parse(xml)?)
+    }
+
+    fn from_element(root: &xml::Element) -> Result<Self> {
+        if root.local_name() != "Potwierdzenie" {
+            return Err(KsefError::InvalidResponse(format!(
+                "Not a UPO document (root element {})",
+                root.name
+            )));
+        }
+
+        let context_identifier = root
+            .path(&["Uwierzytelnienie", "IdKontekstu"])
+            .and_then(|context| context.elements().next())
+            .map(|identifier| UpoContextIdentifier {
+                identifier_type: identifier.local_name().to_string(),
+                value: identifier.text().trim().to_string(),
+            });
+
+        let documents = root
+            .elements()
+            .filter(|e| e.local_name() == "Dokument")
+            .map(|document| UpoDocument {
+                seller_nip: document.text_at(&["NipSprzedawcy"]),
+                ksef_number: document.text_at(&["NumerKSeFDokumentu"]),
+                invoice_number: document.text_at(&["NumerFaktury"]),
+                issue_date: document.text_at(&["DataWystawieniaFaktury"]),
+                submitted_at: document.text_at(&["DataPrzeslaniaDokumentu"]),
+                accepted_at: document.text_at(&["DataNadaniaNumeruKSeF"]),
+                invoice_hash: document.text_at(&["SkrotDokumentu"]),
+                submission_mode: document.text_at(&["TrybWysylki"]),
+            })
+            .collect();
+
+        Ok(Self {
+            session_reference_number: root.text_at(&["NumerReferencyjnySesji"]),
+            receiving_entity: root.text_at(&["NazwaPodmiotuPrzyjmujacego"]),
+            context_identifier,
+            auth_document_hash: root
+                .text_at(&["Uwierzytelnienie", "SkrotDokumentuUwierzytelniajacego"]),
+            schema_name: root.text_at(&["NazwaStrukturyLogicznej"]),
+            form_code: root.text_at(&["KodFormularza"]),
+            documents,
+        })
+    }
+
+    /// Confirmation of the invoice with the given KSeF number
+    pub fn document(&self, ksef_number: &str) -> Option<&UpoDocument> {
+        self.documents
+            .iter()
+            .find(|d| d.ksef_number.as_deref() == Some(ksef_number))
+    }
+}
+
+/// What a UPO is expected to confirm; unset fields are not checked
+#[derive(Debug, Clone, Default)]
+pub struct UpoExpectation<'a> {
+    pub ksef_number: Option<&'a str>,
+    /// Invoice XML exactly as sent, compared with `SkrotDokumentu`
+    pub invoice_xml: Option<&'a str>,
+    pub seller_nip: Option<&'a str>,
+    pub session_reference_number: Option<&'a str>,
+    /// DER certificates trusted to sign UPOs (the KSeF signing certificate or its CA);
+    /// without them no signer is trusted
+    pub trusted_certificates: &'a [Vec<u8>],
+}
+
+/// Outcome of `verify_upo`
+#[derive(Debug, Clone)]
+pub struct UpoVerification {
+    pub upo: Upo,
+    /// Signer of the UPO, or why its signature is invalid
+    pub signature: std::result::Result<SignatureInfo, String>,
+    /// Whether the signer chains to a trusted certificate, or why not
+    pub trusted_signer: std::result::Result<(), String>,
+    /// Index into `upo.documents` of the confirmation that was checked
+    pub document: Option<usize>,
+    /// Whether the invoice XML hashes to `SkrotDokumentu` (`None` if no XML was given)
+    pub invoice_hash_matches: Option<bool>,
+    /// Differences between the UPO and the expectation
+    pub mismatches: Vec<String>,
+}
+
+impl UpoVerification {
+    pub fn is_valid(&self) -> bool {
+        self.signature.is_ok() && self.trusted_signer.is_ok() && self.mismatches.is_empty()
+    }
+
+    pub fn checked_document(&self) -> Option<&UpoDocument> {
+        self.document.map(|i| &self.upo.documents[i])
+    }
+}
+
+/// Reads trusted certificates from PEM (any number of `CERTIFICATE` blocks) or a single DER
+pub fn parse_trusted_certificates(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if !data.trim_ascii_start().starts_with(b"-----") {
+        return Ok(vec![data.to_vec()]);
+    }
+    x509_parser::pem::Pem::iter_from_buffer(data)
+        .map(|pem| {
+            pem.map(|pem| pem.contents)
+                .map_err(|e| KsefError::Crypto(format!("Failed to parse certificate PEM: {}", e)))
+        })
+        .collect()
+}
+
+/// SHA-256 (base64) of an invoice file, as reported in `SkrotDokumentu`
+pub fn invoice_hash(xml: &str) -> String {
+    BASE64.encode(Sha256::digest(xml.as_bytes()))
+}
+
+/// Parses a UPO, verifies its signature and compares it with the expectation.
+/// Fails only if the XML is not a UPO; problems are reported in the result.
+pub fn verify_upo(upo_xml: &str, expected: &UpoExpectation) -> Result<UpoVerification> {
+    let root = xml::parse(upo_xml)?;
+    let upo = Upo::from_element(&root)?;
+    let signature = xades::verify_enveloped(&root).map_err(|e| e.to_string());
+    let trusted_signer = match &signature {
+        Ok(signer) => xades::verify_chain(&signer.certificates, expected.trusted_certificates)
+            .map_err(|e| e.to_string()),
+        Err(_) => Err("Signature is invalid".to_string()),
+    };
+
+    let mut mismatches = Vec::new();
+    let hash = expected.invoice_xml.map(invoice_hash);
+
+    let document = match (expected.ksef_number, hash.as_deref()) {
+        (Some(ksef_number), _) => {
+            let index = upo
+                .documents
+                .iter()
+                .position(|d| d.ksef_number.as_deref() == Some(ksef_number));
+            if index.is_none() {
+                mismatches.push(format!("UPO does not confirm invoice {}", ksef_number));
+            }
+            index
+        }
+        (None, Some(hash)) => {
+            let index = upo
+                .documents
+                .iter()
+                .position(|d| d.invoice_hash.as_deref() == Some(hash));
+            if index.is_none() {
+                mismatches.push(format!(
+                    "No document in the UPO has the invoice hash {}",
+                    hash
+                ));
+            }
+            index
+        }
+        (None, None) => (upo.documents.len() == 1).then_some(0),
+    };
+
+    let invoice_hash_matches = hash.as_deref().map(|hash| {
+        document.is_some_and(|i| upo.documents[i].invoice_hash.as_deref() == Some(hash))
+    });
+
+    if let Some(document) = document.map(|i| &upo.documents[i]) {
+        if let Some(hash) = hash.as_deref() {
+            if invoice_hash_matches == Some(false) {
+                mismatches.push(format!(
+                    "Invoice hash mismatch: UPO has {}, invoice XML hashes to {}",
+                    document.invoice_hash.as_deref().unwrap_or("none"),
+                    hash
+                ));
+            }
+        }
+        if let Some(nip) = expected.seller_nip {
+            if document.seller_nip.as_deref() != Some(nip) {
+                mismatches.push(format!(
+                    "Seller NIP mismatch: UPO has {}, expected {}",
+                    document.seller_nip.as_deref().unwrap_or("none"),
+                    nip
+                ));
+            }
+        }
+    }
+
+    if let Some(reference) = expected.session_reference_number {
+        if upo.session_reference_number.as_deref() != Some(reference) {
+            mismatches.push(format!(
+                "Session reference mismatch: UPO has {}, expected {}",
+                upo.session_reference_number.as_deref().unwrap_or("none"),
+                reference
+            ));
+        }
+    }
+
+    Ok(UpoVerification {
+        upo,
+        signature,
+        trusted_signer,
+        document,
+        invoice_hash_matches,
+        mismatches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CertificateCredentials, SigningKey};
+
+    const INVOICE: &str = "<Faktura><P_2>FV/2025/10/1</P_2></Faktura>";
+
+    fn upo_xml(invoice_hash: &str) -> String {
+        format!(
+            concat!(
+                r#"<Potwierdzenie xmlns="http://upo.schematy.mf.gov.pl/KSeF/v4-2">"#,
+                "<NazwaPodmiotuPrzyjmujacego>Ministerstwo Finansów</NazwaPodmiotuPrzyjmujacego>",
+                "<NumerReferencyjnySesji>20251010-SO-2F14610000-2E1D5EC2FA-4B</NumerReferencyjnySesji>",
+                "<Uwierzytelnienie><IdKontekstu><Nip>5265877635</Nip></IdKontekstu>",
+                "<SkrotDokumentuUwierzytelniajacego>abc=</SkrotDokumentuUwierzytelniajacego>",
+                "</Uwierzytelnienie>",
+                "<NazwaStrukturyLogicznej>schemat.xsd</NazwaStrukturyLogicznej>",
+                "<KodFormularza>FA (3)</KodFormularza>",
+                "<Dokument><NipSprzedawcy>5265877635</NipSprzedawcy>",
+                "<NumerKSeFDokumentu>5265877635-20251010-0100001D5F4A-1B</NumerKSeFDokumentu>",
+                "<NumerFaktury>FV/2025/10/1</NumerFaktury>",
+                "<DataWystawieniaFaktury>2025-10-10</DataWystawieniaFaktury>",
+                "<DataPrzeslaniaDokumentu>2025-10-10T09:15:00Z</DataPrzeslaniaDokumentu>",
+                "<DataNadaniaNumeruKSeF>2025-10-10T09:15:03Z</DataNadaniaNumeruKSeF>",
+                "<SkrotDokumentu>{hash}</SkrotDokumentu>",
+                "<TrybWysylki>Online</TrybWysylki></Dokument>",
+                "</Potwierdzenie>"
+            ),
+            hash = invoice_hash
+        )
+    }
+
+    /// Certificate named `name`, self-signed or issued by `issuer`
+    fn certificate(
+        name: &str,
+        ca: bool,
+        issuer: Option<&(rcgen::Certificate, rcgen::KeyPair)>,
+    ) -> (rcgen::Certificate, rcgen::KeyPair) {
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        if ca {
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        }
+        let cert = match issuer {
+            Some((issuer, issuer_key)) => params.signed_by(&key_pair, issuer, issuer_key),
+            None => params.self_signed(&key_pair),
+        };
+        (cert.unwrap(), key_pair)
+    }
+
+    fn sign(document: &str, (cert, key_pair): &(rcgen::Certificate, rcgen::KeyPair)) -> String {
+        let credentials = CertificateCredentials::new(
+            cert.der().to_vec(),
+            SigningKey::from_pem(&key_pair.serialize_pem()).unwrap(),
+        )
+        .unwrap();
+        xades::sign_enveloped(document, &credentials).unwrap()
+    }
+
+    #[test]
+    fn test_verify_upo() {
+        let signer = certificate("KSeF UPO", false, None);
+        let signed = sign(&upo_xml(&invoice_hash(INVOICE)), &signer);
+        let trusted = [signer.0.der().to_vec()];
+        let expected = UpoExpectation {
+            ksef_number: Some("5265877635-20251010-0100001D5F4A-1B"),
+            invoice_xml: Some(INVOICE),
+            trusted_certificates: &trusted,
+            ..Default::default()
+        };
+
+        let verification = verify_upo(&signed, &expected).unwrap();
+        assert!(verification.is_valid(), "{:?}", verification);
+        let upo = &verification.upo;
+        assert_eq!(upo.form_code.as_deref(), Some("FA (3)"));
+        assert_eq!(
+            upo.context_identifier,
+            Some(UpoContextIdentifier {
+                identifier_type: "Nip".to_string(),
+                value: "5265877635".to_string(),
+            })
+        );
+        let document = verification.checked_document().unwrap();
+        assert_eq!(
+            document.accepted_at.as_deref(),
+            Some("2025-10-10T09:15:03Z")
+        );
+        assert_eq!(document.invoice_number.as_deref(), Some("FV/2025/10/1"));
+
+        // A different invoice is reported, the signature still holds
+        let other = verify_upo(
+            &signed,
+            &UpoExpectation {
+                invoice_xml: Some("<Faktura/>"),
+                ..expected.clone()
+            },
+        )
+        .unwrap();
+        assert!(other.signature.is_ok());
+        assert_eq!(other.mismatches.len(), 1);
+        assert_eq!(other.invoice_hash_matches, Some(false));
+        assert!(other.mismatches[0].starts_with("Invoice hash mismatch"));
+
+        // Editing the UPO breaks the signature
+        let tampered = signed.replace("2025-10-10T09:15:03Z", "2025-10-09T09:15:03Z");
+        let tampered = verify_upo(&tampered, &expected).unwrap();
+        assert!(tampered.signature.is_err());
+        assert!(!tampered.is_valid());
+    }
+
+    #[test]
+    fn test_verify_upo_rejects_untrusted_signer() {
+        let root = certificate("KSeF Root CA", true, None);
+        let trusted = [root.0.der().to_vec()];
+        let xml = upo_xml(&invoice_hash(INVOICE));
+        let expected = UpoExpectation {
+            invoice_xml: Some(INVOICE),
+            trusted_certificates: &trusted,
+            ..Default::default()
+        };
+
+        let issued = sign(&xml, &certificate("KSeF UPO", false, Some(&root)));
+        let verification = verify_upo(&issued, &expected).unwrap();
+        assert!(verification.is_valid(), "{:?}", verification.trusted_signer);
+
+        // A forged UPO carries a valid signature, but by a self-signed certificate
+        let forged = sign(&xml, &certificate("KSeF UPO", false, None));
+        let verification = verify_upo(&forged, &expected).unwrap();
+        assert!(verification.signature.is_ok());
+        assert!(verification.mismatches.is_empty());
+        assert!(verification.trusted_signer.is_err());
+        assert!(!verification.is_valid());
+
+        // Without trusted certificates no signer is trusted
+        let untrusted = verify_upo(&issued, &UpoExpectation::default()).unwrap();
+        assert!(untrusted.trusted_signer.is_err());
+
+        let pem = format!(
+            "{}{}",
+            root.0.pem(),
+            certificate("Other", true, None).0.pem()
+        );
+        let certificates = parse_trusted_certificates(pem.as_bytes()).unwrap();
+        assert_eq!(certificates.len(), 2);
+        assert_eq!(certificates[0], trusted[0]);
+    }
+}
@@ -0,0 +1,735 @@
+//! Certificate-based authentication: building and XAdES-BES signing of the
+//! `AuthTokenRequest` document posted to `/auth/xades-signature`, and
+//! verification of the enveloped signatures KSeF puts on UPO documents.
+//!
+//! The document and signature are emitted directly in Exclusive XML
+//! Canonicalization form (no insignificant whitespace, explicit end tags,
+//! namespace declarations on every canonicalized apex), so the digested and
+//! signed bytes are exactly the bytes written into the document.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{SecondsFormat, Utc};
+use sha2::{Digest, Sha256};
+use x509_parser::certificate::X509Certificate;
+
+use crate::xml::{C14n, Element};
+use crate::{AuthContext, KsefError, Result};
+
+const AUTH_TOKEN_REQUEST_NS: &str = "http://ksef.mf.gov.pl/auth/token/2.0";
+const DS_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+const XADES_NS: &str = "http://uri.etsi.org/01903/v1.3.2#";
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const SHA256_DIGEST: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const SHA512_DIGEST: &str = "http://www.w3.org/2001/04/xmlenc#sha512";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const ECDSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256";
+const SIGNED_PROPERTIES_TYPE: &str = "http://uri.etsi.org/01903#SignedProperties";
+
+/// Private key used to produce the XAdES signature
+#[derive(Clone)]
+pub enum SigningKey {
+    Rsa(Box<rsa::RsaPrivateKey>),
+    EcdsaP256(p256::ecdsa::SigningKey),
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SigningKey::Rsa(_) => write!(f, "SigningKey::Rsa(..)"),
+            SigningKey::EcdsaP256(_) => write!(f, "SigningKey::EcdsaP256(..)"),
+        }
+    }
+}
+
+impl SigningKey {
+    /// Parses an unencrypted PKCS#8 DER private key (RSA or EC P-256)
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self> {
+        use rsa::pkcs8::DecodePrivateKey;
+
+        if let Ok(key) = rsa::RsaPrivateKey::from_pkcs8_der(der) {
+            return Ok(SigningKey::Rsa(Box::new(key)));
+        }
+        p256::ecdsa::SigningKey::from_pkcs8_der(der)
+            .map(SigningKey::EcdsaP256)
+            .map_err(|_| {
+                KsefError::Crypto(
+                    "Unsupported private key (expected RSA or EC P-256 in PKCS#8)".to_string(),
+                )
+            })
+    }
+
+    /// Parses a PEM private key (`PRIVATE KEY`, `RSA PRIVATE KEY` or `EC PRIVATE KEY`)
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        use rsa::pkcs1::DecodeRsaPrivateKey;
+
+        if pem.contains("BEGIN RSA PRIVATE KEY") {
+            return rsa::RsaPrivateKey::from_pkcs1_pem(pem)
+                .map(|k| SigningKey::Rsa(Box::new(k)))
+                .map_err(|e| KsefError::Crypto(format!("Failed to parse RSA private key: {}", e)));
+        }
+        if pem.contains("BEGIN EC PRIVATE KEY") {
+            return p256::SecretKey::from_sec1_pem(pem)
+                .map(|k| SigningKey::EcdsaP256(k.into()))
+                .map_err(|e| KsefError::Crypto(format!("Failed to parse EC private key: {}", e)));
+        }
+
+        let (_, doc) = p256::pkcs8::SecretDocument::from_pem(pem)
+            .map_err(|e| KsefError::Crypto(format!("Failed to parse private key PEM: {}", e)))?;
+        Self::from_pkcs8_der(doc.as_bytes())
+    }
+
+    fn signature_method(&self) -> &'static str {
+        match self {
+            SigningKey::Rsa(_) => RSA_SHA256,
+            SigningKey::EcdsaP256(_) => ECDSA_SHA256,
+        }
+    }
+
+    /// Signs `data` (RSASSA-PKCS1-v1_5 or raw r||s ECDSA, both with SHA-256)
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        use rsa::signature::{SignatureEncoding, Signer};
+
+        match self {
+            SigningKey::Rsa(key) => {
+                let signer = rsa::pkcs1v15::SigningKey::<Sha256>::new(key.as_ref().clone());
+                let signature = signer
+                    .try_sign(data)
+                    .map_err(|e| KsefError::Crypto(format!("RSA signing failed: {}", e)))?;
+                Ok(signature.to_vec())
+            }
+            SigningKey::EcdsaP256(key) => {
+                let signature: p256::ecdsa::Signature = key
+                    .try_sign(data)
+                    .map_err(|e| KsefError::Crypto(format!("ECDSA signing failed: {}", e)))?;
+                Ok(signature.to_bytes().to_vec())
+            }
+        }
+    }
+}
+
+/// Signing certificate with its private key
+#[derive(Debug, Clone)]
+pub struct CertificateCredentials {
+    certificate_der: Vec<u8>,
+    key: SigningKey,
+}
+
+impl CertificateCredentials {
+    pub fn new(certificate_der: Vec<u8>, key: SigningKey) -> Result<Self> {
+        // Fail early on malformed certificates
+        x509_parser::parse_x509_certificate(&certificate_der)
+            .map_err(|e| KsefError::Crypto(format!("Failed to parse X.509 certificate: {}", e)))?;
+        Ok(Self {
+            certificate_der,
+            key,
+        })
+    }
+
+    /// Loads credentials from a PEM certificate and a PEM private key
+    pub fn from_pem(certificate_pem: &str, private_key_pem: &str) -> Result<Self> {
+        let (_, pem) = x509_parser::pem::parse_x509_pem(certificate_pem.as_bytes())
+            .map_err(|e| KsefError::Crypto(format!("Failed to parse certificate PEM: {}", e)))?;
+        Self::new(pem.contents, SigningKey::from_pem(private_key_pem)?)
+    }
+
+    /// Loads the first private key and its certificate from a PKCS#12 (.p12/.pfx) archive
+    pub fn from_pkcs12(data: &[u8], password: &str) -> Result<Self> {
+        let keystore = p12_keystore::KeyStore::from_pkcs12(
+            data,
+            password,
+            p12_keystore::Pkcs12ImportPolicy::Relaxed,
+        )
+        .map_err(|e| KsefError::Crypto(format!("Failed to open PKCS#12 archive: {}", e)))?;
+
+        let (_, chain) = keystore.private_key_chain().ok_or_else(|| {
+            KsefError::Crypto("PKCS#12 archive contains no private key".to_string())
+        })?;
+        let certificate = chain.certs().first().ok_or_else(|| {
+            KsefError::Crypto("PKCS#12 archive contains no certificate for the key".to_string())
+        })?;
+
+        Self::new(
+            certificate.as_der().to_vec(),
+            SigningKey::from_pkcs8_der(chain.key().as_der())?,
+        )
+    }
+
+    pub fn certificate_der(&self) -> &[u8] {
+        &self.certificate_der
+    }
+
+    /// Subject of the signing certificate (for status output)
+    pub fn subject(&self) -> String {
+        x509_parser::parse_x509_certificate(&self.certificate_der)
+            .map(|(_, cert)| cert.subject().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Builds the unsigned `AuthTokenRequest` document for the given challenge and context
+pub fn build_auth_token_request(challenge: &str, context: &AuthContext) -> String {
+    format!(
+        r#"<AuthTokenRequest xmlns="{ns}"><Challenge>{challenge}</Challenge><ContextIdentifier><{element}>{value}</{element}></ContextIdentifier><SubjectIdentifierType>certificateSubject</SubjectIdentifierType></AuthTokenRequest>"#,
+        ns = AUTH_TOKEN_REQUEST_NS,
+        challenge = escape_text(challenge),
+        element = context.xml_element(),
+        value = escape_text(context.value())
+    )
+}
+
+/// Signs a document (already in canonical form) with an enveloped XAdES-BES signature
+pub fn sign_enveloped(document: &str, credentials: &CertificateCredentials) -> Result<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&credentials.certificate_der)
+        .map_err(|e| KsefError::Crypto(format!("Failed to parse X.509 certificate: {}", e)))?;
+
+    let root_end = document
+        .rfind("</")
+        .ok_or_else(|| KsefError::Validation("Document has no root end tag".to_string()))?;
+
+    let id = random_id();
+    let signature_id = format!("Signature-{}", id);
+    let signed_properties_id = format!("SignedProperties-{}", id);
+
+    let signed_properties = format!(
+        concat!(
+            r#"<xades:SignedProperties xmlns:xades="{xades}" Id="{props_id}">"#,
+            "<xades:SignedSignatureProperties>",
+            "<xades:SigningTime>{time}</xades:SigningTime>",
+            "<xades:SigningCertificate><xades:Cert><xades:CertDigest>",
+            r#"<ds:DigestMethod xmlns:ds="{ds}" Algorithm="{sha256}"></ds:DigestMethod>"#,
+            r#"<ds:DigestValue xmlns:ds="{ds}">{cert_digest}</ds:DigestValue>"#,
+            "</xades:CertDigest><xades:IssuerSerial>",
+            r#"<ds:X509IssuerName xmlns:ds="{ds}">{issuer}</ds:X509IssuerName>"#,
+            r#"<ds:X509SerialNumber xmlns:ds="{ds}">{serial}</ds:X509SerialNumber>"#,
+            "</xades:IssuerSerial></xades:Cert></xades:SigningCertificate>",
+            "</xades:SignedSignatureProperties>",
+            "</xades:SignedProperties>"
+        ),
+        xades = XADES_NS,
+        ds = DS_NS,
+        props_id = signed_properties_id,
+        time = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        sha256 = SHA256_DIGEST,
+        cert_digest = sha256_base64(&credentials.certificate_der),
+        issuer = escape_text(&rfc2253_name(cert.issuer())),
+        serial = cert.tbs_certificate.serial,
+    );
+
+    let signed_info = format!(
+        concat!(
+            r#"<ds:SignedInfo xmlns:ds="{ds}">"#,
+            r#"<ds:CanonicalizationMethod Algorithm="{c14n}"></ds:CanonicalizationMethod>"#,
+            r#"<ds:SignatureMethod Algorithm="{sig_method}"></ds:SignatureMethod>"#,
+            r#"<ds:Reference Id="Reference-{id}" URI="">"#,
+            "<ds:Transforms>",
+            r#"<ds:Transform Algorithm="{enveloped}"></ds:Transform>"#,
+            r#"<ds:Transform Algorithm="{c14n}"></ds:Transform>"#,
+            "</ds:Transforms>",
+            r#"<ds:DigestMethod Algorithm="{sha256}"></ds:DigestMethod>"#,
+            "<ds:DigestValue>{doc_digest}</ds:DigestValue>",
+            "</ds:Reference>",
+            r##"<ds:Reference Type="{props_type}" URI="#{props_id}">"##,
+            "<ds:Transforms>",
+            r#"<ds:Transform Algorithm="{c14n}"></ds:Transform>"#,
+            "</ds:Transforms>",
+            r#"<ds:DigestMethod Algorithm="{sha256}"></ds:DigestMethod>"#,
+            "<ds:DigestValue>{props_digest}</ds:DigestValue>",
+            "</ds:Reference>",
+            "</ds:SignedInfo>"
+        ),
+        ds = DS_NS,
+        c14n = EXC_C14N,
+        sig_method = credentials.key.signature_method(),
+        id = id,
+        enveloped = ENVELOPED_SIGNATURE,
+        sha256 = SHA256_DIGEST,
+        doc_digest = sha256_base64(document.as_bytes()),
+        props_type = SIGNED_PROPERTIES_TYPE,
+        props_id = signed_properties_id,
+        props_digest = sha256_base64(signed_properties.as_bytes()),
+    );
+
+    let signature_value = BASE64.encode(credentials.key.sign(signed_info.as_bytes())?);
+
+    let signature = format!(
+        concat!(
+            r#"<ds:Signature xmlns:ds="{ds}" Id="{sig_id}">"#,
+            "{signed_info}",
+            "<ds:SignatureValue>{signature_value}</ds:SignatureValue>",
+            "<ds:KeyInfo><ds:X509Data><ds:X509Certificate>{cert}</ds:X509Certificate></ds:X509Data></ds:KeyInfo>",
+            "<ds:Object>",
+            r##"<xades:QualifyingProperties xmlns:xades="{xades}" Target="#{sig_id}">"##,
+            "{signed_properties}",
+            "</xades:QualifyingProperties>",
+            "</ds:Object>",
+            "</ds:Signature>"
+        ),
+        ds = DS_NS,
+        xades = XADES_NS,
+        sig_id = signature_id,
+        signed_info = signed_info,
+        signature_value = signature_value,
+        cert = BASE64.encode(&credentials.certificate_der),
+        signed_properties = signed_properties,
+    );
+
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>{}{}{}"#,
+        &document[..root_end],
+        signature,
+        &document[root_end..]
+    ))
+}
+
+/// Signer of a verified XML signature
+#[derive(Debug, Clone)]
+pub struct SignatureInfo {
+    /// Subject of the certificate embedded in `KeyInfo`
+    pub subject: String,
+    pub issuer: String,
+    pub serial_number: String,
+    /// `xades:SigningTime`, if the signature has signed properties
+    pub signing_time: Option<String>,
+    /// DER certificates from `KeyInfo`, the signer first
+    pub certificates: Vec<Vec<u8>>,
+}
+
+/// Verifies the enveloped XMLDSig/XAdES signature of a document: every
+/// reference digest, the signature value against the certificate in
+/// `KeyInfo` and, for XAdES, the signing certificate digest. The certificate
+/// chain is checked separately by `verify_chain`.
+pub(crate) fn verify_enveloped(document: &Element) -> Result<SignatureInfo> {
+    let signature = document
+        .find(&|e| e.is(DS_NS, "Signature"))
+        .ok_or_else(|| KsefError::Crypto("Document is not signed".to_string()))?;
+    let signed_info = required(signature, &["SignedInfo"])?;
+
+    let c14n_method = algorithm(signed_info, "CanonicalizationMethod")?;
+    let c14n = C14n::from_algorithm(c14n_method).ok_or_else(|| {
+        KsefError::Crypto(format!(
+            "Unsupported canonicalization method {}",
+            c14n_method
+        ))
+    })?;
+
+    let references: Vec<&Element> = signed_info
+        .elements()
+        .filter(|e| e.local_name() == "Reference")
+        .collect();
+    if references.is_empty() {
+        return Err(KsefError::Crypto(
+            "SignedInfo has no references".to_string(),
+        ));
+    }
+    for reference in references {
+        verify_reference(document, signature, reference)?;
+    }
+
+    let certificate_der = decode_base64(
+        &required(signature, &["KeyInfo", "X509Data", "X509Certificate"])?.text(),
+        "X509Certificate",
+    )?;
+    let mut certificates = vec![certificate_der.clone()];
+    for data in required(signature, &["KeyInfo"])?.elements() {
+        let more = data
+            .elements()
+            .filter(|e| e.local_name() == "X509Certificate");
+        for certificate in more {
+            let der = decode_base64(&certificate.text(), "X509Certificate")?;
+            if !certificates.contains(&der) {
+                certificates.push(der);
+            }
+        }
+    }
+    let (_, cert) = x509_parser::parse_x509_certificate(&certificate_der)
+        .map_err(|e| KsefError::Crypto(format!("Failed to parse X.509 certificate: {}", e)))?;
+    let signature_value = decode_base64(
+        &required(signature, &["SignatureValue"])?.text(),
+        "SignatureValue",
+    )?;
+    verify_signature_value(
+        algorithm(signed_info, "SignatureMethod")?,
+        &cert,
+        signed_info.canonicalize(c14n, None).as_bytes(),
+        &signature_value,
+    )?;
+
+    let signed_properties = signature.find(&|e| e.is(XADES_NS, "SignedProperties"));
+    if let Some(cert_digest) = signed_properties.and_then(|p| {
+        p.path(&[
+            "SignedSignatureProperties",
+            "SigningCertificate",
+            "Cert",
+            "CertDigest",
+        ])
+    }) {
+        let expected = required(cert_digest, &["DigestValue"])?.text();
+        let actual = digest_base64(algorithm(cert_digest, "DigestMethod")?, &certificate_der)?;
+        if strip_whitespace(&expected) != actual {
+            return Err(KsefError::Crypto(
+                "SigningCertificate digest does not match the KeyInfo certificate".to_string(),
+            ));
+        }
+    }
+
+    Ok(SignatureInfo {
+        subject: cert.subject().to_string(),
+        issuer: rfc2253_name(cert.issuer()),
+        serial_number: cert.tbs_certificate.serial.to_string(),
+        signing_time: signed_properties
+            .and_then(|p| p.text_at(&["SignedSignatureProperties", "SigningTime"])),
+        certificates,
+    })
+}
+
+/// Checks that the signer (`chain[0]`) is one of the `trusted` certificates or is issued
+/// by one of them, directly or through the other certificates of `chain`
+pub(crate) fn verify_chain(chain: &[Vec<u8>], trusted: &[Vec<u8>]) -> Result<()> {
+    let signer = chain
+        .first()
+        .ok_or_else(|| KsefError::Crypto("Signature has no certificate".to_string()))?;
+    let anchors = trusted
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+    let intermediates = chain[1..]
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+
+    let issued_by = |cert: &X509Certificate, issuer: &X509Certificate| {
+        issuer.is_ca()
+            && cert.issuer() == issuer.subject()
+            && cert.verify_signature(Some(issuer.public_key())).is_ok()
+    };
+
+    let mut der = signer.as_slice();
+    let mut cert = parse_certificate(der)?;
+    // A path to an anchor uses each certificate of the chain at most once
+    for _ in 0..=intermediates.len() {
+        if trusted.iter().any(|t| t.as_slice() == der)
+            || anchors.iter().any(|anchor| issued_by(&cert, anchor))
+        {
+            return Ok(());
+        }
+        match chain[1..]
+            .iter()
+            .zip(&intermediates)
+            .find(|(_, issuer)| issued_by(&cert, issuer))
+        {
+            Some((issuer_der, issuer)) => {
+                der = issuer_der;
+                cert = issuer.clone();
+            }
+            None => break,
+        }
+    }
+
+    Err(KsefError::Crypto(format!(
+        "Signer {} is not issued by a trusted certificate",
+        rfc2253_name(parse_certificate(signer)?.subject())
+    )))
+}
+
+fn parse_certificate(der: &[u8]) -> Result<X509Certificate<'_>> {
+    x509_parser::parse_x509_certificate(der)
+        .map(|(_, cert)| cert)
+        .map_err(|e| KsefError::Crypto(format!("Failed to parse X.509 certificate: {}", e)))
+}
+
+/// Recomputes the digest of one `ds:Reference` (whole document or `#Id`)
+fn verify_reference(document: &Element, signature: &Element, reference: &Element) -> Result<()> {
+    let uri = reference.attribute("URI").unwrap_or("");
+    let target = if uri.is_empty() {
+        document
+    } else {
+        let id = uri
+            .strip_prefix('#')
+            .ok_or_else(|| KsefError::Crypto(format!("Unsupported reference URI {}", uri)))?;
+        document
+            .find_by_id(id)
+            .ok_or_else(|| KsefError::Crypto(format!("Referenced element {} not found", uri)))?
+    };
+
+    let mut exclude = None;
+    let mut c14n = C14n::Inclusive;
+    let transforms = reference
+        .child("Transforms")
+        .into_iter()
+        .flat_map(|t| t.elements());
+    for transform in transforms {
+        let method = transform.attribute("Algorithm").unwrap_or("");
+        if method == ENVELOPED_SIGNATURE {
+            exclude = Some(signature);
+        } else {
+            c14n = C14n::from_algorithm(method)
+                .ok_or_else(|| KsefError::Crypto(format!("Unsupported transform {}", method)))?;
+        }
+    }
+
+    let expected = required(reference, &["DigestValue"])?.text();
+    let actual = digest_base64(
+        algorithm(reference, "DigestMethod")?,
+        target.canonicalize(c14n, exclude).as_bytes(),
+    )?;
+    if strip_whitespace(&expected) == actual {
+        Ok(())
+    } else {
+        Err(KsefError::Crypto(format!(
+            "Digest mismatch for reference \"{}\" (expected {}, got {})",
+            uri,
+            strip_whitespace(&expected),
+            actual
+        )))
+    }
+}
+
+fn verify_signature_value(
+    method: &str,
+    cert: &x509_parser::certificate::X509Certificate,
+    data: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    use rsa::signature::Verifier;
+
+    let invalid =
+        |e: &dyn std::fmt::Display| KsefError::Crypto(format!("Signature value is invalid: {}", e));
+    match method {
+        RSA_SHA256 => {
+            use rsa::pkcs8::DecodePublicKey;
+
+            let key = rsa::RsaPublicKey::from_public_key_der(cert.public_key().raw)
+                .map_err(|e| invalid(&e))?;
+            let signature =
+                rsa::pkcs1v15::Signature::try_from(signature).map_err(|e| invalid(&e))?;
+            rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key)
+                .verify(data, &signature)
+                .map_err(|e| invalid(&e))
+        }
+        ECDSA_SHA256 => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(
+                &cert.public_key().subject_public_key.data,
+            )
+            .map_err(|e| invalid(&e))?;
+            let signature =
+                p256::ecdsa::Signature::from_slice(signature).map_err(|e| invalid(&e))?;
+            key.verify(data, &signature).map_err(|e| invalid(&e))
+        }
+        _ => Err(KsefError::Crypto(format!(
+            "Unsupported signature method {}",
+            method
+        ))),
+    }
+}
+
+fn required<'a>(element: &'a Element, path: &[&str]) -> Result<&'a Element> {
+    element
+        .path(path)
+        .ok_or_else(|| KsefError::Crypto(format!("Signature has no {} element", path.join("/"))))
+}
+
+/// `Algorithm` attribute of the named child element
+fn algorithm<'a>(element: &'a Element, child: &str) -> Result<&'a str> {
+    required(element, &[child])?
+        .attribute("Algorithm")
+        .ok_or_else(|| KsefError::Crypto(format!("{} has no Algorithm", child)))
+}
+
+fn digest_base64(method: &str, data: &[u8]) -> Result<String> {
+    match method {
+        SHA256_DIGEST => Ok(sha256_base64(data)),
+        SHA512_DIGEST => Ok(BASE64.encode(sha2::Sha512::digest(data))),
+        _ => Err(KsefError::Crypto(format!(
+            "Unsupported digest method {}",
+            method
+        ))),
+    }
+}
+
+fn decode_base64(text: &str, what: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(strip_whitespace(text))
+        .map_err(|e| KsefError::Crypto(format!("Invalid base64 in {}: {}", what, e)))
+}
+
+fn strip_whitespace(text: &str) -> String {
+    text.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+fn sha256_base64(data: &[u8]) -> String {
+    BASE64.encode(Sha256::digest(data))
+}
+
+fn random_id() -> String {
+    use rand::Rng;
+
+    let bytes: [u8; 8] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Distinguished name in RFC 2253 order (most specific RDN first)
+fn rfc2253_name(name: &x509_parser::x509::X509Name) -> String {
+    let registry = x509_parser::objects::oid_registry();
+    let rdns: Vec<String> = name
+        .iter_rdn()
+        .map(|rdn| {
+            rdn.iter()
+                .map(|attr| {
+                    let key = x509_parser::objects::oid2abbrev(attr.attr_type(), registry)
+                        .map(|s| s.to_string())
+                        .unwrap_or_else(|_| attr.attr_type().to_id_string());
+                    let value = attr
+                        .as_str()
+                        .map(escape_dn_value)
+                        .unwrap_or_else(|_| format!("#{}", hex_encode(attr.as_slice())));
+                    format!("{}={}", key, value)
+                })
+                .collect::<Vec<_>>()
+                .join("+")
+        })
+        .collect();
+    rdns.into_iter().rev().collect::<Vec<_>>().join(",")
+}
+
+fn escape_dn_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        let leading = i == 0 && (c == ' ' || c == '#');
+        let trailing = i == value.chars().count() - 1 && c == ' ';
+        if leading || trailing || matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Text node escaping as required by canonical XML
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier;
+
+    fn self_signed_credentials() -> CertificateCredentials {
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Test Seal, Sp. z o.o.");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "Test");
+        let cert = params.self_signed(&key_pair).unwrap();
+
+        CertificateCredentials::from_pem(&cert.pem(), &key_pair.serialize_pem()).unwrap()
+    }
+
+    fn extract<'a>(xml: &'a str, start: &str, end: &str) -> &'a str {
+        let from = xml.find(start).unwrap();
+        let to = xml[from..].find(end).unwrap() + from + end.len();
+        &xml[from..to]
+    }
+
+    fn inner<'a>(xml: &'a str, start: &str, end: &str) -> &'a str {
+        let from = xml.find(start).unwrap() + start.len();
+        let to = xml[from..].find(end).unwrap() + from;
+        &xml[from..to]
+    }
+
+    #[test]
+    fn test_build_auth_token_request() {
+        let xml = build_auth_token_request(
+            "20250625-CR-20F5EE4000-DA48AE4124-46",
+            &AuthContext::Nip("5265877635".to_string()),
+        );
+
+        assert!(
+            xml.starts_with(r#"<AuthTokenRequest xmlns="http://ksef.mf.gov.pl/auth/token/2.0">"#)
+        );
+        assert!(xml.contains("<Challenge>20250625-CR-20F5EE4000-DA48AE4124-46</Challenge>"));
+        assert!(xml.contains("<Nip>5265877635</Nip>"));
+    }
+
+    #[test]
+    fn test_signature_is_self_consistent() {
+        let credentials = self_signed_credentials();
+        let document = build_auth_token_request(
+            "challenge-1",
+            &AuthContext::InternalId("5265877635-00001".to_string()),
+        );
+
+        let signed = sign_enveloped(&document, &credentials).unwrap();
+
+        // Enveloped transform: removing the signature yields the original document
+        let signature = extract(&signed, "<ds:Signature ", "</ds:Signature>");
+        let unsigned = signed
+            .replace(signature, "")
+            .replace(r#"<?xml version="1.0" encoding="utf-8"?>"#, "");
+        assert_eq!(unsigned, document);
+
+        let digests: Vec<&str> = signed
+            .match_indices("<ds:DigestValue>")
+            .map(|(i, _)| inner(&signed[i..], "<ds:DigestValue>", "</ds:DigestValue>"))
+            .collect();
+        assert_eq!(digests[0], sha256_base64(document.as_bytes()));
+        let signed_properties = extract(
+            &signed,
+            "<xades:SignedProperties ",
+            "</xades:SignedProperties>",
+        );
+        assert_eq!(digests[1], sha256_base64(signed_properties.as_bytes()));
+
+        // SignatureValue verifies against the certificate key
+        let signed_info = extract(&signed, "<ds:SignedInfo ", "</ds:SignedInfo>");
+        let signature_value = BASE64
+            .decode(inner(
+                &signed,
+                "<ds:SignatureValue>",
+                "</ds:SignatureValue>",
+            ))
+            .unwrap();
+        let (_, cert) = x509_parser::parse_x509_certificate(credentials.certificate_der()).unwrap();
+        let verifying_key =
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(&cert.public_key().subject_public_key.data)
+                .unwrap();
+        let signature = p256::ecdsa::Signature::from_slice(&signature_value).unwrap();
+        assert!(verifying_key
+            .verify(signed_info.as_bytes(), &signature)
+            .is_ok());
+
+        // Issuer name is in RFC 2253 order with escaped commas
+        assert!(signed.contains("<ds:X509IssuerName xmlns:ds=\"http://www.w3.org/2000/09/xmldsig#\">O=Test,CN=Test Seal\\, Sp. z o.o.</ds:X509IssuerName>"));
+    }
+
+    #[test]
+    fn test_verify_enveloped() {
+        let credentials = self_signed_credentials();
+        let document =
+            build_auth_token_request("challenge-1", &AuthContext::Nip("5265877635".to_string()));
+        let signed = sign_enveloped(&document, &credentials).unwrap();
+
+        let info = verify_enveloped(&crate::xml::parse(&signed).unwrap()).unwrap();
+        assert_eq!(info.subject, credentials.subject());
+        assert!(info.signing_time.is_some());
+
+        // Whitespace between elements is part of the signed content
+        let pretty = signed.replace("<Challenge>", "\n  <Challenge>");
+        assert!(verify_enveloped(&crate::xml::parse(&pretty).unwrap()).is_err());
+
+        let tampered = signed.replace("5265877635", "7352765225");
+        let error = verify_enveloped(&crate::xml::parse(&tampered).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("Digest mismatch"));
+    }
+}
@@ -0,0 +1,376 @@
+//! Minimal XML tree with Canonical XML 1.0 and Exclusive XML Canonicalization
+//! output, used to verify XML signatures on documents returned by KSeF.
+//!
+//! Comments, processing instructions and DTDs are dropped while parsing, which
+//! matches the "without comments" variants of both algorithms.
+
+use quick_xml::events::{BytesStart, Event};
+use std::collections::BTreeMap;
+
+use crate::{KsefError, Result};
+
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+const C14N: &str = "http://www.w3.org/TR/2001/REC-xml-c14n-20010315";
+const C14N_WITH_COMMENTS: &str = "http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments";
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const EXC_C14N_WITH_COMMENTS: &str = "http://www.w3.org/2001/10/xml-exc-c14n#WithComments";
+
+/// Canonicalization algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum C14n {
+    Inclusive,
+    Exclusive,
+}
+
+impl C14n {
+    pub(crate) fn from_algorithm(uri: &str) -> Option<Self> {
+        match uri {
+            C14N | C14N_WITH_COMMENTS => Some(C14n::Inclusive),
+            EXC_C14N | EXC_C14N_WITH_COMMENTS => Some(C14n::Exclusive),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Element {
+    /// Qualified name as written in the document
+    pub name: String,
+    /// Attributes other than namespace declarations, in document order
+    pub attributes: Vec<(String, String)>,
+    /// Namespaces in scope (prefix `""` is the default namespace)
+    scope: BTreeMap<String, String>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub(crate) fn prefix(&self) -> &str {
+        self.name.split_once(':').map(|(p, _)| p).unwrap_or("")
+    }
+
+    pub(crate) fn local_name(&self) -> &str {
+        self.name
+            .split_once(':')
+            .map(|(_, l)| l)
+            .unwrap_or(&self.name)
+    }
+
+    pub(crate) fn namespace(&self) -> Option<&str> {
+        self.scope
+            .get(self.prefix())
+            .map(String::as_str)
+            .filter(|ns| !ns.is_empty())
+    }
+
+    pub(crate) fn is(&self, namespace: &str, local_name: &str) -> bool {
+        self.local_name() == local_name && self.namespace() == Some(namespace)
+    }
+
+    pub(crate) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub(crate) fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// First child element with the given local name
+    pub(crate) fn child(&self, local_name: &str) -> Option<&Element> {
+        self.elements().find(|e| e.local_name() == local_name)
+    }
+
+    /// Follows a path of child local names
+    pub(crate) fn path(&self, local_names: &[&str]) -> Option<&Element> {
+        local_names
+            .iter()
+            .try_fold(self, |element, name| element.child(name))
+    }
+
+    /// Trimmed text content of a descendant at `local_names`
+    pub(crate) fn text_at(&self, local_names: &[&str]) -> Option<String> {
+        self.path(local_names)
+            .map(|e| e.text().trim().to_string())
+            .filter(|t| !t.is_empty())
+    }
+
+    /// Concatenated text of the direct text children
+    pub(crate) fn text(&self) -> String {
+        self.children
+            .iter()
+            .filter_map(|node| match node {
+                Node::Text(text) => Some(text.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+
+    /// First element (depth-first, including `self`) matching the predicate
+    pub(crate) fn find(&self, predicate: &dyn Fn(&Element) -> bool) -> Option<&Element> {
+        if predicate(self) {
+            return Some(self);
+        }
+        self.elements().find_map(|e| e.find(predicate))
+    }
+
+    /// Element whose `Id` (or `ID`, `id`) attribute equals `id`
+    pub(crate) fn find_by_id(&self, id: &str) -> Option<&Element> {
+        self.find(&|e| {
+            ["Id", "ID", "id"]
+                .iter()
+                .any(|name| e.attribute(name) == Some(id))
+        })
+    }
+
+    /// Canonical form of the subtree rooted at this element, leaving out `exclude`
+    /// (used for the enveloped-signature transform)
+    pub(crate) fn canonicalize(&self, method: C14n, exclude: Option<&Element>) -> String {
+        let mut out = String::new();
+        write_canonical(&mut out, self, method, &BTreeMap::new(), exclude);
+        out
+    }
+}
+
+/// Parses a document into its root element
+pub(crate) fn parse(xml: &str) -> Result<Element> {
+    let invalid =
+        |e: &dyn std::fmt::Display| KsefError::InvalidResponse(format!("Invalid XML: {}", e));
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+
+    loop {
+        match reader.read_event().map_err(|e| invalid(&e))? {
+            Event::Start(start) => {
+                let scope = stack.last().map(|e| &e.scope);
+                stack.push(start_element(&start, scope).map_err(|e| invalid(&e))?);
+            }
+            Event::Empty(start) => {
+                let scope = stack.last().map(|e| &e.scope);
+                let element = start_element(&start, scope).map_err(|e| invalid(&e))?;
+                close_element(&mut stack, &mut root, element);
+            }
+            Event::End(_) => {
+                let element = stack.pop().ok_or_else(|| invalid(&"unexpected end tag"))?;
+                close_element(&mut stack, &mut root, element);
+            }
+            Event::Text(text) => {
+                if let Some(parent) = stack.last_mut() {
+                    let raw = String::from_utf8_lossy(text.as_ref()).replace("\r\n", "\n");
+                    let text = quick_xml::escape::unescape(&raw).map_err(|e| invalid(&e))?;
+                    push_text(parent, &text);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(parent) = stack.last_mut() {
+                    push_text(
+                        parent,
+                        &String::from_utf8_lossy(&data).replace("\r\n", "\n"),
+                    );
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err(invalid(&"unclosed element"));
+    }
+    root.ok_or_else(|| invalid(&"no root element"))
+}
+
+fn start_element(
+    start: &BytesStart,
+    parent_scope: Option<&BTreeMap<String, String>>,
+) -> std::result::Result<Element, quick_xml::Error> {
+    let mut scope = parent_scope.cloned().unwrap_or_default();
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute?;
+        let name = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        // Attribute-value normalization of literal whitespace
+        let raw = String::from_utf8_lossy(&attribute.value).replace(['\t', '\n', '\r'], " ");
+        let value = quick_xml::escape::unescape(&raw)?.into_owned();
+        if name == "xmlns" {
+            scope.insert(String::new(), value);
+        } else if let Some(prefix) = name.strip_prefix("xmlns:") {
+            scope.insert(prefix.to_string(), value);
+        } else {
+            attributes.push((name, value));
+        }
+    }
+
+    Ok(Element {
+        name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+        attributes,
+        scope,
+        children: Vec::new(),
+    })
+}
+
+fn close_element(stack: &mut [Element], root: &mut Option<Element>, element: Element) {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(Node::Element(element)),
+        None => *root = Some(element),
+    }
+}
+
+fn push_text(parent: &mut Element, text: &str) {
+    if let Some(Node::Text(previous)) = parent.children.last_mut() {
+        previous.push_str(text);
+    } else {
+        parent.children.push(Node::Text(text.to_string()));
+    }
+}
+
+fn write_canonical(
+    out: &mut String,
+    element: &Element,
+    method: C14n,
+    rendered: &BTreeMap<String, String>,
+    exclude: Option<&Element>,
+) {
+    if exclude.is_some_and(|e| std::ptr::eq(e, element)) {
+        return;
+    }
+
+    let candidates: Vec<&str> = match method {
+        C14n::Inclusive => element.scope.keys().map(String::as_str).collect(),
+        C14n::Exclusive => {
+            // Only the namespaces visibly utilized by the element and its attributes
+            let mut used = vec![element.prefix()];
+            used.extend(
+                element
+                    .attributes
+                    .iter()
+                    .filter_map(|(name, _)| name.split_once(':').map(|(p, _)| p)),
+            );
+            used
+        }
+    };
+
+    let mut declarations = BTreeMap::new();
+    for prefix in candidates {
+        if prefix == "xml" {
+            continue;
+        }
+        let uri = element.scope.get(prefix).map(String::as_str).unwrap_or("");
+        let current = rendered.get(prefix).map(String::as_str).unwrap_or("");
+        if uri != current {
+            declarations.insert(prefix.to_string(), uri.to_string());
+        }
+    }
+
+    let mut attributes: Vec<(&str, &str, &str, &str)> = element
+        .attributes
+        .iter()
+        .map(|(name, value)| {
+            let (namespace, local) = match name.split_once(':') {
+                Some(("xml", local)) => (XML_NS, local),
+                Some((prefix, local)) => (
+                    element.scope.get(prefix).map(String::as_str).unwrap_or(""),
+                    local,
+                ),
+                None => ("", name.as_str()),
+            };
+            (namespace, local, name.as_str(), value.as_str())
+        })
+        .collect();
+    attributes.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    out.push('<');
+    out.push_str(&element.name);
+    for (prefix, uri) in &declarations {
+        if prefix.is_empty() {
+            out.push_str(" xmlns=\"");
+        } else {
+            out.push_str(" xmlns:");
+            out.push_str(prefix);
+            out.push_str("=\"");
+        }
+        out.push_str(&escape_attribute(uri));
+        out.push('"');
+    }
+    for (_, _, name, value) in attributes {
+        out.push(' ');
+        out.push_str(name);
+        out.push_str("=\"");
+        out.push_str(&escape_attribute(value));
+        out.push('"');
+    }
+    out.push('>');
+
+    let mut in_scope = rendered.clone();
+    in_scope.extend(declarations);
+    for child in &element.children {
+        match child {
+            Node::Element(child) => write_canonical(out, child, method, &in_scope, exclude),
+            Node::Text(text) => out.push_str(&escape_text(text)),
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(&element.name);
+    out.push('>');
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize_subtree() {
+        let xml = concat!(
+            "<?xml version=\"1.0\"?>\r\n",
+            "<a:Root xmlns:a=\"urn:a\" xmlns:b=\"urn:b\" xmlns=\"urn:default\">",
+            "<!-- comment --><Child z=\"1\" b:y='2' a=\"x &amp; y\"/>",
+            "<b:Inner>text &lt; 5</b:Inner></a:Root>"
+        );
+        let root = parse(xml).unwrap();
+        let child = root.child("Child").unwrap();
+        assert_eq!(child.namespace(), Some("urn:default"));
+
+        assert_eq!(
+            child.canonicalize(C14n::Exclusive, None),
+            r#"<Child xmlns="urn:default" xmlns:b="urn:b" a="x &amp; y" z="1" b:y="2"></Child>"#
+        );
+        assert_eq!(
+            child.canonicalize(C14n::Inclusive, None),
+            r#"<Child xmlns="urn:default" xmlns:a="urn:a" xmlns:b="urn:b" a="x &amp; y" z="1" b:y="2"></Child>"#
+        );
+        assert_eq!(
+            root.canonicalize(C14n::Exclusive, Some(child)),
+            r#"<a:Root xmlns:a="urn:a"><b:Inner xmlns:b="urn:b">text &lt; 5</b:Inner></a:Root>"#
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Advance (`ZAL`) and settlement (`ROZ`) invoices.
+//!
+//! An advance invoice documents a payment received before the supply: the
+//! ordered items go into `Zamowienie` and the advance is split over their
+//! rates. The settlement invoice lists the full supply, references the
+//! advance invoices and only charges what the advances did not cover.
+
+use rust_decimal::Decimal;
+
+use crate::totals::{round_grosze, RATE_GROUPS};
+use crate::{cena, escape_xml, kwota, FormVariant, Invoice, InvoiceLineItem, RateTotal};
+
+/// Error returned for an advance outside the value of the order
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AdvanceError {
+    #[error("Advance amount must be greater than zero, got {0}")]
+    NotPositive(Decimal),
+
+    #[error("Advance amount {kwota} exceeds the gross order value {wartosc}")]
+    ExceedsOrder { kwota: Decimal, wartosc: Decimal },
+}
+
+/// Advance invoice settled by a `ROZ` invoice (`FakturaZaliczkowa`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdvanceInvoice {
+    /// Invoice number, used when the advance invoice was issued outside KSeF
+    pub numer: String,
+    /// KSeF number (NrKSeFFaZaliczkowej)
+    pub numer_ksef: Option<String>,
+    /// Totals of the advance invoice, deducted from the settlement
+    pub sumy: Vec<RateTotal>,
+}
+
+impl AdvanceInvoice {
+    /// Reference to an advance invoice with the totals it declared
+    pub fn from_invoice(invoice: &Invoice, numer_ksef: Option<String>) -> Self {
+        Self {
+            numer: invoice.numer.clone(),
+            numer_ksef,
+            sumy: invoice.rate_totals(),
+        }
+    }
+}
+
+impl Invoice {
+    /// Checks that `kwota_zaliczki` is positive and covered by the gross order value,
+    /// so that its split over the rates stays within the ordered amounts
+    pub fn check_advance(&self) -> Result<(), AdvanceError> {
+        let Some(kwota) = self.kwota_zaliczki else {
+            return Ok(());
+        };
+        let wartosc = self.calculate_order_value();
+        if kwota <= Decimal::ZERO {
+            Err(AdvanceError::NotPositive(kwota))
+        } else if kwota > wartosc {
+            Err(AdvanceError::ExceedsOrder { kwota, wartosc })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Splits a gross advance over the rate groups in proportion to their gross
+/// order value; VAT of each part is the group's VAT share, the rest is net
+pub(crate) fn split_advance(zamowienie: &[RateTotal], zaliczka: Decimal) -> Vec<RateTotal> {
+    let brutto = |t: &RateTotal| t.netto + t.vat.unwrap_or_default();
+    let wartosc: Decimal = zamowienie.iter().map(brutto).sum();
+    if wartosc.is_zero() {
+        return Vec::new();
+    }
+
+    let mut pozostalo = zaliczka;
+    zamowienie
+        .iter()
+        .enumerate()
+        .map(|(i, total)| {
+            // The last group takes the remainder so that the parts add up to the advance
+            let czesc = if i + 1 == zamowienie.len() {
+                pozostalo
+            } else {
+                round_grosze(zaliczka * brutto(total) / wartosc)
+            };
+            pozostalo -= czesc;
+            let vat = total
+                .vat
+                .filter(|_| !brutto(total).is_zero())
+                .map(|vat| round_grosze(czesc * vat / brutto(total)));
+            RateTotal {
+                stawka: total.stawka,
+                netto: czesc - vat.unwrap_or_default(),
+                vat,
+            }
+        })
+        .collect()
+}
+
+/// Subtracts the totals of the advance invoices from those of the settlement
+pub(crate) fn deduct_advances(sumy: Vec<RateTotal>, zaliczki: &[AdvanceInvoice]) -> Vec<RateTotal> {
+    RATE_GROUPS
+        .iter()
+        .filter_map(|group| {
+            let zaliczki: Vec<&RateTotal> = zaliczki
+                .iter()
+                .flat_map(|z| &z.sumy)
+                .filter(|t| t.stawka == group.stawka)
+                .collect();
+            let total = sumy.iter().find(|t| t.stawka == group.stawka);
+            if total.is_none() && zaliczki.is_empty() {
+                return None;
+            }
+            let netto = total.map(|t| t.netto).unwrap_or_default()
+                - zaliczki.iter().map(|t| t.netto).sum::<Decimal>();
+            let vat = group.vat.map(|_| {
+                total.and_then(|t| t.vat).unwrap_or_default()
+                    - zaliczki.iter().filter_map(|t| t.vat).sum::<Decimal>()
+            });
+            Some(RateTotal {
+                stawka: group.stawka,
+                netto,
+                vat,
+            })
+        })
+        .collect()
+}
+
+/// `FakturaZaliczkowa` references of a settlement invoice
+pub(crate) fn faktury_zaliczkowe_xml(zaliczki: &[AdvanceInvoice]) -> String {
+    let mut xml = String::new();
+    for zaliczka in zaliczki {
+        let numer = match zaliczka.numer_ksef {
+            Some(ref numer_ksef) => format!(
+                "<NrKSeFFaZaliczkowej>{}</NrKSeFFaZaliczkowej>",
+                escape_xml(numer_ksef)
+            ),
+            None => format!(
+                "<NrKSeFZN>1</NrKSeFZN>\n      <NrFaZaliczkowej>{}</NrFaZaliczkowej>",
+                escape_xml(&zaliczka.numer)
+            ),
+        };
+        xml.push_str(&format!(
+            "    <FakturaZaliczkowa>\n      {}\n    </FakturaZaliczkowa>\n",
+            numer
+        ));
+    }
+    xml
+}
+
+/// `Zamowienie` of an advance invoice: the ordered items and the gross order value
+pub(crate) fn zamowienie_xml(
+    pozycje: &[InvoiceLineItem],
+    wartosc: Decimal,
+    wariant: FormVariant,
+) -> String {
+    if pozycje.is_empty() {
+        return String::new();
+    }
+
+    let mut xml = format!(
+        "    <Zamowienie>\n      <WartoscZamowienia>{}</WartoscZamowienia>\n",
+        kwota(wartosc)
+    );
+    for item in pozycje {
+        let vat = item.stawka_vat.percent().map(|percent| {
+            format!(
+                "\n        <P_11VatZ>{}</P_11VatZ>",
+                kwota(
+                    round_grosze(item.kwota_netto) * Decimal::from(percent) / Decimal::ONE_HUNDRED
+                )
+            )
+        });
+        xml.push_str(&format!(
+            r#"      <ZamowienieWiersz>
+        <NrWierszaZam>{}</NrWierszaZam>
+        <P_7Z>{}</P_7Z>
+        <P_8AZ>{}</P_8AZ>
+        <P_8BZ>{}</P_8BZ>
+        <P_9AZ>{}</P_9AZ>
+        <P_11NettoZ>{}</P_11NettoZ>{}
+        <P_12Z>{}</P_12Z>{}{}
+      </ZamowienieWiersz>
+"#,
+            item.nr_wiersza,
+            escape_xml(&item.opis),
+            escape_xml(&item.jednostka),
+            item.ilosc.normalize(),
+            cena(item.cena_netto),
+            kwota(item.kwota_netto),
+            vat.unwrap_or_default(),
+            item.stawka_vat.code_for(wariant),
+            if item.zalacznik_15 {
+                "\n        <P_12Z_Zal_15>1</P_12Z_Zal_15>"
+            } else {
+                ""
+            },
+            if item.stan_przed {
+                "\n        <StanPrzedZ>1</StanPrzedZ>"
+            } else {
+                ""
+            }
+        ));
+    }
+    xml.push_str("    </Zamowienie>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{validate_xml, InvoiceKind, VatRate};
+    use rust_decimal_macros::dec;
+
+    fn invoice(numer: &str, rodzaj: InvoiceKind) -> Invoice {
+        let mut invoice = crate::tests::invoice(numer);
+        invoice.wariant = FormVariant::Fa3;
+        invoice.rodzaj = rodzaj;
+        invoice
+    }
+
+    fn order() -> Vec<InvoiceLineItem> {
+        [(dec!(1000), VatRate::Vat23), (dec!(500), VatRate::Vat8)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, (netto, stawka_vat))| InvoiceLineItem {
+                nr_wiersza: i as u32 + 1,
+                opis: "Machine part".to_string(),
+                jednostka: "szt".to_string(),
+                ilosc: Decimal::ONE,
+                cena_netto: netto,
+                kwota_netto: netto,
+                stawka_vat,
+                stan_przed: false,
+                zalacznik_15: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_advance_and_settlement() {
+        // Half of the 1770.00 order paid in advance
+        let mut zal = invoice("ZAL/1/2026", InvoiceKind::Zal);
+        zal.zamowienie = order();
+        zal.kwota_zaliczki = Some(dec!(885));
+        assert_eq!(zal.calculate_order_value(), dec!(1770));
+        assert_eq!(
+            zal.rate_totals(),
+            vec![
+                RateTotal {
+                    stawka: "23%",
+                    netto: dec!(500),
+                    vat: Some(dec!(115))
+                },
+                RateTotal {
+                    stawka: "8%",
+                    netto: dec!(250),
+                    vat: Some(dec!(20))
+                },
+            ]
+        );
+        let xml = zal.generate_ksef_xml();
+        assert!(xml.contains("<WartoscZamowienia>1770.00</WartoscZamowienia>"));
+        assert!(!xml.contains("<FaWiersz>"));
+        assert_eq!(validate_xml(&xml, FormVariant::Fa3), Ok(()));
+        let read = Invoice::from_ksef_xml(&xml).unwrap();
+        assert_eq!(read.kwota_zaliczki, Some(dec!(885.00)));
+        assert_eq!(read.zamowienie.len(), 2);
+
+        // The settlement charges only what the advance did not cover
+        let mut roz = invoice("FV/2/2026", InvoiceKind::Roz);
+        roz.pozycje = order();
+        roz.zaliczki = vec![
+            AdvanceInvoice::from_invoice(&zal, Some("5265877635-20260201-0100A0B1C2D3-4E".into())),
+            AdvanceInvoice {
+                numer: "ZAL/0/2026".to_string(),
+                numer_ksef: None,
+                sumy: Vec::new(),
+            },
+        ];
+        assert_eq!(roz.calculate_total_gross(), dec!(885));
+        let xml = roz.generate_ksef_xml();
+        assert!(xml.contains("<P_13_2>250.00</P_13_2>\n    <P_14_2>20.00</P_14_2>"));
+        assert!(xml.contains("<NrFaZaliczkowej>ZAL/0/2026</NrFaZaliczkowej>"));
+        assert_eq!(validate_xml(&xml, FormVariant::Fa3), Ok(()));
+        let read = Invoice::from_ksef_xml(&xml).unwrap();
+        assert_eq!(read.zaliczki[0].numer_ksef, roz.zaliczki[0].numer_ksef);
+    }
+
+    #[test]
+    fn test_advance_amount_bounds() {
+        let mut zal = invoice("ZAL/1/2026", InvoiceKind::Zal);
+        zal.zamowienie = order();
+
+        for (kwota, error) in [
+            (dec!(0), AdvanceError::NotPositive(dec!(0))),
+            (dec!(-100), AdvanceError::NotPositive(dec!(-100))),
+            (
+                dec!(1770.01),
+                AdvanceError::ExceedsOrder {
+                    kwota: dec!(1770.01),
+                    wartosc: dec!(1770),
+                },
+            ),
+        ] {
+            zal.kwota_zaliczki = Some(kwota);
+            assert_eq!(zal.check_advance(), Err(error));
+        }
+
+        // The whole order may be paid in advance
+        zal.kwota_zaliczki = Some(dec!(1770));
+        assert_eq!(zal.check_advance(), Ok(()));
+    }
+}
@@ -0,0 +1,92 @@
+//! FA(3) invoice attachment (`Zalacznik`): structured data blocks with
+//! key/value metadata, text paragraphs and tables.
+
+use crate::escape_xml;
+
+/// Data block of the attachment (`Zalacznik/BlokDanych`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AttachmentBlock {
+    /// Block header (ZNaglowek)
+    pub naglowek: Option<String>,
+    /// Key/value pairs (MetaDane); the schema requires at least one
+    pub metadane: Vec<(String, String)>,
+    /// Text paragraphs (Tekst/Akapit)
+    pub akapity: Vec<String>,
+    pub tabele: Vec<AttachmentTable>,
+}
+
+/// Table of a data block (`Tabela`); all columns are text columns
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AttachmentTable {
+    /// Table description (Opis)
+    pub opis: Option<String>,
+    /// Column headers (TNaglowek/Kol/NKom)
+    pub kolumny: Vec<String>,
+    /// Rows (Wiersz/WKom)
+    pub wiersze: Vec<Vec<String>>,
+}
+
+/// `Zalacznik` element, empty when there are no blocks
+pub(crate) fn zalacznik_xml(blocks: &[AttachmentBlock]) -> String {
+    if blocks.is_empty() {
+        return String::new();
+    }
+
+    let mut xml = String::from("  <Zalacznik>\n");
+    for block in blocks {
+        xml.push_str("    <BlokDanych>\n");
+        if let Some(ref naglowek) = block.naglowek {
+            xml.push_str(&format!(
+                "      <ZNaglowek>{}</ZNaglowek>\n",
+                escape_xml(naglowek)
+            ));
+        }
+        for (klucz, wartosc) in &block.metadane {
+            xml.push_str(&format!(
+                "      <MetaDane>\n        <ZKlucz>{}</ZKlucz>\n        <ZWartosc>{}</ZWartosc>\n      </MetaDane>\n",
+                escape_xml(klucz),
+                escape_xml(wartosc)
+            ));
+        }
+        if !block.akapity.is_empty() {
+            xml.push_str("      <Tekst>\n");
+            for akapit in &block.akapity {
+                xml.push_str(&format!(
+                    "        <Akapit>{}</Akapit>\n",
+                    escape_xml(akapit)
+                ));
+            }
+            xml.push_str("      </Tekst>\n");
+        }
+        for tabela in &block.tabele {
+            xml.push_str(&tabela_xml(tabela));
+        }
+        xml.push_str("    </BlokDanych>\n");
+    }
+    xml.push_str("  </Zalacznik>\n");
+    xml
+}
+
+fn tabela_xml(tabela: &AttachmentTable) -> String {
+    let mut xml = String::from("      <Tabela>\n");
+    if let Some(ref opis) = tabela.opis {
+        xml.push_str(&format!("        <Opis>{}</Opis>\n", escape_xml(opis)));
+    }
+    xml.push_str("        <TNaglowek>\n");
+    for kolumna in &tabela.kolumny {
+        xml.push_str(&format!(
+            "          <Kol Typ=\"txt\">\n            <NKom>{}</NKom>\n          </Kol>\n",
+            escape_xml(kolumna)
+        ));
+    }
+    xml.push_str("        </TNaglowek>\n");
+    for wiersz in &tabela.wiersze {
+        xml.push_str("        <Wiersz>\n");
+        for komorka in wiersz {
+            xml.push_str(&format!("          <WKom>{}</WKom>\n", escape_xml(komorka)));
+        }
+        xml.push_str("        </Wiersz>\n");
+    }
+    xml.push_str("      </Tabela>\n");
+    xml
+}
@@ -0,0 +1,157 @@
+//! Correction invoices (`KOR`): the reason and type of the correction and the
+//! invoices it corrects.
+//!
+//! Changed lines are given twice, as they were (`StanPrzed`) and as they are
+//! after the correction; the totals of a correction are the differences.
+
+use crate::escape_xml;
+
+/// Period in which the correction is accounted for (`TypKorekty`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorrectionType {
+    /// 1 - in the period of the corrected invoice
+    OriginalDate,
+    /// 2 - in the period of the correction invoice
+    CorrectionDate,
+    /// 3 - on another date
+    OtherDate,
+}
+
+impl CorrectionType {
+    pub fn code(&self) -> u8 {
+        match self {
+            CorrectionType::OriginalDate => 1,
+            CorrectionType::CorrectionDate => 2,
+            CorrectionType::OtherDate => 3,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(CorrectionType::OriginalDate),
+            2 => Some(CorrectionType::CorrectionDate),
+            3 => Some(CorrectionType::OtherDate),
+            _ => None,
+        }
+    }
+}
+
+/// Invoice being corrected (`DaneFaKorygowanej`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorrectedInvoice {
+    /// Issue date (DataWystFaKorygowanej)
+    pub data_wystawienia: String,
+    /// Invoice number (NrFaKorygowanej)
+    pub numer: String,
+    /// KSeF number, `None` for an invoice issued outside KSeF
+    pub numer_ksef: Option<String>,
+}
+
+/// Correction data of a `KOR` invoice
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Correction {
+    /// Reason for the correction (PrzyczynaKorekty)
+    pub przyczyna: Option<String>,
+    pub typ: Option<CorrectionType>,
+    /// Corrected invoices; the schema allows several per correction
+    pub faktury: Vec<CorrectedInvoice>,
+}
+
+/// `PrzyczynaKorekty`, `TypKorekty` and `DaneFaKorygowanej`, following `RodzajFaktury`
+pub(crate) fn korekta_xml(korekta: &Correction) -> String {
+    let mut xml = String::new();
+    if let Some(ref przyczyna) = korekta.przyczyna {
+        xml.push_str(&format!(
+            "    <PrzyczynaKorekty>{}</PrzyczynaKorekty>\n",
+            escape_xml(przyczyna)
+        ));
+    }
+    if let Some(typ) = korekta.typ {
+        xml.push_str(&format!("    <TypKorekty>{}</TypKorekty>\n", typ.code()));
+    }
+    for faktura in &korekta.faktury {
+        let nr_ksef = match faktura.numer_ksef {
+            Some(ref numer) => format!(
+                "<NrKSeF>1</NrKSeF>\n      <NrKSeFFaKorygowanej>{}</NrKSeFFaKorygowanej>",
+                escape_xml(numer)
+            ),
+            None => "<NrKSeFN>1</NrKSeFN>".to_string(),
+        };
+        xml.push_str(&format!(
+            r#"    <DaneFaKorygowanej>
+      <DataWystFaKorygowanej>{}</DataWystFaKorygowanej>
+      <NrFaKorygowanej>{}</NrFaKorygowanej>
+      {}
+    </DaneFaKorygowanej>
+"#,
+            faktura.data_wystawienia,
+            escape_xml(&faktura.numer),
+            nr_ksef
+        ));
+    }
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::invoice;
+    use crate::{
+        validate_xml, Decimal, FormVariant, Invoice, InvoiceKind, InvoiceLineItem, VatRate,
+    };
+    use rust_decimal_macros::dec;
+
+    fn line(nr_wiersza: u32, ilosc: Decimal, stan_przed: bool) -> InvoiceLineItem {
+        InvoiceLineItem {
+            nr_wiersza,
+            opis: "Consulting".to_string(),
+            jednostka: "h".to_string(),
+            ilosc,
+            cena_netto: dec!(200),
+            kwota_netto: ilosc * dec!(200),
+            stawka_vat: VatRate::Vat23,
+            stan_przed,
+            zalacznik_15: false,
+        }
+    }
+
+    #[test]
+    fn test_correction_invoice() {
+        let mut invoice = invoice("KOR/1/2026");
+        invoice.data_wystawienia = "2026-02-10".to_string();
+        invoice.wariant = FormVariant::Fa3;
+        invoice.rodzaj = InvoiceKind::Kor;
+        invoice.korekta = Some(Correction {
+            przyczyna: Some("Fewer hours delivered".to_string()),
+            typ: Some(CorrectionType::CorrectionDate),
+            faktury: vec![
+                CorrectedInvoice {
+                    data_wystawienia: "2026-02-01".to_string(),
+                    numer: "FV/2/2026".to_string(),
+                    numer_ksef: Some("5265877635-20260201-0100A0B1C2D3-4E".to_string()),
+                },
+                CorrectedInvoice {
+                    data_wystawienia: "2025-12-01".to_string(),
+                    numer: "FV/12/2025".to_string(),
+                    numer_ksef: None,
+                },
+            ],
+        });
+        invoice.add_line_item(line(1, dec!(10), true));
+        invoice.add_line_item(line(2, dec!(7.5), false));
+
+        // Totals are the differences: -500.00 net, -115.00 VAT
+        assert_eq!(invoice.calculate_total_gross(), dec!(-615));
+        let xml = invoice.generate_ksef_xml();
+        assert!(xml.contains("<P_13_1>-500.00</P_13_1>"));
+        assert!(xml.contains("<RodzajFaktury>KOR</RodzajFaktury>"));
+        assert!(xml.contains("<NrKSeFN>1</NrKSeFN>"));
+        assert!(xml.contains("<P_12>23</P_12>\n      <StanPrzed>1</StanPrzed>"));
+        assert_eq!(validate_xml(&xml, FormVariant::Fa3), Ok(()));
+
+        let read = Invoice::from_ksef_xml(&xml).unwrap();
+        assert_eq!(read.rodzaj, InvoiceKind::Kor);
+        assert_eq!(read.korekta, invoice.korekta);
+        assert!(read.pozycje[0].stan_przed && !read.pozycje[1].stan_przed);
+    }
+}
@@ -1,67 +1,211 @@
 use chrono::{DateTime, Local};
 use serde::Serialize;
 
-/// Represents a party (buyer or seller) in the invoice
-#[derive(Debug, Clone, Serialize)]
-pub struct Party {
-    /// Tax identification number (NIP)
-    #[serde(rename = "NIP")]
-    pub nip: String,
-    /// Name of the party
-    #[serde(rename = "Nazwa")]
-    pub nazwa: String,
-    /// Address of the party
-    #[serde(rename = "AdresL1", skip_serializing_if = "Option::is_none")]
-    pub adres: Option<String>,
+mod advance;
+mod attachment;
+mod correction;
+mod party;
+mod payment;
+mod reader;
+mod schema;
+mod totals;
+mod vat;
+mod xsd;
+
+pub use advance::{AdvanceError, AdvanceInvoice};
+pub use attachment::{AttachmentBlock, AttachmentTable};
+pub use correction::{CorrectedInvoice, Correction, CorrectionType};
+pub use party::{Address, Party, PartyId, Seller};
+pub use payment::{AccountError, BankAccount, PartialPayment, Payment};
+pub use reader::{parse_invoice_xml, ParsedInvoice, ReadError};
+pub use rust_decimal::Decimal;
+pub use schema::{validate_invoice_xml, validate_xml, ValidationError};
+pub use totals::{round_grosze, RateTotal};
+pub use vat::{Exemption, ExemptionError, VatRate};
+
+/// Logical structure (schema) of the generated invoice
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FormVariant {
+    /// FA(2), http://crd.gov.pl/wzor/2023/06/29/12648/
+    #[default]
+    Fa2,
+    /// FA(3), mandatory with KSeF 2.0, http://crd.gov.pl/wzor/2025/06/25/13775/
+    Fa3,
+}
+
+impl FormVariant {
+    /// `kodSystemowy` of the form, also used as the session form code
+    pub fn kod_systemowy(&self) -> &'static str {
+        match self {
+            FormVariant::Fa2 => "FA (2)",
+            FormVariant::Fa3 => "FA (3)",
+        }
+    }
+
+    pub fn wersja_schemy(&self) -> &'static str {
+        "1-0E"
+    }
+
+    /// WariantFormularza
+    pub fn wariant(&self) -> u8 {
+        match self {
+            FormVariant::Fa2 => 2,
+            FormVariant::Fa3 => 3,
+        }
+    }
+
+    pub fn namespace(&self) -> &'static str {
+        match self {
+            FormVariant::Fa2 => "http://crd.gov.pl/wzor/2023/06/29/12648/",
+            FormVariant::Fa3 => "http://crd.gov.pl/wzor/2025/06/25/13775/",
+        }
+    }
+
+    /// Parses a system code such as "FA (3)" (spaces and case are ignored)
+    pub fn from_system_code(code: &str) -> Option<Self> {
+        let code: String = code
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_uppercase();
+        match code.as_str() {
+            "FA(2)" | "FA2" => Some(FormVariant::Fa2),
+            "FA(3)" | "FA3" => Some(FormVariant::Fa3),
+            _ => None,
+        }
+    }
+
+    /// Variant whose namespace is declared on the `Faktura` root element of `xml`
+    pub fn detect(xml: &str) -> Option<Self> {
+        let start = xml.find("<Faktura").or_else(|| xml.find(":Faktura"))?;
+        let end = start + xml[start..].find('>')?;
+        let root = &xml[start..end];
+        [FormVariant::Fa2, FormVariant::Fa3]
+            .into_iter()
+            .find(|variant| root.contains(variant.namespace()))
+    }
+}
+
+/// Invoice type (RodzajFaktury)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InvoiceKind {
+    /// Basic VAT invoice
+    #[default]
+    Vat,
+    /// Correction invoice; the correction data is in `Invoice::korekta`
+    Kor,
+    /// Advance invoice for `Invoice::kwota_zaliczki` on the order in `Invoice::zamowienie`
+    Zal,
+    /// Settlement invoice deducting the advance invoices in `Invoice::zaliczki`
+    Roz,
+    /// Correction of an advance invoice
+    KorZal,
+    /// Correction of a settlement invoice
+    KorRoz,
+    /// Simplified invoice up to 450 PLN or 100 EUR gross (art. 106e ust. 5 pkt 3),
+    /// the buyer is identified by NIP only
+    Upr,
 }
 
-/// Represents a line item in the invoice
+impl InvoiceKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            InvoiceKind::Vat => "VAT",
+            InvoiceKind::Kor => "KOR",
+            InvoiceKind::Zal => "ZAL",
+            InvoiceKind::Roz => "ROZ",
+            InvoiceKind::KorZal => "KOR_ZAL",
+            InvoiceKind::KorRoz => "KOR_ROZ",
+            InvoiceKind::Upr => "UPR",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.trim() {
+            "VAT" => Some(InvoiceKind::Vat),
+            "KOR" => Some(InvoiceKind::Kor),
+            "ZAL" => Some(InvoiceKind::Zal),
+            "ROZ" => Some(InvoiceKind::Roz),
+            "KOR_ZAL" => Some(InvoiceKind::KorZal),
+            "KOR_ROZ" => Some(InvoiceKind::KorRoz),
+            "UPR" => Some(InvoiceKind::Upr),
+            _ => None,
+        }
+    }
+}
+
+/// Represents a line item in the invoice (`FaWiersz`, or `ZamowienieWiersz` for the ordered
+/// items of an advance invoice); serialized with the Rust field names, as the XML names
+/// depend on where the line is emitted
 #[derive(Debug, Clone, Serialize)]
 pub struct InvoiceLineItem {
-    /// Line number
-    #[serde(rename = "NrWierszaFa")]
+    /// Line number (NrWierszaFa)
     pub nr_wiersza: u32,
-    /// Product/service description
-    #[serde(rename = "P_7")]
+    /// Product/service description (P_7)
     pub opis: String,
-    /// Unit of measurement
-    #[serde(rename = "P_8A")]
+    /// Unit of measurement (P_8A)
     pub jednostka: String,
-    /// Quantity
-    #[serde(rename = "P_8B")]
-    pub ilosc: f64,
-    /// Net unit price
-    #[serde(rename = "P_9A")]
-    pub cena_netto: f64,
-    /// Net amount (quantity * unit price)
-    #[serde(rename = "P_11")]
-    pub kwota_netto: f64,
-    /// VAT rate percentage
-    #[serde(rename = "P_12")]
-    pub stawka_vat: u8,
+    /// Quantity (P_8B)
+    pub ilosc: Decimal,
+    /// Net unit price (P_9A), gross with `Invoice::ceny_brutto` (P_9B)
+    pub cena_netto: Decimal,
+    /// Net amount (quantity * unit price, P_11), rounded to grosze in the XML;
+    /// gross with `Invoice::ceny_brutto` (P_11A)
+    pub kwota_netto: Decimal,
+    /// VAT rate (P_12)
+    pub stawka_vat: VatRate,
+    /// State of the line before the correction, subtracted from the totals (KOR only, StanPrzed)
+    pub stan_przed: bool,
+    /// Goods or services listed in Annex 15 to the VAT act, subject to split payment (P_12_Zal_15)
+    pub zalacznik_15: bool,
 }
 
 /// Main invoice structure
 #[derive(Debug, Clone)]
 pub struct Invoice {
     /// Seller (Podmiot1)
-    pub sprzedawca: Party,
-    /// Buyer (Podmiot2)
+    pub sprzedawca: Seller,
+    /// Buyer (Podmiot2); an empty name is omitted, as on simplified invoices
     pub nabywca: Party,
     /// Invoice line items
     pub pozycje: Vec<InvoiceLineItem>,
+    /// Line prices and amounts are gross (P_9B, P_11A); VAT is taken out of
+    /// the gross total of each rate. Ordered items of an advance invoice are always net
+    pub ceny_brutto: bool,
     /// Invoice date
     pub data_wystawienia: String,
     /// Invoice number
     pub numer: String,
     /// Currency code (default: PLN)
     pub waluta: String,
+    /// Payment terms
+    pub platnosc: Option<Payment>,
+    /// Declares the split payment mechanism (P_18A) regardless of the amount, e.g. for
+    /// an invoice in a foreign currency; see `split_payment`
+    pub podzielona_platnosc: bool,
+    /// Schema of the generated XML (default: FA(2))
+    pub wariant: FormVariant,
+    /// Attachment data blocks (FA(3) only)
+    pub zalacznik: Vec<AttachmentBlock>,
+    /// Legal basis of the exemption, set exactly when any line is `VatRate::Zw`
+    /// (see `Invoice::check_exemption`)
+    pub zwolnienie: Option<Exemption>,
+    /// Invoice type (default: VAT)
+    pub rodzaj: InvoiceKind,
+    /// Correction data, required for `InvoiceKind::Kor`, `KorZal` and `KorRoz`
+    pub korekta: Option<Correction>,
+    /// Ordered items of an advance invoice (Zamowienie)
+    pub zamowienie: Vec<InvoiceLineItem>,
+    /// Gross advance received (ZAL, P_15)
+    pub kwota_zaliczki: Option<Decimal>,
+    /// Advance invoices deducted by a settlement invoice (ROZ)
+    pub zaliczki: Vec<AdvanceInvoice>,
 }
 
 impl Invoice {
     /// Creates a new invoice
     pub fn new(
-        sprzedawca: Party,
+        sprzedawca: Seller,
         nabywca: Party,
         data_wystawienia: String,
         numer: String,
@@ -70,9 +214,20 @@ impl Invoice {
             sprzedawca,
             nabywca,
             pozycje: Vec::new(),
+            ceny_brutto: false,
             data_wystawienia,
             numer,
             waluta: "PLN".to_string(),
+            platnosc: None,
+            podzielona_platnosc: false,
+            wariant: FormVariant::default(),
+            zalacznik: Vec::new(),
+            zwolnienie: None,
+            rodzaj: InvoiceKind::default(),
+            korekta: None,
+            zamowienie: Vec::new(),
+            kwota_zaliczki: None,
+            zaliczki: Vec::new(),
         }
     }
 
@@ -81,28 +236,43 @@ impl Invoice {
         self.pozycje.push(item);
     }
 
-    /// Calculates total net amount
-    pub fn calculate_total_net(&self) -> f64 {
-        self.pozycje.iter().map(|p| p.kwota_netto).sum()
+    /// Calculates total net amount; for a correction, the difference
+    pub fn calculate_total_net(&self) -> Decimal {
+        self.rate_totals().iter().map(|t| t.netto).sum()
     }
 
-    /// Calculates total VAT amount
-    pub fn calculate_total_vat(&self) -> f64 {
-        self.pozycje
+    /// Calculates total VAT amount, rounded per rate group
+    pub fn calculate_total_vat(&self) -> Decimal {
+        self.rate_totals().iter().filter_map(|t| t.vat).sum()
+    }
+
+    /// Calculates total gross amount (P_15)
+    pub fn calculate_total_gross(&self) -> Decimal {
+        self.calculate_total_net() + self.calculate_total_vat()
+    }
+
+    /// Gross value of the order of an advance invoice (WartoscZamowienia)
+    pub fn calculate_order_value(&self) -> Decimal {
+        totals::line_totals(self.zamowienie.iter().filter(|i| !i.stan_przed), false)
             .iter()
-            .map(|p| p.kwota_netto * (p.stawka_vat as f64 / 100.0))
+            .map(|t| t.netto + t.vat.unwrap_or_default())
             .sum()
     }
 
-    /// Calculates total gross amount
-    pub fn calculate_total_gross(&self) -> f64 {
-        self.calculate_total_net() + self.calculate_total_vat()
+    /// Highest gross amount of a simplified invoice (UPR) in the invoice currency:
+    /// 450 PLN or 100 EUR; `None` for other currencies
+    pub fn simplified_limit(&self) -> Option<Decimal> {
+        match self.waluta.as_str() {
+            "PLN" => Some(Decimal::from(450)),
+            "EUR" => Some(Decimal::ONE_HUNDRED),
+            _ => None,
+        }
     }
 
     /// Generates KSeF 2.0 compliant XML for the invoice
     ///
-    /// This generates an FA(2) structured VAT invoice according to the KSeF 2.0 format.
-    /// The XML follows the schema: http://crd.gov.pl/wzor/2023/06/29/12648/
+    /// This generates an FA(2) or FA(3) structured VAT invoice, depending on `wariant`.
+    /// Attachments are only emitted for FA(3), the only schema that defines them.
     ///
     /// # Returns
     ///
@@ -111,18 +281,20 @@ impl Invoice {
     /// # Example
     ///
     /// ```
-    /// use ksef_invoice_generator::{Invoice, Party, InvoiceLineItem};
+    /// use ksef_invoice_generator::{
+    ///     Address, Decimal, Invoice, InvoiceLineItem, Party, PartyId, Seller, VatRate,
+    /// };
     ///
-    /// let seller = Party {
+    /// let seller = Seller {
     ///     nip: "1234567890".to_string(),
     ///     nazwa: "Example Company Sp. z o.o.".to_string(),
-    ///     adres: Some("ul. Testowa 1, 00-001 Warszawa".to_string()),
+    ///     adres: Some(Address::new("PL", "ul. Testowa 1, 00-001 Warszawa")),
     /// };
     ///
     /// let buyer = Party {
-    ///     nip: "9876543210".to_string(),
+    ///     identyfikator: PartyId::Nip("9876543210".to_string()),
     ///     nazwa: "Buyer Company Sp. z o.o.".to_string(),
-    ///     adres: Some("ul. Kupiecka 2, 00-002 Warszawa".to_string()),
+    ///     adres: Some(Address::new("PL", "ul. Kupiecka 2, 00-002 Warszawa")),
     /// };
     ///
     /// let mut invoice = Invoice::new(
@@ -136,10 +308,12 @@ impl Invoice {
     ///     nr_wiersza: 1,
     ///     opis: "Usługa konsultingowa".to_string(),
     ///     jednostka: "szt".to_string(),
-    ///     ilosc: 1.0,
-    ///     cena_netto: 1000.0,
-    ///     kwota_netto: 1000.0,
-    ///     stawka_vat: 23,
+    ///     ilosc: Decimal::ONE,
+    ///     cena_netto: Decimal::from(1000),
+    ///     kwota_netto: Decimal::from(1000),
+    ///     stawka_vat: VatRate::Vat23,
+    ///     stan_przed: false,
+    ///     zalacznik_15: false,
     /// };
     ///
     /// invoice.add_line_item(item);
@@ -150,11 +324,31 @@ impl Invoice {
         let now: DateTime<Local> = Local::now();
         let data_wytworzenia = now.to_rfc3339();
 
-        let total_net = self.calculate_total_net();
-        let total_vat = self.calculate_total_vat();
         let total_gross = self.calculate_total_gross();
 
+        // Build rate group totals XML
+        let mut totals_xml = String::new();
+        for total in self.rate_totals() {
+            let group = totals::RATE_GROUPS
+                .iter()
+                .find(|g| g.stawka == total.stawka)
+                .expect("rate total of a known group");
+            totals_xml.push_str(&format!(
+                "    <{0}>{1}</{0}>\n",
+                group.netto,
+                kwota(total.netto)
+            ));
+            if let (Some(field), Some(vat)) = (group.vat, total.vat) {
+                totals_xml.push_str(&format!("    <{0}>{1}</{0}>\n", field, kwota(vat)));
+            }
+        }
+
         // Build line items XML
+        let (cena_pole, kwota_pole) = if self.ceny_brutto {
+            ("P_9B", "P_11A")
+        } else {
+            ("P_9A", "P_11")
+        };
         let mut line_items_xml = String::new();
         for item in &self.pozycje {
             line_items_xml.push_str(&format!(
@@ -163,32 +357,71 @@ impl Invoice {
       <P_7>{}</P_7>
       <P_8A>{}</P_8A>
       <P_8B>{}</P_8B>
-      <P_9A>{:.2}</P_9A>
-      <P_11>{:.2}</P_11>
-      <P_12>{}</P_12>
+      <{cena_pole}>{}</{cena_pole}>
+      <{kwota_pole}>{}</{kwota_pole}>
+      <P_12>{}</P_12>{}{}
     </FaWiersz>
 "#,
                 item.nr_wiersza,
                 escape_xml(&item.opis),
                 escape_xml(&item.jednostka),
-                item.ilosc,
-                item.cena_netto,
-                item.kwota_netto,
-                item.stawka_vat
+                item.ilosc.normalize(),
+                cena(item.cena_netto),
+                kwota(item.kwota_netto),
+                item.stawka_vat.code_for(self.wariant),
+                if item.zalacznik_15 {
+                    "\n      <P_12_Zal_15>1</P_12_Zal_15>"
+                } else {
+                    ""
+                },
+                if item.stan_przed {
+                    "\n      <StanPrzed>1</StanPrzed>"
+                } else {
+                    ""
+                }
             ));
         }
 
-        // Build seller address XML
-        let sprzedawca_adres_xml = if let Some(ref adres) = self.sprzedawca.adres {
-            format!(
-                r#"    <Adres>
-      <KodKraju>PL</KodKraju>
-      <AdresL1>{}</AdresL1>
-    </Adres>"#,
-                escape_xml(adres)
-            )
-        } else {
-            String::new()
+        // Reverse charge (P_18) follows from the line rates
+        let reverse_charge = self
+            .pozycje
+            .iter()
+            .any(|i| i.stawka_vat.is_reverse_charge());
+        let odwrotne_obciazenie = if reverse_charge { 1 } else { 2 };
+        let mpp = if self.split_payment() { 1 } else { 2 };
+        let zwolnienie_xml = match &self.zwolnienie {
+            Some(zwolnienie) => {
+                let (field, podstawa) = zwolnienie.field();
+                format!(
+                    "<P_19>1</P_19>\n        <{0}>{1}</{0}>",
+                    field,
+                    escape_xml(podstawa)
+                )
+            }
+            None => "<P_19N>1</P_19N>".to_string(),
+        };
+
+        // FA(3) requires the JST and GV markers for the buyer
+        let nabywca_znaczniki_xml = match self.wariant {
+            FormVariant::Fa2 => "",
+            FormVariant::Fa3 => "\n    <JST>2</JST>\n    <GV>2</GV>",
+        };
+        let korekta_xml = self
+            .korekta
+            .as_ref()
+            .map(correction::korekta_xml)
+            .unwrap_or_default();
+        let zaliczki_xml = advance::faktury_zaliczkowe_xml(&self.zaliczki);
+        let platnosc_xml = self
+            .platnosc
+            .as_ref()
+            .map(payment::platnosc_xml)
+            .unwrap_or_default();
+        let zamowienie_xml =
+            advance::zamowienie_xml(&self.zamowienie, self.calculate_order_value(), self.wariant);
+        let zalacznik_xml = match self.wariant {
+            FormVariant::Fa2 => String::new(),
+            FormVariant::Fa3 => attachment::zalacznik_xml(&self.zalacznik),
         };
 
         // Generate complete XML document
@@ -197,44 +430,32 @@ impl Invoice {
 <Faktura
     xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
     xmlns:xsd="http://www.w3.org/2001/XMLSchema"
-    xmlns="http://crd.gov.pl/wzor/2023/06/29/12648/">
+    xmlns="{}">
   <Naglowek>
-    <KodFormularza kodSystemowy="FA (2)" wersjaSchemy="1-0E">FA</KodFormularza>
-    <WariantFormularza>2</WariantFormularza>
+    <KodFormularza kodSystemowy="{}" wersjaSchemy="{}">FA</KodFormularza>
+    <WariantFormularza>{}</WariantFormularza>
     <DataWytworzeniaFa>{}</DataWytworzeniaFa>
     <SystemInfo>KSeF Rust Client 1.0</SystemInfo>
   </Naglowek>
   <Podmiot1>
-    <DaneIdentyfikacyjne>
-      <NIP>{}</NIP>
-      <Nazwa>{}</Nazwa>
-    </DaneIdentyfikacyjne>
 {}
   </Podmiot1>
   <Podmiot2>
-    <DaneIdentyfikacyjne>
-      <NIP>{}</NIP>
-      <Nazwa>{}</Nazwa>
-    </DaneIdentyfikacyjne>
-    <NrKlienta>2</NrKlienta>
-    <JST>2</JST>
-    <GV>2</GV>
+{}{}
   </Podmiot2>
   <Fa>
     <KodWaluty>{}</KodWaluty>
     <P_1>{}</P_1>
     <P_1M>dom</P_1M>
     <P_2>{}</P_2>
-    <P_13_1>{:.2}</P_13_1>
-    <P_14_1>{:.2}</P_14_1>
-    <P_15>{:.2}</P_15>
+{}    <P_15>{}</P_15>
     <Adnotacje>
       <P_16>2</P_16>
       <P_17>2</P_17>
-      <P_18>2</P_18>
-      <P_18A>2</P_18A>
+      <P_18>{}</P_18>
+      <P_18A>{}</P_18A>
       <Zwolnienie>
-        <P_19N>1</P_19N>
+        {}
       </Zwolnienie>
       <NoweSrodkiTransportu>
         <P_22N>1</P_22N>
@@ -244,26 +465,51 @@ impl Invoice {
         <P_PMarzyN>1</P_PMarzyN>
       </PMarzy>
     </Adnotacje>
-    <RodzajFaktury>VAT</RodzajFaktury>
-{}  </Fa>
-</Faktura>"#,
+    <RodzajFaktury>{}</RodzajFaktury>
+{}{}{}{}{}  </Fa>
+{}</Faktura>"#,
+            self.wariant.namespace(),
+            self.wariant.kod_systemowy(),
+            self.wariant.wersja_schemy(),
+            self.wariant.wariant(),
             data_wytworzenia,
-            self.sprzedawca.nip,
-            escape_xml(&self.sprzedawca.nazwa),
-            sprzedawca_adres_xml,
-            self.nabywca.nip,
-            escape_xml(&self.nabywca.nazwa),
+            party::podmiot_xml(&Party::from(self.sprzedawca.clone())),
+            party::podmiot_xml(&self.nabywca),
+            nabywca_znaczniki_xml,
             self.waluta,
             self.data_wystawienia,
             escape_xml(&self.numer),
-            total_net,
-            total_vat,
-            total_gross,
-            line_items_xml
+            totals_xml,
+            kwota(total_gross),
+            odwrotne_obciazenie,
+            mpp,
+            zwolnienie_xml,
+            self.rodzaj.code(),
+            korekta_xml,
+            zaliczki_xml,
+            line_items_xml,
+            platnosc_xml,
+            zamowienie_xml,
+            zalacznik_xml
         )
     }
 }
 
+/// Amount in grosze precision (TKwotowy)
+fn kwota(amount: Decimal) -> String {
+    format!("{:.2}", round_grosze(amount))
+}
+
+/// Unit price with at least two and at most eight decimal places (TKwotowy2)
+fn cena(price: Decimal) -> String {
+    let price = price.round_dp(8).normalize();
+    if price.scale() < 2 {
+        format!("{:.2}", price)
+    } else {
+        price.to_string()
+    }
+}
+
 /// Helper function to escape XML special characters
 fn escape_xml(text: &str) -> String {
     text.replace('&', "&amp;")
@@ -274,19 +520,39 @@ fn escape_xml(text: &str) -> String {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use rust_decimal_macros::dec;
+
+    /// Invoice between two Polish companies, shared by the tests of all modules
+    pub(crate) fn invoice(numer: &str) -> Invoice {
+        let adres = Some(Address::new("PL", "ul. Prosta 1, 00-001 Warszawa"));
+        Invoice::new(
+            Seller {
+                nip: "5265877635".to_string(),
+                nazwa: "Party".to_string(),
+                adres: adres.clone(),
+            },
+            Party {
+                identyfikator: PartyId::Nip("7352765225".to_string()),
+                nazwa: "Party".to_string(),
+                adres,
+            },
+            "2026-02-01".to_string(),
+            numer.to_string(),
+        )
+    }
 
     #[test]
     fn test_invoice_creation() {
-        let seller = Party {
+        let seller = Seller {
             nip: "1234567890".to_string(),
             nazwa: "Test Seller".to_string(),
-            adres: Some("Test Address 1".to_string()),
+            adres: Some(Address::new("PL", "Test Address 1")),
         };
 
         let buyer = Party {
-            nip: "9876543210".to_string(),
+            identyfikator: PartyId::Nip("9876543210".to_string()),
             nazwa: "Test Buyer".to_string(),
             adres: None,
         };
@@ -294,21 +560,21 @@ mod tests {
         let invoice = Invoice::new(seller, buyer, "2026-01-03".to_string(), "FV/1/2026".to_string());
 
         assert_eq!(invoice.sprzedawca.nip, "1234567890");
-        assert_eq!(invoice.nabywca.nip, "9876543210");
+        assert_eq!(invoice.nabywca.identyfikator.nip(), Some("9876543210"));
         assert_eq!(invoice.data_wystawienia, "2026-01-03");
         assert_eq!(invoice.numer, "FV/1/2026");
     }
 
     #[test]
     fn test_add_line_item() {
-        let seller = Party {
+        let seller = Seller {
             nip: "1234567890".to_string(),
             nazwa: "Test Seller".to_string(),
-            adres: Some("Test Address".to_string()),
+            adres: Some(Address::new("PL", "Test Address")),
         };
 
         let buyer = Party {
-            nip: "9876543210".to_string(),
+            identyfikator: PartyId::Nip("9876543210".to_string()),
             nazwa: "Test Buyer".to_string(),
             adres: None,
         };
@@ -319,10 +585,12 @@ mod tests {
             nr_wiersza: 1,
             opis: "Test Item".to_string(),
             jednostka: "szt".to_string(),
-            ilosc: 2.0,
-            cena_netto: 100.0,
-            kwota_netto: 200.0,
-            stawka_vat: 23,
+            ilosc: dec!(2),
+            cena_netto: dec!(100),
+            kwota_netto: dec!(200),
+            stawka_vat: VatRate::Vat23,
+            stan_przed: false,
+            zalacznik_15: false,
         };
 
         invoice.add_line_item(item);
@@ -333,14 +601,14 @@ mod tests {
 
     #[test]
     fn test_calculate_totals() {
-        let seller = Party {
+        let seller = Seller {
             nip: "1234567890".to_string(),
             nazwa: "Test Seller".to_string(),
-            adres: Some("Test Address".to_string()),
+            adres: Some(Address::new("PL", "Test Address")),
         };
 
         let buyer = Party {
-            nip: "9876543210".to_string(),
+            identyfikator: PartyId::Nip("9876543210".to_string()),
             nazwa: "Test Buyer".to_string(),
             adres: None,
         };
@@ -351,40 +619,44 @@ mod tests {
             nr_wiersza: 1,
             opis: "Item 1".to_string(),
             jednostka: "szt".to_string(),
-            ilosc: 2.0,
-            cena_netto: 100.0,
-            kwota_netto: 200.0,
-            stawka_vat: 23,
+            ilosc: dec!(2),
+            cena_netto: dec!(100),
+            kwota_netto: dec!(200),
+            stawka_vat: VatRate::Vat23,
+            stan_przed: false,
+            zalacznik_15: false,
         };
 
         let item2 = InvoiceLineItem {
             nr_wiersza: 2,
             opis: "Item 2".to_string(),
             jednostka: "szt".to_string(),
-            ilosc: 1.0,
-            cena_netto: 300.0,
-            kwota_netto: 300.0,
-            stawka_vat: 23,
+            ilosc: dec!(1),
+            cena_netto: dec!(300),
+            kwota_netto: dec!(300),
+            stawka_vat: VatRate::Vat23,
+            stan_przed: false,
+            zalacznik_15: false,
         };
 
         invoice.add_line_item(item1);
         invoice.add_line_item(item2);
 
-        assert_eq!(invoice.calculate_total_net(), 500.0);
-        assert_eq!(invoice.calculate_total_vat(), 115.0);
-        assert_eq!(invoice.calculate_total_gross(), 615.0);
+        assert_eq!(invoice.calculate_total_net(), dec!(500));
+        assert_eq!(invoice.calculate_total_vat(), dec!(115));
+        assert_eq!(invoice.calculate_total_gross(), dec!(615));
     }
 
     #[test]
     fn test_generate_xml() {
-        let seller = Party {
+        let seller = Seller {
             nip: "1234567890".to_string(),
             nazwa: "Example Company".to_string(),
-            adres: Some("ul. Testowa 1".to_string()),
+            adres: Some(Address::new("PL", "ul. Testowa 1")),
         };
 
         let buyer = Party {
-            nip: "9876543210".to_string(),
+            identyfikator: PartyId::Nip("9876543210".to_string()),
             nazwa: "Buyer Company".to_string(),
             adres: None,
         };
@@ -395,10 +667,12 @@ mod tests {
             nr_wiersza: 1,
             opis: "Test Service".to_string(),
             jednostka: "szt".to_string(),
-            ilosc: 1.0,
-            cena_netto: 1000.0,
-            kwota_netto: 1000.0,
-            stawka_vat: 23,
+            ilosc: dec!(1),
+            cena_netto: dec!(1000),
+            kwota_netto: dec!(1000),
+            stawka_vat: VatRate::Vat23,
+            stan_przed: false,
+            zalacznik_15: false,
         };
 
         invoice.add_line_item(item);
@@ -411,6 +685,108 @@ mod tests {
         assert!(xml.contains("<NIP>1234567890</NIP>"));
         assert!(xml.contains("<Nazwa>Example Company</Nazwa>"));
         assert!(xml.contains("<P_15>1230.00</P_15>"));
+        assert!(xml.contains("<P_18>2</P_18>"));
+
+        invoice.pozycje[0].stawka_vat = VatRate::Oo;
+        let xml = invoice.generate_ksef_xml();
+        assert!(xml.contains("<P_12>oo</P_12>"));
+        assert!(xml.contains("<P_13_10>1000.00</P_13_10>"));
+        assert!(xml.contains("<P_18>1</P_18>"));
+    }
+
+    #[test]
+    fn test_generate_fa3_xml() {
+        let seller = Seller {
+            nip: "1234567890".to_string(),
+            nazwa: "Example Company".to_string(),
+            adres: Some(Address::new("PL", "ul. Testowa 1")),
+        };
+
+        let buyer = Party {
+            identyfikator: PartyId::Nip("9876543210".to_string()),
+            nazwa: "Buyer Company".to_string(),
+            adres: Some(Address::new("PL", "ul. Kupiecka 2")),
+        };
+
+        let mut invoice = Invoice::new(
+            seller,
+            buyer,
+            "2026-02-01".to_string(),
+            "FV/2/2026".to_string(),
+        );
+        invoice.wariant = FormVariant::Fa3;
+        invoice.zalacznik.push(AttachmentBlock {
+            naglowek: Some("Delivery".to_string()),
+            metadane: vec![("Order".to_string(), "ZAM/7".to_string())],
+            akapity: vec!["Delivered in two parts".to_string()],
+            tabele: vec![AttachmentTable {
+                opis: None,
+                kolumny: vec!["Part".to_string(), "Date".to_string()],
+                wiersze: vec![vec!["1".to_string(), "2026-01-20".to_string()]],
+            }],
+        });
+
+        let xml = invoice.generate_ksef_xml();
+
+        assert!(xml.contains("xmlns=\"http://crd.gov.pl/wzor/2025/06/25/13775/\""));
+        assert!(xml.contains(r#"<KodFormularza kodSystemowy="FA (3)" wersjaSchemy="1-0E">"#));
+        assert!(xml.contains("<WariantFormularza>3</WariantFormularza>"));
+        assert!(xml.contains("</Adres>\n    <JST>2</JST>\n    <GV>2</GV>\n  </Podmiot2>"));
+        assert!(xml.contains("<ZKlucz>Order</ZKlucz>"));
+        assert!(xml.contains("<Kol Typ=\"txt\">"));
+        assert!(xml.ends_with("  </Zalacznik>\n</Faktura>"));
+
+        let read = Invoice::from_ksef_xml(&xml).unwrap();
+        assert_eq!(read.wariant, FormVariant::Fa3);
+        assert_eq!(read.zalacznik, invoice.zalacznik);
+
+        invoice.wariant = FormVariant::Fa2;
+        let xml = invoice.generate_ksef_xml();
+        assert!(!xml.contains("<JST>"));
+        assert!(!xml.contains("<Zalacznik>"));
+    }
+
+    #[test]
+    fn test_simplified_invoice() {
+        let mut invoice = invoice("P/1");
+        invoice.nabywca.nazwa.clear();
+        invoice.nabywca.adres = None;
+        invoice.wariant = FormVariant::Fa3;
+        invoice.rodzaj = InvoiceKind::Upr;
+        invoice.ceny_brutto = true;
+        for (nr_wiersza, brutto, stawka_vat) in [
+            (1, dec!(0.99), VatRate::Vat23),
+            (2, dec!(0.99), VatRate::Vat23),
+            (3, dec!(10.80), VatRate::Vat8),
+        ] {
+            invoice.add_line_item(InvoiceLineItem {
+                nr_wiersza,
+                opis: "Item".to_string(),
+                jednostka: "szt".to_string(),
+                ilosc: dec!(1),
+                cena_netto: brutto,
+                kwota_netto: brutto,
+                stawka_vat,
+                stan_przed: false,
+                zalacznik_15: false,
+            });
+        }
+
+        // VAT is taken out of the gross sum: 1.98 * 23/123 = 0.3702 -> 0.37
+        assert_eq!(invoice.calculate_total_vat(), dec!(1.17));
+        assert_eq!(invoice.calculate_total_gross(), dec!(12.78));
+        assert!(invoice.calculate_total_gross() <= invoice.simplified_limit().unwrap());
+
+        let xml = invoice.generate_ksef_xml();
+        assert!(xml.contains("<P_9B>0.99</P_9B>\n      <P_11A>0.99</P_11A>"));
+        assert!(xml.contains("<P_13_1>1.61</P_13_1>"));
+        assert!(xml.contains("<NIP>7352765225</NIP>\n    </DaneIdentyfikacyjne>"));
+        assert_eq!(validate_xml(&xml, FormVariant::Fa3), Ok(()));
+
+        let read = Invoice::from_ksef_xml(&xml).unwrap();
+        assert_eq!(read.rodzaj, InvoiceKind::Upr);
+        assert!(read.ceny_brutto);
+        assert_eq!(read.calculate_total_gross(), dec!(12.78));
     }
 
     #[test]
@@ -0,0 +1,236 @@
+//! Parties of an invoice (`Podmiot1`, `Podmiot2`): identifier, name and address.
+//!
+//! The seller is always identified by NIP (`Seller`). A buyer may instead have
+//! an EU VAT number, a foreign tax number or no identifier at all (a consumer).
+
+use serde::Serialize;
+
+use crate::escape_xml;
+
+/// Identifier of a party (`DaneIdentyfikacyjne`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum PartyId {
+    /// Polish tax identification number
+    #[serde(rename = "NIP")]
+    Nip(String),
+    /// EU VAT number without the country prefix, with the country code (KodUE)
+    #[serde(rename = "NrVatUE")]
+    VatUe {
+        #[serde(rename = "KodUE")]
+        kod_ue: String,
+        #[serde(rename = "NrVatUE")]
+        nr_vat_ue: String,
+    },
+    /// Other tax number, with the issuing country when known (KodKraju)
+    #[serde(rename = "NrID")]
+    NrId {
+        #[serde(rename = "KodKraju")]
+        kod_kraju: Option<String>,
+        #[serde(rename = "NrID")]
+        nr_id: String,
+    },
+    /// No identifier, e.g. a consumer (BrakID)
+    #[serde(rename = "BrakID")]
+    BrakId,
+}
+
+impl PartyId {
+    /// NIP of a Polish party
+    pub fn nip(&self) -> Option<&str> {
+        match self {
+            PartyId::Nip(nip) => Some(nip),
+            _ => None,
+        }
+    }
+
+    /// Identifier as a single number: the NIP, the EU VAT number with its
+    /// country prefix or the foreign tax number
+    pub fn numer(&self) -> Option<String> {
+        match self {
+            PartyId::Nip(nip) => Some(nip.clone()),
+            PartyId::VatUe { kod_ue, nr_vat_ue } => Some(format!("{}{}", kod_ue, nr_vat_ue)),
+            PartyId::NrId { nr_id, .. } => Some(nr_id.clone()),
+            PartyId::BrakId => None,
+        }
+    }
+}
+
+/// Postal address (`Adres`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Address {
+    /// Country code, e.g. "PL" (KodKraju)
+    #[serde(rename = "KodKraju")]
+    pub kod_kraju: String,
+    /// First line, e.g. street and number
+    #[serde(rename = "AdresL1")]
+    pub adres_l1: String,
+    /// Second line, e.g. postal code and city
+    #[serde(rename = "AdresL2", skip_serializing_if = "Option::is_none")]
+    pub adres_l2: Option<String>,
+}
+
+impl Address {
+    /// Single-line address in the given country
+    pub fn new(kod_kraju: &str, adres_l1: &str) -> Self {
+        Self {
+            kod_kraju: kod_kraju.to_string(),
+            adres_l1: adres_l1.to_string(),
+            adres_l2: None,
+        }
+    }
+}
+
+/// Buyer of the invoice (`Podmiot2`)
+#[derive(Debug, Clone, Serialize)]
+pub struct Party {
+    /// Tax identifier
+    #[serde(rename = "DaneIdentyfikacyjne")]
+    pub identyfikator: PartyId,
+    /// Name of the party; an empty name is omitted
+    #[serde(rename = "Nazwa")]
+    pub nazwa: String,
+    /// Address of the party
+    #[serde(rename = "Adres", skip_serializing_if = "Option::is_none")]
+    pub adres: Option<Address>,
+}
+
+/// Seller of the invoice (`Podmiot1`), which must be identified by NIP
+#[derive(Debug, Clone, Serialize)]
+pub struct Seller {
+    /// Tax identification number
+    #[serde(rename = "NIP")]
+    pub nip: String,
+    /// Name of the seller
+    #[serde(rename = "Nazwa")]
+    pub nazwa: String,
+    /// Address of the seller
+    #[serde(rename = "Adres", skip_serializing_if = "Option::is_none")]
+    pub adres: Option<Address>,
+}
+
+impl From<Seller> for Party {
+    fn from(seller: Seller) -> Self {
+        Self {
+            identyfikator: PartyId::Nip(seller.nip),
+            nazwa: seller.nazwa,
+            adres: seller.adres,
+        }
+    }
+}
+
+/// `DaneIdentyfikacyjne` and `Adres` of a party
+pub(crate) fn podmiot_xml(party: &Party) -> String {
+    let identyfikator = match party.identyfikator {
+        PartyId::Nip(ref nip) => format!("<NIP>{}</NIP>", escape_xml(nip)),
+        PartyId::VatUe {
+            ref kod_ue,
+            ref nr_vat_ue,
+        } => format!(
+            "<KodUE>{}</KodUE>\n      <NrVatUE>{}</NrVatUE>",
+            escape_xml(kod_ue),
+            escape_xml(nr_vat_ue)
+        ),
+        PartyId::NrId {
+            ref kod_kraju,
+            ref nr_id,
+        } => format!(
+            "{}<NrID>{}</NrID>",
+            kod_kraju
+                .as_ref()
+                .map(|kod| format!("<KodKraju>{}</KodKraju>\n      ", escape_xml(kod)))
+                .unwrap_or_default(),
+            escape_xml(nr_id)
+        ),
+        PartyId::BrakId => "<BrakID>1</BrakID>".to_string(),
+    };
+    let nazwa = if party.nazwa.is_empty() {
+        String::new()
+    } else {
+        format!("\n      <Nazwa>{}</Nazwa>", escape_xml(&party.nazwa))
+    };
+    let adres = match party.adres {
+        Some(ref adres) => format!(
+            "\n    <Adres>\n      <KodKraju>{}</KodKraju>\n      <AdresL1>{}</AdresL1>{}\n    </Adres>",
+            escape_xml(&adres.kod_kraju),
+            escape_xml(&adres.adres_l1),
+            adres
+                .adres_l2
+                .as_ref()
+                .map(|linia| format!("\n      <AdresL2>{}</AdresL2>", escape_xml(linia)))
+                .unwrap_or_default()
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        "    <DaneIdentyfikacyjne>\n      {}{}\n    </DaneIdentyfikacyjne>{}",
+        identyfikator, nazwa, adres
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{validate_xml, Decimal, FormVariant, Invoice, InvoiceLineItem, VatRate};
+
+    #[test]
+    fn test_foreign_buyers() {
+        let seller = Seller {
+            nip: "5265877635".to_string(),
+            nazwa: "Seller".to_string(),
+            adres: Some(Address {
+                kod_kraju: "PL".to_string(),
+                adres_l1: "ul. Prosta 1".to_string(),
+                adres_l2: Some("00-001 Warszawa".to_string()),
+            }),
+        };
+        let buyers = [
+            PartyId::VatUe {
+                kod_ue: "DE".to_string(),
+                nr_vat_ue: "123456789".to_string(),
+            },
+            PartyId::NrId {
+                kod_kraju: Some("US".to_string()),
+                nr_id: "12-3456789".to_string(),
+            },
+            PartyId::BrakId,
+        ];
+
+        for identyfikator in buyers {
+            let buyer = Party {
+                identyfikator,
+                nazwa: "Buyer".to_string(),
+                adres: Some(Address::new("DE", "Hauptstraße 1, 10115 Berlin")),
+            };
+            let mut invoice = Invoice::new(
+                seller.clone(),
+                buyer,
+                "2026-02-01".to_string(),
+                "FV/4/2026".to_string(),
+            );
+            invoice.add_line_item(InvoiceLineItem {
+                nr_wiersza: 1,
+                opis: "Machine part".to_string(),
+                jednostka: "szt".to_string(),
+                ilosc: Decimal::ONE,
+                cena_netto: Decimal::ONE_HUNDRED,
+                kwota_netto: Decimal::ONE_HUNDRED,
+                stawka_vat: VatRate::ZeroWdt,
+                stan_przed: false,
+                zalacznik_15: false,
+            });
+
+            for wariant in [FormVariant::Fa2, FormVariant::Fa3] {
+                invoice.wariant = wariant;
+                let xml = invoice.generate_ksef_xml();
+                assert!(xml.contains("<AdresL2>00-001 Warszawa</AdresL2>"));
+                assert_eq!(validate_xml(&xml, wariant), Ok(()));
+
+                let read = Invoice::from_ksef_xml(&xml).unwrap();
+                assert_eq!(read.nabywca.identyfikator, invoice.nabywca.identyfikator);
+                assert_eq!(read.nabywca.adres, invoice.nabywca.adres);
+                assert_eq!(read.sprzedawca.adres, invoice.sprzedawca.adres);
+            }
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! Payment terms (`Platnosc`) and the split payment annotation (`P_18A`).
+//!
+//! Account numbers given to `BankAccount::new` are checked with the IBAN
+//! mod-97 checksum; a domestic 26-digit NRB is checked as a PL IBAN.
+
+use rust_decimal::Decimal;
+
+use crate::{escape_xml, kwota, Invoice};
+
+/// Error returned for an invalid bank account
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AccountError {
+    #[error("Invalid bank account number: {0}")]
+    Number(String),
+
+    #[error("Invalid SWIFT code: {0}")]
+    Swift(String),
+}
+
+/// Bank account (`RachunekBankowy`, `RachunekBankowyFaktora`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BankAccount {
+    /// NRB or IBAN without spaces (NrRB)
+    pub numer: String,
+    /// SWIFT/BIC code of the bank
+    pub swift: Option<String>,
+    /// NazwaBanku
+    pub nazwa_banku: Option<String>,
+    /// OpisRachunku
+    pub opis: Option<String>,
+}
+
+impl BankAccount {
+    /// Account with a checked number and SWIFT code; spaces are removed and
+    /// letters upper-cased
+    pub fn new(numer: &str, swift: Option<&str>) -> Result<Self, AccountError> {
+        let normalize = |text: &str| -> String {
+            text.chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<String>()
+                .to_uppercase()
+        };
+
+        let numer = normalize(numer);
+        if !valid_account_number(&numer) {
+            return Err(AccountError::Number(numer));
+        }
+        let swift = swift.map(normalize);
+        if let Some(ref swift) = swift {
+            if !valid_swift(swift) {
+                return Err(AccountError::Swift(swift.clone()));
+            }
+        }
+
+        Ok(Self {
+            numer,
+            swift,
+            nazwa_banku: None,
+            opis: None,
+        })
+    }
+}
+
+/// Partial payment (`ZaplataCzesciowa`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialPayment {
+    pub kwota: Decimal,
+    pub data: String,
+}
+
+/// Payment terms (Platnosc)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Payment {
+    /// Due dates (TerminPlatnosci)
+    pub terminy: Vec<String>,
+    /// Payment method code (FormaPlatnosci, e.g. 1 - cash, 6 - transfer)
+    pub forma: Option<String>,
+    /// Seller's bank accounts (RachunekBankowy)
+    pub rachunki: Vec<BankAccount>,
+    /// Accounts of the factor the receivable was assigned to (RachunekBankowyFaktora)
+    pub rachunki_faktora: Vec<BankAccount>,
+    /// Whether the invoice has been paid in full (Zaplacono); requires `data_zaplaty`
+    pub zaplacono: bool,
+    pub data_zaplaty: Option<String>,
+    /// Payments received so far, for an invoice not paid in full
+    pub zaplaty_czesciowe: Vec<PartialPayment>,
+}
+
+impl Invoice {
+    /// Whether the split payment mechanism is declared (`P_18A`): a PLN invoice
+    /// of 15 000 PLN gross or more with Annex 15 goods, or `podzielona_platnosc`
+    pub fn split_payment(&self) -> bool {
+        let zalacznik_15 = self
+            .pozycje
+            .iter()
+            .chain(&self.zamowienie)
+            .any(|item| item.zalacznik_15 && !item.stan_przed);
+        self.podzielona_platnosc
+            || zalacznik_15
+                && self.waluta == "PLN"
+                && self.calculate_total_gross() >= Decimal::from(15_000)
+    }
+}
+
+/// IBAN mod-97 check; a 26-digit number is a Polish NRB
+fn valid_account_number(numer: &str) -> bool {
+    let iban = if numer.len() == 26 && numer.bytes().all(|b| b.is_ascii_digit()) {
+        format!("PL{}", numer)
+    } else {
+        numer.to_string()
+    };
+    let bytes = iban.as_bytes();
+    if !(15..=34).contains(&bytes.len())
+        || !bytes[..2].iter().all(u8::is_ascii_uppercase)
+        || !bytes[2..4].iter().all(u8::is_ascii_digit)
+        || !bytes.iter().all(u8::is_ascii_alphanumeric)
+    {
+        return false;
+    }
+
+    // Country code and check digits move to the end, letters count as 10..35
+    let (poczatek, reszta) = iban.split_at(4);
+    let mut modulo = 0;
+    for c in reszta.chars().chain(poczatek.chars()) {
+        let value = c.to_digit(36).expect("alphanumeric");
+        modulo = if value < 10 {
+            (modulo * 10 + value) % 97
+        } else {
+            (modulo * 100 + value) % 97
+        };
+    }
+    modulo == 1
+}
+
+/// Bank code, country, location and optional branch (SWIFT_Type)
+fn valid_swift(swift: &str) -> bool {
+    let bytes = swift.as_bytes();
+    matches!(bytes.len(), 8 | 11)
+        && bytes[..6].iter().all(u8::is_ascii_uppercase)
+        && bytes[6..]
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+}
+
+/// `Platnosc`, following the invoice lines
+pub(crate) fn platnosc_xml(platnosc: &Payment) -> String {
+    let mut xml = String::from("    <Platnosc>\n");
+    if platnosc.zaplacono {
+        xml.push_str("      <Zaplacono>1</Zaplacono>\n");
+        if let Some(ref data) = platnosc.data_zaplaty {
+            xml.push_str(&format!("      <DataZaplaty>{}</DataZaplaty>\n", data));
+        }
+    } else if !platnosc.zaplaty_czesciowe.is_empty() {
+        xml.push_str("      <ZnacznikZaplatyCzesciowej>1</ZnacznikZaplatyCzesciowej>\n");
+        for zaplata in &platnosc.zaplaty_czesciowe {
+            xml.push_str(&format!(
+                r#"      <ZaplataCzesciowa>
+        <KwotaZaplatyCzesciowej>{}</KwotaZaplatyCzesciowej>
+        <DataZaplatyCzesciowej>{}</DataZaplatyCzesciowej>
+      </ZaplataCzesciowa>
+"#,
+                kwota(zaplata.kwota),
+                zaplata.data
+            ));
+        }
+    }
+    for termin in &platnosc.terminy {
+        xml.push_str(&format!(
+            "      <TerminPlatnosci>\n        <Termin>{}</Termin>\n      </TerminPlatnosci>\n",
+            termin
+        ));
+    }
+    if let Some(ref forma) = platnosc.forma {
+        xml.push_str(&format!(
+            "      <FormaPlatnosci>{}</FormaPlatnosci>\n",
+            forma
+        ));
+    }
+    for (element, rachunki) in [
+        ("RachunekBankowy", &platnosc.rachunki),
+        ("RachunekBankowyFaktora", &platnosc.rachunki_faktora),
+    ] {
+        for rachunek in rachunki {
+            xml.push_str(&format!(
+                "      <{}>\n        <NrRB>{}</NrRB>\n",
+                element,
+                escape_xml(&rachunek.numer)
+            ));
+            for (pole, wartosc) in [
+                ("SWIFT", &rachunek.swift),
+                ("NazwaBanku", &rachunek.nazwa_banku),
+                ("OpisRachunku", &rachunek.opis),
+            ] {
+                if let Some(wartosc) = wartosc {
+                    xml.push_str(&format!(
+                        "        <{0}>{1}</{0}>\n",
+                        pole,
+                        escape_xml(wartosc)
+                    ));
+                }
+            }
+            xml.push_str(&format!("      </{}>\n", element));
+        }
+    }
+    xml.push_str("    </Platnosc>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::invoice;
+    use crate::{validate_xml, FormVariant, InvoiceLineItem, VatRate};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_bank_account_validation() {
+        let nrb = BankAccount::new("61 1090 1014 0000 0712 1981 2874", Some("wbkpplpp")).unwrap();
+        assert_eq!(nrb.numer, "61109010140000071219812874");
+        assert_eq!(nrb.swift.as_deref(), Some("WBKPPLPP"));
+        assert!(BankAccount::new("DE89 3704 0044 0532 0130 00", None).is_ok());
+        assert!(BankAccount::new("PL61109010140000071219812874", None).is_ok());
+
+        assert_eq!(
+            BankAccount::new("61109010140000071219812875", None),
+            Err(AccountError::Number(
+                "61109010140000071219812875".to_string()
+            ))
+        );
+        assert!(BankAccount::new("12345", None).is_err());
+        assert_eq!(
+            BankAccount::new("DE89370400440532013000", Some("COBA-DE")),
+            Err(AccountError::Swift("COBA-DE".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_payment_and_split_payment() {
+        let mut invoice = invoice("FV/3/2026");
+        invoice.wariant = FormVariant::Fa3;
+        // Smartphones (Annex 15 item 28), 12 200.00 net + 2 806.00 VAT = 15 006.00 gross
+        invoice.add_line_item(InvoiceLineItem {
+            nr_wiersza: 1,
+            opis: "Smartphone".to_string(),
+            jednostka: "szt".to_string(),
+            ilosc: dec!(4),
+            cena_netto: dec!(3050),
+            kwota_netto: dec!(12200),
+            stawka_vat: VatRate::Vat23,
+            stan_przed: false,
+            zalacznik_15: true,
+        });
+        let mut faktor = BankAccount::new("DE89370400440532013000", Some("COBADEFFXXX")).unwrap();
+        faktor.nazwa_banku = Some("Commerzbank".to_string());
+        invoice.platnosc = Some(Payment {
+            terminy: vec!["2026-02-15".to_string(), "2026-03-15".to_string()],
+            forma: Some("6".to_string()),
+            rachunki: vec![BankAccount::new("61109010140000071219812874", None).unwrap()],
+            rachunki_faktora: vec![faktor],
+            zaplaty_czesciowe: vec![PartialPayment {
+                kwota: dec!(5000),
+                data: "2026-02-01".to_string(),
+            }],
+            ..Payment::default()
+        });
+
+        assert!(invoice.split_payment());
+        let xml = invoice.generate_ksef_xml();
+        assert!(xml.contains("<P_18A>1</P_18A>"));
+        assert!(xml.contains("<P_12>23</P_12>\n      <P_12_Zal_15>1</P_12_Zal_15>"));
+        assert!(xml.contains("<KwotaZaplatyCzesciowej>5000.00</KwotaZaplatyCzesciowej>"));
+        assert_eq!(validate_xml(&xml, FormVariant::Fa3), Ok(()));
+
+        let read = Invoice::from_ksef_xml(&xml).unwrap();
+        assert_eq!(read.platnosc, invoice.platnosc);
+        assert!(read.pozycje[0].zalacznik_15 && read.podzielona_platnosc);
+
+        // Below the threshold the annotation is not set
+        invoice.pozycje[0].kwota_netto = dec!(12000);
+        assert!(!invoice.split_payment());
+        assert!(invoice.generate_ksef_xml().contains("<P_18A>2</P_18A>"));
+    }
+}
@@ -0,0 +1,717 @@
+//! Reader for KSeF invoice XML (FA(2) and FA(3)).
+//!
+//! Elements are matched by local name, so both schema namespaces are accepted.
+//! Elements the `Invoice` model has no place for are skipped.
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::totals::RateTotal;
+use crate::{
+    Address, AdvanceInvoice, AttachmentBlock, AttachmentTable, BankAccount, CorrectedInvoice,
+    Correction, CorrectionType, Exemption, FormVariant, Invoice, InvoiceKind, InvoiceLineItem,
+    PartialPayment, Party, PartyId, Payment, Seller, VatRate,
+};
+
+/// Error returned when an invoice XML cannot be read
+#[derive(Debug, thiserror::Error)]
+pub enum ReadError {
+    #[error("Invalid invoice XML: {0}")]
+    Xml(#[from] quick_xml::DeError),
+
+    #[error("Unsupported invoice content: {0}")]
+    Unsupported(String),
+}
+
+/// Invoice read from XML together with the document-level data
+#[derive(Debug, Clone)]
+pub struct ParsedInvoice {
+    pub invoice: Invoice,
+    /// `kodSystemowy` of the form, e.g. "FA (2)" or "FA (3)"
+    pub kod_systemowy: String,
+    /// `RodzajFaktury` (VAT, KOR, ZAL, ROZ, UPR, ...)
+    pub rodzaj_faktury: String,
+    /// Totals per rate group as declared in the document
+    pub sumy: Vec<RateTotal>,
+    /// Total amount due (`P_15`)
+    pub kwota_naleznosci: Decimal,
+}
+
+/// Parses a Faktura document
+pub fn parse_invoice_xml(xml: &str) -> Result<ParsedInvoice, ReadError> {
+    let faktura: FakturaXml = quick_xml::de::from_str(xml)?;
+    let fa = faktura.fa;
+    let korekta = correction(&fa);
+    // Lines of a simplified invoice may carry gross prices only
+    let ceny_brutto = fa
+        .wiersze
+        .iter()
+        .any(|w| w.p_11.is_none() && w.p_11a.is_some());
+
+    let pozycje = fa
+        .wiersze
+        .into_iter()
+        .map(line_item)
+        .collect::<Result<Vec<_>, _>>()?;
+    let zamowienie = fa
+        .zamowienie
+        .map(|z| z.wiersze.into_iter().map(line_item).collect())
+        .transpose()?
+        .unwrap_or_default();
+    let rodzaj_zal = matches!(fa.rodzaj_faktury.as_str(), "ZAL" | "KOR_ZAL");
+
+    let sumy = [
+        ("23%", fa.p_13_1, fa.p_14_1),
+        ("8%", fa.p_13_2, fa.p_14_2),
+        ("5%", fa.p_13_3, fa.p_14_3),
+        ("4%", fa.p_13_4, fa.p_14_4),
+        ("OSS", fa.p_13_5, fa.p_14_5),
+        ("0%", fa.p_13_6_1, None),
+        ("0% WDT", fa.p_13_6_2, None),
+        ("0% EX", fa.p_13_6_3, None),
+        ("zw", fa.p_13_7, None),
+        ("np I", fa.p_13_8, None),
+        ("np II", fa.p_13_9, None),
+        ("oo", fa.p_13_10, None),
+        ("marża", fa.p_13_11, None),
+    ]
+    .into_iter()
+    .filter_map(|(stawka, netto, vat)| netto.map(|netto| RateTotal { stawka, netto, vat }))
+    .collect();
+
+    let invoice = Invoice {
+        sprzedawca: seller(faktura.podmiot1)?,
+        nabywca: party(faktura.podmiot2),
+        pozycje,
+        ceny_brutto,
+        data_wystawienia: fa.p_1,
+        numer: fa.p_2,
+        waluta: fa.kod_waluty,
+        platnosc: fa.platnosc.map(|p| Payment {
+            terminy: p.terminy.into_iter().filter_map(|t| t.termin).collect(),
+            forma: p.forma,
+            rachunki: p.rachunki.into_iter().map(bank_account).collect(),
+            rachunki_faktora: p.rachunki_faktora.into_iter().map(bank_account).collect(),
+            zaplacono: p.zaplacono.as_deref() == Some("1"),
+            data_zaplaty: p.data_zaplaty,
+            zaplaty_czesciowe: p
+                .zaplaty_czesciowe
+                .into_iter()
+                .map(|z| PartialPayment {
+                    kwota: z.kwota,
+                    data: z.data,
+                })
+                .collect(),
+        }),
+        podzielona_platnosc: fa
+            .adnotacje
+            .as_ref()
+            .is_some_and(|a| a.p_18a.as_deref() == Some("1")),
+        wariant: FormVariant::from_system_code(&faktura.naglowek.kod_formularza.kod_systemowy)
+            .unwrap_or_default(),
+        zalacznik: faktura
+            .zalacznik
+            .map(|z| z.bloki.into_iter().map(attachment_block).collect())
+            .unwrap_or_default(),
+        zwolnienie: fa.adnotacje.and_then(|a| a.zwolnienie).and_then(exemption),
+        rodzaj: InvoiceKind::from_code(&fa.rodzaj_faktury).unwrap_or_default(),
+        korekta,
+        zamowienie,
+        kwota_zaliczki: rodzaj_zal.then_some(fa.p_15),
+        zaliczki: fa
+            .faktury_zaliczkowe
+            .into_iter()
+            .map(|z| AdvanceInvoice {
+                numer: z.nr_fa.unwrap_or_default(),
+                numer_ksef: z.nr_ksef,
+                sumy: Vec::new(),
+            })
+            .collect(),
+    };
+
+    Ok(ParsedInvoice {
+        invoice,
+        kod_systemowy: faktura.naglowek.kod_formularza.kod_systemowy,
+        rodzaj_faktury: fa.rodzaj_faktury,
+        sumy,
+        kwota_naleznosci: fa.p_15,
+    })
+}
+
+impl Invoice {
+    /// Reads an invoice from FA(2) or FA(3) XML
+    pub fn from_ksef_xml(xml: &str) -> Result<Self, ReadError> {
+        parse_invoice_xml(xml).map(|parsed| parsed.invoice)
+    }
+}
+
+/// Seller (`Podmiot1`), which must have a NIP
+fn seller(podmiot: PodmiotXml) -> Result<Seller, ReadError> {
+    match party(podmiot) {
+        Party {
+            identyfikator: PartyId::Nip(nip),
+            nazwa,
+            adres,
+        } => Ok(Seller { nip, nazwa, adres }),
+        _ => Err(ReadError::Unsupported("seller without NIP".to_string())),
+    }
+}
+
+fn party(podmiot: PodmiotXml) -> Party {
+    let dane = podmiot.dane;
+    // Buyers without a Polish NIP are identified by their EU VAT or foreign tax number
+    let identyfikator = match (dane.nip, dane.nr_vat_ue, dane.nr_id) {
+        (Some(nip), _, _) => PartyId::Nip(nip),
+        (None, Some(nr_vat_ue), _) => PartyId::VatUe {
+            kod_ue: dane.kod_ue.unwrap_or_default(),
+            nr_vat_ue,
+        },
+        (None, None, Some(nr_id)) => PartyId::NrId {
+            kod_kraju: dane.kod_kraju,
+            nr_id,
+        },
+        (None, None, None) => PartyId::BrakId,
+    };
+    let adres = podmiot.adres.map(|a| Address {
+        kod_kraju: a.kod_kraju,
+        adres_l1: a.adres_l1,
+        adres_l2: a.adres_l2,
+    });
+
+    Party {
+        identyfikator,
+        nazwa: dane.nazwa.unwrap_or_default(),
+        adres,
+    }
+}
+
+fn line_item(wiersz: WierszXml) -> Result<InvoiceLineItem, ReadError> {
+    let stawka = wiersz.p_12.as_deref().unwrap_or("").trim();
+    let stawka_vat = VatRate::from_code(stawka).ok_or_else(|| {
+        ReadError::Unsupported(format!(
+            "VAT rate \"{}\" in line {}",
+            stawka, wiersz.nr_wiersza
+        ))
+    })?;
+
+    let ilosc = wiersz.p_8b.unwrap_or(Decimal::ONE);
+    let kwota = wiersz.p_11.or(wiersz.p_11a);
+    let cena_netto = wiersz
+        .p_9a
+        .or(wiersz.p_9b)
+        .or_else(|| kwota.filter(|_| !ilosc.is_zero()).map(|n| n / ilosc))
+        .unwrap_or_default();
+
+    Ok(InvoiceLineItem {
+        nr_wiersza: wiersz.nr_wiersza,
+        opis: wiersz.p_7.unwrap_or_default(),
+        jednostka: wiersz.p_8a.unwrap_or_default(),
+        ilosc,
+        cena_netto,
+        kwota_netto: kwota.unwrap_or(ilosc * cena_netto),
+        stawka_vat,
+        stan_przed: wiersz.stan_przed.as_deref() == Some("1"),
+        zalacznik_15: wiersz.p_12_zal_15.as_deref() == Some("1"),
+    })
+}
+
+fn bank_account(rachunek: RachunekBankowyXml) -> BankAccount {
+    BankAccount {
+        numer: rachunek.nr_rb,
+        swift: rachunek.swift,
+        nazwa_banku: rachunek.nazwa_banku,
+        opis: rachunek.opis,
+    }
+}
+
+fn correction(fa: &FaXml) -> Option<Correction> {
+    if !fa.rodzaj_faktury.starts_with("KOR") {
+        return None;
+    }
+    Some(Correction {
+        przyczyna: fa.przyczyna_korekty.clone(),
+        typ: fa.typ_korekty.and_then(CorrectionType::from_code),
+        faktury: fa
+            .dane_fa_korygowanej
+            .iter()
+            .map(|dane| CorrectedInvoice {
+                data_wystawienia: dane.data_wyst.clone(),
+                numer: dane.nr_fa.clone(),
+                numer_ksef: dane.nr_ksef.clone(),
+            })
+            .collect(),
+    })
+}
+
+fn exemption(zwolnienie: ZwolnienieXml) -> Option<Exemption> {
+    zwolnienie
+        .p_19a
+        .map(Exemption::Ustawa)
+        .or_else(|| zwolnienie.p_19b.map(Exemption::Dyrektywa))
+        .or_else(|| zwolnienie.p_19c.map(Exemption::Inna))
+}
+
+fn attachment_block(blok: BlokDanychXml) -> AttachmentBlock {
+    AttachmentBlock {
+        naglowek: blok.naglowek,
+        metadane: blok
+            .metadane
+            .into_iter()
+            .map(|m| (m.klucz, m.wartosc))
+            .collect(),
+        akapity: blok.tekst.map(|t| t.akapity).unwrap_or_default(),
+        tabele: blok
+            .tabele
+            .into_iter()
+            .map(|t| AttachmentTable {
+                opis: t.opis,
+                kolumny: t.naglowek.kolumny.into_iter().map(|k| k.nazwa).collect(),
+                wiersze: t.wiersze.into_iter().map(|w| w.komorki).collect(),
+            })
+            .collect(),
+    }
+}
+
+#[derive(Deserialize)]
+struct FakturaXml {
+    #[serde(rename = "Naglowek")]
+    naglowek: NaglowekXml,
+    #[serde(rename = "Podmiot1")]
+    podmiot1: PodmiotXml,
+    #[serde(rename = "Podmiot2")]
+    podmiot2: PodmiotXml,
+    #[serde(rename = "Fa")]
+    fa: FaXml,
+    #[serde(rename = "Zalacznik")]
+    zalacznik: Option<ZalacznikXml>,
+}
+
+#[derive(Deserialize)]
+struct NaglowekXml {
+    #[serde(rename = "KodFormularza")]
+    kod_formularza: KodFormularzaXml,
+}
+
+#[derive(Deserialize)]
+struct KodFormularzaXml {
+    #[serde(rename = "@kodSystemowy")]
+    kod_systemowy: String,
+}
+
+#[derive(Deserialize)]
+struct PodmiotXml {
+    #[serde(rename = "DaneIdentyfikacyjne")]
+    dane: DaneIdentyfikacyjneXml,
+    #[serde(rename = "Adres")]
+    adres: Option<AdresXml>,
+}
+
+#[derive(Deserialize)]
+struct DaneIdentyfikacyjneXml {
+    #[serde(rename = "NIP")]
+    nip: Option<String>,
+    #[serde(rename = "KodUE")]
+    kod_ue: Option<String>,
+    #[serde(rename = "NrVatUE")]
+    nr_vat_ue: Option<String>,
+    #[serde(rename = "KodKraju")]
+    kod_kraju: Option<String>,
+    #[serde(rename = "NrID")]
+    nr_id: Option<String>,
+    #[serde(rename = "Nazwa")]
+    nazwa: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AdresXml {
+    #[serde(rename = "KodKraju")]
+    kod_kraju: String,
+    #[serde(rename = "AdresL1")]
+    adres_l1: String,
+    #[serde(rename = "AdresL2")]
+    adres_l2: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FaXml {
+    #[serde(rename = "KodWaluty")]
+    kod_waluty: String,
+    #[serde(rename = "P_1")]
+    p_1: String,
+    #[serde(rename = "P_2")]
+    p_2: String,
+    #[serde(rename = "P_13_1", default, with = "rust_decimal::serde::str_option")]
+    p_13_1: Option<Decimal>,
+    #[serde(rename = "P_14_1", default, with = "rust_decimal::serde::str_option")]
+    p_14_1: Option<Decimal>,
+    #[serde(rename = "P_13_2", default, with = "rust_decimal::serde::str_option")]
+    p_13_2: Option<Decimal>,
+    #[serde(rename = "P_14_2", default, with = "rust_decimal::serde::str_option")]
+    p_14_2: Option<Decimal>,
+    #[serde(rename = "P_13_3", default, with = "rust_decimal::serde::str_option")]
+    p_13_3: Option<Decimal>,
+    #[serde(rename = "P_14_3", default, with = "rust_decimal::serde::str_option")]
+    p_14_3: Option<Decimal>,
+    #[serde(rename = "P_13_4", default, with = "rust_decimal::serde::str_option")]
+    p_13_4: Option<Decimal>,
+    #[serde(rename = "P_14_4", default, with = "rust_decimal::serde::str_option")]
+    p_14_4: Option<Decimal>,
+    #[serde(rename = "P_13_5", default, with = "rust_decimal::serde::str_option")]
+    p_13_5: Option<Decimal>,
+    #[serde(rename = "P_14_5", default, with = "rust_decimal::serde::str_option")]
+    p_14_5: Option<Decimal>,
+    #[serde(rename = "P_13_6_1", default, with = "rust_decimal::serde::str_option")]
+    p_13_6_1: Option<Decimal>,
+    #[serde(rename = "P_13_6_2", default, with = "rust_decimal::serde::str_option")]
+    p_13_6_2: Option<Decimal>,
+    #[serde(rename = "P_13_6_3", default, with = "rust_decimal::serde::str_option")]
+    p_13_6_3: Option<Decimal>,
+    #[serde(rename = "P_13_7", default, with = "rust_decimal::serde::str_option")]
+    p_13_7: Option<Decimal>,
+    #[serde(rename = "P_13_8", default, with = "rust_decimal::serde::str_option")]
+    p_13_8: Option<Decimal>,
+    #[serde(rename = "P_13_9", default, with = "rust_decimal::serde::str_option")]
+    p_13_9: Option<Decimal>,
+    #[serde(rename = "P_13_10", default, with = "rust_decimal::serde::str_option")]
+    p_13_10: Option<Decimal>,
+    #[serde(rename = "P_13_11", default, with = "rust_decimal::serde::str_option")]
+    p_13_11: Option<Decimal>,
+    #[serde(rename = "P_15", with = "rust_decimal::serde::str")]
+    p_15: Decimal,
+    #[serde(rename = "Adnotacje")]
+    adnotacje: Option<AdnotacjeXml>,
+    #[serde(rename = "RodzajFaktury")]
+    rodzaj_faktury: String,
+    #[serde(rename = "PrzyczynaKorekty")]
+    przyczyna_korekty: Option<String>,
+    #[serde(rename = "TypKorekty")]
+    typ_korekty: Option<u8>,
+    #[serde(rename = "DaneFaKorygowanej", default)]
+    dane_fa_korygowanej: Vec<DaneFaKorygowanejXml>,
+    #[serde(rename = "FakturaZaliczkowa", default)]
+    faktury_zaliczkowe: Vec<FakturaZaliczkowaXml>,
+    #[serde(rename = "FaWiersz", default)]
+    wiersze: Vec<WierszXml>,
+    #[serde(rename = "Platnosc")]
+    platnosc: Option<PlatnoscXml>,
+    #[serde(rename = "Zamowienie")]
+    zamowienie: Option<ZamowienieXml>,
+}
+
+#[derive(Deserialize)]
+struct DaneFaKorygowanejXml {
+    #[serde(rename = "DataWystFaKorygowanej")]
+    data_wyst: String,
+    #[serde(rename = "NrFaKorygowanej")]
+    nr_fa: String,
+    #[serde(rename = "NrKSeFFaKorygowanej")]
+    nr_ksef: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AdnotacjeXml {
+    #[serde(rename = "P_18A")]
+    p_18a: Option<String>,
+    #[serde(rename = "Zwolnienie")]
+    zwolnienie: Option<ZwolnienieXml>,
+}
+
+#[derive(Deserialize)]
+struct ZwolnienieXml {
+    #[serde(rename = "P_19A")]
+    p_19a: Option<String>,
+    #[serde(rename = "P_19B")]
+    p_19b: Option<String>,
+    #[serde(rename = "P_19C")]
+    p_19c: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WierszXml {
+    #[serde(rename = "NrWierszaFa", alias = "NrWierszaZam")]
+    nr_wiersza: u32,
+    #[serde(rename = "P_7", alias = "P_7Z")]
+    p_7: Option<String>,
+    #[serde(rename = "P_8A", alias = "P_8AZ")]
+    p_8a: Option<String>,
+    #[serde(
+        rename = "P_8B",
+        alias = "P_8BZ",
+        default,
+        with = "rust_decimal::serde::str_option"
+    )]
+    p_8b: Option<Decimal>,
+    #[serde(
+        rename = "P_9A",
+        alias = "P_9AZ",
+        default,
+        with = "rust_decimal::serde::str_option"
+    )]
+    p_9a: Option<Decimal>,
+    #[serde(rename = "P_9B", default, with = "rust_decimal::serde::str_option")]
+    p_9b: Option<Decimal>,
+    #[serde(
+        rename = "P_11",
+        alias = "P_11NettoZ",
+        default,
+        with = "rust_decimal::serde::str_option"
+    )]
+    p_11: Option<Decimal>,
+    #[serde(rename = "P_11A", default, with = "rust_decimal::serde::str_option")]
+    p_11a: Option<Decimal>,
+    #[serde(rename = "P_12", alias = "P_12Z")]
+    p_12: Option<String>,
+    #[serde(rename = "P_12_Zal_15", alias = "P_12Z_Zal_15")]
+    p_12_zal_15: Option<String>,
+    #[serde(rename = "StanPrzed", alias = "StanPrzedZ")]
+    stan_przed: Option<String>,
+}
+
+/// Ordered items of an advance invoice, read as invoice lines
+#[derive(Deserialize)]
+struct ZamowienieXml {
+    #[serde(rename = "ZamowienieWiersz", default)]
+    wiersze: Vec<WierszXml>,
+}
+
+#[derive(Deserialize)]
+struct FakturaZaliczkowaXml {
+    #[serde(rename = "NrKSeFFaZaliczkowej")]
+    nr_ksef: Option<String>,
+    #[serde(rename = "NrFaZaliczkowej")]
+    nr_fa: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PlatnoscXml {
+    #[serde(rename = "Zaplacono")]
+    zaplacono: Option<String>,
+    #[serde(rename = "DataZaplaty")]
+    data_zaplaty: Option<String>,
+    #[serde(rename = "ZaplataCzesciowa", default)]
+    zaplaty_czesciowe: Vec<ZaplataCzesciowaXml>,
+    #[serde(rename = "TerminPlatnosci", default)]
+    terminy: Vec<TerminPlatnosciXml>,
+    #[serde(rename = "FormaPlatnosci")]
+    forma: Option<String>,
+    #[serde(rename = "RachunekBankowy", default)]
+    rachunki: Vec<RachunekBankowyXml>,
+    #[serde(rename = "RachunekBankowyFaktora", default)]
+    rachunki_faktora: Vec<RachunekBankowyXml>,
+}
+
+#[derive(Deserialize)]
+struct ZaplataCzesciowaXml {
+    #[serde(rename = "KwotaZaplatyCzesciowej", with = "rust_decimal::serde::str")]
+    kwota: Decimal,
+    #[serde(rename = "DataZaplatyCzesciowej")]
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct TerminPlatnosciXml {
+    #[serde(rename = "Termin")]
+    termin: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RachunekBankowyXml {
+    #[serde(rename = "NrRB")]
+    nr_rb: String,
+    #[serde(rename = "SWIFT")]
+    swift: Option<String>,
+    #[serde(rename = "NazwaBanku")]
+    nazwa_banku: Option<String>,
+    #[serde(rename = "OpisRachunku")]
+    opis: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ZalacznikXml {
+    #[serde(rename = "BlokDanych", default)]
+    bloki: Vec<BlokDanychXml>,
+}
+
+#[derive(Deserialize)]
+struct BlokDanychXml {
+    #[serde(rename = "ZNaglowek")]
+    naglowek: Option<String>,
+    #[serde(rename = "MetaDane", default)]
+    metadane: Vec<MetaDaneXml>,
+    #[serde(rename = "Tekst")]
+    tekst: Option<TekstXml>,
+    #[serde(rename = "Tabela", default)]
+    tabele: Vec<TabelaXml>,
+}
+
+#[derive(Deserialize)]
+struct MetaDaneXml {
+    #[serde(rename = "ZKlucz")]
+    klucz: String,
+    #[serde(rename = "ZWartosc")]
+    wartosc: String,
+}
+
+#[derive(Deserialize)]
+struct TekstXml {
+    #[serde(rename = "Akapit", default)]
+    akapity: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TabelaXml {
+    #[serde(rename = "Opis")]
+    opis: Option<String>,
+    #[serde(rename = "TNaglowek")]
+    naglowek: TNaglowekXml,
+    #[serde(rename = "Wiersz", default)]
+    wiersze: Vec<WierszTabeliXml>,
+}
+
+#[derive(Deserialize)]
+struct TNaglowekXml {
+    #[serde(rename = "Kol", default)]
+    kolumny: Vec<KolXml>,
+}
+
+#[derive(Deserialize)]
+struct KolXml {
+    #[serde(rename = "NKom")]
+    nazwa: String,
+}
+
+#[derive(Deserialize)]
+struct WierszTabeliXml {
+    #[serde(rename = "WKom", default)]
+    komorki: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_generated_invoice_round_trip() {
+        let mut invoice = Invoice::new(
+            Seller {
+                nip: "1234567890".to_string(),
+                nazwa: "Seller & Co".to_string(),
+                adres: Some(Address::new("PL", "ul. Testowa 1, 00-001 Warszawa")),
+            },
+            Party {
+                identyfikator: PartyId::Nip("9876543210".to_string()),
+                nazwa: "Buyer".to_string(),
+                adres: None,
+            },
+            "2026-01-03".to_string(),
+            "FV/1/2026".to_string(),
+        );
+        invoice.add_line_item(InvoiceLineItem {
+            nr_wiersza: 1,
+            opis: "Consulting".to_string(),
+            jednostka: "h".to_string(),
+            ilosc: dec!(2.5),
+            cena_netto: dec!(200),
+            kwota_netto: dec!(500),
+            stawka_vat: VatRate::Vat23,
+            stan_przed: false,
+            zalacznik_15: false,
+        });
+
+        let parsed = parse_invoice_xml(&invoice.generate_ksef_xml()).unwrap();
+
+        assert_eq!(parsed.kod_systemowy, "FA (2)");
+        assert_eq!(parsed.rodzaj_faktury, "VAT");
+        assert_eq!(parsed.kwota_naleznosci, dec!(615));
+        assert_eq!(
+            parsed.sumy,
+            vec![RateTotal {
+                stawka: "23%",
+                netto: dec!(500),
+                vat: Some(dec!(115))
+            }]
+        );
+        let read = parsed.invoice;
+        assert_eq!(read.sprzedawca.nazwa, "Seller & Co");
+        assert_eq!(read.sprzedawca.adres, invoice.sprzedawca.adres);
+        assert_eq!(read.nabywca.identyfikator.nip(), Some("9876543210"));
+        assert_eq!(read.numer, invoice.numer);
+        assert_eq!(read.data_wystawienia, invoice.data_wystawienia);
+        assert_eq!(read.pozycje[0].ilosc, dec!(2.5));
+        assert_eq!(read.pozycje[0].kwota_netto, dec!(500));
+        assert_eq!(
+            read.calculate_total_gross(),
+            invoice.calculate_total_gross()
+        );
+    }
+
+    #[test]
+    fn test_parse_fa3_invoice() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Faktura xmlns="http://crd.gov.pl/wzor/2025/06/25/13775/">
+  <Naglowek>
+    <KodFormularza kodSystemowy="FA (3)" wersjaSchemy="1-0E">FA</KodFormularza>
+    <WariantFormularza>3</WariantFormularza>
+    <DataWytworzeniaFa>2026-02-01T10:00:00Z</DataWytworzeniaFa>
+  </Naglowek>
+  <Podmiot1>
+    <DaneIdentyfikacyjne><NIP>5265877635</NIP><Nazwa>Seller</Nazwa></DaneIdentyfikacyjne>
+    <Adres><KodKraju>PL</KodKraju><AdresL1>ul. Prosta 1</AdresL1><AdresL2>00-001 Warszawa</AdresL2></Adres>
+  </Podmiot1>
+  <Podmiot2>
+    <DaneIdentyfikacyjne><KodUE>DE</KodUE><NrVatUE>123456789</NrVatUE><Nazwa>Käufer GmbH</Nazwa></DaneIdentyfikacyjne>
+    <JST>2</JST><GV>2</GV>
+  </Podmiot2>
+  <Fa>
+    <KodWaluty>PLN</KodWaluty>
+    <P_1>2026-02-01</P_1>
+    <P_2>FV/2/2026</P_2>
+    <P_13_1>100.00</P_13_1><P_14_1>23.00</P_14_1>
+    <P_13_2>50.00</P_13_2><P_14_2>4.00</P_14_2>
+    <P_13_7>10.00</P_13_7>
+    <P_15>187.00</P_15>
+    <Adnotacje>
+      <P_16>2</P_16>
+      <Zwolnienie><P_19>1</P_19><P_19A>art. 43 ust. 1 pkt 37</P_19A></Zwolnienie>
+    </Adnotacje>
+    <RodzajFaktury>VAT</RodzajFaktury>
+    <FaWiersz><NrWierszaFa>1</NrWierszaFa><P_7>A</P_7><P_8A>szt</P_8A><P_8B>1</P_8B><P_9A>100</P_9A><P_11>100</P_11><P_12>23</P_12></FaWiersz>
+    <FaWiersz><NrWierszaFa>2</NrWierszaFa><P_7>B</P_7><P_11>50</P_11><P_12>8</P_12></FaWiersz>
+    <FaWiersz><NrWierszaFa>3</NrWierszaFa><P_7>C</P_7><P_11>10</P_11><P_12>zw</P_12></FaWiersz>
+    <Platnosc>
+      <TerminPlatnosci><Termin>2026-02-15</Termin></TerminPlatnosci>
+      <FormaPlatnosci>6</FormaPlatnosci>
+      <RachunekBankowy><NrRB>61109010140000071219812874</NrRB></RachunekBankowy>
+    </Platnosc>
+  </Fa>
+</Faktura>"#;
+
+        let parsed = parse_invoice_xml(xml).unwrap();
+        assert_eq!(parsed.kod_systemowy, "FA (3)");
+        assert_eq!(parsed.sumy.len(), 3);
+        assert_eq!(parsed.sumy[2].stawka, "zw");
+
+        let invoice = parsed.invoice;
+        let adres = invoice.sprzedawca.adres.unwrap();
+        assert_eq!(adres.adres_l2.as_deref(), Some("00-001 Warszawa"));
+        assert_eq!(
+            invoice.nabywca.identyfikator,
+            PartyId::VatUe {
+                kod_ue: "DE".to_string(),
+                nr_vat_ue: "123456789".to_string()
+            }
+        );
+        assert_eq!(invoice.pozycje[1].cena_netto, dec!(50));
+        assert_eq!(invoice.pozycje[1].stawka_vat, VatRate::Vat8);
+        assert_eq!(invoice.pozycje[2].stawka_vat, VatRate::Zw);
+        assert_eq!(
+            invoice.zwolnienie,
+            Some(Exemption::Ustawa("art. 43 ust. 1 pkt 37".to_string()))
+        );
+        let platnosc = invoice.platnosc.unwrap();
+        assert_eq!(platnosc.terminy, vec!["2026-02-15"]);
+        assert_eq!(platnosc.forma.as_deref(), Some("6"));
+        assert!(!platnosc.zaplacono);
+    }
+}
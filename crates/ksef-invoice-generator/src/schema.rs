@@ -0,0 +1,161 @@
+//! Validation of invoice XML against the bundled FA(2) and FA(3) schemas.
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::xsd::Schema;
+use crate::{FormVariant, Invoice};
+
+const FA2_XSD: &str = include_str!("../schemas/FA2.xsd");
+const FA3_XSD: &str = include_str!("../schemas/FA3.xsd");
+
+/// Schema violation with the path of the offending element or attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Location such as `/Faktura/Fa/FaWiersz[2]/P_12` or `.../KodFormularza/@kodSystemowy`
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+fn schema(variant: FormVariant) -> &'static Schema {
+    static FA2: OnceLock<Schema> = OnceLock::new();
+    static FA3: OnceLock<Schema> = OnceLock::new();
+
+    let (cell, xsd) = match variant {
+        FormVariant::Fa2 => (&FA2, FA2_XSD),
+        FormVariant::Fa3 => (&FA3, FA3_XSD),
+    };
+    cell.get_or_init(|| {
+        Schema::compile(xsd, "Faktura").unwrap_or_else(|e| {
+            panic!(
+                "bundled {} schema is invalid: {}",
+                variant.kod_systemowy(),
+                e
+            )
+        })
+    })
+}
+
+/// Validates an invoice against the schema of the given variant
+pub fn validate_xml(xml: &str, variant: FormVariant) -> Result<(), Vec<ValidationError>> {
+    let errors = schema(variant).validate(xml);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Validates an invoice against the schema matching its root namespace
+pub fn validate_invoice_xml(xml: &str) -> Result<FormVariant, Vec<ValidationError>> {
+    let variant = FormVariant::detect(xml).ok_or_else(|| {
+        vec![ValidationError {
+            path: "/".to_string(),
+            message: format!(
+                "Not an FA (2) or FA (3) invoice: expected root element Faktura in namespace {} or {}",
+                FormVariant::Fa2.namespace(),
+                FormVariant::Fa3.namespace()
+            ),
+        }]
+    })?;
+    validate_xml(xml, variant).map(|()| variant)
+}
+
+impl Invoice {
+    /// Generates the invoice XML and validates it against the schema of `wariant`
+    pub fn generate_validated_xml(&self) -> Result<String, Vec<ValidationError>> {
+        let xml = self.generate_ksef_xml();
+        validate_xml(&xml, self.wariant)?;
+        Ok(xml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AttachmentBlock, Exemption, InvoiceLineItem, PartyId, VatRate};
+    use rust_decimal_macros::dec;
+
+    fn invoice(wariant: FormVariant) -> Invoice {
+        let mut invoice = crate::tests::invoice("FV/2/2026");
+        invoice.wariant = wariant;
+        invoice.add_line_item(InvoiceLineItem {
+            nr_wiersza: 1,
+            opis: "Consulting".to_string(),
+            jednostka: "h".to_string(),
+            ilosc: dec!(2.5),
+            cena_netto: dec!(200),
+            kwota_netto: dec!(500),
+            stawka_vat: VatRate::Vat23,
+            stan_przed: false,
+            zalacznik_15: false,
+        });
+        invoice
+    }
+
+    #[test]
+    fn test_generated_invoices_are_valid() {
+        let mut fa3 = invoice(FormVariant::Fa3);
+        fa3.zalacznik.push(AttachmentBlock {
+            naglowek: None,
+            metadane: vec![("Order".to_string(), "ZAM/7".to_string())],
+            akapity: vec!["Delivered in two parts".to_string()],
+            tabele: Vec::new(),
+        });
+
+        for mut invoice in [invoice(FormVariant::Fa2), fa3] {
+            // Every P_12 code maps to a valid value and totals field in both schemas
+            for (i, stawka_vat) in VatRate::ALL.into_iter().enumerate() {
+                invoice.add_line_item(InvoiceLineItem {
+                    nr_wiersza: i as u32 + 2,
+                    stawka_vat,
+                    ..invoice.pozycje[0].clone()
+                });
+            }
+            invoice.zwolnienie = Some(Exemption::Ustawa("art. 43 ust. 1 pkt 37".to_string()));
+
+            let xml = invoice.generate_validated_xml().unwrap();
+            assert_eq!(validate_invoice_xml(&xml), Ok(invoice.wariant));
+        }
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let mut invoice = invoice(FormVariant::Fa3);
+        invoice.sprzedawca.adres = None;
+        invoice.nabywca.identyfikator = PartyId::Nip("123".to_string());
+        let xml = invoice
+            .generate_ksef_xml()
+            .replace("<P_12>23</P_12>", "<P_12>0</P_12>");
+
+        let errors = validate_invoice_xml(&xml).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "/Faktura/Podmiot1: Missing element, expected Adres",
+                "/Faktura/Podmiot2/DaneIdentyfikacyjne/NIP: Value \"123\" does not match pattern \
+                 [1-9]((\\d[1-9])|([1-9]\\d))\\d{7}",
+                "/Faktura/Fa/FaWiersz/P_12: Value \"0\" is not one of: 23, 22, 8, 7, 5, 4, 3, \
+                 0 KR, 0 WDT, 0 EX, zw, oo, np I, np II",
+            ]
+        );
+
+        // Elements out of schema order are reported where the sequence breaks
+        let swapped = xml.replace("<P_1M>dom</P_1M>", "<P_1M>dom</P_1M>\n    <WZ>WZ/1</WZ>");
+        let errors = validate_xml(&swapped, FormVariant::Fa3).unwrap_err();
+        assert!(errors.contains(&ValidationError {
+            path: "/Faktura/Fa/WZ".to_string(),
+            message: "Unexpected element, expected P_2".to_string(),
+        }));
+        assert!(validate_invoice_xml("<Invoice/>").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! Per-rate totals of an invoice (`P_13_x` / `P_14_x`).
+//!
+//! VAT is computed on the net total of each rate and rounded once to full
+//! grosze: below half a grosz is dropped, half a grosz or more is rounded up
+//! (art. 106e ust. 11 of the VAT act). With gross prices the tax is taken out
+//! of the gross total of each rate instead (art. 106e ust. 8).
+
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::{advance, Invoice, InvoiceKind, InvoiceLineItem, VatRate};
+
+/// Net and VAT total of one rate group (`P_13_x` / `P_14_x`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateTotal {
+    /// Rate group, e.g. "23%" or "zw"
+    pub stawka: &'static str,
+    pub netto: Decimal,
+    pub vat: Option<Decimal>,
+}
+
+/// Rate group with its net and VAT fields, in schema order
+pub(crate) struct RateGroup {
+    pub stawka: &'static str,
+    pub netto: &'static str,
+    pub vat: Option<&'static str>,
+}
+
+pub(crate) const RATE_GROUPS: [RateGroup; 13] = [
+    group("23%", "P_13_1", Some("P_14_1")),
+    group("8%", "P_13_2", Some("P_14_2")),
+    group("5%", "P_13_3", Some("P_14_3")),
+    group("4%", "P_13_4", Some("P_14_4")),
+    group("OSS", "P_13_5", Some("P_14_5")),
+    group("0%", "P_13_6_1", None),
+    group("0% WDT", "P_13_6_2", None),
+    group("0% EX", "P_13_6_3", None),
+    group("zw", "P_13_7", None),
+    group("np I", "P_13_8", None),
+    group("np II", "P_13_9", None),
+    group("oo", "P_13_10", None),
+    group("marża", "P_13_11", None),
+];
+
+const fn group(stawka: &'static str, netto: &'static str, vat: Option<&'static str>) -> RateGroup {
+    RateGroup { stawka, netto, vat }
+}
+
+/// Rounds an amount to full grosze, half a grosz away from zero
+pub fn round_grosze(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+impl Invoice {
+    /// Net and VAT totals of every rate group present on the invoice, in schema order
+    ///
+    /// An advance invoice splits the advance over the rates of the order; a
+    /// settlement invoice deducts the advances from its lines.
+    pub fn rate_totals(&self) -> Vec<RateTotal> {
+        match self.rodzaj {
+            InvoiceKind::Zal | InvoiceKind::KorZal => {
+                let zamowienie =
+                    line_totals(self.zamowienie.iter().filter(|i| !i.stan_przed), false);
+                advance::split_advance(&zamowienie, self.kwota_zaliczki.unwrap_or_default())
+            }
+            InvoiceKind::Roz => {
+                let sumy = line_totals(&self.pozycje, self.ceny_brutto);
+                advance::deduct_advances(sumy, &self.zaliczki)
+            }
+            _ => line_totals(&self.pozycje, self.ceny_brutto),
+        }
+    }
+}
+
+/// Totals of invoice lines in schema order; `brutto` when the line amounts are gross
+pub(crate) fn line_totals<'a>(
+    lines: impl IntoIterator<Item = &'a InvoiceLineItem>,
+    brutto: bool,
+) -> Vec<RateTotal> {
+    // Amount per rate, from line amounts rounded as they appear in P_11 (P_11A);
+    // lines before a correction are kept apart and subtracted
+    let mut per_rate: Vec<(VatRate, bool, Decimal)> = Vec::new();
+    for item in lines {
+        let kwota = round_grosze(item.kwota_netto);
+        let key = (item.stawka_vat, item.stan_przed);
+        match per_rate
+            .iter_mut()
+            .find(|(rate, przed, _)| (*rate, *przed) == key)
+        {
+            Some((_, _, sum)) => *sum += kwota,
+            None => per_rate.push((key.0, key.1, kwota)),
+        }
+    }
+    let signed = |przed: bool, amount: Decimal| if przed { -amount } else { amount };
+
+    RATE_GROUPS
+        .iter()
+        .filter_map(|group| {
+            // Net and VAT of each rate, VAT rounded once per rate
+            let rates: Vec<(Decimal, Option<Decimal>)> = per_rate
+                .iter()
+                .filter(|(rate, _, _)| rate.group() == group.stawka)
+                .map(|(rate, przed, kwota)| {
+                    let vat = rate.percent().map(|percent| {
+                        let percent = Decimal::from(percent);
+                        let podstawa = if brutto {
+                            Decimal::ONE_HUNDRED + percent
+                        } else {
+                            Decimal::ONE_HUNDRED
+                        };
+                        round_grosze(kwota * percent / podstawa)
+                    });
+                    let netto = if brutto {
+                        kwota - vat.unwrap_or_default()
+                    } else {
+                        *kwota
+                    };
+                    (signed(*przed, netto), vat.map(|vat| signed(*przed, vat)))
+                })
+                .collect();
+            if rates.is_empty() {
+                return None;
+            }
+            let netto = rates.iter().map(|(netto, _)| netto).sum();
+            let vat = group
+                .vat
+                .map(|_| rates.iter().filter_map(|(_, vat)| *vat).sum());
+            Some(RateTotal {
+                stawka: group.stawka,
+                netto,
+                vat,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InvoiceLineItem;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_rate_totals() {
+        let mut invoice = crate::tests::invoice("FV/1");
+        let lines = [
+            (dec!(0.10), VatRate::Vat23),
+            (dec!(0.10), VatRate::Vat23),
+            (dec!(0.10), VatRate::Vat22),
+            (dec!(10.005), VatRate::Vat8),
+            (dec!(100), VatRate::ZeroKr),
+            (dec!(40), VatRate::Zw),
+        ];
+        for (i, (netto, stawka_vat)) in lines.into_iter().enumerate() {
+            invoice.add_line_item(InvoiceLineItem {
+                nr_wiersza: i as u32 + 1,
+                opis: "Item".to_string(),
+                jednostka: "szt".to_string(),
+                ilosc: Decimal::ONE,
+                cena_netto: netto,
+                kwota_netto: netto,
+                stawka_vat,
+                stan_przed: false,
+                zalacznik_15: false,
+            });
+        }
+
+        // 0.20 * 23% = 0.046 -> 0.05 and 0.10 * 22% = 0.022 -> 0.02, not 3 * 0.02
+        // 10.01 * 8% = 0.8008 -> 0.80
+        assert_eq!(
+            invoice.rate_totals(),
+            vec![
+                RateTotal {
+                    stawka: "23%",
+                    netto: dec!(0.30),
+                    vat: Some(dec!(0.07))
+                },
+                RateTotal {
+                    stawka: "8%",
+                    netto: dec!(10.01),
+                    vat: Some(dec!(0.80))
+                },
+                RateTotal {
+                    stawka: "0%",
+                    netto: dec!(100),
+                    vat: None
+                },
+                RateTotal {
+                    stawka: "zw",
+                    netto: dec!(40),
+                    vat: None
+                },
+            ]
+        );
+        assert_eq!(invoice.calculate_total_gross(), dec!(151.18));
+        assert_eq!(round_grosze(dec!(-0.125)), dec!(-0.13));
+    }
+}
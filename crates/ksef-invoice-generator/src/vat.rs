@@ -0,0 +1,251 @@
+//! VAT rates of invoice lines (`P_12`) and the exemption annotation.
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+use crate::{FormVariant, Invoice};
+
+/// Error returned when the exemption does not match the exempt lines
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ExemptionError {
+    #[error("Exemption basis is required for lines with VAT rate zw")]
+    Missing,
+
+    #[error("Exemption basis is only allowed with lines of VAT rate zw")]
+    WithoutExemptLines,
+}
+
+/// VAT rate of a line (`P_12`)
+///
+/// FA(2) has a single "0" and "np" code; the 0% and "np" variants are still
+/// summed into their own `P_13_x` fields there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VatRate {
+    Vat23,
+    /// Former basic rate, still valid for supplies taxed before 2011
+    Vat22,
+    Vat8,
+    /// Former reduced rate, summed with 8%
+    Vat7,
+    Vat5,
+    Vat4,
+    /// Flat-rate taxi rate, summed with 4%
+    Vat3,
+    /// 0% domestic supply ("0 KR")
+    ZeroKr,
+    /// 0% intra-Community supply of goods ("0 WDT")
+    ZeroWdt,
+    /// 0% export of goods ("0 EX")
+    ZeroEx,
+    /// Exempt ("zw"); requires the legal basis in `Invoice::zwolnienie`
+    Zw,
+    /// Reverse charge, the buyer settles the tax ("oo")
+    Oo,
+    /// Supply outside Poland, other than art. 100 ust. 1 pkt 4 services ("np I")
+    NpI,
+    /// Services of art. 100 ust. 1 pkt 4 taxed by the EU buyer ("np II")
+    NpII,
+}
+
+impl VatRate {
+    pub const ALL: [VatRate; 14] = [
+        VatRate::Vat23,
+        VatRate::Vat22,
+        VatRate::Vat8,
+        VatRate::Vat7,
+        VatRate::Vat5,
+        VatRate::Vat4,
+        VatRate::Vat3,
+        VatRate::ZeroKr,
+        VatRate::ZeroWdt,
+        VatRate::ZeroEx,
+        VatRate::Zw,
+        VatRate::Oo,
+        VatRate::NpI,
+        VatRate::NpII,
+    ];
+
+    /// `P_12` code in FA(3), e.g. "23", "0 WDT" or "np I"
+    pub fn code(&self) -> &'static str {
+        match self {
+            VatRate::Vat23 => "23",
+            VatRate::Vat22 => "22",
+            VatRate::Vat8 => "8",
+            VatRate::Vat7 => "7",
+            VatRate::Vat5 => "5",
+            VatRate::Vat4 => "4",
+            VatRate::Vat3 => "3",
+            VatRate::ZeroKr => "0 KR",
+            VatRate::ZeroWdt => "0 WDT",
+            VatRate::ZeroEx => "0 EX",
+            VatRate::Zw => "zw",
+            VatRate::Oo => "oo",
+            VatRate::NpI => "np I",
+            VatRate::NpII => "np II",
+        }
+    }
+
+    /// `P_12` code in the given schema
+    pub fn code_for(&self, variant: FormVariant) -> &'static str {
+        match (variant, self) {
+            (FormVariant::Fa2, VatRate::ZeroKr | VatRate::ZeroWdt | VatRate::ZeroEx) => "0",
+            (FormVariant::Fa2, VatRate::NpI | VatRate::NpII) => "np",
+            _ => self.code(),
+        }
+    }
+
+    /// Parses a `P_12` code of either schema; a bare "0" is a domestic 0%
+    /// and FA(2) "np" is read as "np I"
+    pub fn from_code(code: &str) -> Option<Self> {
+        let code = code.trim();
+        match code {
+            "0" => Some(VatRate::ZeroKr),
+            "np" => Some(VatRate::NpI),
+            _ => VatRate::ALL.into_iter().find(|rate| rate.code() == code),
+        }
+    }
+
+    /// Tax percentage, `None` for rates without tax due from the seller
+    pub fn percent(&self) -> Option<u8> {
+        match self {
+            VatRate::Vat23 => Some(23),
+            VatRate::Vat22 => Some(22),
+            VatRate::Vat8 => Some(8),
+            VatRate::Vat7 => Some(7),
+            VatRate::Vat5 => Some(5),
+            VatRate::Vat4 => Some(4),
+            VatRate::Vat3 => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Rate group the line is summed into (`P_13_x`)
+    pub(crate) fn group(&self) -> &'static str {
+        match self {
+            VatRate::Vat23 | VatRate::Vat22 => "23%",
+            VatRate::Vat8 | VatRate::Vat7 => "8%",
+            VatRate::Vat5 => "5%",
+            VatRate::Vat4 | VatRate::Vat3 => "4%",
+            VatRate::ZeroKr => "0%",
+            VatRate::ZeroWdt => "0% WDT",
+            VatRate::ZeroEx => "0% EX",
+            VatRate::Zw => "zw",
+            VatRate::NpI => "np I",
+            VatRate::NpII => "np II",
+            VatRate::Oo => "oo",
+        }
+    }
+
+    /// Whether the buyer settles the tax, marked with `P_18` (odwrotne obciążenie)
+    pub fn is_reverse_charge(&self) -> bool {
+        matches!(self, VatRate::Oo | VatRate::NpII)
+    }
+}
+
+impl fmt::Display for VatRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl Serialize for VatRate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+/// Legal basis of a VAT exemption (`Adnotacje/Zwolnienie`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exemption {
+    /// Provision of the VAT act or of a regulation issued under it (P_19A)
+    Ustawa(String),
+    /// Provision of Directive 2006/112/EC (P_19B)
+    Dyrektywa(String),
+    /// Other legal basis (P_19C)
+    Inna(String),
+}
+
+impl Exemption {
+    /// Element name and text of the legal basis
+    pub(crate) fn field(&self) -> (&'static str, &str) {
+        match self {
+            Exemption::Ustawa(text) => ("P_19A", text),
+            Exemption::Dyrektywa(text) => ("P_19B", text),
+            Exemption::Inna(text) => ("P_19C", text),
+        }
+    }
+}
+
+impl Invoice {
+    /// Whether any line or ordered item is exempt; only then is `zwolnienie` declared
+    pub fn has_exempt_lines(&self) -> bool {
+        self.pozycje
+            .iter()
+            .chain(&self.zamowienie)
+            .any(|item| item.stawka_vat == VatRate::Zw)
+    }
+
+    /// Checks that `zwolnienie` is set exactly when a line or ordered item is exempt
+    pub fn check_exemption(&self) -> Result<(), ExemptionError> {
+        match (self.has_exempt_lines(), self.zwolnienie.is_some()) {
+            (true, false) => Err(ExemptionError::Missing),
+            (false, true) => Err(ExemptionError::WithoutExemptLines),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decimal;
+
+    #[test]
+    fn test_vat_rate_codes() {
+        for rate in VatRate::ALL {
+            assert_eq!(VatRate::from_code(rate.code()), Some(rate));
+        }
+        assert_eq!(VatRate::from_code(" 0 "), Some(VatRate::ZeroKr));
+        assert_eq!(VatRate::from_code("np"), Some(VatRate::NpI));
+        assert_eq!(VatRate::from_code("17"), None);
+
+        assert_eq!(VatRate::ZeroWdt.code_for(FormVariant::Fa2), "0");
+        assert_eq!(VatRate::ZeroWdt.code_for(FormVariant::Fa3), "0 WDT");
+        assert_eq!(VatRate::NpII.code_for(FormVariant::Fa2), "np");
+        assert_eq!(VatRate::Vat7.group(), "8%");
+        assert_eq!(VatRate::Zw.percent(), None);
+    }
+
+    #[test]
+    fn test_exemption_needs_exempt_lines() {
+        let mut invoice = crate::tests::invoice("FV/1/2026");
+        invoice.add_line_item(crate::InvoiceLineItem {
+            nr_wiersza: 1,
+            opis: "Training".to_string(),
+            jednostka: "h".to_string(),
+            ilosc: Decimal::ONE,
+            cena_netto: Decimal::ONE_HUNDRED,
+            kwota_netto: Decimal::ONE_HUNDRED,
+            stawka_vat: VatRate::Vat23,
+            stan_przed: false,
+            zalacznik_15: false,
+        });
+        invoice.zwolnienie = Some(Exemption::Ustawa("art. 43 ust. 1 pkt 29".to_string()));
+
+        // Nothing on the invoice is exempt, so the exemption is rejected
+        assert!(!invoice.has_exempt_lines());
+        assert_eq!(
+            invoice.check_exemption(),
+            Err(ExemptionError::WithoutExemptLines)
+        );
+
+        invoice.pozycje[0].stawka_vat = VatRate::Zw;
+        assert_eq!(invoice.check_exemption(), Ok(()));
+        let xml = invoice.generate_ksef_xml();
+        assert!(xml.contains("<P_19>1</P_19>\n        <P_19A>art. 43 ust. 1 pkt 29</P_19A>"));
+
+        invoice.zwolnienie = None;
+        assert_eq!(invoice.check_exemption(), Err(ExemptionError::Missing));
+    }
+}
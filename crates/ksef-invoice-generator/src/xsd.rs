@@ -0,0 +1,919 @@
+//! Minimal XML Schema engine for the bundled invoice schemas.
+//!
+//! Supports the subset of XSD 1.0 the FA schemas use: global and local
+//! elements, named and anonymous types, `sequence`/`choice`/`any` particles
+//! with occurrence bounds, attributes, `simpleContent` extensions and simple
+//! type restrictions with the enumeration, pattern, length, digit and range
+//! facets. Type references are resolved by local name within one file.
+
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use quick_xml::events::Event;
+use quick_xml::name::ResolveResult;
+use quick_xml::NsReader;
+use regex::Regex;
+
+use crate::ValidationError;
+
+const XSD_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema";
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// Compiled schema with a single root element
+#[derive(Debug)]
+pub(crate) struct Schema {
+    target_namespace: String,
+    root: ElementDecl,
+    types: Vec<TypeDef>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Occurs {
+    min: u32,
+    max: Option<u32>,
+}
+
+#[derive(Debug)]
+struct Particle {
+    term: Term,
+    occurs: Occurs,
+}
+
+#[derive(Debug)]
+enum Term {
+    Element(ElementDecl),
+    Sequence(Vec<Particle>),
+    Choice(Vec<Particle>),
+    Any,
+}
+
+#[derive(Debug)]
+struct ElementDecl {
+    name: String,
+    ty: usize,
+    fixed: Option<String>,
+}
+
+#[derive(Debug)]
+enum TypeDef {
+    /// Placeholder for a named type that is still being compiled
+    Pending,
+    Simple(SimpleType),
+    Complex(ComplexType),
+}
+
+#[derive(Debug)]
+struct ComplexType {
+    content: Content,
+    attributes: Vec<AttributeDecl>,
+}
+
+#[derive(Debug)]
+enum Content {
+    Empty,
+    Elements(Particle),
+    Simple(usize),
+}
+
+#[derive(Debug)]
+struct AttributeDecl {
+    name: String,
+    ty: usize,
+    required: bool,
+    fixed: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct SimpleType {
+    builtin: Builtin,
+    facets: Vec<Facet>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Builtin {
+    String,
+    Token,
+    Decimal,
+    Integer,
+    Date,
+    DateTime,
+    Boolean,
+}
+
+#[derive(Debug, Clone)]
+enum Facet {
+    Enumeration(Vec<String>),
+    Pattern(String, Regex),
+    MinLength(usize),
+    MaxLength(usize),
+    TotalDigits(usize),
+    FractionDigits(usize),
+    MinInclusive(String),
+    MaxInclusive(String),
+}
+
+/// Parsed XML element with namespace-resolved names
+#[derive(Debug)]
+struct Node {
+    namespace: Option<String>,
+    name: String,
+    attributes: Vec<(Option<String>, String, String)>,
+    children: Vec<Node>,
+    text: String,
+}
+
+impl Node {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(ns, local, _)| ns.is_none() && local == name)
+            .map(|(_, _, value)| value.as_str())
+    }
+
+    fn xsd_children(&self) -> impl Iterator<Item = &Node> {
+        self.children
+            .iter()
+            .filter(|c| c.namespace.as_deref() == Some(XSD_NAMESPACE) && c.name != "annotation")
+    }
+}
+
+fn parse_tree(xml: &str) -> Result<Node, String> {
+    let mut reader = NsReader::from_str(xml);
+    let mut stack: Vec<Node> = Vec::new();
+
+    loop {
+        let position = reader.buffer_position();
+        let (ns, event) = reader
+            .read_resolved_event()
+            .map_err(|e| format!("{} after byte {}", e, position))?;
+        let namespace = match ns {
+            ResolveResult::Bound(ns) => Some(String::from_utf8_lossy(ns.as_ref()).into_owned()),
+            _ => None,
+        };
+        match event {
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let mut attributes = Vec::new();
+                for attr in start.attributes() {
+                    let attr = attr.map_err(|e| e.to_string())?;
+                    let key = attr.key;
+                    if key.as_ref() == b"xmlns" || key.as_ref().starts_with(b"xmlns:") {
+                        continue;
+                    }
+                    let (attr_ns, local) = reader.resolve_attribute(key);
+                    let attr_ns = match attr_ns {
+                        ResolveResult::Bound(ns) => {
+                            Some(String::from_utf8_lossy(ns.as_ref()).into_owned())
+                        }
+                        _ => None,
+                    };
+                    let value = attr.unescape_value().map_err(|e| e.to_string())?;
+                    attributes.push((
+                        attr_ns,
+                        String::from_utf8_lossy(local.as_ref()).into_owned(),
+                        value.into_owned(),
+                    ));
+                }
+                let node = Node {
+                    namespace,
+                    name: String::from_utf8_lossy(start.local_name().as_ref()).into_owned(),
+                    attributes,
+                    children: Vec::new(),
+                    text: String::new(),
+                };
+                if matches!(event, Event::Start(_)) {
+                    stack.push(node);
+                } else {
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => return Ok(node),
+                    }
+                }
+            }
+            Event::End(_) => {
+                let node = stack.pop().ok_or("unexpected closing tag")?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return Ok(node),
+                }
+            }
+            Event::Text(text) => {
+                if let Some(node) = stack.last_mut() {
+                    node.text
+                        .push_str(&text.unescape().map_err(|e| e.to_string())?);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(node) = stack.last_mut() {
+                    node.text.push_str(&String::from_utf8_lossy(&data));
+                }
+            }
+            Event::Eof => return Err("no root element".to_string()),
+            _ => {}
+        }
+    }
+}
+
+struct Compiler<'a> {
+    named: HashMap<&'a str, &'a Node>,
+    ids: HashMap<String, usize>,
+    types: Vec<TypeDef>,
+}
+
+impl Schema {
+    /// Compiles a schema document; `root` is the name of its global root element
+    pub(crate) fn compile(xsd: &str, root: &str) -> Result<Self, String> {
+        let document = parse_tree(xsd)?;
+        let target_namespace = document
+            .attribute("targetNamespace")
+            .ok_or("schema without targetNamespace")?
+            .to_string();
+
+        let mut compiler = Compiler {
+            named: HashMap::new(),
+            ids: HashMap::new(),
+            types: Vec::new(),
+        };
+        let mut root_node = None;
+        for child in document.xsd_children() {
+            match child.name.as_str() {
+                "simpleType" | "complexType" => {
+                    let name = child.attribute("name").ok_or("global type without name")?;
+                    compiler.named.insert(name, child);
+                }
+                "element" if child.attribute("name") == Some(root) => root_node = Some(child),
+                _ => {}
+            }
+        }
+        let root_node = root_node.ok_or_else(|| format!("no global element {}", root))?;
+        let root = compiler.element(root_node)?;
+
+        Ok(Schema {
+            target_namespace,
+            root,
+            types: compiler.types,
+        })
+    }
+
+    /// Validates an instance document, returning every violation found
+    pub(crate) fn validate(&self, xml: &str) -> Vec<ValidationError> {
+        let document = match parse_tree(xml) {
+            Ok(document) => document,
+            Err(e) => return vec![error("/", format!("Malformed XML: {}", e))],
+        };
+
+        let mut errors = Vec::new();
+        let path = format!("/{}", document.name);
+        if document.name != self.root.name {
+            errors.push(error(
+                &path,
+                format!("Root element must be {}", self.root.name),
+            ));
+        } else if document.namespace.as_deref() != Some(&self.target_namespace) {
+            errors.push(error(
+                &path,
+                format!(
+                    "Namespace {} does not match {}",
+                    document.namespace.as_deref().unwrap_or("(none)"),
+                    self.target_namespace
+                ),
+            ));
+        } else {
+            self.validate_element(&document, &self.root, &path, &mut errors);
+        }
+        errors
+    }
+
+    fn validate_element(
+        &self,
+        node: &Node,
+        decl: &ElementDecl,
+        path: &str,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        match &self.types[decl.ty] {
+            TypeDef::Simple(simple) => {
+                self.check_attributes(node, &[], path, errors);
+                if !node.children.is_empty() {
+                    errors.push(error(path, "Child elements are not allowed".to_string()));
+                } else {
+                    self.check_value(simple, decl.fixed.as_deref(), &node.text, path, errors);
+                }
+            }
+            TypeDef::Complex(complex) => {
+                self.check_attributes(node, &complex.attributes, path, errors);
+                match &complex.content {
+                    Content::Simple(ty) => {
+                        if !node.children.is_empty() {
+                            errors.push(error(path, "Child elements are not allowed".to_string()));
+                        } else if let TypeDef::Simple(simple) = &self.types[*ty] {
+                            self.check_value(
+                                simple,
+                                decl.fixed.as_deref(),
+                                &node.text,
+                                path,
+                                errors,
+                            );
+                        }
+                    }
+                    Content::Empty => {
+                        if !node.children.is_empty() || !node.text.trim().is_empty() {
+                            errors.push(error(path, "Element must be empty".to_string()));
+                        }
+                    }
+                    Content::Elements(particle) => {
+                        if !node.text.trim().is_empty() {
+                            errors.push(error(path, "Text content is not allowed".to_string()));
+                        }
+                        self.validate_children(node, particle, path, errors);
+                    }
+                }
+            }
+            TypeDef::Pending => {}
+        }
+    }
+
+    fn validate_children(
+        &self,
+        node: &Node,
+        particle: &Particle,
+        path: &str,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let names: Vec<String> = node
+            .children
+            .iter()
+            .map(|child| {
+                if child.namespace.as_deref() == Some(&self.target_namespace) {
+                    child.name.clone()
+                } else {
+                    format!(
+                        "{{{}}}{}",
+                        child.namespace.as_deref().unwrap_or(""),
+                        child.name
+                    )
+                }
+            })
+            .collect();
+        let child_paths: Vec<String> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let same = names.iter().filter(|n| *n == name).count();
+                if same > 1 {
+                    let index = names[..=i].iter().filter(|n| *n == name).count();
+                    format!("{}/{}[{}]", path, name, index)
+                } else {
+                    format!("{}/{}", path, name)
+                }
+            })
+            .collect();
+
+        let mut matcher = Matcher {
+            names: &names,
+            furthest: 0,
+            expected: Vec::new(),
+        };
+        let ends = matcher.particle(particle, BTreeSet::from([0]), true);
+        if !ends.contains(&names.len()) {
+            let expected = format_expected(&matcher.expected);
+            if matcher.furthest < names.len() {
+                errors.push(error(
+                    &child_paths[matcher.furthest],
+                    format!("Unexpected element, expected {}", expected),
+                ));
+            } else {
+                errors.push(error(
+                    path,
+                    format!("Missing element, expected {}", expected),
+                ));
+            }
+        }
+
+        let mut decls = HashMap::new();
+        collect_declarations(particle, &mut decls);
+        for ((child, name), child_path) in node.children.iter().zip(&names).zip(&child_paths) {
+            if let Some(decl) = decls.get(name.as_str()) {
+                self.validate_element(child, decl, child_path, errors);
+            }
+        }
+    }
+
+    fn check_attributes(
+        &self,
+        node: &Node,
+        declared: &[AttributeDecl],
+        path: &str,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        for (ns, name, value) in &node.attributes {
+            if ns.as_deref() == Some(XSI_NAMESPACE) {
+                continue;
+            }
+            let attribute_path = format!("{}/@{}", path, name);
+            match declared.iter().find(|a| ns.is_none() && &a.name == name) {
+                Some(decl) => {
+                    if let TypeDef::Simple(simple) = &self.types[decl.ty] {
+                        let fixed = decl.fixed.as_deref();
+                        self.check_value(simple, fixed, value, &attribute_path, errors);
+                    }
+                }
+                None => errors.push(error(
+                    &attribute_path,
+                    "Attribute is not allowed".to_string(),
+                )),
+            }
+        }
+        for decl in declared.iter().filter(|a| a.required) {
+            if node.attribute(&decl.name).is_none() {
+                errors.push(error(path, format!("Missing attribute {}", decl.name)));
+            }
+        }
+    }
+
+    fn check_value(
+        &self,
+        simple: &SimpleType,
+        fixed: Option<&str>,
+        raw: &str,
+        path: &str,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let value = match simple.builtin {
+            Builtin::String => raw,
+            _ => raw.trim(),
+        };
+        if let Some(fixed) = fixed {
+            if value != fixed {
+                errors.push(error(
+                    path,
+                    format!("Value \"{}\" must be \"{}\"", value, fixed),
+                ));
+                return;
+            }
+        }
+        if let Err(message) = check_simple(simple, value) {
+            errors.push(error(path, message));
+        }
+    }
+}
+
+impl<'a> Compiler<'a> {
+    fn element(&mut self, node: &'a Node) -> Result<ElementDecl, String> {
+        let name = node.attribute("name").ok_or("element without name")?;
+        let ty = match node.attribute("type") {
+            Some(reference) => self.type_reference(reference)?,
+            None => match node.xsd_children().next() {
+                Some(inline) => self.anonymous_type(inline)?,
+                None => self.builtin_type(Builtin::String),
+            },
+        };
+        Ok(ElementDecl {
+            name: name.to_string(),
+            ty,
+            fixed: node.attribute("fixed").map(String::from),
+        })
+    }
+
+    fn anonymous_type(&mut self, node: &'a Node) -> Result<usize, String> {
+        let def = match node.name.as_str() {
+            "simpleType" => TypeDef::Simple(self.simple_type(node)?),
+            "complexType" => TypeDef::Complex(self.complex_type(node)?),
+            other => return Err(format!("unsupported type definition {}", other)),
+        };
+        self.types.push(def);
+        Ok(self.types.len() - 1)
+    }
+
+    fn type_reference(&mut self, reference: &str) -> Result<usize, String> {
+        let name = reference.rsplit(':').next().unwrap_or(reference);
+        if let Some(id) = self.ids.get(name) {
+            return Ok(*id);
+        }
+        if let Some(node) = self.named.get(name).copied() {
+            self.types.push(TypeDef::Pending);
+            let id = self.types.len() - 1;
+            self.ids.insert(name.to_string(), id);
+            self.types[id] = match node.name.as_str() {
+                "simpleType" => TypeDef::Simple(self.simple_type(node)?),
+                _ => TypeDef::Complex(self.complex_type(node)?),
+            };
+            return Ok(id);
+        }
+        let simple = builtin(name).ok_or_else(|| format!("unknown type {}", reference))?;
+        self.types.push(TypeDef::Simple(simple));
+        let id = self.types.len() - 1;
+        self.ids.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    fn builtin_type(&mut self, builtin: Builtin) -> usize {
+        self.types.push(TypeDef::Simple(SimpleType {
+            builtin,
+            facets: Vec::new(),
+        }));
+        self.types.len() - 1
+    }
+
+    fn simple_type(&mut self, node: &'a Node) -> Result<SimpleType, String> {
+        let restriction = node
+            .xsd_children()
+            .find(|c| c.name == "restriction")
+            .ok_or("simpleType without restriction")?;
+        let base = restriction
+            .attribute("base")
+            .ok_or("restriction without base")?;
+        let base = self.type_reference(base)?;
+        let TypeDef::Simple(mut simple) = self.types[base].clone_simple() else {
+            return Err("simple type restricts a complex type".to_string());
+        };
+
+        let mut enumeration = Vec::new();
+        for facet in restriction.xsd_children() {
+            let value = facet.attribute("value").unwrap_or_default().to_string();
+            let number = || {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("bad facet {}", value))
+            };
+            match facet.name.as_str() {
+                "enumeration" => enumeration.push(value),
+                "pattern" => {
+                    let regex = Regex::new(&format!("^(?:{})$", value))
+                        .map_err(|e| format!("bad pattern {}: {}", value, e))?;
+                    simple.facets.push(Facet::Pattern(value, regex));
+                }
+                "minLength" => simple.facets.push(Facet::MinLength(number()?)),
+                "maxLength" => simple.facets.push(Facet::MaxLength(number()?)),
+                "length" => {
+                    simple.facets.push(Facet::MinLength(number()?));
+                    simple.facets.push(Facet::MaxLength(number()?));
+                }
+                "totalDigits" => simple.facets.push(Facet::TotalDigits(number()?)),
+                "fractionDigits" => simple.facets.push(Facet::FractionDigits(number()?)),
+                "minInclusive" => simple.facets.push(Facet::MinInclusive(value)),
+                "maxInclusive" => simple.facets.push(Facet::MaxInclusive(value)),
+                other => return Err(format!("unsupported facet {}", other)),
+            }
+        }
+        if !enumeration.is_empty() {
+            simple
+                .facets
+                .retain(|f| !matches!(f, Facet::Enumeration(_)));
+            simple.facets.push(Facet::Enumeration(enumeration));
+        }
+        Ok(simple)
+    }
+
+    fn complex_type(&mut self, node: &'a Node) -> Result<ComplexType, String> {
+        let mut content = Content::Empty;
+        let mut attributes = Vec::new();
+        for child in node.xsd_children() {
+            match child.name.as_str() {
+                "sequence" | "choice" => content = Content::Elements(self.particle(child)?),
+                "attribute" => attributes.push(self.attribute(child)?),
+                "simpleContent" => {
+                    let extension = child
+                        .xsd_children()
+                        .find(|c| c.name == "extension")
+                        .ok_or("simpleContent without extension")?;
+                    let base = extension
+                        .attribute("base")
+                        .ok_or("extension without base")?;
+                    content = Content::Simple(self.type_reference(base)?);
+                    for attribute in extension.xsd_children().filter(|c| c.name == "attribute") {
+                        attributes.push(self.attribute(attribute)?);
+                    }
+                }
+                other => return Err(format!("unsupported complexType content {}", other)),
+            }
+        }
+        Ok(ComplexType {
+            content,
+            attributes,
+        })
+    }
+
+    fn attribute(&mut self, node: &'a Node) -> Result<AttributeDecl, String> {
+        let name = node.attribute("name").ok_or("attribute without name")?;
+        let ty = match node.attribute("type") {
+            Some(reference) => self.type_reference(reference)?,
+            None => match node.xsd_children().next() {
+                Some(inline) => self.anonymous_type(inline)?,
+                None => self.builtin_type(Builtin::String),
+            },
+        };
+        Ok(AttributeDecl {
+            name: name.to_string(),
+            ty,
+            required: node.attribute("use") == Some("required"),
+            fixed: node.attribute("fixed").map(String::from),
+        })
+    }
+
+    fn particle(&mut self, node: &'a Node) -> Result<Particle, String> {
+        let min = match node.attribute("minOccurs") {
+            Some(min) => min.parse().map_err(|_| format!("bad minOccurs {}", min))?,
+            None => 1,
+        };
+        let max = match node.attribute("maxOccurs") {
+            Some("unbounded") => None,
+            Some(max) => Some(max.parse().map_err(|_| format!("bad maxOccurs {}", max))?),
+            None => Some(1),
+        };
+        let term = match node.name.as_str() {
+            "element" => Term::Element(self.element(node)?),
+            "any" => Term::Any,
+            "sequence" | "choice" => {
+                let particles = node
+                    .xsd_children()
+                    .map(|child| self.particle(child))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if node.name == "sequence" {
+                    Term::Sequence(particles)
+                } else {
+                    Term::Choice(particles)
+                }
+            }
+            other => return Err(format!("unsupported particle {}", other)),
+        };
+        Ok(Particle {
+            term,
+            occurs: Occurs { min, max },
+        })
+    }
+}
+
+impl Builtin {
+    fn name(&self) -> &'static str {
+        match self {
+            Builtin::String | Builtin::Token => "string",
+            Builtin::Decimal => "decimal",
+            Builtin::Integer => "integer",
+            Builtin::Date => "date",
+            Builtin::DateTime => "dateTime",
+            Builtin::Boolean => "boolean",
+        }
+    }
+}
+
+impl TypeDef {
+    fn clone_simple(&self) -> TypeDef {
+        match self {
+            TypeDef::Simple(simple) => TypeDef::Simple(simple.clone()),
+            _ => TypeDef::Pending,
+        }
+    }
+}
+
+fn builtin(name: &str) -> Option<SimpleType> {
+    let (builtin, min, max) = match name {
+        "string" | "anyURI" | "base64Binary" => (Builtin::String, None, None),
+        "token" | "normalizedString" => (Builtin::Token, None, None),
+        "decimal" => (Builtin::Decimal, None, None),
+        "integer" | "long" | "int" => (Builtin::Integer, None, None),
+        "positiveInteger" => (Builtin::Integer, Some("1"), None),
+        "nonNegativeInteger" => (Builtin::Integer, Some("0"), None),
+        "byte" => (Builtin::Integer, Some("-128"), Some("127")),
+        "date" => (Builtin::Date, None, None),
+        "dateTime" => (Builtin::DateTime, None, None),
+        "boolean" => (Builtin::Boolean, None, None),
+        _ => return None,
+    };
+    let mut facets = Vec::new();
+    facets.extend(min.map(|v| Facet::MinInclusive(v.to_string())));
+    facets.extend(max.map(|v| Facet::MaxInclusive(v.to_string())));
+    Some(SimpleType { builtin, facets })
+}
+
+fn check_simple(simple: &SimpleType, value: &str) -> Result<(), String> {
+    let lexical_ok = match simple.builtin {
+        Builtin::String | Builtin::Token => true,
+        Builtin::Decimal => decimal_digits(value).is_some(),
+        Builtin::Integer => {
+            let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+            !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+        }
+        Builtin::Date => NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok(),
+        Builtin::DateTime => {
+            DateTime::parse_from_rfc3339(value).is_ok()
+                || NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").is_ok()
+        }
+        Builtin::Boolean => matches!(value, "true" | "false" | "1" | "0"),
+    };
+    if !lexical_ok {
+        return Err(format!(
+            "Value \"{}\" is not a valid {}",
+            value,
+            simple.builtin.name()
+        ));
+    }
+
+    for facet in &simple.facets {
+        let ok = match facet {
+            Facet::Enumeration(values) => values.iter().any(|v| v == value),
+            Facet::Pattern(_, regex) => regex.is_match(value),
+            Facet::MinLength(min) => value.chars().count() >= *min,
+            Facet::MaxLength(max) => value.chars().count() <= *max,
+            Facet::TotalDigits(max) => decimal_digits(value).is_none_or(|(t, _)| t <= *max),
+            Facet::FractionDigits(max) => decimal_digits(value).is_none_or(|(_, f)| f <= *max),
+            Facet::MinInclusive(min) => {
+                compare(simple.builtin, value, min).is_none_or(|o| o.is_ge())
+            }
+            Facet::MaxInclusive(max) => {
+                compare(simple.builtin, value, max).is_none_or(|o| o.is_le())
+            }
+        };
+        if !ok {
+            return Err(facet_message(facet, value));
+        }
+    }
+    Ok(())
+}
+
+fn facet_message(facet: &Facet, value: &str) -> String {
+    match facet {
+        Facet::Enumeration(values) => {
+            format!("Value \"{}\" is not one of: {}", value, values.join(", "))
+        }
+        Facet::Pattern(pattern, _) => {
+            format!("Value \"{}\" does not match pattern {}", value, pattern)
+        }
+        Facet::MinLength(min) => format!("Value \"{}\" is shorter than {} characters", value, min),
+        Facet::MaxLength(max) => format!("Value is longer than {} characters", max),
+        Facet::TotalDigits(max) => format!("Value \"{}\" has more than {} digits", value, max),
+        Facet::FractionDigits(max) => {
+            format!("Value \"{}\" has more than {} decimal places", value, max)
+        }
+        Facet::MinInclusive(min) => format!("Value \"{}\" is less than {}", value, min),
+        Facet::MaxInclusive(max) => format!("Value \"{}\" is greater than {}", value, max),
+    }
+}
+
+/// Total and fraction digit counts of a decimal literal
+fn decimal_digits(value: &str) -> Option<(usize, usize)> {
+    let unsigned = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let (int, frac) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    if int.is_empty() && frac.is_empty()
+        || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let int = int.trim_start_matches('0');
+    let frac = frac.trim_end_matches('0');
+    Some((int.len().max(1) + frac.len(), frac.len()))
+}
+
+fn compare(builtin: Builtin, value: &str, bound: &str) -> Option<std::cmp::Ordering> {
+    match builtin {
+        Builtin::Decimal | Builtin::Integer => {
+            let value: f64 = value.parse().ok()?;
+            let bound: f64 = bound.parse().ok()?;
+            value.partial_cmp(&bound)
+        }
+        // ISO 8601 values of the same form compare lexicographically
+        Builtin::Date | Builtin::DateTime => Some(value.cmp(bound)),
+        _ => None,
+    }
+}
+
+/// Matches child element names against a content model, tracking the
+/// furthest position where matching failed and what was expected there
+struct Matcher<'a> {
+    names: &'a [String],
+    furthest: usize,
+    /// Element names tried at `furthest`, flagged when the content model requires them
+    expected: Vec<(&'a str, bool)>,
+}
+
+impl<'a> Matcher<'a> {
+    fn particle(
+        &mut self,
+        particle: &'a Particle,
+        from: BTreeSet<usize>,
+        required: bool,
+    ) -> BTreeSet<usize> {
+        let Occurs { min, max } = particle.occurs;
+        let mut result = if min == 0 {
+            from.clone()
+        } else {
+            BTreeSet::new()
+        };
+        let mut seen = from.clone();
+        let mut current = from;
+        let mut count = 0u32;
+
+        while max.is_none_or(|max| count < max) && !current.is_empty() {
+            let needed = required && count < min;
+            count += 1;
+            let mut next = BTreeSet::new();
+            for pos in &current {
+                next.extend(self.term(&particle.term, *pos, needed));
+            }
+            if count >= min {
+                result.extend(next.iter().copied());
+            }
+            // Stop once repetitions no longer reach new positions
+            if count >= min && next.is_subset(&seen) {
+                break;
+            }
+            seen.extend(next.iter().copied());
+            current = next;
+            if count as usize > self.names.len() + min as usize {
+                break;
+            }
+        }
+        result
+    }
+
+    fn term(&mut self, term: &'a Term, pos: usize, required: bool) -> BTreeSet<usize> {
+        match term {
+            Term::Element(decl) => {
+                if self.names.get(pos) == Some(&decl.name) {
+                    BTreeSet::from([pos + 1])
+                } else {
+                    self.fail(pos, &decl.name, required);
+                    BTreeSet::new()
+                }
+            }
+            Term::Any => {
+                if pos < self.names.len() {
+                    BTreeSet::from([pos + 1])
+                } else {
+                    self.fail(pos, "any element", required);
+                    BTreeSet::new()
+                }
+            }
+            Term::Sequence(particles) => {
+                let mut positions = BTreeSet::from([pos]);
+                for particle in particles {
+                    positions = self.particle(particle, positions, required);
+                    if positions.is_empty() {
+                        break;
+                    }
+                }
+                positions
+            }
+            Term::Choice(particles) => {
+                let mut positions = BTreeSet::new();
+                for particle in particles {
+                    positions.extend(self.particle(particle, BTreeSet::from([pos]), required));
+                }
+                positions
+            }
+        }
+    }
+
+    fn fail(&mut self, pos: usize, name: &'a str, required: bool) {
+        if pos > self.furthest {
+            self.furthest = pos;
+            self.expected.clear();
+        }
+        if pos == self.furthest {
+            match self.expected.iter_mut().find(|(n, _)| *n == name) {
+                Some(entry) => entry.1 |= required,
+                None => self.expected.push((name, required)),
+            }
+        }
+    }
+}
+
+fn collect_declarations<'a>(particle: &'a Particle, decls: &mut HashMap<&'a str, &'a ElementDecl>) {
+    match &particle.term {
+        Term::Element(decl) => {
+            decls.entry(decl.name.as_str()).or_insert(decl);
+        }
+        Term::Sequence(particles) | Term::Choice(particles) => {
+            for particle in particles {
+                collect_declarations(particle, decls);
+            }
+        }
+        Term::Any => {}
+    }
+}
+
+/// Lists the required candidates, or all of them when none is required
+fn format_expected(expected: &[(&str, bool)]) -> String {
+    const SHOWN: usize = 6;
+    let any_required = expected.iter().any(|(_, required)| *required);
+    let candidates: Vec<&str> = expected
+        .iter()
+        .filter(|(_, required)| *required || !any_required)
+        .map(|(name, _)| *name)
+        .collect();
+    let mut names: Vec<&str> = candidates.iter().copied().take(SHOWN).collect();
+    if candidates.len() > SHOWN {
+        names.push("...");
+    }
+    match names.len() {
+        0 => "nothing".to_string(),
+        1 => names[0].to_string(),
+        _ => format!("one of: {}", names.join(", ")),
+    }
+}
+
+fn error(path: &str, message: String) -> ValidationError {
+    ValidationError {
+        path: path.to_string(),
+        message,
+    }
+}
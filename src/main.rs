@@ -1,19 +1,93 @@
 use anyhow::{anyhow, Result};
-use ksef_client::KsefClient;
-use ksef_invoice_generator::{Invoice, InvoiceLineItem, Party};
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use ksef_archive::{
+    ArchiveError, Direction, InvoiceArchive, InvoiceQuery, InvoiceStatus, NewInvoice, StatusChange,
+    StatusUpdate, StoredInvoice,
+};
+use ksef_client::{
+    AuthContext, AuthenticationSession, AuthenticationSessionsPage, BatchInvoice,
+    CertificateCredentials, ExportStatusResponse, FormCode, InvoiceMetadata, InvoiceMetadataPage,
+    InvoiceQueryFilters, KsefClient, KsefError, OpenBatchSessionRequest, PollingOptions,
+    RateLimitBudget, RateLimits, SessionInvoiceStatus, SessionInvoicesPage, SessionStatusResponse,
+    SessionsPage, SubjectType, SyncOptions, SyncReport, SyncState, SyncStore, Upo, UpoExpectation,
+    UpoVerification,
+};
+use ksef_invoice_generator::{
+    parse_invoice_xml, validate_xml, Address, AdvanceInvoice, AttachmentBlock, AttachmentTable,
+    BankAccount, CorrectedInvoice, Correction, CorrectionType, Decimal, Exemption, FormVariant,
+    Invoice, InvoiceKind, InvoiceLineItem, ParsedInvoice, PartialPayment, Party, PartyId, Payment,
+    Seller, ValidationError, VatRate,
+};
 use mcp_protocol::{JsonRpcRequest, JsonRpcResponse, ToolCallResult, ToolDefinition};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{json, Value};
 use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// JSON-RPC error codes for KSeF client failures (server-defined range)
+const ERROR_TRANSPORT: i32 = -32001;
+const ERROR_HTTP: i32 = -32002;
+const ERROR_KSEF_EXCEPTION: i32 = -32003;
+const ERROR_RATE_LIMITED: i32 = -32004;
+const ERROR_AUTH_EXPIRED: i32 = -32005;
+const ERROR_AUTH_FAILED: i32 = -32006;
+const ERROR_CRYPTO: i32 = -32007;
+const ERROR_INVALID_RESPONSE: i32 = -32008;
+const ERROR_TIMEOUT: i32 = -32009;
+const ERROR_CANCELLED: i32 = -32010;
+const ERROR_OPERATION_FAILED: i32 = -32011;
+const ERROR_IO: i32 = -32012;
+const ERROR_TRUNCATED: i32 = -32013;
+const ERROR_INVALID_PARAMS: i32 = -32602;
+
+/// Error for invalid tool arguments, answered with `ERROR_INVALID_PARAMS`
+macro_rules! invalid_params {
+    ($($arg:tt)*) => {
+        anyhow::Error::from(KsefError::Validation(format!($($arg)*)))
+    };
+}
+
+const DEFAULT_EXPORT_DIR: &str = "ksef-exports";
+const DEFAULT_SYNC_DIR: &str = "ksef-sync";
+const DEFAULT_ARCHIVE_PATH: &str = "ksef-archive.db";
 
 struct McpServer {
     ksef_client: KsefClient,
+    // Directory where downloaded exports are saved
+    export_dir: PathBuf,
+    sync_store: SyncStore,
+    archive: InvoiceArchive,
 }
 
 impl McpServer {
-    fn new() -> Self {
-        Self {
+    fn new() -> Result<Self> {
+        let export_dir = std::env::var("KSEF_EXPORT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_EXPORT_DIR));
+        let sync_dir = std::env::var("KSEF_SYNC_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_SYNC_DIR));
+
+        let archive_path = std::env::var("KSEF_ARCHIVE_PATH")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_ARCHIVE_PATH));
+        // The archive is the system of record, so the server does not start without it
+        let archive = InvoiceArchive::open(&archive_path).map_err(|e| {
+            anyhow!(
+                "Cannot open invoice archive {}: {}",
+                archive_path.display(),
+                e
+            )
+        })?;
+
+        Ok(Self {
             ksef_client: KsefClient::new(),
-        }
+            export_dir,
+            sync_store: SyncStore::new(sync_dir),
+            archive,
+        })
     }
 
     async fn handle_request(&mut self, request: JsonRpcRequest) -> Option<JsonRpcResponse> {
@@ -76,6 +150,15 @@ impl McpServer {
                         "continuationToken": {
                             "type": "string",
                             "description": "Token for getting next page of results"
+                        },
+                        "all": {
+                            "type": "boolean",
+                            "description": "Follow all pages and return the aggregated results",
+                            "default": false
+                        },
+                        "maxItems": {
+                            "type": "integer",
+                            "description": "With all: stop after this many items"
                         }
                     }
                 }),
@@ -101,13 +184,18 @@ impl McpServer {
             ),
             ToolDefinition::new(
                 "get_invoice",
-                "Get invoice details by KSeF number",
+                "Get invoice details by KSeF number: a JSON summary (seller, buyer, lines, per-rate totals, payment terms) or the raw XML",
                 json!({
                     "type": "object",
                     "properties": {
                         "ksefNumber": {
                             "type": "string",
                             "description": "KSeF invoice number"
+                        },
+                        "format": {
+                            "type": "string",
+                            "description": "summary (default) or xml",
+                            "enum": ["summary", "xml"]
                         }
                     },
                     "required": ["ksefNumber"]
@@ -130,7 +218,8 @@ impl McpServer {
                             "properties": {
                                 "dateType": {
                                     "type": "string",
-                                    "description": "Date type to filter by"
+                                    "description": "Date type to filter by",
+                                    "enum": ["Issue", "Invoicing", "PermanentStorage"]
                                 },
                                 "from": {
                                     "type": "string",
@@ -161,6 +250,21 @@ impl McpServer {
                             "minimum": 10,
                             "maximum": 100,
                             "default": 10
+                        },
+                        "pageOffset": {
+                            "type": "integer",
+                            "description": "Zero-based page index",
+                            "minimum": 0,
+                            "default": 0
+                        },
+                        "all": {
+                            "type": "boolean",
+                            "description": "Follow all pages and return the aggregated results",
+                            "default": false
+                        },
+                        "maxItems": {
+                            "type": "integer",
+                            "description": "With all: stop after this many items"
                         }
                     },
                     "required": ["subjectType", "dateRange"]
@@ -168,25 +272,10 @@ impl McpServer {
             ),
             ToolDefinition::new(
                 "create_invoice_export",
-                "Create an encrypted export of invoices",
+                "Start an encrypted export of invoices. The server generates and keeps the encryption key; fetch the result with download_invoice_export",
                 json!({
                     "type": "object",
                     "properties": {
-                        "encryption": {
-                            "type": "object",
-                            "description": "Encryption info for export result",
-                            "properties": {
-                                "encryptedSymmetricKey": {
-                                    "type": "string",
-                                    "description": "Base64-encoded encrypted symmetric key"
-                                },
-                                "initializationVector": {
-                                    "type": "string",
-                                    "description": "Base64-encoded initialization vector"
-                                }
-                            },
-                            "required": ["encryptedSymmetricKey", "initializationVector"]
-                        },
                         "filters": {
                             "type": "object",
                             "description": "Invoice query filters",
@@ -201,7 +290,8 @@ impl McpServer {
                                     "properties": {
                                         "dateType": {
                                             "type": "string",
-                                            "description": "Date type"
+                                            "description": "Date type",
+                                            "enum": ["Issue", "Invoicing", "PermanentStorage"]
                                         },
                                         "from": {
                                             "type": "string",
@@ -218,7 +308,28 @@ impl McpServer {
                             "required": ["subjectType", "dateRange"]
                         }
                     },
-                    "required": ["encryption", "filters"]
+                    "required": ["filters"]
+                }),
+            ),
+            ToolDefinition::new(
+                "download_invoice_export",
+                "Wait for an invoice export, download and decrypt its package and save the invoice XMLs and _metadata.json to the export directory (KSEF_EXPORT_DIR). Pass referenceNumber of an export started with create_invoice_export, or filters to start a new one",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "referenceNumber": {
+                            "type": "string",
+                            "description": "Reference number returned by create_invoice_export"
+                        },
+                        "filters": {
+                            "type": "object",
+                            "description": "Invoice query filters (same as in create_invoice_export) to start a new export"
+                        },
+                        "timeoutSeconds": {
+                            "type": "integer",
+                            "description": "How long to wait for the export to complete (default: 900)"
+                        }
+                    }
                 }),
             ),
             ToolDefinition::new(
@@ -245,9 +356,108 @@ impl McpServer {
                 "Get current API rate limits status",
                 json!({"type": "object", "properties": {}}),
             ),
+            ToolDefinition::new(
+                "find_local_invoices",
+                "Search the local invoice archive (generated, submitted and downloaded invoices). Works without a KSeF session",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "nip": {
+                            "type": "string",
+                            "description": "Seller or buyer NIP"
+                        },
+                        "direction": {
+                            "type": "string",
+                            "description": "sent (issued by us) or received",
+                            "enum": ["sent", "received"]
+                        },
+                        "status": {
+                            "type": "string",
+                            "enum": ["generated", "submitted", "accepted", "rejected", "received"]
+                        },
+                        "dateFrom": {
+                            "type": "string",
+                            "description": "Issue date from (YYYY-MM-DD, inclusive)"
+                        },
+                        "dateTo": {
+                            "type": "string",
+                            "description": "Issue date to (YYYY-MM-DD, inclusive)"
+                        },
+                        "minAmount": {
+                            "type": "number",
+                            "description": "Minimum gross amount"
+                        },
+                        "maxAmount": {
+                            "type": "number",
+                            "description": "Maximum gross amount"
+                        },
+                        "invoiceNumber": {
+                            "type": "string",
+                            "description": "Part of the invoice number"
+                        },
+                        "ksefNumber": {
+                            "type": "string",
+                            "description": "KSeF number (exact match)"
+                        },
+                        "limit": {
+                            "type": "integer",
+                            "description": "Maximum number of results (default: 100)"
+                        }
+                    }
+                }),
+            ),
+            ToolDefinition::new(
+                "get_local_invoice",
+                "Get an archived invoice with its XML, UPO and status history",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "id": {
+                            "type": "integer",
+                            "description": "Archive id returned by find_local_invoices"
+                        },
+                        "ksefNumber": {
+                            "type": "string",
+                            "description": "KSeF number of the invoice"
+                        }
+                    }
+                }),
+            ),
+            ToolDefinition::new(
+                "sync_invoices",
+                "Download invoices stored in KSeF since the last sync for the authenticated context (incremental, by PermanentStorage date). New invoice XMLs are saved to KSEF_SYNC_DIR; invoices already downloaded are skipped",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "subjectType": {
+                            "type": "string",
+                            "description": "Subject type to sync (default: Subject2 - purchase invoices)",
+                            "enum": ["Subject1", "Subject2", "Subject3", "SubjectAuthorized"]
+                        },
+                        "from": {
+                            "type": "string",
+                            "description": "Start date (ISO 8601) for the first sync of this subject type (default: 90 days ago). Ignored once a high-water mark exists"
+                        },
+                        "pageSize": {
+                            "type": "integer",
+                            "description": "Metadata page size (default: 100)"
+                        }
+                    }
+                }),
+            ),
+            ToolDefinition::new(
+                "sync_status",
+                "Show the high-water mark, last sync time and number of synced invoices for every context and subject type",
+                json!({"type": "object", "properties": {}}),
+            ),
+            ToolDefinition::new(
+                "get_rate_limit_budget",
+                "Show the remaining request budget per endpoint group, as tracked by the client-side throttling. Check it before bulk operations",
+                json!({"type": "object", "properties": {}}),
+            ),
             ToolDefinition::new(
                 "create_online_session",
-                "Create a new online session for invoice processing",
+                "Create a new online session for invoice processing. The server generates and keeps the session encryption key",
                 json!({
                     "type": "object",
                     "properties": {
@@ -269,24 +479,9 @@ impl McpServer {
                                 }
                             },
                             "required": ["systemCode", "schemaVersion", "value"]
-                        },
-                        "encryption": {
-                            "type": "object",
-                            "description": "Symmetric encryption key info encrypted with MF public key",
-                            "properties": {
-                                "encryptedSymmetricKey": {
-                                    "type": "string",
-                                    "description": "Base64-encoded encrypted symmetric key"
-                                },
-                                "initializationVector": {
-                                    "type": "string",
-                                    "description": "Base64-encoded initialization vector"
-                                }
-                            },
-                            "required": ["encryptedSymmetricKey", "initializationVector"]
                         }
                     },
-                    "required": ["formCode", "encryption"]
+                    "required": ["formCode"]
                 }),
             ),
             ToolDefinition::new(
@@ -305,7 +500,7 @@ impl McpServer {
             ),
             ToolDefinition::new(
                 "submit_invoice",
-                "Submit an encrypted invoice to a session",
+                "Encrypt and submit an invoice XML to an online session created with create_online_session",
                 json!({
                     "type": "object",
                     "properties": {
@@ -313,25 +508,9 @@ impl McpServer {
                             "type": "string",
                             "description": "Reference number of the session"
                         },
-                        "invoiceHash": {
+                        "invoiceXml": {
                             "type": "string",
-                            "description": "Base64-encoded SHA256 hash of original invoice"
-                        },
-                        "invoiceSize": {
-                            "type": "integer",
-                            "description": "Size of original invoice in bytes"
-                        },
-                        "encryptedInvoiceHash": {
-                            "type": "string",
-                            "description": "Base64-encoded SHA256 hash of encrypted invoice"
-                        },
-                        "encryptedInvoiceSize": {
-                            "type": "integer",
-                            "description": "Size of encrypted invoice in bytes"
-                        },
-                        "encryptedInvoiceContent": {
-                            "type": "string",
-                            "description": "Base64-encoded encrypted invoice (AES-256-CBC with PKCS#7)"
+                            "description": "Invoice XML document"
                         },
                         "offlineMode": {
                             "type": "boolean",
@@ -343,242 +522,139 @@ impl McpServer {
                             "description": "Base64-encoded SHA256 hash of corrected invoice (for technical corrections)"
                         }
                     },
-                    "required": ["sessionReferenceNumber", "invoiceHash", "invoiceSize", "encryptedInvoiceHash", "encryptedInvoiceSize", "encryptedInvoiceContent"]
+                    "required": ["sessionReferenceNumber", "invoiceXml"]
                 }),
             ),
             ToolDefinition::new(
                 "generate_invoice",
                 "Generate a KSeF-compliant invoice XML with all required fields",
+                invoice_input_schema(json!({}), &[]),
+            ),
+            ToolDefinition::new(
+                "generate_and_submit_invoice",
+                "Generate and submit a KSeF invoice in one step (requires a session created with create_online_session)",
+                invoice_input_schema(
+                    json!({
+                        "sessionReferenceNumber": {
+                            "type": "string",
+                            "description": "Reference number of the active online session"
+                        },
+                        "skipValidation": {
+                            "type": "boolean",
+                            "description": "Submit even if the XML violates the schema, listing the violations in the response (default: false)"
+                        }
+                    }),
+                    &["sessionReferenceNumber"],
+                ),
+            ),
+            ToolDefinition::new(
+                "generate_correction_invoice",
+                "Generate a correction invoice (KOR). With originalKsefNumber the original is downloaded from KSeF: its parties, currency and form variant are the defaults, and each line item replaces the original line with the same lineNumber, which is added as the state before the correction",
+                correction_input_schema(),
+            ),
+            ToolDefinition::new(
+                "generate_simplified_invoice",
+                "Generate a simplified invoice (UPR) of up to 450 PLN or 100 EUR gross, e.g. for a receipt with the buyer's NIP. The buyer is identified by NIP only and line items carry gross prices",
+                simplified_input_schema(),
+            ),
+            ToolDefinition::new(
+                "validate_invoice_xml",
+                "Validate an invoice XML against the bundled FA (2) / FA (3) schema. Reports every violation with its element path",
                 json!({
                     "type": "object",
                     "properties": {
-                        "seller": {
-                            "type": "object",
-                            "description": "Seller information (Podmiot1)",
-                            "properties": {
-                                "nip": {
-                                    "type": "string",
-                                    "description": "Seller NIP (10 digits)",
-                                    "pattern": "^[0-9]{10}$"
-                                },
-                                "name": {
-                                    "type": "string",
-                                    "description": "Seller company name"
-                                },
-                                "address": {
-                                    "type": "string",
-                                    "description": "Seller address (optional)"
-                                }
-                            },
-                            "required": ["nip", "name"]
-                        },
-                        "buyer": {
-                            "type": "object",
-                            "description": "Buyer information (Podmiot2)",
-                            "properties": {
-                                "nip": {
-                                    "type": "string",
-                                    "description": "Buyer NIP (10 digits)",
-                                    "pattern": "^[0-9]{10}$"
-                                },
-                                "name": {
-                                    "type": "string",
-                                    "description": "Buyer company name"
-                                },
-                                "address": {
-                                    "type": "string",
-                                    "description": "Buyer address (optional)"
-                                }
-                            },
-                            "required": ["nip", "name"]
-                        },
-                        "invoiceNumber": {
+                        "invoiceXml": {
                             "type": "string",
-                            "description": "Invoice number (e.g., FV/2026/01/001)"
-                        },
-                        "invoiceDate": {
-                            "type": "string",
-                            "description": "Invoice date (YYYY-MM-DD format)"
-                        },
-                        "lineItems": {
-                            "type": "array",
-                            "description": "Invoice line items",
-                            "items": {
-                                "type": "object",
-                                "properties": {
-                                    "lineNumber": {
-                                        "type": "integer",
-                                        "description": "Line number (sequential, starting from 1)"
-                                    },
-                                    "description": {
-                                        "type": "string",
-                                        "description": "Product/service description"
-                                    },
-                                    "unit": {
-                                        "type": "string",
-                                        "description": "Unit of measurement (e.g., 'szt', 'usł', 'godz')"
-                                    },
-                                    "quantity": {
-                                        "type": "number",
-                                        "description": "Quantity"
-                                    },
-                                    "unitPrice": {
-                                        "type": "number",
-                                        "description": "Net unit price"
-                                    },
-                                    "netAmount": {
-                                        "type": "number",
-                                        "description": "Net amount (quantity * unitPrice)"
-                                    },
-                                    "vatRate": {
-                                        "type": "integer",
-                                        "description": "VAT rate percentage (e.g., 23, 8, 5, 0)"
-                                    }
-                                },
-                                "required": ["lineNumber", "description", "unit", "quantity", "unitPrice", "netAmount", "vatRate"]
-                            },
-                            "minItems": 1
+                            "description": "Invoice XML document"
                         },
-                        "currency": {
+                        "formVariant": {
                             "type": "string",
-                            "description": "Currency code (default: PLN)",
-                            "default": "PLN"
+                            "enum": ["FA (2)", "FA (3)"],
+                            "description": "Schema to validate against (default: detected from the root namespace)"
                         }
                     },
-                    "required": ["seller", "buyer", "invoiceNumber", "invoiceDate", "lineItems"]
+                    "required": ["invoiceXml"]
                 }),
             ),
             ToolDefinition::new(
-                "generate_and_submit_invoice",
-                "Generate and submit a KSeF invoice in one step (requires active session with encryption key)",
+                "authenticate",
+                "Authenticate with KSeF API using NIP and KSeF token (public key is fetched automatically)",
                 json!({
                     "type": "object",
                     "properties": {
-                        "sessionReferenceNumber": {
-                            "type": "string",
-                            "description": "Reference number of the active online session"
-                        },
-                        "symmetricKey": {
-                            "type": "string",
-                            "description": "Base64-encoded AES-256 symmetric key (32 bytes) used to create the session"
-                        },
-                        "initializationVector": {
+                        "nip": {
                             "type": "string",
-                            "description": "Base64-encoded initialization vector (16 bytes) used to create the session"
+                            "description": "Polish tax identification number (NIP) - 10 digits. Shorthand for contextIdentifier of type nip",
+                            "pattern": "^[0-9]{10}$"
                         },
-                        "seller": {
+                        "contextIdentifier": {
                             "type": "object",
-                            "description": "Seller information (Podmiot1)",
+                            "description": "Context to authenticate in (use instead of nip for internal or EU identifiers)",
                             "properties": {
-                                "nip": {
-                                    "type": "string",
-                                    "description": "Seller NIP (10 digits)",
-                                    "pattern": "^[0-9]{10}$"
-                                },
-                                "name": {
+                                "type": {
                                     "type": "string",
-                                    "description": "Seller company name"
+                                    "enum": ["nip", "internalId", "nipVatUe"]
                                 },
-                                "address": {
+                                "value": {
                                     "type": "string",
-                                    "description": "Seller address (optional)"
+                                    "description": "nip: 10 digits; internalId: NIP-5 digits; nipVatUe: NIP-EU VAT number (e.g. 5265877635-DE123456789)"
                                 }
                             },
-                            "required": ["nip", "name"]
+                            "required": ["type", "value"]
+                        },
+                        "ksefToken": {
+                            "type": "string",
+                            "description": "KSeF authorization token generated from KSeF portal"
+                        }
+                    },
+                    "required": ["ksefToken"]
+                }),
+            ),
+            ToolDefinition::new(
+                "authenticate_with_certificate",
+                "Authenticate with KSeF API using a qualified certificate or seal (XAdES signature). Provide either pkcs12Path or certificatePath with privateKeyPath",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "nip": {
+                            "type": "string",
+                            "description": "Polish tax identification number (NIP) - 10 digits. Shorthand for contextIdentifier of type nip",
+                            "pattern": "^[0-9]{10}$"
                         },
-                        "buyer": {
+                        "contextIdentifier": {
                             "type": "object",
-                            "description": "Buyer information (Podmiot2)",
+                            "description": "Context to authenticate in (use instead of nip for internal or EU identifiers)",
                             "properties": {
-                                "nip": {
+                                "type": {
                                     "type": "string",
-                                    "description": "Buyer NIP (10 digits)",
-                                    "pattern": "^[0-9]{10}$"
+                                    "enum": ["nip", "internalId", "nipVatUe"]
                                 },
-                                "name": {
-                                    "type": "string",
-                                    "description": "Buyer company name"
-                                },
-                                "address": {
+                                "value": {
                                     "type": "string",
-                                    "description": "Buyer address (optional)"
+                                    "description": "nip: 10 digits; internalId: NIP-5 digits; nipVatUe: NIP-EU VAT number (e.g. 5265877635-DE123456789)"
                                 }
                             },
-                            "required": ["nip", "name"]
+                            "required": ["type", "value"]
                         },
-                        "invoiceNumber": {
+                        "pkcs12Path": {
                             "type": "string",
-                            "description": "Invoice number (e.g., FV/2026/01/001)"
+                            "description": "Path to a PKCS#12 (.p12/.pfx) file with the certificate and private key"
                         },
-                        "invoiceDate": {
+                        "password": {
                             "type": "string",
-                            "description": "Invoice date (YYYY-MM-DD format)"
+                            "description": "Password of the PKCS#12 file",
+                            "default": ""
                         },
-                        "lineItems": {
-                            "type": "array",
-                            "description": "Invoice line items",
-                            "items": {
-                                "type": "object",
-                                "properties": {
-                                    "lineNumber": {
-                                        "type": "integer",
-                                        "description": "Line number (sequential, starting from 1)"
-                                    },
-                                    "description": {
-                                        "type": "string",
-                                        "description": "Product/service description"
-                                    },
-                                    "unit": {
-                                        "type": "string",
-                                        "description": "Unit of measurement (e.g., 'szt', 'usł', 'godz')"
-                                    },
-                                    "quantity": {
-                                        "type": "number",
-                                        "description": "Quantity"
-                                    },
-                                    "unitPrice": {
-                                        "type": "number",
-                                        "description": "Net unit price"
-                                    },
-                                    "netAmount": {
-                                        "type": "number",
-                                        "description": "Net amount (quantity * unitPrice)"
-                                    },
-                                    "vatRate": {
-                                        "type": "integer",
-                                        "description": "VAT rate percentage (e.g., 23, 8, 5, 0)"
-                                    }
-                                },
-                                "required": ["lineNumber", "description", "unit", "quantity", "unitPrice", "netAmount", "vatRate"]
-                            },
-                            "minItems": 1
-                        },
-                        "currency": {
-                            "type": "string",
-                            "description": "Currency code (default: PLN)",
-                            "default": "PLN"
-                        }
-                    },
-                    "required": ["sessionReferenceNumber", "symmetricKey", "initializationVector", "seller", "buyer", "invoiceNumber", "invoiceDate", "lineItems"]
-                }),
-            ),
-            ToolDefinition::new(
-                "authenticate",
-                "Authenticate with KSeF API using NIP and KSeF token (public key is fetched automatically)",
-                json!({
-                    "type": "object",
-                    "properties": {
-                        "nip": {
+                        "certificatePath": {
                             "type": "string",
-                            "description": "Polish tax identification number (NIP) - 10 digits",
-                            "pattern": "^[0-9]{10}$"
+                            "description": "Path to a PEM-encoded certificate"
                         },
-                        "ksefToken": {
+                        "privateKeyPath": {
                             "type": "string",
-                            "description": "KSeF authorization token generated from KSeF portal"
+                            "description": "Path to an unencrypted PEM-encoded private key (RSA or EC P-256)"
                         }
                     },
-                    "required": ["nip", "ksefToken"]
+                    "required": []
                 }),
             ),
             ToolDefinition::new(
@@ -612,6 +688,15 @@ impl McpServer {
                         "continuationToken": {
                             "type": "string",
                             "description": "Token for getting next page of results"
+                        },
+                        "all": {
+                            "type": "boolean",
+                            "description": "Follow all pages and return the aggregated results",
+                            "default": false
+                        },
+                        "maxItems": {
+                            "type": "integer",
+                            "description": "With all: stop after this many items"
                         }
                     }
                 }),
@@ -643,6 +728,15 @@ impl McpServer {
                         "continuationToken": {
                             "type": "string",
                             "description": "Token for getting next page of results"
+                        },
+                        "all": {
+                            "type": "boolean",
+                            "description": "Follow all pages and return the aggregated results",
+                            "default": false
+                        },
+                        "maxItems": {
+                            "type": "integer",
+                            "description": "With all: stop after this many items"
                         }
                     },
                     "required": ["referenceNumber"]
@@ -702,6 +796,35 @@ impl McpServer {
                     "required": ["sessionReferenceNumber", "upoReferenceNumber"]
                 }),
             ),
+            ToolDefinition::new(
+                "verify_upo",
+                "Verify a UPO: check its XAdES signature, that the signer is issued by a trusted certificate and that it confirms the given invoice (KSeF number, invoice hash, seller NIP, session). Uses the archived UPO and invoice XML when they are not passed",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "upoXml": {
+                            "type": "string",
+                            "description": "UPO XML (default: UPO archived for ksefNumber, or downloaded with sessionReferenceNumber)"
+                        },
+                        "ksefNumber": {
+                            "type": "string",
+                            "description": "KSeF number of the invoice the UPO should confirm"
+                        },
+                        "sessionReferenceNumber": {
+                            "type": "string",
+                            "description": "Session to download the UPO from when it is not archived"
+                        },
+                        "invoiceXml": {
+                            "type": "string",
+                            "description": "Invoice XML exactly as sent (default: archived XML for ksefNumber)"
+                        },
+                        "trustedCertificatesPath": {
+                            "type": "string",
+                            "description": "PEM or DER file with the certificates trusted to sign UPOs (default: KSEF_UPO_TRUSTED_CERTS)"
+                        }
+                    }
+                }),
+            ),
             ToolDefinition::new(
                 "create_batch_session",
                 "Create a new batch session for bulk invoice processing",
@@ -802,6 +925,52 @@ impl McpServer {
                     "required": ["referenceNumber"]
                 }),
             ),
+            ToolDefinition::new(
+                "submit_invoice_batch",
+                "Submit many invoices in one batch session: the server packs them into a ZIP, splits and encrypts it, uploads all parts and closes the session",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "formCode": {
+                            "type": "object",
+                            "description": "Invoice schema of all invoices in the batch",
+                            "properties": {
+                                "systemCode": {"type": "string"},
+                                "schemaVersion": {"type": "string"},
+                                "value": {"type": "string"}
+                            },
+                            "required": ["systemCode", "schemaVersion", "value"]
+                        },
+                        "invoices": {
+                            "type": "array",
+                            "description": "Invoices to send; each item has either invoiceXml or path",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "fileName": {
+                                        "type": "string",
+                                        "description": "Name of the file in the ZIP (default: invoice-N.xml or the file name of path)"
+                                    },
+                                    "invoiceXml": {
+                                        "type": "string",
+                                        "description": "Invoice XML document"
+                                    },
+                                    "path": {
+                                        "type": "string",
+                                        "description": "Path to an invoice XML file"
+                                    }
+                                }
+                            }
+                        },
+                        "offlineMode": {
+                            "type": "boolean",
+                            "description": "Offline invoicing mode",
+                            "default": false
+                        }
+                    },
+                    "required": ["formCode", "invoices"]
+                }),
+            ),
         ];
 
         JsonRpcResponse::success(id, json!({ "tools": tools }))
@@ -824,7 +993,12 @@ impl McpServer {
 
         match result {
             Ok(content) => JsonRpcResponse::success(id, json!(ToolCallResult::text(content))),
-            Err(e) => JsonRpcResponse::internal_error(id, format!("Tool execution failed: {}", e)),
+            Err(e) => match e.downcast_ref::<KsefError>() {
+                Some(ksef_error) => ksef_error_response(id, ksef_error),
+                None => {
+                    JsonRpcResponse::internal_error(id, format!("Tool execution failed: {}", e))
+                }
+            },
         }
     }
 
@@ -834,368 +1008,448 @@ impl McpServer {
                 let page_size = args.get("pageSize").and_then(|v| v.as_i64()).unwrap_or(10);
                 let continuation_token = args.get("continuationToken").and_then(|v| v.as_str());
 
-                let result = self.ksef_client.get_active_sessions(page_size, continuation_token).await?;
-                Ok(format!("Active sessions:\n{}", result))
+                let result = if fetch_all(args) {
+                    AuthenticationSessionsPage {
+                        continuation_token: None,
+                        items: self
+                            .ksef_client
+                            .active_sessions_stream(page_size, max_items(args))
+                            .try_collect()
+                            .await?,
+                    }
+                } else {
+                    self.ksef_client
+                        .get_active_sessions(page_size, continuation_token)
+                        .await?
+                };
+                Ok(format!(
+                    "Active sessions:\n{}",
+                    format_auth_sessions(&result.items, result.continuation_token.as_deref())
+                ))
             }
             "get_current_session" => {
                 let result = self.ksef_client.get_current_session().await?;
-                Ok(format!("Current session:\n{}", result))
+                Ok(format!(
+                    "Current session:\n{}",
+                    format_auth_sessions(&[result], None)
+                ))
             }
             "terminate_session" => {
                 let reference_number = args
                     .get("referenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing referenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing referenceNumber"))?;
 
-                let result = self.ksef_client.terminate_session(reference_number).await?;
-                Ok(format!("Session terminated:\n{}", result))
+                self.ksef_client.terminate_session(reference_number).await?;
+                Ok(format!("Session {} terminated", reference_number))
             }
             "get_invoice" => {
                 let ksef_number = args
                     .get("ksefNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing ksefNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing ksefNumber"))?;
 
                 let result = self.ksef_client.get_invoice(ksef_number).await?;
-                Ok(format!("Invoice details:\n{}", result))
+                let parsed = parse_invoice_xml(&result);
+                let mut invoice = match &parsed {
+                    Ok(parsed) => NewInvoice {
+                        ksef_number: Some(ksef_number.to_string()),
+                        ..archived_invoice(&parsed.invoice, &result)
+                    },
+                    Err(_) => NewInvoice {
+                        ksef_number: Some(ksef_number.to_string()),
+                        seller_nip: ksef_number.split('-').next().map(|s| s.to_string()),
+                        ..NewInvoice::received(result.as_str())
+                    },
+                };
+                // Our own sales invoices are sent, everything else was received
+                let context = self.ksef_client.auth_context();
+                invoice.direction = match (&invoice.seller_nip, &context) {
+                    (Some(seller), Some(context)) if seller == context.nip() => Direction::Sent,
+                    _ => Direction::Received,
+                };
+                self.archive_invoice(invoice);
+
+                match (args.get("format").and_then(|v| v.as_str()), parsed) {
+                    (Some("xml"), _) => Ok(format!("Invoice details:\n{}", result)),
+                    (_, Ok(parsed)) => Ok(format!(
+                        "Invoice details:\n{}",
+                        serde_json::to_string_pretty(&invoice_summary(&parsed))?
+                    )),
+                    (_, Err(e)) => Ok(format!(
+                        "Invoice details (could not be summarised: {}):\n{}",
+                        e, result
+                    )),
+                }
             }
             "query_invoice_metadata" => {
-                let result = self.ksef_client.query_invoice_metadata(args).await?;
-                Ok(format!("Invoice metadata:\n{}", result))
+                let filters: InvoiceQueryFilters = parse_args(args)?;
+                let page_offset = args.get("pageOffset").and_then(|v| v.as_i64()).unwrap_or(0);
+                let page_size = args.get("pageSize").and_then(|v| v.as_i64()).unwrap_or(10);
+
+                let result = if fetch_all(args) {
+                    // A truncated result that cannot be resumed still returns what was read
+                    let mut page = InvoiceMetadataPage {
+                        has_more: false,
+                        is_truncated: false,
+                        permanent_storage_hwm_date: None,
+                        invoices: Vec::new(),
+                    };
+                    let mut stream = std::pin::pin!(self.ksef_client.invoice_metadata_stream(
+                        &filters,
+                        page_size,
+                        max_items(args)
+                    ));
+                    while let Some(item) = stream.next().await {
+                        match item {
+                            Ok(invoice) => page.invoices.push(invoice),
+                            Err(KsefError::Truncated(_)) => page.is_truncated = true,
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                    page
+                } else {
+                    self.ksef_client
+                        .query_invoice_metadata(&filters, page_offset, page_size)
+                        .await?
+                };
+                Ok(format!(
+                    "Invoice metadata:\n{}",
+                    format_invoice_metadata(&result)
+                ))
             }
             "create_invoice_export" => {
-                let result = self.ksef_client.create_invoice_export(args).await?;
-                Ok(format!("Export created:\n{}", result))
+                let filters: InvoiceQueryFilters = parse_args(
+                    args.get("filters")
+                        .ok_or_else(|| invalid_params!("Missing filters"))?,
+                )?;
+                let reference_number = self.ksef_client.start_invoice_export(filters).await?;
+                Ok(format!(
+                    "Export created. Reference number: {}",
+                    reference_number
+                ))
+            }
+            "download_invoice_export" => {
+                let mut options = PollingOptions::for_export();
+                if let Some(timeout) = args.get("timeoutSeconds").and_then(|v| v.as_u64()) {
+                    options.timeout = Duration::from_secs(timeout);
+                }
+
+                let reference_number = args.get("referenceNumber").and_then(|v| v.as_str());
+                let export = match (reference_number, args.get("filters")) {
+                    (Some(reference_number), _) => {
+                        self.ksef_client
+                            .download_invoice_export(reference_number, &options)
+                            .await?
+                    }
+                    (None, Some(filters)) => {
+                        let filters: InvoiceQueryFilters = parse_args(filters)?;
+                        self.ksef_client.export_invoices(filters, &options).await?
+                    }
+                    (None, None) => {
+                        return Err(invalid_params!("Provide referenceNumber or filters"))
+                    }
+                };
+
+                let target = export.save_to(&self.export_dir)?;
+                Ok(format!(
+                    "Export {} downloaded.\nInvoices: {}{}\nSaved to: {}{}",
+                    export.reference_number,
+                    export.invoices.len(),
+                    if export.metadata_json.is_some() {
+                        " (+ _metadata.json)"
+                    } else {
+                        ""
+                    },
+                    target.display(),
+                    if export.package.is_truncated {
+                        "\nPackage is truncated: start another export from the last date in the package"
+                    } else {
+                        ""
+                    }
+                ))
             }
             "get_export_status" => {
                 let reference_number = args
                     .get("referenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing referenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing referenceNumber"))?;
 
                 let result = self.ksef_client.get_export_status(reference_number).await?;
-                Ok(format!("Export status:\n{}", result))
+                Ok(format!("Export status:\n{}", format_export_status(&result)))
             }
             "get_public_key_certificates" => {
                 let result = self.ksef_client.get_public_key_certificates().await?;
-                Ok(format!("Public key certificates:\n{}", result))
+                Ok(format!(
+                    "Public key certificates:\n{}",
+                    to_pretty_json(&result)?
+                ))
             }
             "get_rate_limits" => {
                 let result = self.ksef_client.get_rate_limits().await?;
-                Ok(format!("Rate limits:\n{}", result))
+                Ok(format!("Rate limits:\n{}", format_rate_limits(&result)))
+            }
+            "find_local_invoices" => {
+                let string = |name: &str| args.get(name).and_then(|v| v.as_str()).map(String::from);
+                let query = InvoiceQuery {
+                    nip: string("nip"),
+                    direction: parse_enum_arg(args, "direction", Direction::parse)?,
+                    status: parse_enum_arg(args, "status", InvoiceStatus::parse)?,
+                    issue_date_from: string("dateFrom"),
+                    issue_date_to: string("dateTo"),
+                    min_amount: args.get("minAmount").and_then(|v| v.as_f64()),
+                    max_amount: args.get("maxAmount").and_then(|v| v.as_f64()),
+                    invoice_number: string("invoiceNumber"),
+                    ksef_number: string("ksefNumber"),
+                    limit: args
+                        .get("limit")
+                        .and_then(|v| v.as_u64())
+                        .map(|n| n as usize),
+                };
+
+                let invoices = self.archive.find(&query)?;
+                Ok(format!(
+                    "Found {} archived invoice(s):\n{}",
+                    invoices.len(),
+                    format_local_invoices(&invoices)
+                ))
+            }
+            "get_local_invoice" => {
+                let invoice = match (
+                    args.get("id").and_then(|v| v.as_i64()),
+                    args.get("ksefNumber").and_then(|v| v.as_str()),
+                ) {
+                    (Some(id), _) => self.archive.get(id)?,
+                    (None, Some(ksef_number)) => self.archive.get_by_ksef_number(ksef_number)?,
+                    (None, None) => return Err(invalid_params!("Provide id or ksefNumber")),
+                };
+                let history = self.archive.history(invoice.id)?;
+                Ok(format_local_invoice(&invoice, &history))
+            }
+            "sync_invoices" => {
+                let subject_type: SubjectType = match args.get("subjectType") {
+                    Some(value) => parse_args(value)?,
+                    None => SubjectType::Subject2,
+                };
+                let mut options = SyncOptions::default();
+                if let Some(from) = args.get("from").and_then(|v| v.as_str()) {
+                    let from = DateTime::parse_from_rfc3339(from)
+                        .map_err(|e| invalid_params!("Invalid from date: {}", e))?;
+                    options.initial_from = Some(from.with_timezone(&Utc));
+                }
+                if let Some(page_size) = args.get("pageSize").and_then(|v| v.as_i64()) {
+                    options.page_size = page_size;
+                }
+
+                let report = self
+                    .ksef_client
+                    .sync_invoices(&self.sync_store, subject_type, &options)
+                    .await?;
+                for invoice in &report.new_invoices {
+                    let path = self.sync_store.invoice_path(
+                        &report.context,
+                        report.subject_type,
+                        &invoice.ksef_number,
+                    );
+                    match std::fs::read_to_string(&path) {
+                        Ok(xml) => {
+                            self.archive_invoice(synced_invoice(invoice, subject_type, xml));
+                        }
+                        Err(e) => eprintln!("Cannot archive {}: {}", path.display(), e),
+                    }
+                }
+                Ok(format_sync_report(&report, &self.sync_store))
+            }
+            "sync_status" => {
+                let state = self.sync_store.load_state()?;
+                Ok(format_sync_state(&state, &self.sync_store))
+            }
+            "get_rate_limit_budget" => {
+                if self.ksef_client.rate_limit_budget().is_empty() {
+                    self.ksef_client.get_rate_limits().await?;
+                }
+                Ok(format!(
+                    "Remaining request budget:\n{}",
+                    format_rate_limit_budget(&self.ksef_client.rate_limit_budget())
+                ))
             }
             "create_online_session" => {
-                let result = self.ksef_client.create_online_session(args).await?;
-                Ok(format!("Online session created:\n{}", result))
+                let form_code: FormCode = parse_args(
+                    args.get("formCode")
+                        .ok_or_else(|| invalid_params!("Missing formCode"))?,
+                )?;
+                let result = self.ksef_client.open_online_session(form_code).await?;
+                Ok(format!(
+                    "Online session created.\nReference number: {}\nValid until: {}",
+                    result.reference_number, result.valid_until
+                ))
             }
             "close_online_session" => {
                 let reference_number = args
                     .get("referenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing referenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing referenceNumber"))?;
 
-                let result = self.ksef_client.close_online_session(reference_number).await?;
-                Ok(format!("Session closed:\n{}", result))
+                self.ksef_client
+                    .close_online_session(reference_number)
+                    .await?;
+                Ok(format!("Session {} closed", reference_number))
             }
             "submit_invoice" => {
                 let session_ref = args
                     .get("sessionReferenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing sessionReferenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing sessionReferenceNumber"))?;
 
-                // Extract invoice data (remove sessionReferenceNumber from args)
-                let mut invoice_data = args.clone();
-                if let Some(obj) = invoice_data.as_object_mut() {
-                    obj.remove("sessionReferenceNumber");
-                }
+                let invoice_xml = args
+                    .get("invoiceXml")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| invalid_params!("Missing invoiceXml"))?;
+                let offline_mode = args
+                    .get("offlineMode")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                let hash_of_corrected_invoice = args
+                    .get("hashOfCorrectedInvoice")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string());
 
-                let result = self.ksef_client.submit_invoice(session_ref, &invoice_data).await?;
-                Ok(format!("Invoice submitted:\n{}", result))
+                let archive_id = self.archive_invoice(NewInvoice::sent(invoice_xml));
+                let result = self
+                    .ksef_client
+                    .send_online_invoice(
+                        session_ref,
+                        invoice_xml,
+                        offline_mode,
+                        hash_of_corrected_invoice,
+                    )
+                    .await?;
+                self.archive_submission(archive_id, session_ref, Some(&result.reference_number));
+                Ok(format!(
+                    "Invoice submitted. Reference number: {}",
+                    result.reference_number
+                ))
             }
             "generate_invoice" => {
-                // Parse seller
-                let seller_obj = args
-                    .get("seller")
-                    .ok_or_else(|| anyhow!("Missing seller"))?;
-                let seller = Party {
-                    nip: seller_obj
-                        .get("nip")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing seller.nip"))?
-                        .to_string(),
-                    nazwa: seller_obj
-                        .get("name")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing seller.name"))?
-                        .to_string(),
-                    adres: seller_obj
-                        .get("address")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                };
-
-                // Parse buyer
-                let buyer_obj = args
-                    .get("buyer")
-                    .ok_or_else(|| anyhow!("Missing buyer"))?;
-                let buyer = Party {
-                    nip: buyer_obj
-                        .get("nip")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing buyer.nip"))?
-                        .to_string(),
-                    nazwa: buyer_obj
-                        .get("name")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing buyer.name"))?
-                        .to_string(),
-                    adres: buyer_obj
-                        .get("address")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
+                let mut invoice = parse_invoice(args, FormVariant::default())?;
+                invoice.zaliczki = self.advance_invoices(args, invoice.rodzaj).await?;
+                Ok(self.generated_invoice(&invoice))
+            }
+            "generate_correction_invoice" => {
+                // Start from the original invoice when its KSeF number is given
+                let original = match args.get("originalKsefNumber").and_then(|v| v.as_str()) {
+                    Some(ksef_number) => {
+                        let xml = self.ksef_client.get_invoice(ksef_number).await?;
+                        let parsed = parse_invoice_xml(&xml).map_err(|e| {
+                            anyhow!("Cannot read original invoice {}: {}", ksef_number, e)
+                        })?;
+                        Some((ksef_number, parsed.invoice))
+                    }
+                    None => None,
                 };
 
-                // Parse invoice details
-                let invoice_number = args
-                    .get("invoiceNumber")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing invoiceNumber"))?
-                    .to_string();
-                let invoice_date = args
-                    .get("invoiceDate")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing invoiceDate"))?
-                    .to_string();
-                let currency = args
-                    .get("currency")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("PLN")
-                    .to_string();
-
-                // Create invoice
-                let mut invoice = Invoice::new(seller, buyer, invoice_date, invoice_number);
-                invoice.waluta = currency;
-
-                // Parse line items
-                let line_items_arr = args
-                    .get("lineItems")
-                    .and_then(|v| v.as_array())
-                    .ok_or_else(|| anyhow!("Missing lineItems"))?;
-
-                for item_val in line_items_arr {
-                    let item = InvoiceLineItem {
-                        nr_wiersza: item_val
-                            .get("lineNumber")
-                            .and_then(|v| v.as_u64())
-                            .ok_or_else(|| anyhow!("Missing lineNumber"))? as u32,
-                        opis: item_val
-                            .get("description")
-                            .and_then(|v| v.as_str())
-                            .ok_or_else(|| anyhow!("Missing description"))?
-                            .to_string(),
-                        jednostka: item_val
-                            .get("unit")
-                            .and_then(|v| v.as_str())
-                            .ok_or_else(|| anyhow!("Missing unit"))?
-                            .to_string(),
-                        ilosc: item_val
-                            .get("quantity")
-                            .and_then(|v| v.as_f64())
-                            .ok_or_else(|| anyhow!("Missing quantity"))?,
-                        cena_netto: item_val
-                            .get("unitPrice")
-                            .and_then(|v| v.as_f64())
-                            .ok_or_else(|| anyhow!("Missing unitPrice"))?,
-                        kwota_netto: item_val
-                            .get("netAmount")
-                            .and_then(|v| v.as_f64())
-                            .ok_or_else(|| anyhow!("Missing netAmount"))?,
-                        stawka_vat: item_val
-                            .get("vatRate")
-                            .and_then(|v| v.as_u64())
-                            .ok_or_else(|| anyhow!("Missing vatRate"))? as u8,
-                    };
-                    invoice.add_line_item(item);
-                }
-
-                // Generate XML
-                let xml = invoice.generate_ksef_xml();
-                Ok(format!("Invoice XML generated successfully:\n\n{}", xml))
+                let invoice = parse_correction_invoice(args, original)?;
+                Ok(self.generated_invoice(&invoice))
+            }
+            "generate_simplified_invoice" => {
+                let invoice = parse_simplified_invoice(args)?;
+                Ok(self.generated_invoice(&invoice))
             }
             "generate_and_submit_invoice" => {
-                use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-
                 // Get session reference
                 let session_ref = args
                     .get("sessionReferenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing sessionReferenceNumber"))?;
-
-                // Get encryption parameters
-                let symmetric_key_base64 = args
-                    .get("symmetricKey")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing symmetricKey"))?;
-                let iv_base64 = args
-                    .get("initializationVector")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing initializationVector"))?;
-
-                // Decode encryption parameters
-                let symmetric_key_bytes = BASE64
-                    .decode(symmetric_key_base64.as_bytes())
-                    .map_err(|e| anyhow!("Failed to decode symmetric key: {}", e))?;
-                let symmetric_key: [u8; 32] = symmetric_key_bytes
-                    .try_into()
-                    .map_err(|_| anyhow!("Symmetric key must be 32 bytes"))?;
-
-                let iv_bytes = BASE64
-                    .decode(iv_base64.as_bytes())
-                    .map_err(|e| anyhow!("Failed to decode IV: {}", e))?;
-                let iv: [u8; 16] = iv_bytes
-                    .try_into()
-                    .map_err(|_| anyhow!("IV must be 16 bytes"))?;
-
-                // Parse seller
-                let seller_obj = args
-                    .get("seller")
-                    .ok_or_else(|| anyhow!("Missing seller"))?;
-                let seller = Party {
-                    nip: seller_obj
-                        .get("nip")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing seller.nip"))?
-                        .to_string(),
-                    nazwa: seller_obj
-                        .get("name")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing seller.name"))?
-                        .to_string(),
-                    adres: seller_obj
-                        .get("address")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                };
-
-                // Parse buyer
-                let buyer_obj = args
-                    .get("buyer")
-                    .ok_or_else(|| anyhow!("Missing buyer"))?;
-                let buyer = Party {
-                    nip: buyer_obj
-                        .get("nip")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing buyer.nip"))?
-                        .to_string(),
-                    nazwa: buyer_obj
-                        .get("name")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing buyer.name"))?
-                        .to_string(),
-                    adres: buyer_obj
-                        .get("address")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                };
+                    .ok_or_else(|| invalid_params!("Missing sessionReferenceNumber"))?;
 
-                // Parse invoice details
-                let invoice_number = args
-                    .get("invoiceNumber")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing invoiceNumber"))?
-                    .to_string();
-                let invoice_date = args
-                    .get("invoiceDate")
-                    .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing invoiceDate"))?
-                    .to_string();
-                let currency = args
-                    .get("currency")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("PLN")
-                    .to_string();
-
-                // Create invoice
-                let mut invoice = Invoice::new(seller, buyer, invoice_date, invoice_number);
-                invoice.waluta = currency;
-
-                // Parse line items
-                let line_items_arr = args
-                    .get("lineItems")
-                    .and_then(|v| v.as_array())
-                    .ok_or_else(|| anyhow!("Missing lineItems"))?;
-
-                for item_val in line_items_arr {
-                    let item = InvoiceLineItem {
-                        nr_wiersza: item_val
-                            .get("lineNumber")
-                            .and_then(|v| v.as_u64())
-                            .ok_or_else(|| anyhow!("Missing lineNumber"))? as u32,
-                        opis: item_val
-                            .get("description")
-                            .and_then(|v| v.as_str())
-                            .ok_or_else(|| anyhow!("Missing description"))?
-                            .to_string(),
-                        jednostka: item_val
-                            .get("unit")
-                            .and_then(|v| v.as_str())
-                            .ok_or_else(|| anyhow!("Missing unit"))?
-                            .to_string(),
-                        ilosc: item_val
-                            .get("quantity")
-                            .and_then(|v| v.as_f64())
-                            .ok_or_else(|| anyhow!("Missing quantity"))?,
-                        cena_netto: item_val
-                            .get("unitPrice")
-                            .and_then(|v| v.as_f64())
-                            .ok_or_else(|| anyhow!("Missing unitPrice"))?,
-                        kwota_netto: item_val
-                            .get("netAmount")
-                            .and_then(|v| v.as_f64())
-                            .ok_or_else(|| anyhow!("Missing netAmount"))?,
-                        stawka_vat: item_val
-                            .get("vatRate")
-                            .and_then(|v| v.as_u64())
-                            .ok_or_else(|| anyhow!("Missing vatRate"))? as u8,
-                    };
-                    invoice.add_line_item(item);
+                // Default to the schema the session was opened for
+                let session_variant = self
+                    .ksef_client
+                    .online_session_form_code(session_ref)
+                    .and_then(|form_code| FormVariant::from_system_code(&form_code.system_code));
+                let mut invoice = parse_invoice(args, session_variant.unwrap_or_default())?;
+                invoice.zaliczki = self.advance_invoices(args, invoice.rodzaj).await?;
+                if let Some(variant) = session_variant.filter(|v| *v != invoice.wariant) {
+                    return Err(invalid_params!(
+                        "Session {} was opened for {}, cannot submit a {} invoice",
+                        session_ref,
+                        variant.kod_systemowy(),
+                        invoice.wariant.kod_systemowy()
+                    ));
                 }
 
-                // Generate XML
+                // Generate XML; KSeF would reject a document that does not match the schema
                 let invoice_xml = invoice.generate_ksef_xml();
+                let skip_validation = args
+                    .get("skipValidation")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                let skipped = match validate_xml(&invoice_xml, invoice.wariant) {
+                    Ok(()) => String::new(),
+                    Err(errors) if skip_validation => format!(
+                        "\n\nSchema validation skipped: {}",
+                        format_validation_errors(invoice.wariant, &errors)
+                    ),
+                    Err(errors) => {
+                        return Err(KsefError::Validation(format_validation_errors(
+                            invoice.wariant,
+                            &errors,
+                        ))
+                        .into())
+                    }
+                };
 
-                // Encrypt invoice
-                let (encrypted_content, original_hash, encrypted_hash, original_size, encrypted_size) =
-                    KsefClient::encrypt_invoice_content(&invoice_xml, &symmetric_key, &iv)?;
-
-                // Prepare submission data
-                let submit_data = json!({
-                    "invoiceHash": original_hash,
-                    "invoiceSize": original_size,
-                    "encryptedInvoiceHash": encrypted_hash,
-                    "encryptedInvoiceSize": encrypted_size,
-                    "encryptedInvoiceContent": encrypted_content,
-                });
+                let archive_id = self.archive_invoice(archived_invoice(&invoice, &invoice_xml));
 
-                // Submit invoice
-                let result = self.ksef_client.submit_invoice(session_ref, &submit_data).await?;
+                // Encrypt with the session key and submit
+                let result = self
+                    .ksef_client
+                    .send_online_invoice(session_ref, &invoice_xml, false, None)
+                    .await?;
+                self.archive_submission(archive_id, session_ref, Some(&result.reference_number));
                 Ok(format!(
-                    "Invoice generated and submitted successfully!\n\nSubmission response:\n{}",
-                    result
+                    "Invoice generated and submitted successfully!\n\nInvoice reference number: {}{}",
+                    result.reference_number, skipped
                 ))
             }
-            "authenticate" => {
-                let nip = args
-                    .get("nip")
+            "validate_invoice_xml" => {
+                let invoice_xml = args
+                    .get("invoiceXml")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing nip"))?;
+                    .ok_or_else(|| invalid_params!("Missing invoiceXml"))?;
+
+                let variant = parse_enum_arg(args, "formVariant", FormVariant::from_system_code)?;
+                let variant = match variant {
+                    Some(variant) => variant,
+                    None => FormVariant::detect(invoice_xml).ok_or_else(|| {
+                        invalid_params!(
+                            "Root element is not an FA (2) or FA (3) Faktura; pass formVariant"
+                        )
+                    })?,
+                };
+                Ok(match validate_xml(invoice_xml, variant) {
+                    Ok(()) => format!("Invoice XML is valid {}", variant.kod_systemowy()),
+                    Err(errors) => format_validation_errors(variant, &errors),
+                })
+            }
+            "authenticate" => {
+                let context = parse_auth_context(args)?;
 
                 let ksef_token = args
                     .get("ksefToken")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing ksefToken"))?;
+                    .ok_or_else(|| invalid_params!("Missing ksefToken"))?;
 
-                let result = self.ksef_client.authenticate(nip, ksef_token).await?;
+                let result = self.ksef_client.authenticate(&context, ksef_token).await?;
+                Ok(result)
+            }
+            "authenticate_with_certificate" => {
+                let context = parse_auth_context(args)?;
+                let credentials = load_certificate_credentials(args)?;
+                let result = self
+                    .ksef_client
+                    .authenticate_with_certificate(&context, &credentials)
+                    .await?;
                 Ok(result)
             }
             "get_authentication_status" => {
@@ -1214,88 +1468,2057 @@ impl McpServer {
                 let page_size = args.get("pageSize").and_then(|v| v.as_i64()).unwrap_or(10);
                 let continuation_token = args.get("continuationToken").and_then(|v| v.as_str());
 
-                let result = self.ksef_client.get_sessions(page_size, continuation_token).await?;
-                Ok(format!("Sessions list:\n{}", result))
+                let result = if fetch_all(args) {
+                    SessionsPage {
+                        continuation_token: None,
+                        sessions: self
+                            .ksef_client
+                            .sessions_stream(page_size, max_items(args))
+                            .try_collect()
+                            .await?,
+                    }
+                } else {
+                    self.ksef_client
+                        .get_sessions(page_size, continuation_token)
+                        .await?
+                };
+                Ok(format!("Sessions list:\n{}", format_sessions(&result)))
             }
             "get_session_status" => {
                 let reference_number = args
                     .get("referenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing referenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing referenceNumber"))?;
 
-                let result = self.ksef_client.get_session_status(reference_number).await?;
-                Ok(format!("Session status:\n{}", result))
+                let result = self
+                    .ksef_client
+                    .get_session_status(reference_number)
+                    .await?;
+                Ok(format!(
+                    "Session status:\n{}",
+                    format_session_status(&result)
+                ))
             }
             "get_session_invoices" => {
                 let reference_number = args
                     .get("referenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing referenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing referenceNumber"))?;
                 let continuation_token = args.get("continuationToken").and_then(|v| v.as_str());
 
-                let result = self.ksef_client.get_session_invoices(reference_number, continuation_token).await?;
-                Ok(format!("Session invoices:\n{}", result))
+                let result = if fetch_all(args) {
+                    SessionInvoicesPage {
+                        continuation_token: None,
+                        invoices: self
+                            .ksef_client
+                            .session_invoices_stream(reference_number, max_items(args))
+                            .try_collect()
+                            .await?,
+                    }
+                } else {
+                    self.ksef_client
+                        .get_session_invoices(reference_number, continuation_token)
+                        .await?
+                };
+                self.archive_session_invoices(reference_number, &result.invoices);
+                Ok(format!(
+                    "Session invoices:\n{}",
+                    format_session_invoices(&result)
+                ))
             }
             "get_invoice_upo_by_ksef" => {
                 let session_ref = args
                     .get("sessionReferenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing sessionReferenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing sessionReferenceNumber"))?;
                 let ksef_number = args
                     .get("ksefNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing ksefNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing ksefNumber"))?;
 
                 let result = self.ksef_client.get_invoice_upo_by_ksef(session_ref, ksef_number).await?;
-                Ok(format!("Invoice UPO:\n{}", result))
+                self.archive_upo(ksef_number, &result);
+                Ok(format!(
+                    "Invoice UPO:\n{}{}",
+                    format_upo_summary(&result),
+                    result
+                ))
             }
             "get_invoice_upo_by_reference" => {
                 let session_ref = args
                     .get("sessionReferenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing sessionReferenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing sessionReferenceNumber"))?;
                 let invoice_ref = args
                     .get("invoiceReferenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing invoiceReferenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing invoiceReferenceNumber"))?;
 
                 let result = self.ksef_client.get_invoice_upo_by_reference(session_ref, invoice_ref).await?;
-                Ok(format!("Invoice UPO:\n{}", result))
+                self.archive_upo(invoice_ref, &result);
+                Ok(format!(
+                    "Invoice UPO:\n{}{}",
+                    format_upo_summary(&result),
+                    result
+                ))
             }
             "get_session_upo" => {
                 let session_ref = args
                     .get("sessionReferenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing sessionReferenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing sessionReferenceNumber"))?;
                 let upo_ref = args
                     .get("upoReferenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing upoReferenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing upoReferenceNumber"))?;
+
+                let result = self
+                    .ksef_client
+                    .get_session_upo(session_ref, upo_ref)
+                    .await?;
+                Ok(format!(
+                    "Session UPO:\n{}{}",
+                    format_upo_summary(&result),
+                    result
+                ))
+            }
+            "verify_upo" => {
+                let ksef_number = args.get("ksefNumber").and_then(|v| v.as_str());
+                let archived = match ksef_number.map(|k| self.archive.get_by_ksef_number(k)) {
+                    Some(Ok(invoice)) => Some(invoice),
+                    Some(Err(ArchiveError::NotFound(_))) | None => None,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+
+                let upo_xml = match (
+                    args.get("upoXml").and_then(|v| v.as_str()),
+                    archived.as_ref().and_then(|i| i.upo.as_deref()),
+                    args.get("sessionReferenceNumber").and_then(|v| v.as_str()),
+                    ksef_number,
+                ) {
+                    (Some(upo), _, _, _) | (None, Some(upo), _, _) => upo.to_string(),
+                    (None, None, Some(session_ref), Some(ksef_number)) => {
+                        let upo = self
+                            .ksef_client
+                            .get_invoice_upo_by_ksef(session_ref, ksef_number)
+                            .await?;
+                        self.archive_upo(ksef_number, &upo);
+                        upo
+                    }
+                    _ => {
+                        return Err(invalid_params!(
+                            "Provide upoXml, or ksefNumber of an archived invoice with a UPO, or sessionReferenceNumber and ksefNumber"
+                        ))
+                    }
+                };
+
+                let trusted_path = args
+                    .get("trustedCertificatesPath")
+                    .and_then(|v| v.as_str())
+                    .map(String::from)
+                    .or_else(|| std::env::var("KSEF_UPO_TRUSTED_CERTS").ok());
+                let trusted = match trusted_path {
+                    Some(path) => ksef_client::parse_trusted_certificates(
+                        &std::fs::read(&path)
+                            .map_err(|e| anyhow!("Failed to read '{}': {}", path, e))?,
+                    )?,
+                    None => Vec::new(),
+                };
 
-                let result = self.ksef_client.get_session_upo(session_ref, upo_ref).await?;
-                Ok(format!("Session UPO:\n{}", result))
+                let expected = UpoExpectation {
+                    ksef_number,
+                    invoice_xml: args
+                        .get("invoiceXml")
+                        .and_then(|v| v.as_str())
+                        .or(archived.as_ref().map(|i| i.xml.as_str())),
+                    seller_nip: archived.as_ref().and_then(|i| i.seller_nip.as_deref()),
+                    session_reference_number: archived
+                        .as_ref()
+                        .and_then(|i| i.session_reference.as_deref()),
+                    trusted_certificates: &trusted,
+                };
+                let verification = ksef_client::verify_upo(&upo_xml, &expected)?;
+                Ok(format_upo_verification(&verification))
             }
             "create_batch_session" => {
-                let result = self.ksef_client.create_batch_session(args).await?;
-                Ok(format!("Batch session created:\n{}", result))
+                let request: OpenBatchSessionRequest = parse_args(args)?;
+                let result = self.ksef_client.create_batch_session(&request).await?;
+                Ok(format!(
+                    "Batch session created. Reference number: {}\n\nPart upload requests:\n{}",
+                    result.reference_number,
+                    to_pretty_json(&result.part_upload_requests)?
+                ))
             }
             "close_batch_session" => {
                 let reference_number = args
                     .get("referenceNumber")
                     .and_then(|v| v.as_str())
-                    .ok_or_else(|| anyhow!("Missing referenceNumber"))?;
+                    .ok_or_else(|| invalid_params!("Missing referenceNumber"))?;
+
+                self.ksef_client
+                    .close_batch_session(reference_number)
+                    .await?;
+                Ok(format!("Batch session {} closed", reference_number))
+            }
+            "submit_invoice_batch" => {
+                let form_code: FormCode = parse_args(
+                    args.get("formCode")
+                        .ok_or_else(|| invalid_params!("Missing formCode"))?,
+                )?;
+                let invoices = parse_batch_invoices(args)?;
+                let offline_mode = args
+                    .get("offlineMode")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+
+                let result = self
+                    .ksef_client
+                    .submit_batch(form_code, &invoices, offline_mode)
+                    .await?;
+                for invoice in &invoices {
+                    let archive_id = self.archive_invoice(NewInvoice::sent(invoice.xml.as_str()));
+                    self.archive_submission(archive_id, &result.reference_number, None);
+                }
+                Ok(format!(
+                    "Batch submitted and closed.\nReference number: {}\nInvoices: {}\nParts: {}\nPackage size: {} bytes",
+                    result.reference_number,
+                    result.invoice_count,
+                    result.part_count,
+                    result.package_size
+                ))
+            }
+            _ => Err(invalid_params!("Unknown tool: {}", tool_name)),
+        }
+    }
+
+    // Archive bookkeeping is best effort: a failure is logged and never fails
+    // a KSeF operation that already succeeded.
+
+    fn archive_invoice(&self, invoice: NewInvoice) -> Option<i64> {
+        self.archive
+            .insert(&invoice)
+            .map_err(|e| eprintln!("Failed to archive invoice: {}", e))
+            .ok()
+    }
+
+    /// Download the advance invoices a ROZ invoice settles, with their declared totals
+    async fn advance_invoices(
+        &self,
+        args: &Value,
+        rodzaj: InvoiceKind,
+    ) -> Result<Vec<AdvanceInvoice>> {
+        let ksef_numbers = args
+            .get("advanceInvoices")
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+        match (rodzaj, ksef_numbers.is_empty()) {
+            (InvoiceKind::Roz, true) => {
+                return Err(invalid_params!(
+                    "advanceInvoices is required for invoiceType ROZ"
+                ))
+            }
+            (InvoiceKind::Roz, false) => {}
+            (_, true) => return Ok(Vec::new()),
+            (_, false) => {
+                return Err(invalid_params!(
+                    "advanceInvoices is only allowed for invoiceType ROZ"
+                ))
+            }
+        }
+
+        let mut zaliczki = Vec::new();
+        for ksef_number in &ksef_numbers {
+            let ksef_number = ksef_number
+                .as_str()
+                .ok_or_else(|| invalid_params!("Invalid advanceInvoices: expected KSeF numbers"))?;
+            let xml = self.ksef_client.get_invoice(ksef_number).await?;
+            let parsed = parse_invoice_xml(&xml)
+                .map_err(|e| anyhow!("Cannot read advance invoice {}: {}", ksef_number, e))?;
+            if advance_kind(parsed.invoice.rodzaj) != InvoiceKind::Zal {
+                return Err(invalid_params!(
+                    "{} is a {} invoice, not an advance invoice (ZAL)",
+                    ksef_number,
+                    parsed.rodzaj_faktury
+                ));
+            }
+            zaliczki.push(AdvanceInvoice {
+                numer: parsed.invoice.numer,
+                numer_ksef: Some(ksef_number.to_string()),
+                sumy: parsed.sumy,
+            });
+        }
+        Ok(zaliczki)
+    }
+
+    /// Archive a generated invoice and return its XML, with schema violations as a warning
+    fn generated_invoice(&self, invoice: &Invoice) -> String {
+        let xml = invoice.generate_ksef_xml();
+        let archive_id = self.archive_invoice(archived_invoice(invoice, &xml));
+        let warnings = match validate_xml(&xml, invoice.wariant) {
+            Ok(()) => String::new(),
+            Err(errors) => format!(
+                "\n\nWarning: {}",
+                format_validation_errors(invoice.wariant, &errors)
+            ),
+        };
+        format!(
+            "Invoice XML generated successfully{}:\n\n{}{}",
+            archive_id
+                .map(|id| format!(" (archived as #{})", id))
+                .unwrap_or_default(),
+            xml,
+            warnings
+        )
+    }
+
+    fn archive_submission(
+        &self,
+        archive_id: Option<i64>,
+        session_ref: &str,
+        invoice_ref: Option<&str>,
+    ) {
+        let Some(id) = archive_id else {
+            return;
+        };
+        if let Err(e) = self.archive.record_submission(id, session_ref, invoice_ref) {
+            eprintln!("Failed to archive submission of invoice #{}: {}", id, e);
+        }
+    }
 
-                let result = self.ksef_client.close_batch_session(reference_number).await?;
-                Ok(format!("Batch session closed:\n{}", result))
+    fn archive_session_invoices(&self, session_ref: &str, invoices: &[SessionInvoiceStatus]) {
+        for invoice in invoices {
+            let status = match invoice.status.code {
+                200 => InvoiceStatus::Accepted,
+                code if code < 200 => InvoiceStatus::Submitted,
+                _ => InvoiceStatus::Rejected,
+            };
+            let update = StatusUpdate {
+                session_reference: session_ref,
+                invoice_reference: &invoice.reference_number,
+                invoice_hash: &invoice.invoice_hash,
+                ksef_number: invoice.ksef_number.as_deref(),
+                status,
+                description: Some(&invoice.status.description),
+            };
+            if let Err(e) = self.archive.update_status(&update) {
+                eprintln!(
+                    "Failed to archive status of {}: {}",
+                    invoice.reference_number, e
+                );
             }
-            _ => Err(anyhow!("Unknown tool: {}", tool_name)),
         }
     }
+
+    fn archive_upo(&self, ksef_or_reference_number: &str, upo: &str) {
+        if let Err(e) = self.archive.attach_upo(ksef_or_reference_number, upo) {
+            eprintln!(
+                "Failed to archive UPO of {}: {}",
+                ksef_or_reference_number, e
+            );
+        }
+    }
+}
+
+/// Maps a KSeF client error to a JSON-RPC error with a distinct code and structured data
+fn ksef_error_response(id: Option<Value>, error: &KsefError) -> JsonRpcResponse {
+    let (code, data) = match error {
+        KsefError::Transport(_) => (ERROR_TRANSPORT, json!({ "kind": "transport" })),
+        KsefError::Http { status, body } => (
+            ERROR_HTTP,
+            json!({ "kind": "http", "status": status, "body": body }),
+        ),
+        KsefError::Api { status, exception } => (
+            ERROR_KSEF_EXCEPTION,
+            json!({ "kind": "ksefException", "status": status, "exception": exception }),
+        ),
+        KsefError::RateLimited { retry_after, .. } => (
+            ERROR_RATE_LIMITED,
+            json!({
+                "kind": "rateLimited",
+                "status": 429,
+                "retryAfterSeconds": retry_after.map(|d| d.as_secs())
+            }),
+        ),
+        KsefError::AuthExpired(_) => (
+            ERROR_AUTH_EXPIRED,
+            json!({ "kind": "authExpired", "status": 401 }),
+        ),
+        KsefError::AuthenticationFailed {
+            reason,
+            code,
+            description,
+        } => (
+            ERROR_AUTH_FAILED,
+            json!({
+                "kind": "authenticationFailed",
+                "reason": reason.as_str(),
+                "code": code,
+                "description": description
+            }),
+        ),
+        KsefError::Crypto(_) => (ERROR_CRYPTO, json!({ "kind": "crypto" })),
+        KsefError::Validation(_) => (ERROR_INVALID_PARAMS, json!({ "kind": "validation" })),
+        KsefError::Timeout(_) => (ERROR_TIMEOUT, json!({ "kind": "timeout" })),
+        KsefError::Cancelled(_) => (ERROR_CANCELLED, json!({ "kind": "cancelled" })),
+        KsefError::InvalidResponse(_) => {
+            (ERROR_INVALID_RESPONSE, json!({ "kind": "invalidResponse" }))
+        }
+        KsefError::OperationFailed {
+            operation,
+            code,
+            description,
+        } => (
+            ERROR_OPERATION_FAILED,
+            json!({
+                "kind": "operationFailed",
+                "operation": operation,
+                "code": code,
+                "description": description
+            }),
+        ),
+        KsefError::Truncated(_) => (ERROR_TRUNCATED, json!({ "kind": "truncated" })),
+        KsefError::Io(_) => (ERROR_IO, json!({ "kind": "io" })),
+    };
+
+    JsonRpcResponse::error(
+        id,
+        code,
+        format!("Tool execution failed: {}", error),
+        Some(data),
+    )
+}
+
+/// Deserialize tool arguments into a typed request
+fn parse_args<T: DeserializeOwned>(args: &Value) -> Result<T> {
+    serde_json::from_value(args.clone()).map_err(|e| invalid_params!("Invalid arguments: {}", e))
+}
+
+/// Optional string argument parsed into an enum
+fn parse_enum_arg<T>(args: &Value, name: &str, parse: fn(&str) -> Option<T>) -> Result<Option<T>> {
+    args.get(name)
+        .and_then(|v| v.as_str())
+        .map(|v| parse(v).ok_or_else(|| invalid_params!("Invalid {}: {}", name, v)))
+        .transpose()
+}
+
+/// Read a decimal amount given as a JSON number or a string such as "1234.56"
+fn decimal_arg(args: &Value, name: &str) -> Result<Decimal> {
+    let value = args
+        .get(name)
+        .ok_or_else(|| invalid_params!("Missing {}", name))?;
+    let text = match value {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.trim().to_string(),
+        _ => return Err(invalid_params!("Invalid {}: expected a number", name)),
+    };
+    text.parse::<Decimal>()
+        .or_else(|_| Decimal::from_scientific(&text))
+        .map_err(|_| invalid_params!("Invalid {}: {}", name, text))
+}
+
+/// `all: true` - follow every page instead of returning a single one
+fn fetch_all(args: &Value) -> bool {
+    args.get("all").and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+/// `maxItems` cap for `all: true`
+fn max_items(args: &Value) -> Option<usize> {
+    args.get("maxItems")
+        .and_then(|v| v.as_u64())
+        .map(|n| n as usize)
+}
+
+/// Read the authentication context from `contextIdentifier` or the `nip` shorthand
+fn parse_auth_context(args: &Value) -> Result<AuthContext> {
+    if let Some(identifier) = args.get("contextIdentifier") {
+        let identifier_type = identifier
+            .get("type")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid_params!("Missing contextIdentifier.type"))?;
+        let value = identifier
+            .get("value")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid_params!("Missing contextIdentifier.value"))?;
+        return Ok(AuthContext::parse(identifier_type, value)?);
+    }
+
+    let nip = args
+        .get("nip")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| invalid_params!("Missing nip or contextIdentifier"))?;
+    Ok(AuthContext::parse("nip", nip)?)
+}
+
+/// Input schema shared by the invoice generation tools, extended with tool-specific properties
+fn invoice_input_schema(extra_properties: Value, extra_required: &[&str]) -> Value {
+    let bank_account = json!({
+        "type": "object",
+        "properties": {
+            "number": {
+                "type": "string",
+                "description": "IBAN or 26-digit Polish account number (NRB); checked with the IBAN checksum"
+            },
+            "swift": {"type": "string", "description": "SWIFT/BIC code"},
+            "bankName": {"type": "string"},
+            "description": {"type": "string"}
+        },
+        "required": ["number"]
+    });
+    let mut schema = json!({
+        "type": "object",
+        "properties": {
+            "seller": {
+                "type": "object",
+                "description": "Seller information (Podmiot1)",
+                "properties": {
+                    "nip": {
+                        "type": "string",
+                        "description": "Seller NIP (10 digits)",
+                        "pattern": "^[0-9]{10}$"
+                    },
+                    "name": {
+                        "type": "string",
+                        "description": "Seller company name"
+                    },
+                    "address": {
+                        "oneOf": [
+                            {"type": "string", "description": "Single-line Polish address"},
+                            {
+                                "type": "object",
+                                "properties": {
+                                    "countryCode": {"type": "string", "description": "ISO country code (KodKraju)"},
+                                    "line1": {"type": "string", "description": "Street and number (AdresL1)"},
+                                    "line2": {"type": "string", "description": "Postal code and city (AdresL2)"}
+                                },
+                                "required": ["countryCode", "line1"]
+                            }
+                        ],
+                        "description": "Seller address (optional): a single line in Poland or a structured address"
+                    }
+                },
+                "required": ["nip", "name"]
+            },
+            "buyer": {
+                "type": "object",
+                "description": "Buyer information (Podmiot2), identified by exactly one of nip, euVat, foreignId or noId",
+                "properties": {
+                    "nip": {
+                        "type": "string",
+                        "description": "Buyer NIP (10 digits)",
+                        "pattern": "^[0-9]{10}$"
+                    },
+                    "euVat": {
+                        "type": "object",
+                        "description": "EU VAT number of a buyer registered in another member state (KodUE, NrVatUE)",
+                        "properties": {
+                            "countryCode": {"type": "string", "description": "EU country code, e.g. DE"},
+                            "number": {"type": "string", "description": "VAT number without the country prefix"}
+                        },
+                        "required": ["countryCode", "number"]
+                    },
+                    "foreignId": {
+                        "type": "object",
+                        "description": "Tax number of a buyer outside the EU (KodKraju, NrID)",
+                        "properties": {
+                            "countryCode": {"type": "string", "description": "Issuing country code (optional)"},
+                            "number": {"type": "string"}
+                        },
+                        "required": ["number"]
+                    },
+                    "noId": {
+                        "type": "boolean",
+                        "description": "Buyer without a tax identifier, e.g. a consumer (BrakID)"
+                    },
+                    "name": {
+                        "type": "string",
+                        "description": "Buyer company name"
+                    },
+                    "address": {
+                        "oneOf": [
+                            {"type": "string", "description": "Single-line Polish address"},
+                            {
+                                "type": "object",
+                                "properties": {
+                                    "countryCode": {"type": "string", "description": "ISO country code (KodKraju)"},
+                                    "line1": {"type": "string", "description": "Street and number (AdresL1)"},
+                                    "line2": {"type": "string", "description": "Postal code and city (AdresL2)"}
+                                },
+                                "required": ["countryCode", "line1"]
+                            }
+                        ],
+                        "description": "Buyer address (optional): a single line in Poland or a structured address"
+                    }
+                },
+                "required": ["name"]
+            },
+            "invoiceNumber": {
+                "type": "string",
+                "description": "Invoice number (e.g., FV/2026/01/001)"
+            },
+            "invoiceDate": {
+                "type": "string",
+                "description": "Invoice date (YYYY-MM-DD format)"
+            },
+            "lineItems": {
+                "type": "array",
+                "description": "Invoice line items; for an advance invoice (ZAL) the ordered items (Zamowienie)",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "lineNumber": {
+                            "type": "integer",
+                            "description": "Line number (sequential, starting from 1)"
+                        },
+                        "description": {
+                            "type": "string",
+                            "description": "Product/service description"
+                        },
+                        "unit": {
+                            "type": "string",
+                            "description": "Unit of measurement (e.g., 'szt', 'usł', 'godz')"
+                        },
+                        "quantity": {
+                            "type": ["number", "string"],
+                            "description": "Quantity; a decimal string such as \"2.5\" avoids float rounding"
+                        },
+                        "unitPrice": {
+                            "type": ["number", "string"],
+                            "description": "Net unit price, up to 8 decimal places"
+                        },
+                        "netAmount": {
+                            "type": ["number", "string"],
+                            "description": "Net amount (quantity * unitPrice), rounded to grosze on the invoice"
+                        },
+                        "vatRate": {
+                            "type": ["integer", "string"],
+                            "enum": [23, 22, 8, 7, 5, 4, 3, 0, "23", "22", "8", "7", "5", "4", "3", "0", "0 KR", "0 WDT", "0 EX", "zw", "oo", "np I", "np II"],
+                            "description": "VAT rate (P_12): a percentage, 0 / \"0 KR\" (domestic 0%), \"0 WDT\" (intra-Community supply), \"0 EX\" (export), \"zw\" (exempt, requires exemption), \"oo\" (reverse charge), \"np I\" (outside Poland) or \"np II\" (art. 100 ust. 1 pkt 4 services)"
+                        },
+                        "annex15": {
+                            "type": "boolean",
+                            "description": "Goods or services listed in Annex 15 to the VAT act (P_12_Zal_15); from 15 000 PLN gross the invoice is marked for split payment"
+                        }
+                    },
+                    "required": ["lineNumber", "description", "unit", "quantity", "unitPrice", "netAmount", "vatRate"]
+                },
+                "minItems": 1
+            },
+            "currency": {
+                "type": "string",
+                "description": "Currency code (default: PLN)",
+                "default": "PLN"
+            },
+            "invoiceType": {
+                "type": "string",
+                "enum": ["VAT", "ZAL", "ROZ"],
+                "description": "VAT (default), ZAL - advance invoice for advanceAmount on the order in lineItems, ROZ - settlement invoice deducting advanceInvoices"
+            },
+            "advanceAmount": {
+                "type": ["number", "string"],
+                "description": "Gross advance received (ZAL), above zero and at most the gross order value; split over the VAT rates of the order"
+            },
+            "advanceInvoices": {
+                "type": "array",
+                "items": {"type": "string"},
+                "description": "KSeF numbers of the advance invoices settled by a ROZ invoice; they are downloaded and their totals deducted (requires an authenticated session)"
+            },
+            "exemption": {
+                "type": "object",
+                "description": "Legal basis of the VAT exemption (Zwolnienie), required when a line has vatRate zw and only allowed then",
+                "properties": {
+                    "basis": {
+                        "type": "string",
+                        "enum": ["act", "directive", "other"],
+                        "description": "act - VAT act or regulation (P_19A), directive - Directive 2006/112/EC (P_19B), other (P_19C)"
+                    },
+                    "provision": {
+                        "type": "string",
+                        "description": "Provision, e.g. \"art. 43 ust. 1 pkt 37 ustawy o VAT\""
+                    }
+                },
+                "required": ["basis", "provision"]
+            },
+            "payment": {
+                "type": "object",
+                "description": "Payment terms (Platnosc)",
+                "properties": {
+                    "dueDates": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Due dates (YYYY-MM-DD), one per instalment"
+                    },
+                    "method": {
+                        "type": "integer",
+                        "enum": [1, 2, 3, 4, 5, 6, 7],
+                        "description": "FormaPlatnosci: 1 - cash, 2 - card, 3 - voucher, 4 - cheque, 5 - credit, 6 - transfer, 7 - mobile"
+                    },
+                    "bankAccounts": {
+                        "type": "array",
+                        "description": "Seller's bank accounts (RachunekBankowy)",
+                        "items": bank_account.clone()
+                    },
+                    "factorBankAccounts": {
+                        "type": "array",
+                        "description": "Accounts of the factor the receivable was assigned to (RachunekBankowyFaktora)",
+                        "items": bank_account
+                    },
+                    "paid": {
+                        "type": "boolean",
+                        "description": "Paid in full (Zaplacono); requires paymentDate"
+                    },
+                    "paymentDate": {
+                        "type": "string",
+                        "description": "Date of the full payment (YYYY-MM-DD)"
+                    },
+                    "partialPayments": {
+                        "type": "array",
+                        "description": "Payments received so far on an invoice not paid in full",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "amount": {"type": ["number", "string"]},
+                                "date": {"type": "string", "description": "YYYY-MM-DD"}
+                            },
+                            "required": ["amount", "date"]
+                        }
+                    }
+                }
+            },
+            "splitPayment": {
+                "type": "boolean",
+                "description": "Declare the split payment mechanism (P_18A) regardless of the amount, e.g. on an invoice in a foreign currency worth 15 000 PLN or more. Set automatically for PLN invoices of 15 000 PLN gross or more with annex15 lines"
+            },
+            "formVariant": {
+                "type": "string",
+                "enum": ["FA (2)", "FA (3)"],
+                "description": "Invoice schema. generate_and_submit_invoice defaults to the form code of the session, generate_invoice to FA (2)"
+            },
+            "attachments": {
+                "type": "array",
+                "description": "Attachment data blocks (Zalacznik), FA (3) only",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "header": {
+                            "type": "string",
+                            "description": "Block header"
+                        },
+                        "metadata": {
+                            "type": "array",
+                            "description": "Key/value pairs describing the block (at least one)",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "key": {"type": "string"},
+                                    "value": {"type": "string"}
+                                },
+                                "required": ["key", "value"]
+                            },
+                            "minItems": 1
+                        },
+                        "paragraphs": {
+                            "type": "array",
+                            "description": "Text paragraphs",
+                            "items": {"type": "string"}
+                        },
+                        "tables": {
+                            "type": "array",
+                            "description": "Tables of text columns",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "description": {"type": "string"},
+                                    "columns": {
+                                        "type": "array",
+                                        "items": {"type": "string"},
+                                        "minItems": 1
+                                    },
+                                    "rows": {
+                                        "type": "array",
+                                        "items": {
+                                            "type": "array",
+                                            "items": {"type": "string"}
+                                        }
+                                    }
+                                },
+                                "required": ["columns"]
+                            }
+                        }
+                    },
+                    "required": ["metadata"]
+                }
+            }
+        },
+        "required": ["seller", "buyer", "invoiceNumber", "invoiceDate", "lineItems"]
+    });
+
+    if let Value::Object(extra) = extra_properties {
+        schema["properties"].as_object_mut().unwrap().extend(extra);
+    }
+    let required = schema["required"].as_array_mut().unwrap();
+    for (i, name) in extra_required.iter().enumerate() {
+        required.insert(i, json!(name));
+    }
+    schema
+}
+
+/// Input schema of `generate_correction_invoice`; the parties may come from the original
+fn correction_input_schema() -> Value {
+    let mut schema = invoice_input_schema(
+        json!({
+            "originalKsefNumber": {
+                "type": "string",
+                "description": "KSeF number of the invoice being corrected; it is downloaded and used as the starting point (requires an authenticated session)"
+            },
+            "correctedInvoices": {
+                "type": "array",
+                "description": "Corrected invoices (DaneFaKorygowanej), required without originalKsefNumber",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "invoiceNumber": {"type": "string", "description": "Number of the corrected invoice"},
+                        "invoiceDate": {"type": "string", "description": "Issue date of the corrected invoice (YYYY-MM-DD)"},
+                        "ksefNumber": {"type": "string", "description": "KSeF number; omit for an invoice issued outside KSeF"}
+                    },
+                    "required": ["invoiceNumber", "invoiceDate"]
+                }
+            },
+            "correctionReason": {
+                "type": "string",
+                "description": "Reason for the correction (PrzyczynaKorekty)"
+            },
+            "correctionType": {
+                "type": "integer",
+                "enum": [1, 2, 3],
+                "description": "When the correction takes effect (TypKorekty): 1 - date of the original invoice, 2 - date of the correction, 3 - another date"
+            }
+        }),
+        &[],
+    );
+    schema["required"] = json!(["invoiceNumber", "invoiceDate", "lineItems"]);
+    schema["properties"]["lineItems"]["description"] =
+        json!("Line items after the correction; totals are the differences to the lines before it");
+    schema["properties"]["lineItems"]["items"]["properties"]["beforeCorrection"] = json!({
+        "type": "boolean",
+        "description": "Line state before the correction (StanPrzed); added automatically from originalKsefNumber"
+    });
+    schema
+}
+
+/// Read a correction invoice, taking the parties and corrected lines from `original`
+fn parse_correction_invoice(args: &Value, original: Option<(&str, Invoice)>) -> Result<Invoice> {
+    let mut args = args.clone();
+    if let (Some((_, original)), Value::Object(obj)) = (&original, &mut args) {
+        let mut defaults = vec![
+            (
+                "seller",
+                party_json(&Party::from(original.sprzedawca.clone())),
+            ),
+            ("buyer", party_json(&original.nabywca)),
+            ("currency", json!(original.waluta)),
+            ("formVariant", json!(original.wariant.kod_systemowy())),
+            ("invoiceType", json!(advance_kind(original.rodzaj).code())),
+            ("splitPayment", json!(original.podzielona_platnosc)),
+        ];
+        let zwolnienie = original.zwolnienie.as_ref();
+        if let Some(zwolnienie) = zwolnienie.filter(|_| original.has_exempt_lines()) {
+            defaults.push(("exemption", exemption_json(zwolnienie)));
+        }
+        for (name, value) in defaults {
+            obj.entry(name).or_insert(value);
+        }
+
+        // Each corrected line follows the original line with the same number
+        let oryginalne = match advance_kind(original.rodzaj) {
+            InvoiceKind::Zal => &original.zamowienie,
+            _ => &original.pozycje,
+        };
+        if let Some(Value::Array(items)) = obj.get_mut("lineItems") {
+            let mut skorygowane = Vec::new();
+            for item in items.drain(..) {
+                let stan_przed = item.get("beforeCorrection").and_then(|v| v.as_bool());
+                let nr_wiersza = item.get("lineNumber").and_then(|v| v.as_u64());
+                let przed = oryginalne.iter().find(|o| {
+                    stan_przed != Some(true)
+                        && Some(u64::from(o.nr_wiersza)) == nr_wiersza
+                        && !o.stan_przed
+                });
+                if let Some(przed) = przed {
+                    let mut przed = line_item_json(przed, false);
+                    przed["beforeCorrection"] = json!(true);
+                    skorygowane.push(przed);
+                }
+                skorygowane.push(item);
+            }
+            *items = skorygowane;
+        }
+    }
+
+    let mut invoice = parse_invoice(&args, FormVariant::default())?;
+    invoice.rodzaj = match invoice.rodzaj {
+        InvoiceKind::Zal => InvoiceKind::KorZal,
+        InvoiceKind::Roz => InvoiceKind::KorRoz,
+        _ => InvoiceKind::Kor,
+    };
+
+    let mut faktury = Vec::new();
+    for item in args
+        .get("correctedInvoices")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+    {
+        faktury.push(CorrectedInvoice {
+            numer: item
+                .get("invoiceNumber")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| invalid_params!("Missing correctedInvoices.invoiceNumber"))?
+                .to_string(),
+            data_wystawienia: item
+                .get("invoiceDate")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| invalid_params!("Missing correctedInvoices.invoiceDate"))?
+                .to_string(),
+            numer_ksef: item
+                .get("ksefNumber")
+                .and_then(|v| v.as_str())
+                .map(String::from),
+        });
+    }
+
+    if let Some((ksef_number, original)) = original {
+        if faktury.is_empty() {
+            faktury.push(CorrectedInvoice {
+                data_wystawienia: original.data_wystawienia.clone(),
+                numer: original.numer.clone(),
+                numer_ksef: Some(ksef_number.to_string()),
+            });
+        }
+    }
+    if faktury.is_empty() {
+        return Err(invalid_params!(
+            "Missing correctedInvoices or originalKsefNumber"
+        ));
+    }
+
+    invoice.korekta = Some(Correction {
+        przyczyna: args
+            .get("correctionReason")
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        typ: args
+            .get("correctionType")
+            .and_then(|v| v.as_u64())
+            .map(|code| {
+                CorrectionType::from_code(code as u8)
+                    .ok_or_else(|| invalid_params!("Invalid correctionType: {}", code))
+            })
+            .transpose()?,
+        faktury,
+    });
+    Ok(invoice)
+}
+
+/// Input schema of `generate_simplified_invoice`: gross line amounts, buyer NIP only
+fn simplified_input_schema() -> Value {
+    let mut schema = invoice_input_schema(json!({}), &[]);
+    let properties = schema["properties"].as_object_mut().unwrap();
+    for name in ["invoiceType", "advanceAmount", "advanceInvoices"] {
+        properties.remove(name);
+    }
+    properties["buyer"]["description"] =
+        json!("Buyer (Podmiot2), identified by NIP; name and address are optional");
+    properties["buyer"]["required"] = json!(["nip"]);
+    let buyer = properties["buyer"]["properties"].as_object_mut().unwrap();
+    for name in ["euVat", "foreignId", "noId"] {
+        buyer.remove(name);
+    }
+    properties["currency"]["enum"] = json!(["PLN", "EUR"]);
+
+    let line = &mut properties["lineItems"]["items"];
+    line["properties"]["unitPrice"]["description"] =
+        json!("Gross unit price (P_9B), up to 8 decimal places");
+    line["properties"]
+        .as_object_mut()
+        .unwrap()
+        .remove("netAmount");
+    line["properties"]["grossAmount"] = json!({
+        "type": ["number", "string"],
+        "description": "Gross amount (quantity * unitPrice, P_11A); VAT is taken out of the gross total of each rate"
+    });
+    line["required"] = json!([
+        "lineNumber",
+        "description",
+        "unit",
+        "quantity",
+        "unitPrice",
+        "grossAmount",
+        "vatRate"
+    ]);
+    properties["lineItems"]["description"] = json!("Line items with gross prices");
+    schema
+}
+
+/// Read a simplified invoice; the buyer name may be left out
+fn parse_simplified_invoice(args: &Value) -> Result<Invoice> {
+    let mut args = args.clone();
+    if let Value::Object(obj) = &mut args {
+        obj.insert("invoiceType".to_string(), json!(InvoiceKind::Upr.code()));
+        if let Some(Value::Object(buyer)) = obj.get_mut("buyer") {
+            buyer.entry("name").or_insert(json!(""));
+        }
+    }
+    parse_invoice(&args, FormVariant::default())
+}
+
+/// Advance-related kind of an invoice, ignoring whether it is a correction
+fn advance_kind(rodzaj: InvoiceKind) -> InvoiceKind {
+    match rodzaj {
+        InvoiceKind::Zal | InvoiceKind::KorZal => InvoiceKind::Zal,
+        InvoiceKind::Roz | InvoiceKind::KorRoz => InvoiceKind::Roz,
+        _ => InvoiceKind::Vat,
+    }
+}
+
+/// Read the invoice of the generation tools; `formVariant` overrides `default_variant`
+fn parse_invoice(args: &Value, default_variant: FormVariant) -> Result<Invoice> {
+    let seller = match parse_party(args, "seller")? {
+        Party {
+            identyfikator: PartyId::Nip(nip),
+            nazwa,
+            adres,
+        } => Seller { nip, nazwa, adres },
+        _ => return Err(invalid_params!("seller must be identified by nip")),
+    };
+    let buyer = parse_party(args, "buyer")?;
+
+    // Parse invoice details
+    let invoice_number = args
+        .get("invoiceNumber")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| invalid_params!("Missing invoiceNumber"))?
+        .to_string();
+    let invoice_date = args
+        .get("invoiceDate")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| invalid_params!("Missing invoiceDate"))?
+        .to_string();
+    let currency = args
+        .get("currency")
+        .and_then(|v| v.as_str())
+        .unwrap_or("PLN")
+        .to_string();
+
+    // Create invoice
+    let mut invoice = Invoice::new(seller, buyer, invoice_date, invoice_number);
+    invoice.waluta = currency;
+    invoice.wariant = parse_enum_arg(args, "formVariant", FormVariant::from_system_code)?
+        .unwrap_or(default_variant);
+    invoice.zalacznik = parse_attachments(args)?;
+    if !invoice.zalacznik.is_empty() && invoice.wariant != FormVariant::Fa3 {
+        return Err(invalid_params!("attachments require formVariant FA (3)"));
+    }
+
+    invoice.rodzaj =
+        parse_enum_arg(args, "invoiceType", InvoiceKind::from_code)?.unwrap_or_default();
+    if !matches!(
+        invoice.rodzaj,
+        InvoiceKind::Vat | InvoiceKind::Zal | InvoiceKind::Roz | InvoiceKind::Upr
+    ) {
+        return Err(invalid_params!(
+            "invoiceType {} is not supported here; use generate_correction_invoice",
+            invoice.rodzaj.code()
+        ));
+    }
+    // Simplified invoices are priced gross
+    invoice.ceny_brutto = invoice.rodzaj == InvoiceKind::Upr;
+    let amount = if invoice.ceny_brutto {
+        "grossAmount"
+    } else {
+        "netAmount"
+    };
+    invoice.kwota_zaliczki = args
+        .get("advanceAmount")
+        .map(|_| decimal_arg(args, "advanceAmount"))
+        .transpose()?;
+    match (invoice.rodzaj, invoice.kwota_zaliczki) {
+        (InvoiceKind::Zal, None) => {
+            return Err(invalid_params!(
+                "advanceAmount is required for invoiceType ZAL"
+            ))
+        }
+        (InvoiceKind::Zal, Some(_)) | (_, None) => {}
+        (_, Some(_)) => {
+            return Err(invalid_params!(
+                "advanceAmount is only allowed for invoiceType ZAL"
+            ))
+        }
+    }
+
+    // Parse line items; those of an advance invoice are the ordered items
+    let line_items_arr = args
+        .get("lineItems")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| invalid_params!("Missing lineItems"))?;
+
+    for item_val in line_items_arr {
+        let item = InvoiceLineItem {
+            nr_wiersza: item_val
+                .get("lineNumber")
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| invalid_params!("Missing lineNumber"))?
+                as u32,
+            opis: item_val
+                .get("description")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| invalid_params!("Missing description"))?
+                .to_string(),
+            jednostka: item_val
+                .get("unit")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| invalid_params!("Missing unit"))?
+                .to_string(),
+            ilosc: decimal_arg(item_val, "quantity")?,
+            cena_netto: decimal_arg(item_val, "unitPrice")?,
+            kwota_netto: decimal_arg(item_val, amount)?,
+            stawka_vat: vat_rate_arg(item_val)?,
+            stan_przed: item_val
+                .get("beforeCorrection")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            zalacznik_15: item_val
+                .get("annex15")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        };
+        match invoice.rodzaj {
+            InvoiceKind::Zal => invoice.zamowienie.push(item),
+            _ => invoice.add_line_item(item),
+        }
+    }
+
+    invoice
+        .check_advance()
+        .map_err(|e| invalid_params!("advanceAmount: {}", e))?;
+
+    invoice.zwolnienie = parse_exemption(args)?;
+    invoice
+        .check_exemption()
+        .map_err(|e| invalid_params!("exemption: {}", e))?;
+
+    invoice.platnosc = parse_payment(args)?;
+    invoice.podzielona_platnosc = args
+        .get("splitPayment")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    if invoice.rodzaj == InvoiceKind::Upr {
+        if invoice.nabywca.identyfikator.nip().is_none() {
+            return Err(invalid_params!("Simplified invoices require a buyer nip"));
+        }
+        let limit = invoice
+            .simplified_limit()
+            .ok_or_else(|| invalid_params!("Simplified invoices must be issued in PLN or EUR"))?;
+        let gross = invoice.calculate_total_gross();
+        if gross > limit {
+            return Err(invalid_params!(
+                "Gross total {} {} exceeds the simplified invoice limit of {} {}",
+                gross,
+                invoice.waluta,
+                limit,
+                invoice.waluta
+            ));
+        }
+    }
+
+    Ok(invoice)
+}
+
+/// Read `vatRate` given as a percentage or a P_12 code such as "zw"
+fn vat_rate_arg(item: &Value) -> Result<VatRate> {
+    let code = match item.get("vatRate") {
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::String(s)) => s.clone(),
+        _ => return Err(invalid_params!("Missing vatRate")),
+    };
+    VatRate::from_code(&code).ok_or_else(|| invalid_params!("Invalid vatRate: {}", code))
+}
+
+/// Read the optional `exemption` object
+fn parse_exemption(args: &Value) -> Result<Option<Exemption>> {
+    let Some(obj) = args.get("exemption") else {
+        return Ok(None);
+    };
+    let provision = obj
+        .get("provision")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| invalid_params!("Missing exemption.provision"))?
+        .to_string();
+    match obj.get("basis").and_then(|v| v.as_str()) {
+        Some("act") => Ok(Some(Exemption::Ustawa(provision))),
+        Some("directive") => Ok(Some(Exemption::Dyrektywa(provision))),
+        Some("other") => Ok(Some(Exemption::Inna(provision))),
+        Some(basis) => Err(invalid_params!("Invalid exemption.basis: {}", basis)),
+        None => Err(invalid_params!("Missing exemption.basis")),
+    }
+}
+
+/// Read the optional `payment` object
+fn parse_payment(args: &Value) -> Result<Option<Payment>> {
+    let Some(obj) = args.get("payment") else {
+        return Ok(None);
+    };
+    let strings = |name: &str| -> Vec<String> {
+        obj.get(name)
+            .and_then(|v| v.as_array())
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let accounts = |name: &str| -> Result<Vec<BankAccount>> {
+        let items = obj.get(name).and_then(|v| v.as_array());
+        items
+            .into_iter()
+            .flatten()
+            .map(|item| {
+                let number = item
+                    .get("number")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| invalid_params!("Missing payment.{}.number", name))?;
+                let swift = item.get("swift").and_then(|v| v.as_str());
+                let mut account = BankAccount::new(number, swift)
+                    .map_err(|e| invalid_params!("payment.{}: {}", name, e))?;
+                let text = |field: &str| item.get(field).and_then(|v| v.as_str()).map(String::from);
+                account.nazwa_banku = text("bankName");
+                account.opis = text("description");
+                Ok(account)
+            })
+            .collect()
+    };
+
+    let mut zaplaty_czesciowe = Vec::new();
+    for item in obj
+        .get("partialPayments")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+    {
+        zaplaty_czesciowe.push(PartialPayment {
+            kwota: decimal_arg(item, "amount")
+                .map_err(|e| invalid_params!("payment.partialPayments: {}", e))?,
+            data: item
+                .get("date")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| invalid_params!("Missing payment.partialPayments.date"))?
+                .to_string(),
+        });
+    }
+
+    let payment = Payment {
+        terminy: strings("dueDates"),
+        forma: obj.get("method").map(|v| match v {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }),
+        rachunki: accounts("bankAccounts")?,
+        rachunki_faktora: accounts("factorBankAccounts")?,
+        zaplacono: obj.get("paid").and_then(|v| v.as_bool()).unwrap_or(false),
+        data_zaplaty: obj
+            .get("paymentDate")
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        zaplaty_czesciowe,
+    };
+    if let Some(ref forma) = payment.forma {
+        if !matches!(forma.as_str(), "1" | "2" | "3" | "4" | "5" | "6" | "7") {
+            return Err(invalid_params!("Invalid payment.method: {}", forma));
+        }
+    }
+    if payment.zaplacono && payment.data_zaplaty.is_none() {
+        return Err(invalid_params!(
+            "payment.paymentDate is required when paid is true"
+        ));
+    }
+    if payment.zaplacono && !payment.zaplaty_czesciowe.is_empty() {
+        return Err(invalid_params!(
+            "payment.partialPayments are only allowed when paid is false"
+        ));
+    }
+    Ok(Some(payment))
+}
+
+/// Read the `seller` or `buyer` object; exactly one of `nip`, `euVat`, `foreignId` and
+/// `noId` identifies the party
+fn parse_party(args: &Value, name: &str) -> Result<Party> {
+    let obj = args
+        .get(name)
+        .ok_or_else(|| invalid_params!("Missing {}", name))?;
+    let text = |value: &Value, field: &str| -> Result<String> {
+        value
+            .get(field)
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .ok_or_else(|| invalid_params!("Missing {}.{}", name, field))
+    };
+
+    let mut identyfikatory = Vec::new();
+    if obj.get("nip").is_some() {
+        identyfikatory.push(PartyId::Nip(text(obj, "nip")?));
+    }
+    if let Some(eu_vat) = obj.get("euVat") {
+        identyfikatory.push(PartyId::VatUe {
+            kod_ue: text(eu_vat, "countryCode")?.to_uppercase(),
+            nr_vat_ue: text(eu_vat, "number")?,
+        });
+    }
+    if let Some(foreign_id) = obj.get("foreignId") {
+        identyfikatory.push(PartyId::NrId {
+            kod_kraju: text(foreign_id, "countryCode")
+                .ok()
+                .map(|kod| kod.to_uppercase()),
+            nr_id: text(foreign_id, "number")?,
+        });
+    }
+    if obj.get("noId").and_then(|v| v.as_bool()).unwrap_or(false) {
+        identyfikatory.push(PartyId::BrakId);
+    }
+    if identyfikatory.len() != 1 {
+        return Err(invalid_params!(
+            "{} needs exactly one of nip, euVat, foreignId or noId",
+            name
+        ));
+    }
+
+    let adres = match obj.get("address") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(adres)) => Some(Address::new("PL", adres)),
+        Some(adres) => Some(Address {
+            kod_kraju: text(adres, "countryCode")?.to_uppercase(),
+            adres_l1: text(adres, "line1")?,
+            adres_l2: adres
+                .get("line2")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+        }),
+    };
+
+    Ok(Party {
+        identyfikator: identyfikatory.remove(0),
+        nazwa: text(obj, "name")?,
+        adres,
+    })
+}
+
+/// Read the optional `attachments` blocks
+fn parse_attachments(args: &Value) -> Result<Vec<AttachmentBlock>> {
+    let strings = |value: Option<&Value>| -> Vec<String> {
+        value
+            .and_then(|v| v.as_array())
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let Some(blocks) = args.get("attachments").and_then(|v| v.as_array()) else {
+        return Ok(Vec::new());
+    };
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| {
+            let metadane = block
+                .get("metadata")
+                .and_then(|v| v.as_array())
+                .map(|pairs| {
+                    pairs
+                        .iter()
+                        .map(|pair| {
+                            let field = |key: &str| {
+                                pair.get(key).and_then(|v| v.as_str()).map(String::from)
+                            };
+                            field("key").zip(field("value")).ok_or_else(|| {
+                                invalid_params!("attachments[{}].metadata needs key and value", i)
+                            })
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .transpose()?
+                .unwrap_or_default();
+            if metadane.is_empty() {
+                return Err(invalid_params!(
+                    "attachments[{}] needs at least one metadata pair",
+                    i
+                ));
+            }
+
+            let tabele = block
+                .get("tables")
+                .and_then(|v| v.as_array())
+                .map(|tables| {
+                    tables
+                        .iter()
+                        .map(|table| AttachmentTable {
+                            opis: table
+                                .get("description")
+                                .and_then(|v| v.as_str())
+                                .map(String::from),
+                            kolumny: strings(table.get("columns")),
+                            wiersze: table
+                                .get("rows")
+                                .and_then(|v| v.as_array())
+                                .map(|rows| rows.iter().map(|row| strings(Some(row))).collect())
+                                .unwrap_or_default(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            Ok(AttachmentBlock {
+                naglowek: block
+                    .get("header")
+                    .and_then(|v| v.as_str())
+                    .map(String::from),
+                metadane,
+                akapity: strings(block.get("paragraphs")),
+                tabele,
+            })
+        })
+        .collect()
+}
+
+/// Read batch invoices given inline or as file paths
+fn parse_batch_invoices(args: &Value) -> Result<Vec<BatchInvoice>> {
+    let items = args
+        .get("invoices")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| invalid_params!("Missing invoices"))?;
+
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let path = item.get("path").and_then(|v| v.as_str());
+            let xml = match (item.get("invoiceXml").and_then(|v| v.as_str()), path) {
+                (Some(xml), _) => xml.to_string(),
+                (None, Some(path)) => std::fs::read_to_string(path)
+                    .map_err(|e| anyhow!("Failed to read invoice '{}': {}", path, e))?,
+                (None, None) => {
+                    return Err(invalid_params!("invoices[{}] needs invoiceXml or path", i))
+                }
+            };
+
+            let file_name = item
+                .get("fileName")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+                .or_else(|| {
+                    path.and_then(|p| std::path::Path::new(p).file_name())
+                        .map(|n| n.to_string_lossy().into_owned())
+                })
+                .unwrap_or_else(|| format!("invoice-{}.xml", i + 1));
+
+            Ok(BatchInvoice { file_name, xml })
+        })
+        .collect()
+}
+
+/// Load signing credentials from either a PKCS#12 file or a PEM certificate/key pair
+fn load_certificate_credentials(args: &Value) -> Result<CertificateCredentials> {
+    let read = |key: &str| -> Result<Option<Vec<u8>>> {
+        match args.get(key).and_then(|v| v.as_str()) {
+            Some(path) => std::fs::read(path)
+                .map(Some)
+                .map_err(|e| anyhow!("Failed to read {} '{}': {}", key, path, e)),
+            None => Ok(None),
+        }
+    };
+
+    if let Some(pkcs12) = read("pkcs12Path")? {
+        let password = args.get("password").and_then(|v| v.as_str()).unwrap_or("");
+        return Ok(CertificateCredentials::from_pkcs12(&pkcs12, password)?);
+    }
+
+    match (read("certificatePath")?, read("privateKeyPath")?) {
+        (Some(certificate), Some(private_key)) => Ok(CertificateCredentials::from_pem(
+            &String::from_utf8_lossy(&certificate),
+            &String::from_utf8_lossy(&private_key),
+        )?),
+        _ => Err(invalid_params!(
+            "Provide either pkcs12Path or both certificatePath and privateKeyPath"
+        )),
+    }
+}
+
+fn to_pretty_json<T: Serialize>(value: &T) -> Result<String> {
+    Ok(serde_json::to_string_pretty(value)?)
+}
+
+fn format_continuation(continuation_token: Option<&str>) -> String {
+    match continuation_token {
+        Some(token) => format!("\nMore results available. continuationToken: {}", token),
+        None => String::new(),
+    }
+}
+
+fn format_auth_sessions(
+    sessions: &[AuthenticationSession],
+    continuation_token: Option<&str>,
+) -> String {
+    if sessions.is_empty() {
+        return "No sessions found".to_string();
+    }
+
+    let mut out = String::new();
+    for s in sessions {
+        out.push_str(&format!(
+            "- {}{} | {} | started {} | status {} ({})\n",
+            s.reference_number,
+            if s.is_current { " (current)" } else { "" },
+            s.authentication_method,
+            s.start_date,
+            s.status.code,
+            s.status.description
+        ));
+    }
+    out.push_str(&format_continuation(continuation_token));
+    out
+}
+
+fn format_invoice_metadata(page: &InvoiceMetadataPage) -> String {
+    let mut out = format!("{} invoice(s)\n", page.invoices.len());
+    for inv in &page.invoices {
+        out.push_str(&format!(
+            "- {} | {} | issued {} | seller {} | buyer {} | net {:.2} VAT {:.2} gross {:.2} {}\n",
+            inv.ksef_number,
+            inv.invoice_number,
+            inv.issue_date,
+            inv.seller.nip,
+            inv.buyer.identifier.value.as_deref().unwrap_or("-"),
+            inv.net_amount,
+            inv.vat_amount,
+            inv.gross_amount,
+            inv.currency
+        ));
+    }
+    if page.has_more {
+        out.push_str("\nMore results available (increase pageOffset)");
+    }
+    if page.is_truncated {
+        out.push_str("\nResult set truncated - narrow the date range");
+    }
+    out
+}
+
+fn format_export_status(status: &ExportStatusResponse) -> String {
+    let mut out = format!(
+        "Status: {} - {}\n",
+        status.status.code, status.status.description
+    );
+    if let Some(ref completed) = status.completed_date {
+        out.push_str(&format!("Completed: {}\n", completed));
+    }
+    if let Some(ref package) = status.package {
+        out.push_str(&format!(
+            "Package: {} invoice(s), {} bytes, {} part(s)\n",
+            package.invoice_count,
+            package.size,
+            package.parts.len()
+        ));
+        for part in &package.parts {
+            out.push_str(&format!(
+                "- part {} {} ({} bytes encrypted), expires {}\n",
+                part.ordinal_number, part.part_name, part.encrypted_part_size, part.expiration_date
+            ));
+        }
+    }
+    out
+}
+
+fn format_rate_limits(limits: &RateLimits) -> String {
+    let mut out = format!(
+        "{:<22} {:>8} {:>8} {:>8}\n",
+        "Group", "/second", "/minute", "/hour"
+    );
+    for (name, values) in limits.groups() {
+        match values {
+            Some(values) => out.push_str(&format!(
+                "{:<22} {:>8} {:>8} {:>8}\n",
+                name, values.per_second, values.per_minute, values.per_hour
+            )),
+            None => out.push_str(&format!("{:<22} {:>8}\n", name, "unlimited")),
+        }
+    }
+    out
+}
+
+fn format_rate_limit_budget(budget: &[RateLimitBudget]) -> String {
+    let mut out = format!(
+        "{:<22} {:>11} {:>11} {:>13} {:>10} {:>6}\n",
+        "Group", "/second", "/minute", "/hour", "throttled", "429s"
+    );
+    for b in budget {
+        out.push_str(&format!(
+            "{:<22} {:>11} {:>11} {:>13} {:>10} {:>6}\n",
+            b.group.as_str(),
+            format!("{}/{}", b.available_per_second, b.limits.per_second),
+            format!("{}/{}", b.available_per_minute, b.limits.per_minute),
+            format!("{}/{}", b.available_per_hour, b.limits.per_hour),
+            b.throttled_requests,
+            b.rate_limited_responses
+        ));
+    }
+    out
+}
+
+/// Archive entry for an invoice generated by this server
+fn archived_invoice(invoice: &Invoice, xml: &str) -> NewInvoice {
+    NewInvoice {
+        invoice_number: Some(invoice.numer.clone()),
+        seller_nip: Some(invoice.sprzedawca.nip.clone()),
+        buyer_nip: invoice.nabywca.identyfikator.numer(),
+        issue_date: Some(invoice.data_wystawienia.clone()),
+        gross_amount: f64::try_from(invoice.calculate_total_gross()).ok(),
+        currency: Some(invoice.waluta.clone()),
+        ..NewInvoice::sent(xml)
+    }
+}
+
+/// Archive entry for an invoice downloaded by `sync_invoices`
+fn synced_invoice(
+    metadata: &InvoiceMetadata,
+    subject_type: SubjectType,
+    xml: String,
+) -> NewInvoice {
+    let invoice = match subject_type {
+        SubjectType::Subject1 => NewInvoice::sent(xml),
+        _ => NewInvoice::received(xml),
+    };
+    NewInvoice {
+        invoice_number: Some(metadata.invoice_number.clone()),
+        seller_nip: Some(metadata.seller.nip.clone()),
+        buyer_nip: metadata.buyer.identifier.value.clone(),
+        issue_date: Some(metadata.issue_date.clone()),
+        gross_amount: Some(metadata.gross_amount),
+        currency: Some(metadata.currency.clone()),
+        ksef_number: Some(metadata.ksef_number.clone()),
+        ..invoice
+    }
+}
+
+fn format_local_invoices(invoices: &[StoredInvoice]) -> String {
+    let mut out = String::new();
+    for i in invoices {
+        out.push_str(&format!(
+            "- #{} | {} | {} | {} | issued {} | {} -> {} | {} {} | KSeF {}\n",
+            i.id,
+            i.direction.as_str(),
+            i.status.as_str(),
+            i.invoice_number.as_deref().unwrap_or("?"),
+            i.issue_date.as_deref().unwrap_or("?"),
+            i.seller_nip.as_deref().unwrap_or("?"),
+            i.buyer_nip.as_deref().unwrap_or("?"),
+            i.gross_amount
+                .map(|a| format!("{:.2}", a))
+                .unwrap_or_else(|| "?".to_string()),
+            i.currency.as_deref().unwrap_or(""),
+            i.ksef_number.as_deref().unwrap_or("-")
+        ));
+    }
+    out
+}
+
+fn format_local_invoice(invoice: &StoredInvoice, history: &[StatusChange]) -> String {
+    let mut out = format_local_invoices(std::slice::from_ref(invoice));
+    out.push_str(&format!(
+        "Hash: {} ({} bytes)\nSession: {}\nInvoice reference: {}\n\nStatus history:\n",
+        invoice.invoice_hash,
+        invoice.file_size,
+        invoice.session_reference.as_deref().unwrap_or("-"),
+        invoice.invoice_reference.as_deref().unwrap_or("-")
+    ));
+    for change in history {
+        out.push_str(&format!(
+            "- {} {}{}\n",
+            change.changed_at,
+            change.status.as_str(),
+            change
+                .description
+                .as_deref()
+                .map(|d| format!(" ({})", d))
+                .unwrap_or_default()
+        ));
+    }
+    out.push_str(&format!("\nXML:\n{}\n", invoice.xml));
+    if let Some(upo) = &invoice.upo {
+        out.push_str(&format!("\nUPO:\n{}\n", upo));
+    }
+    out
+}
+
+/// JSON summary of a parsed invoice; parties and lines use the field names of `generate_invoice`
+/// Party in the shape of the `seller` / `buyer` tool arguments
+fn party_json(party: &Party) -> Value {
+    let mut json = json!({ "name": party.nazwa });
+    match party.identyfikator {
+        PartyId::Nip(ref nip) => json["nip"] = json!(nip),
+        PartyId::VatUe {
+            ref kod_ue,
+            ref nr_vat_ue,
+        } => json["euVat"] = json!({ "countryCode": kod_ue, "number": nr_vat_ue }),
+        PartyId::NrId {
+            ref kod_kraju,
+            ref nr_id,
+        } => json["foreignId"] = json!({ "countryCode": kod_kraju, "number": nr_id }),
+        PartyId::BrakId => json["noId"] = json!(true),
+    }
+    if let Some(ref adres) = party.adres {
+        json["address"] = json!({
+            "countryCode": adres.kod_kraju,
+            "line1": adres.adres_l1,
+            "line2": adres.adres_l2,
+        });
+    }
+    json
+}
+
+/// Line item in the shape of the `lineItems` tool argument; `brutto` lines have a
+/// gross `unitPrice` and `grossAmount` instead of `netAmount`
+fn line_item_json(item: &InvoiceLineItem, brutto: bool) -> Value {
+    let mut line = json!({
+        "lineNumber": item.nr_wiersza,
+        "description": item.opis,
+        "unit": item.jednostka,
+        "quantity": item.ilosc,
+        "unitPrice": item.cena_netto,
+        "vatRate": item.stawka_vat,
+        "annex15": item.zalacznik_15,
+        "beforeCorrection": item.stan_przed,
+    });
+    let amount = if brutto { "grossAmount" } else { "netAmount" };
+    line[amount] = json!(item.kwota_netto);
+    line
+}
+
+/// Bank account in the shape of the `payment.bankAccounts` tool argument
+fn bank_account_json(rachunek: &BankAccount) -> Value {
+    json!({
+        "number": rachunek.numer,
+        "swift": rachunek.swift,
+        "bankName": rachunek.nazwa_banku,
+        "description": rachunek.opis,
+    })
+}
+
+/// Exemption in the shape of the `exemption` tool argument
+fn exemption_json(zwolnienie: &Exemption) -> Value {
+    let (basis, provision) = match zwolnienie {
+        Exemption::Ustawa(p) => ("act", p),
+        Exemption::Dyrektywa(p) => ("directive", p),
+        Exemption::Inna(p) => ("other", p),
+    };
+    json!({"basis": basis, "provision": provision})
+}
+
+fn invoice_summary(parsed: &ParsedInvoice) -> Value {
+    let invoice = &parsed.invoice;
+
+    json!({
+        "formVariant": parsed.kod_systemowy,
+        "invoiceType": parsed.rodzaj_faktury,
+        "invoiceNumber": invoice.numer,
+        "invoiceDate": invoice.data_wystawienia,
+        "currency": invoice.waluta,
+        "seller": party_json(&Party::from(invoice.sprzedawca.clone())),
+        "buyer": party_json(&invoice.nabywca),
+        "lineItems": invoice
+            .pozycje
+            .iter()
+            .map(|item| line_item_json(item, invoice.ceny_brutto))
+            .collect::<Vec<_>>(),
+        "orderItems": invoice
+            .zamowienie
+            .iter()
+            .map(|item| line_item_json(item, false))
+            .collect::<Vec<_>>(),
+        "advanceAmount": invoice.kwota_zaliczki,
+        "advanceInvoices": invoice.zaliczki.iter().map(|zaliczka| json!({
+            "invoiceNumber": zaliczka.numer,
+            "ksefNumber": zaliczka.numer_ksef,
+        })).collect::<Vec<_>>(),
+        "totals": parsed.sumy.iter().map(|total| json!({
+            "rate": total.stawka,
+            "net": total.netto,
+            "vat": total.vat,
+        })).collect::<Vec<_>>(),
+        "totalDue": parsed.kwota_naleznosci,
+        "exemption": invoice.zwolnienie.as_ref().map(exemption_json),
+        "correction": invoice.korekta.as_ref().map(|korekta| json!({
+            "reason": korekta.przyczyna,
+            "type": korekta.typ.map(|typ| typ.code()),
+            "correctedInvoices": korekta.faktury.iter().map(|faktura| json!({
+                "invoiceNumber": faktura.numer,
+                "invoiceDate": faktura.data_wystawienia,
+                "ksefNumber": faktura.numer_ksef,
+            })).collect::<Vec<_>>(),
+        })),
+        "payment": invoice.platnosc.as_ref().map(|payment| json!({
+            "dueDates": payment.terminy,
+            "method": payment.forma,
+            "bankAccounts": payment.rachunki.iter().map(bank_account_json).collect::<Vec<_>>(),
+            "factorBankAccounts": payment
+                .rachunki_faktora
+                .iter()
+                .map(bank_account_json)
+                .collect::<Vec<_>>(),
+            "paid": payment.zaplacono,
+            "paymentDate": payment.data_zaplaty,
+            "partialPayments": payment.zaplaty_czesciowe.iter().map(|zaplata| json!({
+                "amount": zaplata.kwota,
+                "date": zaplata.data,
+            })).collect::<Vec<_>>(),
+        })),
+        "splitPayment": invoice.split_payment(),
+        "attachments": invoice.zalacznik.iter().map(|block| json!({
+            "header": block.naglowek,
+            "metadata": block.metadane.iter().map(|(key, value)| json!({
+                "key": key,
+                "value": value,
+            })).collect::<Vec<_>>(),
+            "paragraphs": block.akapity,
+            "tables": block.tabele.iter().map(|table| json!({
+                "description": table.opis,
+                "columns": table.kolumny,
+                "rows": table.wiersze,
+            })).collect::<Vec<_>>(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn format_validation_errors(variant: FormVariant, errors: &[ValidationError]) -> String {
+    let mut out = format!(
+        "Invoice XML is not valid {} ({} error{}):\n",
+        variant.kod_systemowy(),
+        errors.len(),
+        if errors.len() == 1 { "" } else { "s" }
+    );
+    for error in errors {
+        out.push_str(&format!("- {}\n", error));
+    }
+    out
+}
+
+fn format_upo(upo: &Upo) -> String {
+    let mut out = format!(
+        "Session: {}\nReceived by: {}\nForm: {}\n",
+        upo.session_reference_number.as_deref().unwrap_or("-"),
+        upo.receiving_entity.as_deref().unwrap_or("-"),
+        upo.form_code.as_deref().unwrap_or("-")
+    );
+    if let Some(context) = &upo.context_identifier {
+        out.push_str(&format!(
+            "Context: {} {}\n",
+            context.identifier_type, context.value
+        ));
+    }
+    for document in &upo.documents {
+        out.push_str(&format!(
+            "- {} (invoice {}, seller {}): accepted {}, hash {}\n",
+            document.ksef_number.as_deref().unwrap_or("-"),
+            document.invoice_number.as_deref().unwrap_or("-"),
+            document.seller_nip.as_deref().unwrap_or("-"),
+            document.accepted_at.as_deref().unwrap_or("-"),
+            document.invoice_hash.as_deref().unwrap_or("-")
+        ));
+    }
+    out
+}
+
+/// Parsed summary placed before the raw UPO XML, empty if the XML is not a UPO
+fn format_upo_summary(xml: &str) -> String {
+    match Upo::parse(xml) {
+        Ok(upo) => format!("{}\n", format_upo(&upo)),
+        Err(_) => String::new(),
+    }
+}
+
+fn format_upo_verification(verification: &UpoVerification) -> String {
+    let mut out = format!(
+        "UPO verification {}\n\n",
+        if verification.is_valid() {
+            "passed"
+        } else {
+            "FAILED"
+        }
+    );
+    match &verification.signature {
+        Ok(signer) => out.push_str(&format!(
+            "Signature: valid\nSigner: {}\nIssuer: {}\nSigned at: {}\n",
+            signer.subject,
+            signer.issuer,
+            signer.signing_time.as_deref().unwrap_or("-")
+        )),
+        Err(reason) => out.push_str(&format!("Signature: INVALID ({})\n", reason)),
+    }
+    match &verification.trusted_signer {
+        Ok(()) => out.push_str("Signer trusted: yes\n"),
+        Err(reason) => out.push_str(&format!("Signer trusted: NO ({})\n", reason)),
+    }
+    if let Some(document) = verification.checked_document() {
+        out.push_str(&format!(
+            "Confirmed invoice: {}\nInvoice hash: {}\n",
+            document.ksef_number.as_deref().unwrap_or("-"),
+            match verification.invoice_hash_matches {
+                Some(true) => "matches",
+                Some(false) => "MISMATCH",
+                None => "not checked (no invoice XML)",
+            }
+        ));
+    }
+    if !verification.mismatches.is_empty() {
+        out.push_str("\nMismatches:\n");
+        for mismatch in &verification.mismatches {
+            out.push_str(&format!("- {}\n", mismatch));
+        }
+    }
+    out.push_str(&format!("\n{}", format_upo(&verification.upo)));
+    out
+}
+
+fn format_sync_report(report: &SyncReport, store: &SyncStore) -> String {
+    let mut out = format!(
+        "Sync of {} for {} finished.\nNew invoices: {}\nAlready downloaded: {}\nHigh-water mark: {}\n",
+        report.subject_type.as_str(),
+        report.context,
+        report.new_invoices.len(),
+        report.duplicates,
+        report.high_water_mark.as_deref().unwrap_or("none")
+    );
+    for invoice in &report.new_invoices {
+        out.push_str(&format!(
+            "- {}\n",
+            store
+                .invoice_path(&report.context, report.subject_type, &invoice.ksef_number)
+                .display()
+        ));
+    }
+    out
+}
+
+fn format_sync_state(state: &SyncState, store: &SyncStore) -> String {
+    if state.cursors.is_empty() {
+        return format!(
+            "No invoices synced yet (directory: {})",
+            store.dir().display()
+        );
+    }
+
+    let mut out = format!("Sync directory: {}\n", store.dir().display());
+    for cursor in state.cursors.values() {
+        out.push_str(&format!(
+            "- {} {} | high-water mark {} | last sync {} | {} invoices\n",
+            cursor.context,
+            cursor.subject_type.as_str(),
+            cursor.high_water_mark.as_deref().unwrap_or("none"),
+            cursor.last_sync.as_deref().unwrap_or("never"),
+            cursor.synced_invoices
+        ));
+    }
+    out
+}
+
+fn format_sessions(page: &SessionsPage) -> String {
+    if page.sessions.is_empty() {
+        return "No sessions found".to_string();
+    }
+
+    let mut out = String::new();
+    for s in &page.sessions {
+        out.push_str(&format!(
+            "- {} | created {} | status {} ({}) | invoices {} ({} ok, {} failed)\n",
+            s.reference_number,
+            s.date_created,
+            s.status.code,
+            s.status.description,
+            s.total_invoice_count,
+            s.successful_invoice_count,
+            s.failed_invoice_count
+        ));
+    }
+    out.push_str(&format_continuation(page.continuation_token.as_deref()));
+    out
+}
+
+fn format_session_status(status: &SessionStatusResponse) -> String {
+    let mut out = format!(
+        "Status: {} - {}\n",
+        status.status.code, status.status.description
+    );
+    for detail in &status.status.details {
+        out.push_str(&format!("  {}\n", detail));
+    }
+    if let Some(ref created) = status.date_created {
+        out.push_str(&format!("Created: {}\n", created));
+    }
+    if let Some(ref valid_until) = status.valid_until {
+        out.push_str(&format!("Valid until: {}\n", valid_until));
+    }
+    if let Some(count) = status.invoice_count {
+        out.push_str(&format!(
+            "Invoices: {} ({} successful, {} failed)\n",
+            count,
+            status.successful_invoice_count.unwrap_or(0),
+            status.failed_invoice_count.unwrap_or(0)
+        ));
+    }
+    if let Some(ref upo) = status.upo {
+        for page in &upo.pages {
+            out.push_str(&format!(
+                "UPO: {} ({})\n",
+                page.reference_number, page.download_url
+            ));
+        }
+    }
+    out
+}
+
+fn format_session_invoices(page: &SessionInvoicesPage) -> String {
+    if page.invoices.is_empty() {
+        return "No invoices in session".to_string();
+    }
+
+    let mut out = String::new();
+    for inv in &page.invoices {
+        out.push_str(&format!(
+            "- #{} {} | invoice {} | KSeF {} | status {} ({})\n",
+            inv.ordinal_number,
+            inv.reference_number,
+            inv.invoice_number.as_deref().unwrap_or("-"),
+            inv.ksef_number.as_deref().unwrap_or("-"),
+            inv.status.code,
+            inv.status.description
+        ));
+        for detail in &inv.status.details {
+            out.push_str(&format!("    {}\n", detail));
+        }
+    }
+    out.push_str(&format_continuation(page.continuation_token.as_deref()));
+    out
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut server = McpServer::new();
+    let mut server = McpServer::new()?;
     let stdin = io::stdin();
     let mut stdout = io::stdout();
 